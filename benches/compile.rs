@@ -0,0 +1,83 @@
+// Compiler throughput on synthetic programs: tokens per second through the tokenizer,
+// and the whole pipeline from source text to written assembly. Run with `cargo bench`.
+
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use std::hint::black_box;
+
+use noble::asm::Syntax;
+use noble::diagnostic::Severity;
+use noble::generate::Generator;
+use noble::optimize::PassManager;
+use noble::parse::Parser;
+use noble::synthetic;
+use noble::target;
+use noble::tokenize::Tokenizer;
+use noble::typecheck::TypeChecker;
+
+// program sizes, in top-level units
+const SIZES: [usize; 3] = [100, 1_000, 10_000];
+const OPTIMIZED_SIZE_LIMIT: usize = 1_000;
+
+fn tokenize(c: &mut Criterion) {
+    let mut group = c.benchmark_group("tokenize");
+    for units in SIZES {
+        let source = synthetic::program(units);
+        let tokens = Tokenizer::new(source.clone()).tokenize().len();
+        group.throughput(Throughput::Elements(tokens as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(units), &source, |b, source| {
+            b.iter(|| Tokenizer::new(black_box(source.clone())).tokenize())
+        });
+    }
+    group.finish();
+}
+
+fn compile(c: &mut Criterion) {
+    let mut group = c.benchmark_group("compile");
+    group.sample_size(10);
+    for units in SIZES {
+        let source = synthetic::program(units);
+        group.throughput(Throughput::Bytes(source.len() as u64));
+        for opt_level in [0, 2] {
+            // the -O2 passes grow faster than linearly, so the largest size would
+            // take minutes per sample
+            if opt_level > 0 && units > OPTIMIZED_SIZE_LIMIT {
+                continue;
+            }
+            let id = BenchmarkId::new(format!("O{}", opt_level), units);
+            group.bench_with_input(id, &source, |b, source| {
+                b.iter(|| end_to_end(black_box(source), opt_level))
+            });
+        }
+    }
+    group.finish();
+}
+
+// Source text to assembly, the way the driver does it for a freestanding build
+fn end_to_end(source: &str, opt_level: u8) -> usize {
+    let tokens = Tokenizer::new(source.to_string()).tokenize();
+    let mut parser = Parser::new(tokens);
+    let mut ast = parser.parse_to_ast();
+    assert!(
+        parser.errors().is_empty(),
+        "synthetic program failed to parse"
+    );
+    let diagnostics = TypeChecker::new().check(&mut ast);
+    assert!(
+        diagnostics.iter().all(|d| d.severity != Severity::Error),
+        "synthetic program failed to type check"
+    );
+
+    let mut passes = PassManager::new(opt_level);
+    passes.run_ast(&mut ast);
+    let mut generator = Generator::new("synthetic.nbl", source, "_start", &target::X86_64_LINUX);
+    generator.generate_boilerplate();
+    generator.generate_x64(&ast);
+    generator.optimize(&mut passes);
+    let mut output = Vec::new();
+    generator
+        .write(&mut output, Syntax::Intel)
+        .expect("writing to memory")
+}
+
+criterion_group!(benches, tokenize, compile);
+criterion_main!(benches);
@@ -0,0 +1,390 @@
+use std::io::Write;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Syntax {
+    Intel,
+    Att,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Size {
+    Byte,
+    Dword,
+    Qword,
+}
+
+impl Size {
+    pub fn keyword(&self) -> &'static str {
+        match self {
+            Size::Byte => "byte",
+            Size::Dword => "dword",
+            Size::Qword => "qword",
+        }
+    }
+
+    pub fn bytes(&self) -> usize {
+        match self {
+            Size::Byte => 1,
+            Size::Dword => 4,
+            Size::Qword => 8,
+        }
+    }
+
+    fn att_suffix(&self) -> char {
+        match self {
+            Size::Byte => 'b',
+            Size::Dword => 'l',
+            Size::Qword => 'q',
+        }
+    }
+
+    fn reserve_directive(&self) -> &'static str {
+        match self {
+            Size::Byte => "resb",
+            Size::Dword => "resd",
+            Size::Qword => "resq",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operand {
+    Register(String),
+    Immediate(i64),
+    // base is either a register or a symbol; symbols are rip-relative ("default rel")
+    Memory {
+        size: Option<Size>,
+        base: String,
+        offset: i64,
+    },
+    Label(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Line {
+    Instruction {
+        mnemonic: String,
+        operands: Vec<Operand>,
+    },
+    Label(String),
+    Comment(String),
+    Section(String),
+    Global(String),
+    Reserve {
+        name: String,
+        size: Size,
+        count: usize,
+    },
+    // 8-byte address of a label, e.g. a jump table entry
+    Quad(String),
+    // NUL-terminated string constant
+    Asciz {
+        name: String,
+        text: String,
+    },
+    Extern(String),
+    Blank,
+    // source line and column of the instructions that follow, 0 when they have none;
+    // not written to the output, only used for the source map
+    Location {
+        line: usize,
+        column: usize,
+    },
+}
+
+// Parses one instruction in the NASM form the generator writes, e.g. "mov dword [x], eax"
+pub fn parse_instruction(text: &str) -> Line {
+    let text = text.trim();
+    let (mnemonic, rest) = match text.split_once(' ') {
+        Some((mnemonic, rest)) => (mnemonic, rest.trim()),
+        None => (text, ""),
+    };
+    let operands = if rest.is_empty() {
+        Vec::new()
+    } else {
+        rest.split(',').map(|op| parse_operand(op.trim())).collect()
+    };
+    Line::Instruction {
+        mnemonic: mnemonic.to_string(),
+        operands,
+    }
+}
+
+fn parse_operand(text: &str) -> Operand {
+    if let Some(open) = text.find('[') {
+        let size = match text[..open].trim() {
+            "byte" => Some(Size::Byte),
+            "dword" => Some(Size::Dword),
+            "qword" => Some(Size::Qword),
+            _ => None,
+        };
+        let address = text[open + 1..].trim_end_matches(']').trim();
+        // the sign of the displacement is the first '+' or '-' after the base
+        let (base, offset) = match address[1..].find(['+', '-']) {
+            Some(i) => {
+                let (base, displacement) = address.split_at(i + 1);
+                let displacement = displacement.replace(' ', "");
+                (
+                    base.trim().to_string(),
+                    displacement.trim_start_matches('+').parse().unwrap_or(0),
+                )
+            }
+            None => (address.to_string(), 0),
+        };
+        return Operand::Memory { size, base, offset };
+    }
+    if let Ok(value) = text.parse::<i64>() {
+        return Operand::Immediate(value);
+    }
+    if is_register(text) {
+        return Operand::Register(text.to_string());
+    }
+    Operand::Label(text.to_string())
+}
+
+pub fn register_size(name: &str) -> Option<Size> {
+    match name {
+        "al" | "bl" | "cl" | "dl" | "sil" | "dil" | "r8b" | "r9b" | "r10b" | "r11b" => {
+            Some(Size::Byte)
+        }
+        "eax" | "ebx" | "ecx" | "edx" | "esi" | "edi" | "esp" | "ebp" | "r8d" | "r9d" | "r10d"
+        | "r11d" | "r12d" | "r13d" | "r14d" | "r15d" => Some(Size::Dword),
+        "rax" | "rbx" | "rcx" | "rdx" | "rsi" | "rdi" | "rsp" | "rbp" | "r8" | "r9" | "r10"
+        | "r11" | "r12" | "r13" | "r14" | "r15" => Some(Size::Qword),
+        _ => None,
+    }
+}
+
+// Whether a name is a register rather than a symbol: a general-purpose register in any
+// of its sizes, an xmm register or rip
+pub fn is_register(name: &str) -> bool {
+    register_family(name).is_some()
+        || name == "rip"
+        || name
+            .strip_prefix("xmm")
+            .is_some_and(|n| (0..16).any(|i| n == i.to_string()))
+}
+
+// The 64-bit register a name is part of, so a write to al is seen to change eax, or None
+// for anything that is not a general-purpose register
+pub fn register_family(name: &str) -> Option<&'static str> {
+    const FAMILIES: [&[&str]; 16] = [
+        &["al", "ah", "ax", "eax", "rax"],
+        &["bl", "bh", "bx", "ebx", "rbx"],
+        &["cl", "ch", "cx", "ecx", "rcx"],
+        &["dl", "dh", "dx", "edx", "rdx"],
+        &["sil", "si", "esi", "rsi"],
+        &["dil", "di", "edi", "rdi"],
+        &["spl", "sp", "esp", "rsp"],
+        &["bpl", "bp", "ebp", "rbp"],
+        &["r8b", "r8w", "r8d", "r8"],
+        &["r9b", "r9w", "r9d", "r9"],
+        &["r10b", "r10w", "r10d", "r10"],
+        &["r11b", "r11w", "r11d", "r11"],
+        &["r12b", "r12w", "r12d", "r12"],
+        &["r13b", "r13w", "r13d", "r13"],
+        &["r14b", "r14w", "r14d", "r14"],
+        &["r15b", "r15w", "r15d", "r15"],
+    ];
+    FAMILIES
+        .iter()
+        .find(|family| family.contains(&name))
+        .map(|family| family[family.len() - 1])
+}
+
+impl Line {
+    pub fn render(&self, syntax: Syntax) -> String {
+        match (self, syntax) {
+            (Line::Instruction { mnemonic, operands }, Syntax::Intel) => {
+                if operands.is_empty() {
+                    format!("    {}", mnemonic)
+                } else {
+                    let operands: Vec<String> = operands.iter().map(render_intel).collect();
+                    format!("    {} {}", mnemonic, operands.join(", "))
+                }
+            }
+            (Line::Instruction { mnemonic, operands }, Syntax::Att) => {
+                // indirect jumps and calls take a '*' before a register or memory target
+                let indirect = matches!(mnemonic.as_str(), "jmp" | "call");
+                let mnemonic = att_mnemonic(mnemonic, operands);
+                if operands.is_empty() {
+                    format!("    {}", mnemonic)
+                } else {
+                    let operands: Vec<String> = operands
+                        .iter()
+                        .rev()
+                        .map(|op| match op {
+                            Operand::Label(_) => render_att(op),
+                            _ if indirect => format!("*{}", render_att(op)),
+                            _ => render_att(op),
+                        })
+                        .collect();
+                    format!("    {} {}", mnemonic, operands.join(", "))
+                }
+            }
+            (Line::Label(name), _) => format!("{}:", name),
+            (Line::Comment(text), Syntax::Intel) => format!("    ; {}", text),
+            (Line::Comment(text), Syntax::Att) => format!("    # {}", text),
+            (Line::Section(name), Syntax::Intel) => format!("segment {}", name),
+            (Line::Section(name), Syntax::Att) => name.to_string(),
+            (Line::Global(name), Syntax::Intel) => format!("global {}", name),
+            (Line::Global(name), Syntax::Att) => format!(".globl {}", name),
+            (Line::Reserve { name, size, count }, Syntax::Intel) => {
+                format!("{} {} {}", name, size.reserve_directive(), count)
+            }
+            (Line::Reserve { name, size, count }, Syntax::Att) => {
+                format!("{}: .zero {}", name, size.bytes() * count)
+            }
+            (Line::Asciz { name, text }, Syntax::Intel) => {
+                // backquoted NASM strings understand C-style escapes
+                format!("{} db `{}`, 0", name, escape(text))
+            }
+            (Line::Asciz { name, text }, Syntax::Att) => {
+                format!("{}: .asciz \"{}\"", name, escape(text))
+            }
+            (Line::Extern(name), Syntax::Intel) => format!("extern {}", name),
+            (Line::Extern(name), Syntax::Att) => format!(".extern {}", name),
+            (Line::Quad(label), Syntax::Intel) => format!("    dq {}", label),
+            (Line::Quad(label), Syntax::Att) => format!("    .quad {}", label),
+            (Line::Blank, _) | (Line::Location { .. }, _) => String::new(),
+        }
+    }
+}
+
+// Returns the number of bytes written
+pub fn write_program(
+    lines: &[Line],
+    syntax: Syntax,
+    writer: &mut impl Write,
+) -> std::io::Result<usize> {
+    let mut text = header(syntax).to_string();
+    for line in lines
+        .iter()
+        .filter(|line| !matches!(line, Line::Location { .. }))
+    {
+        text.push_str(&line.render(syntax));
+        text.push('\n');
+    }
+    writer.write_all(text.as_bytes())?;
+    writer.flush()?;
+    Ok(text.len())
+}
+
+fn header(syntax: Syntax) -> &'static str {
+    match syntax {
+        Syntax::Intel => "bits 64\ndefault rel\n\n",
+        Syntax::Att => "",
+    }
+}
+
+// (output line, source line, source column) of every instruction that has a source
+// location, with lines numbered from 1 as write_program lays them out
+pub fn source_locations(lines: &[Line], syntax: Syntax) -> Vec<(usize, usize, usize)> {
+    let mut output_line = header(syntax).lines().count();
+    let mut location = (0, 0);
+    let mut locations = Vec::new();
+    for line in lines {
+        match line {
+            Line::Location { line, column } => location = (*line, *column),
+            _ => {
+                output_line += 1;
+                if matches!(line, Line::Instruction { .. }) && location.0 > 0 {
+                    locations.push((output_line, location.0, location.1));
+                }
+            }
+        }
+    }
+    locations
+}
+
+fn escape(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '\n' => "\\n".to_string(),
+            '\t' => "\\t".to_string(),
+            '\\' => "\\\\".to_string(),
+            '"' => "\\\"".to_string(),
+            '`' => "\\`".to_string(),
+            c => c.to_string(),
+        })
+        .collect()
+}
+
+fn render_intel(operand: &Operand) -> String {
+    match operand {
+        Operand::Register(name) | Operand::Label(name) => name.clone(),
+        Operand::Immediate(value) => value.to_string(),
+        Operand::Memory { size, base, offset } => {
+            let address = match offset {
+                0 => base.clone(),
+                o if *o > 0 => format!("{}+{}", base, o),
+                o => format!("{}{}", base, o),
+            };
+            match size {
+                Some(size) => format!("{} [{}]", size.keyword(), address),
+                None => format!("[{}]", address),
+            }
+        }
+    }
+}
+
+fn render_att(operand: &Operand) -> String {
+    match operand {
+        Operand::Register(name) => format!("%{}", name),
+        Operand::Label(name) => name.clone(),
+        Operand::Immediate(value) => format!("${}", value),
+        Operand::Memory { base, offset, .. } => {
+            if register_size(base).is_some() {
+                match offset {
+                    0 => format!("(%{})", base),
+                    o => format!("{}(%{})", o, base),
+                }
+            } else {
+                match offset {
+                    0 => format!("{}(%rip)", base),
+                    o if *o > 0 => format!("{}+{}(%rip)", base, o),
+                    o => format!("{}{}(%rip)", base, o),
+                }
+            }
+        }
+    }
+}
+
+fn att_mnemonic(mnemonic: &str, operands: &[Operand]) -> String {
+    match mnemonic {
+        "cdq" => return "cltd".to_string(),
+        "cqo" => return "cqto".to_string(),
+        "movzx" | "movsx" => {
+            // AT&T spells out both widths: movzx eax, byte [x] -> movzbl
+            let source = match operands.get(1) {
+                Some(Operand::Register(name)) => register_size(name),
+                Some(Operand::Memory { size, .. }) => *size,
+                _ => None,
+            };
+            let destination = match operands.first() {
+                Some(Operand::Register(name)) => register_size(name),
+                _ => None,
+            };
+            if let (Some(source), Some(destination)) = (source, destination) {
+                return format!(
+                    "{}{}{}",
+                    &mnemonic[..4],
+                    source.att_suffix(),
+                    destination.att_suffix()
+                );
+            }
+        }
+        _ => {}
+    }
+
+    // Without a register operand the operand size has to be carried by the mnemonic
+    let has_register = operands.iter().any(|op| matches!(op, Operand::Register(_)));
+    let memory_size = operands.iter().find_map(|op| match op {
+        Operand::Memory { size, .. } => *size,
+        _ => None,
+    });
+    match (has_register, memory_size) {
+        (false, Some(size)) => format!("{}{}", mnemonic, size.att_suffix()),
+        _ => mnemonic.to_string(),
+    }
+}
@@ -0,0 +1,502 @@
+use crate::arena::{Arena, Id};
+use crate::consteval::Value;
+use crate::intern::Name;
+use crate::tokenize::Span;
+use std::fmt;
+
+#[derive(Debug, Clone)]
+pub enum Stmt {
+    Entry,
+    Exit(Expr),
+    PrintInt(Expr),
+    Assert(Expr),
+    VariableDeclaration {
+        name: Name,
+        type_: Type,
+        // None for `i32s x;`, which must be assigned before it is read
+        value: Option<Expr>,
+    },
+    VariableAssignment {
+        name: Name,
+        value: Expr,
+    },
+    // *name = value, where name is a reference
+    DerefAssignment {
+        name: Name,
+        value: Expr,
+    },
+    For {
+        iterator_name: Name,
+        // i32s unless the loop names another; only i32s and f32s type check
+        type_: Type,
+        iterator_begin: Expr,
+        iterator_end: Expr,
+        // "to" includes iterator_end, "until" stops before it
+        inclusive: bool,
+        // added after each pass through the body; None counts up by one
+        step: Option<Expr>,
+        body: NodeId,
+    },
+    // else_body is the Block of an else, or the If of an else if
+    If {
+        condition: Expr,
+        body: NodeId,
+        else_body: Option<NodeId>,
+    },
+    // a scope and nothing more: a bare `{ ... }`, and the body of every loop, branch,
+    // match arm and function, which open their scopes only through it
+    Block {
+        body: Vec<NodeId>,
+    },
+    While {
+        condition: Expr,
+        body: NodeId,
+    },
+    // integer arms are tried in order; else_body is required by the type checker
+    Match {
+        scrutinee: Expr,
+        arms: Vec<(i32, NodeId)>,
+        else_body: Option<NodeId>,
+    },
+    // body runs once before the condition is first checked
+    DoWhile {
+        body: NodeId,
+        condition: Expr,
+    },
+    FunctionDeclaration {
+        name: Name,
+        params: Vec<(Name, Type)>,
+        return_type: Type,
+        // an expression body "= e;" is lowered to a block of a single return statement;
+        // an empty block for an extern function
+        body: NodeId,
+        linkage: Linkage,
+    },
+    // no value in a void function
+    Return(Option<Expr>),
+    // evaluated for its side effects, e.g. a call to a void function
+    Expression(Expr),
+    // uses were replaced by the value while parsing, so later stages skip it
+    ConstantDeclaration {
+        name: Name,
+        value: Value,
+    },
+    // uses were replaced by the expression while parsing, so later stages skip it too
+    Define {
+        name: Name,
+        value: Expr,
+    },
+}
+
+// How a function is seen from outside the program
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Linkage {
+    // only called from Noble code, with Noble's own calling convention
+    Internal,
+    // "extern fn": defined in another object file and called with the C calling convention
+    Extern,
+    // "export fn": also callable from C under its own name
+    Export,
+}
+
+#[derive(Debug, Clone)]
+pub struct Node {
+    pub stmt: Stmt,
+    pub children: Vec<NodeId>,
+    pub span: Span,
+}
+
+pub type NodeId = Id<Node>;
+
+// Every statement of a program in one arena; bodies and children refer to them by id
+#[derive(Debug, Clone)]
+pub struct Ast {
+    pub nodes: Arena<Node>,
+    pub root: NodeId,
+}
+
+impl Ast {
+    pub fn root(&self) -> &Node {
+        &self.nodes[self.root]
+    }
+
+    // nested statements are in the arena too, so this counts loop, if and block bodies
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    // Every statement of the program, depth-first from the root
+    pub fn iter(&self) -> Statements<'_> {
+        self.iter_from(self.root)
+    }
+
+    // A statement and everything nested in it, depth-first
+    pub fn iter_from(&self, id: NodeId) -> Statements<'_> {
+        Statements {
+            nodes: &self.nodes,
+            pending: vec![(id, None, 0)],
+            last: None,
+        }
+    }
+}
+
+// A statement reached by Ast::iter: the statement it is nested in, None for the one
+// the walk started from, and how many statements enclose it within the walk
+#[derive(Debug, Clone, Copy)]
+pub struct Visit<'a> {
+    pub id: NodeId,
+    pub node: &'a Node,
+    pub parent: Option<NodeId>,
+    pub depth: usize,
+}
+
+// Each statement comes before the ones nested in it, and those come in source order
+pub struct Statements<'a> {
+    nodes: &'a Arena<Node>,
+    // (id, parent, depth) still to visit, the next one last
+    pending: Vec<(NodeId, Option<NodeId>, usize)>,
+    // the statement just visited, whose nested statements are visited next unless
+    // skip_children is called
+    last: Option<(NodeId, usize)>,
+}
+
+impl Statements<'_> {
+    // Leaves out the statements nested in the one just visited, e.g. a function body
+    pub fn skip_children(&mut self) {
+        self.last = None;
+    }
+}
+
+impl<'a> Iterator for Statements<'a> {
+    type Item = Visit<'a>;
+
+    fn next(&mut self) -> Option<Visit<'a>> {
+        if let Some((id, depth)) = self.last.take() {
+            let children = self.nodes[id].child_ids();
+            self.pending.extend(
+                children
+                    .into_iter()
+                    .rev()
+                    .map(|child| (child, Some(id), depth + 1)),
+            );
+        }
+        let (id, parent, depth) = self.pending.pop()?;
+        self.last = Some((id, depth));
+        Some(Visit {
+            id,
+            node: &self.nodes[id],
+            parent,
+            depth,
+        })
+    }
+}
+
+impl Node {
+    // Ids of the statements directly inside this one, in source order
+    pub fn child_ids(&self) -> Vec<NodeId> {
+        self.bodies().concat()
+    }
+
+    // The statements of a block, which every body is
+    pub fn block(&self) -> &[NodeId] {
+        match &self.stmt {
+            Stmt::Block { body } => body,
+            stmt => panic!("Expected a block, found {:?}", stmt),
+        }
+    }
+
+    // The runs of statements directly inside this one: the program and a block's
+    // statements, or the single block of a loop's or function's body, and each branch of
+    // an if or match
+    pub fn bodies(&self) -> Vec<&[NodeId]> {
+        let mut bodies = Vec::new();
+        if !self.children.is_empty() {
+            bodies.push(self.children.as_slice());
+        }
+        match &self.stmt {
+            Stmt::Block { body } => bodies.push(body.as_slice()),
+            Stmt::For { body, .. }
+            | Stmt::While { body, .. }
+            | Stmt::DoWhile { body, .. }
+            | Stmt::FunctionDeclaration { body, .. } => bodies.push(std::slice::from_ref(body)),
+            Stmt::If {
+                body, else_body, ..
+            } => {
+                bodies.push(std::slice::from_ref(body));
+                bodies.extend(else_body.as_ref().map(std::slice::from_ref));
+            }
+            Stmt::Match {
+                arms, else_body, ..
+            } => {
+                bodies.extend(arms.iter().map(|(_, body)| std::slice::from_ref(body)));
+                bodies.extend(else_body.as_ref().map(std::slice::from_ref));
+            }
+            _ => {}
+        }
+        bodies
+    }
+}
+
+impl Stmt {
+    // The expressions a statement evaluates itself, not those of the statements nested in it
+    pub fn expressions(&self) -> Vec<&Expr> {
+        match self {
+            Stmt::Exit(expr)
+            | Stmt::PrintInt(expr)
+            | Stmt::Assert(expr)
+            | Stmt::Expression(expr)
+            | Stmt::Return(Some(expr))
+            | Stmt::VariableDeclaration {
+                value: Some(expr), ..
+            }
+            | Stmt::VariableAssignment { value: expr, .. }
+            | Stmt::DerefAssignment { value: expr, .. }
+            | Stmt::If {
+                condition: expr, ..
+            }
+            | Stmt::While {
+                condition: expr, ..
+            }
+            | Stmt::DoWhile {
+                condition: expr, ..
+            }
+            | Stmt::Match {
+                scrutinee: expr, ..
+            } => vec![expr],
+            Stmt::For {
+                iterator_begin,
+                iterator_end,
+                step,
+                ..
+            } => [iterator_begin, iterator_end]
+                .into_iter()
+                .chain(step)
+                .collect(),
+            Stmt::Entry
+            | Stmt::Return(None)
+            | Stmt::VariableDeclaration { value: None, .. }
+            | Stmt::Block { .. }
+            | Stmt::FunctionDeclaration { .. }
+            | Stmt::ConstantDeclaration { .. }
+            | Stmt::Define { .. } => vec![],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    I32S,
+    F32S,
+    Bool,
+    Char,
+    // only as a function return type
+    Void,
+    // only as the type of a ref parameter
+    Ref(Box<Type>),
+}
+
+impl Type {
+    pub fn integer_range(&self) -> Option<(i128, i128)> {
+        match self {
+            Type::I32S => Some((i32::MIN as i128, i32::MAX as i128)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Type::I32S => "i32s",
+            Type::F32S => "f32s",
+            Type::Bool => "bool",
+            Type::Char => "char",
+            Type::Void => "void",
+            Type::Ref(inner) => return write!(f, "ref {}", inner),
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// An expression and the type the checker resolved for it
+#[derive(Clone)]
+pub struct Expr {
+    pub kind: ExprKind,
+    // filled in by the type checker for codegen; None before checking, and for an
+    // expression that did not type check
+    pub type_: Option<Type>,
+}
+
+#[derive(Debug, Clone)]
+pub enum ExprKind {
+    Int(i32),
+    Float(f32),
+    Bool(bool),
+    Char(char),
+    Ident(Name),
+    // &name and *name
+    AddressOf(Name),
+    Deref(Name),
+    // an explicit f32s(x) or i32s(x), or an i32s operand the type checker promoted
+    // to f32s
+    Convert {
+        value: Box<Expr>,
+        to: Type,
+    },
+    // -value, other than of a literal, which the AST builder folds
+    Negate {
+        value: Box<Expr>,
+    },
+    Call {
+        name: Name,
+        args: Vec<Expr>,
+    },
+    BinaryOp {
+        left: Box<Expr>,
+        op: BinOpType,
+        right: Box<Expr>,
+    },
+}
+
+impl Expr {
+    pub fn new(kind: ExprKind) -> Self {
+        Expr { kind, type_: None }
+    }
+
+    // An expression whose type is known without checking, such as a folded constant
+    pub fn typed(kind: ExprKind, type_: Type) -> Self {
+        Expr {
+            kind,
+            type_: Some(type_),
+        }
+    }
+
+    // The expressions directly inside this one, left to right
+    pub fn operands(&self) -> Vec<&Expr> {
+        match &self.kind {
+            ExprKind::Call { args, .. } => args.iter().collect(),
+            ExprKind::BinaryOp { left, right, .. } => vec![left, right],
+            ExprKind::Convert { value, .. } | ExprKind::Negate { value } => vec![value],
+            ExprKind::Int(_)
+            | ExprKind::Float(_)
+            | ExprKind::Bool(_)
+            | ExprKind::Char(_)
+            | ExprKind::Ident(_)
+            | ExprKind::AddressOf(_)
+            | ExprKind::Deref(_) => Vec::new(),
+        }
+    }
+
+    // This expression and every one inside it, each before its operands
+    pub fn walk(&self) -> Exprs<'_> {
+        Exprs {
+            pending: vec![ExprVisit {
+                expr: self,
+                parent: None,
+                depth: 0,
+            }],
+        }
+    }
+
+    // The variable it reads, takes the address of, or reads through, if it is one of those
+    pub fn variable(&self) -> Option<Name> {
+        match self.kind {
+            ExprKind::Ident(name) | ExprKind::AddressOf(name) | ExprKind::Deref(name) => Some(name),
+            _ => None,
+        }
+    }
+
+    // Whether evaluating it calls a function, which may have side effects
+    pub fn has_call(&self) -> bool {
+        self.walk()
+            .any(|visit| matches!(visit.expr.kind, ExprKind::Call { .. }))
+    }
+}
+
+// An expression reached by Expr::walk: the expression it is an operand of, None for
+// the one walked, and how many expressions enclose it
+#[derive(Debug, Clone, Copy)]
+pub struct ExprVisit<'a> {
+    pub expr: &'a Expr,
+    pub parent: Option<&'a Expr>,
+    pub depth: usize,
+}
+
+pub struct Exprs<'a> {
+    // still to visit, the next one last
+    pending: Vec<ExprVisit<'a>>,
+}
+
+impl<'a> Iterator for Exprs<'a> {
+    type Item = ExprVisit<'a>;
+
+    fn next(&mut self) -> Option<ExprVisit<'a>> {
+        let visit = self.pending.pop()?;
+        let operands = visit.expr.operands();
+        self.pending
+            .extend(operands.into_iter().rev().map(|operand| ExprVisit {
+                expr: operand,
+                parent: Some(visit.expr),
+                depth: visit.depth + 1,
+            }));
+        Some(visit)
+    }
+}
+
+// Written as the expression itself, followed by ": type" once it has one
+impl fmt::Debug for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.kind.fmt(f)?;
+        match &self.type_ {
+            Some(type_) => write!(f, ": {}", type_),
+            None => Ok(()),
+        }
+    }
+}
+
+impl From<ExprKind> for Expr {
+    fn from(kind: ExprKind) -> Self {
+        Expr::new(kind)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum BinOpType {
+    Multiply,
+    Divide,
+    Add,
+    Subtract,
+    Equal,
+    NotEqual,
+    LessThan,
+    LessThanOrEqual,
+    GreaterThan,
+    GreaterThanOrEqual,
+}
+
+impl BinOpType {
+    pub fn is_arithmetic(&self) -> bool {
+        matches!(
+            self,
+            BinOpType::Add | BinOpType::Subtract | BinOpType::Multiply | BinOpType::Divide
+        )
+    }
+}
+
+impl fmt::Display for BinOpType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let symbol = match self {
+            BinOpType::Multiply => "*",
+            BinOpType::Divide => "/",
+            BinOpType::Add => "+",
+            BinOpType::Subtract => "-",
+            BinOpType::Equal => "==",
+            BinOpType::NotEqual => "!=",
+            BinOpType::LessThan => "<",
+            BinOpType::LessThanOrEqual => "<=",
+            BinOpType::GreaterThan => ">",
+            BinOpType::GreaterThanOrEqual => ">=",
+        };
+        write!(f, "{}", symbol)
+    }
+}
@@ -0,0 +1,518 @@
+use crate::asm::{self, Line, Operand, Syntax};
+use crate::runtime;
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+
+// A straight run of instructions, entered only at the first and left only after the last
+#[derive(Debug, Clone)]
+pub struct BasicBlock {
+    pub label: Option<String>,
+    // indices of the program lines it spans, comments and markers included
+    pub lines: Range<usize>,
+    pub successors: Vec<usize>,
+    pub predecessors: Vec<usize>,
+}
+
+// The control-flow graph of a generated program's code. Blocks are in program order, so
+// a block that does not end in a jump, a ret or the call to the exit routine falls
+// through to the next one.
+#[derive(Debug, Clone)]
+pub struct Cfg {
+    pub blocks: Vec<BasicBlock>,
+    // blocks that start a routine: the entry point, exports and every call target
+    pub entries: Vec<usize>,
+}
+
+impl Cfg {
+    pub fn build(lines: &[Line]) -> Self {
+        let mut blocks: Vec<BasicBlock> = Vec::new();
+        // jump tables are labels followed by addresses rather than instructions
+        let mut tables: HashMap<String, Vec<String>> = HashMap::new();
+        let mut open: Option<BasicBlock> = None;
+        let mut table: Option<String> = None;
+        let mut in_text = false;
+        for (i, line) in lines.iter().enumerate() {
+            match line {
+                Line::Section(name) => {
+                    blocks.extend(open.take());
+                    in_text = name == ".text";
+                }
+                Line::Label(name) if in_text => {
+                    blocks.extend(open.take().filter(|block| !block_is_empty(lines, block)));
+                    table = Some(name.clone());
+                    open = Some(BasicBlock::new(Some(name.clone()), i));
+                }
+                Line::Quad(target) => {
+                    if let Some(name) = &table {
+                        tables.entry(name.clone()).or_default().push(target.clone());
+                    }
+                    open = None;
+                }
+                Line::Instruction { mnemonic, .. } if in_text => {
+                    table = None;
+                    let block = open.get_or_insert_with(|| BasicBlock::new(None, i));
+                    block.lines.end = i + 1;
+                    if is_jump(mnemonic) || mnemonic == "ret" || is_exit(line) {
+                        blocks.extend(open.take());
+                    }
+                }
+                _ => {
+                    if let Some(block) = &mut open {
+                        block.lines.end = i + 1;
+                    }
+                }
+            }
+        }
+        blocks.extend(open.take().filter(|block| !block_is_empty(lines, block)));
+
+        let index: HashMap<&str, usize> = blocks
+            .iter()
+            .enumerate()
+            .filter_map(|(i, block)| block.label.as_deref().map(|label| (label, i)))
+            .collect();
+        let mut edges = Vec::new();
+        for (i, block) in blocks.iter().enumerate() {
+            let fallthrough = || (i + 1 < blocks.len()).then_some(i + 1);
+            let targets: Vec<usize> = match last_instruction(lines, block) {
+                Some((mnemonic, [Operand::Label(target)])) if is_jump(mnemonic) => {
+                    // a jump to a runtime routine leaves the program's code
+                    let taken = index.get(target.as_str()).copied();
+                    match mnemonic {
+                        "jmp" => taken.into_iter().collect(),
+                        _ => taken.into_iter().chain(fallthrough()).collect(),
+                    }
+                }
+                // an indirect jump goes wherever its table says
+                Some(("jmp", _)) => jump_table(lines, block, &tables)
+                    .iter()
+                    .filter_map(|target| index.get(target.as_str()).copied())
+                    .collect(),
+                Some(("ret", _)) => vec![],
+                // the process ends there rather than falling into the code after main
+                Some(("call", [Operand::Label(target)])) if target == runtime::EXIT => vec![],
+                _ => fallthrough().into_iter().collect(),
+            };
+            for target in targets {
+                if !edges.contains(&(i, target)) {
+                    edges.push((i, target));
+                }
+            }
+        }
+        for (from, to) in edges {
+            blocks[from].successors.push(to);
+            blocks[to].predecessors.push(from);
+        }
+
+        let mut routines: HashSet<&str> = HashSet::new();
+        for line in lines {
+            match line {
+                Line::Global(name) => {
+                    routines.insert(name);
+                }
+                Line::Instruction { mnemonic, operands } if mnemonic == "call" => {
+                    if let [Operand::Label(target)] = operands.as_slice() {
+                        routines.insert(target);
+                    }
+                }
+                _ => {}
+            }
+        }
+        let entries = blocks
+            .iter()
+            .enumerate()
+            .filter(|(_, block)| block.label.as_deref().is_some_and(|l| routines.contains(l)))
+            .map(|(i, _)| i)
+            .collect();
+        Self { blocks, entries }
+    }
+
+    // Iterates a dataflow analysis to its fixed point and returns the fact at the start
+    // and at the end of every block. `transfer` carries a fact across one block in the
+    // direction of the analysis; `meet` combines the facts arriving at a block from its
+    // predecessors (forward) or successors (backward), and gets none at the boundary.
+    pub fn solve<T: Clone + PartialEq>(
+        &self,
+        direction: Direction,
+        initial: T,
+        transfer: impl Fn(usize, &T) -> T,
+        meet: impl Fn(usize, &[&T]) -> T,
+    ) -> Vec<(T, T)> {
+        let mut facts = vec![(initial.clone(), initial); self.blocks.len()];
+        let order: Vec<usize> = match direction {
+            Direction::Forward => (0..self.blocks.len()).collect(),
+            Direction::Backward => (0..self.blocks.len()).rev().collect(),
+        };
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &b in &order {
+                let block = &self.blocks[b];
+                let (before, after) = match direction {
+                    Direction::Forward => {
+                        let incoming: Vec<&T> =
+                            block.predecessors.iter().map(|&p| &facts[p].1).collect();
+                        let start = meet(b, &incoming);
+                        let end = transfer(b, &start);
+                        (start, end)
+                    }
+                    Direction::Backward => {
+                        let incoming: Vec<&T> =
+                            block.successors.iter().map(|&s| &facts[s].0).collect();
+                        let end = meet(b, &incoming);
+                        let start = transfer(b, &end);
+                        (start, end)
+                    }
+                };
+                if facts[b] != (before.clone(), after.clone()) {
+                    facts[b] = (before, after);
+                    changed = true;
+                }
+            }
+        }
+        facts
+    }
+
+    // For every block, the instructions (by line index) whose writes may still be what
+    // a place holds when the block starts
+    pub fn reaching_definitions(&self, lines: &[Line]) -> Vec<HashSet<usize>> {
+        let mut definitions: HashMap<Place, HashSet<usize>> = HashMap::new();
+        for block in &self.blocks {
+            for i in block.lines.clone() {
+                for place in effects(&lines[i]).writes {
+                    definitions.entry(place).or_default().insert(i);
+                }
+            }
+        }
+        let facts = self.solve(
+            Direction::Forward,
+            HashSet::new(),
+            |b, reaching: &HashSet<usize>| {
+                let mut reaching = reaching.clone();
+                for i in self.blocks[b].lines.clone() {
+                    let effects = effects(&lines[i]);
+                    for place in &effects.writes {
+                        // a partial write leaves the rest of the old value in place
+                        if !effects.reads.contains(place) {
+                            reaching.retain(|d| !definitions[place].contains(d));
+                        }
+                        reaching.insert(i);
+                    }
+                }
+                reaching
+            },
+            |_, incoming| {
+                incoming
+                    .iter()
+                    .flat_map(|facts| facts.iter().copied())
+                    .collect()
+            },
+        );
+        facts.into_iter().map(|(start, _)| start).collect()
+    }
+
+    // For every block, the registers whose values may be read after it ends. Leaving the
+    // program's code other than by ret, as a jump to a runtime routine does, may read any.
+    pub fn live_registers(&self, lines: &[Line]) -> Vec<HashSet<Place>> {
+        let facts = self.solve(
+            Direction::Backward,
+            HashSet::new(),
+            |b, live: &HashSet<Place>| {
+                let mut live = live.clone();
+                for i in self.blocks[b].lines.clone().rev() {
+                    step_liveness(&mut live, &lines[i]);
+                }
+                live
+            },
+            |b, incoming| {
+                let block = &self.blocks[b];
+                if block.successors.is_empty()
+                    && !matches!(last_instruction(lines, block), Some(("ret", _)))
+                {
+                    return ALL_REGISTERS.iter().map(|&r| Place::Register(r)).collect();
+                }
+                incoming
+                    .iter()
+                    .flat_map(|live| live.iter().cloned())
+                    .collect()
+            },
+        );
+        facts.into_iter().map(|(_, end)| end).collect()
+    }
+
+    // The registers live right after each line of a block, given those live at its end,
+    // indexed from the block's first line
+    pub fn live_after(
+        &self,
+        lines: &[Line],
+        b: usize,
+        live_out: &HashSet<Place>,
+    ) -> Vec<HashSet<Place>> {
+        let range = self.blocks[b].lines.clone();
+        let mut after = vec![HashSet::new(); range.len()];
+        let mut live = live_out.clone();
+        for i in range.clone().rev() {
+            after[i - range.start] = live.clone();
+            step_liveness(&mut live, &lines[i]);
+        }
+        after
+    }
+
+    // The graph in Graphviz DOT, one box per block with its instructions, a cluster per
+    // routine and the taken edge of a conditional jump labeled with its mnemonic
+    pub fn to_dot(&self, lines: &[Line], syntax: Syntax) -> String {
+        let mut dot = String::from("digraph cfg {\n");
+        dot.push_str("    node [shape=box, fontname=\"monospace\"];\n");
+        let mut starts = self.entries.clone();
+        starts.sort();
+        let mut routine_of = vec![None; self.blocks.len()];
+        for (r, &start) in starts.iter().enumerate() {
+            let end = starts.get(r + 1).copied().unwrap_or(self.blocks.len());
+            for routine in &mut routine_of[start..end] {
+                *routine = Some(r);
+            }
+        }
+
+        for (r, &start) in starts.iter().enumerate() {
+            let name = self.blocks[start].label.as_deref().unwrap_or_default();
+            dot.push_str(&format!("    subgraph cluster_{} {{\n", r));
+            dot.push_str(&format!("        label=\"{}\";\n", escape(name)));
+            for b in (0..self.blocks.len()).filter(|&b| routine_of[b] == Some(r)) {
+                dot.push_str(&format!("        {}\n", self.node(b, lines, syntax)));
+            }
+            dot.push_str("    }\n");
+        }
+        for b in (0..self.blocks.len()).filter(|&b| routine_of[b].is_none()) {
+            dot.push_str(&format!("    {}\n", self.node(b, lines, syntax)));
+        }
+
+        for (b, block) in self.blocks.iter().enumerate() {
+            let jump = match last_instruction(lines, block) {
+                Some((mnemonic, _)) if is_jump(mnemonic) && mnemonic != "jmp" => Some(mnemonic),
+                _ => None,
+            };
+            for &s in &block.successors {
+                match jump {
+                    Some(mnemonic) if s != b + 1 => {
+                        dot.push_str(&format!("    b{} -> b{} [label=\"{}\"];\n", b, s, mnemonic))
+                    }
+                    _ => dot.push_str(&format!("    b{} -> b{};\n", b, s)),
+                }
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    fn node(&self, b: usize, lines: &[Line], syntax: Syntax) -> String {
+        let block = &self.blocks[b];
+        let mut text = String::new();
+        if let Some(label) = &block.label {
+            text.push_str(&format!("{}:\\l", escape(label)));
+        }
+        for line in &lines[block.lines.clone()] {
+            if let Line::Instruction { .. } = line {
+                text.push_str(&format!("{}\\l", escape(line.render(syntax).trim())));
+            }
+        }
+        format!("b{} [label=\"{}\"];", b, text)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Direction {
+    Forward,
+    Backward,
+}
+
+// Something an instruction can read or write that an analysis keeps track of
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Place {
+    // a general-purpose register, by its 64-bit name
+    Register(&'static str),
+    // a global or an rbp-relative slot
+    Variable { base: String, offset: i64 },
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Effects {
+    pub reads: Vec<Place>,
+    pub writes: Vec<Place>,
+}
+
+// Registers a call may read arguments from and may leave changed, under either calling
+// convention; Noble functions also use rbx as scratch
+const CALL_READS: [&str; 6] = ["rcx", "rdx", "r8", "r9", "rdi", "rsi"];
+const CALL_WRITES: [&str; 10] = [
+    "rax", "rbx", "rcx", "rdx", "rsi", "rdi", "r8", "r9", "r10", "r11",
+];
+
+// The places an instruction reads and writes, including the registers the ones the
+// generator emits use implicitly. Memory reached through a register other than rbp is
+// not a place; only the register holding its address is read.
+pub fn effects(line: &Line) -> Effects {
+    let Line::Instruction { mnemonic, operands } = line else {
+        return Effects::default();
+    };
+    let mut effects = Effects::default();
+    let registers = |names: &[&'static str]| names.iter().map(|&r| Place::Register(r)).collect();
+    match mnemonic.as_str() {
+        "call" => {
+            effects.reads = registers(&CALL_READS);
+            effects.writes = registers(&CALL_WRITES);
+        }
+        "syscall" => {
+            effects.reads = registers(&["rax", "rdi", "rsi", "rdx", "r10", "r8", "r9"]);
+            effects.writes = registers(&["rax", "rcx", "r11"]);
+        }
+        "ret" => effects.reads = registers(&["rax"]),
+        "cdq" | "cqo" => {
+            effects.reads = registers(&["rax"]);
+            effects.writes = registers(&["rdx"]);
+        }
+        "idiv" | "div" => {
+            effects.reads = registers(&["rax", "rdx"]);
+            effects.writes = registers(&["rax", "rdx"]);
+        }
+        "push" => effects.reads = registers(&["rsp"]),
+        "pop" => {
+            effects.reads = registers(&["rsp"]);
+            effects.writes = registers(&["rsp"]);
+        }
+        _ => {}
+    }
+    // whether the first operand is written, and whether it is also read
+    let (writes_first, reads_first) = match mnemonic.as_str() {
+        "mov" | "movzx" | "movsx" | "lea" | "pop" | "movd" | "movss" | "cvtsi2ss" | "cvttss2si" => {
+            (true, false)
+        }
+        m if m.starts_with("set") => (true, false),
+        "cmp" | "test" | "comiss" | "ucomiss" | "push" | "idiv" | "div" => (false, true),
+        m if is_jump(m) || m == "call" => (false, true),
+        _ => (true, true),
+    };
+    for (i, operand) in operands.iter().enumerate() {
+        match operand {
+            Operand::Register(name) => {
+                let Some(family) = asm::register_family(name) else {
+                    continue;
+                };
+                let written = i == 0 && writes_first;
+                // writing fewer than 32 bits keeps the rest of the register
+                let partial = asm::register_size(name).is_none_or(|size| size.bytes() < 4);
+                if !written || reads_first || partial {
+                    effects.reads.push(Place::Register(family));
+                }
+                if written {
+                    effects.writes.push(Place::Register(family));
+                }
+            }
+            Operand::Memory { base, offset, .. } => {
+                if let Some(family) = asm::register_family(base)
+                    && family != "rbp"
+                {
+                    effects.reads.push(Place::Register(family));
+                    continue;
+                }
+                let place = Place::Variable {
+                    base: base.clone(),
+                    offset: *offset,
+                };
+                let written = i == 0 && writes_first && mnemonic != "lea";
+                if mnemonic != "lea" && (!written || reads_first) {
+                    effects.reads.push(place.clone());
+                }
+                if written {
+                    effects.writes.push(place);
+                }
+            }
+            _ => {}
+        }
+    }
+    effects
+}
+
+const ALL_REGISTERS: [&str; 16] = [
+    "rax", "rbx", "rcx", "rdx", "rsi", "rdi", "rsp", "rbp", "r8", "r9", "r10", "r11", "r12", "r13",
+    "r14", "r15",
+];
+
+// Carries the set of live registers backward across one instruction
+fn step_liveness(live: &mut HashSet<Place>, line: &Line) {
+    let effects = effects(line);
+    for place in &effects.writes {
+        live.remove(place);
+    }
+    for place in effects.reads {
+        if let Place::Register(_) = place {
+            live.insert(place);
+        }
+    }
+}
+
+impl BasicBlock {
+    fn new(label: Option<String>, start: usize) -> Self {
+        Self {
+            label,
+            lines: start..start + 1,
+            successors: vec![],
+            predecessors: vec![],
+        }
+    }
+}
+
+fn block_is_empty(lines: &[Line], block: &BasicBlock) -> bool {
+    block.label.is_none() && last_instruction(lines, block).is_none()
+}
+
+fn last_instruction<'a>(lines: &'a [Line], block: &BasicBlock) -> Option<(&'a str, &'a [Operand])> {
+    lines[block.lines.clone()]
+        .iter()
+        .rev()
+        .find_map(|line| match line {
+            Line::Instruction { mnemonic, operands } => {
+                Some((mnemonic.as_str(), operands.as_slice()))
+            }
+            _ => None,
+        })
+}
+
+// The targets of the table an indirect jump indexes, found through the `lea` that loaded
+// its address; every table's targets when there is none
+fn jump_table<'a>(
+    lines: &[Line],
+    block: &BasicBlock,
+    tables: &'a HashMap<String, Vec<String>>,
+) -> Vec<&'a String> {
+    let table = lines[block.lines.clone()]
+        .iter()
+        .find_map(|line| match line {
+            Line::Instruction { mnemonic, operands } if mnemonic == "lea" => {
+                match operands.get(1) {
+                    Some(Operand::Memory { base, .. }) => tables.get(base),
+                    _ => None,
+                }
+            }
+            _ => None,
+        });
+    match table {
+        Some(targets) => targets.iter().collect(),
+        None => tables.values().flatten().collect(),
+    }
+}
+
+// A call to the runtime's exit routine, which does not return
+fn is_exit(line: &Line) -> bool {
+    matches!(
+        line,
+        Line::Instruction { mnemonic, operands }
+            if mnemonic == "call" && operands == &[Operand::Label(runtime::EXIT.to_string())]
+    )
+}
+
+fn is_jump(mnemonic: &str) -> bool {
+    mnemonic.starts_with('j')
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
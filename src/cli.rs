@@ -0,0 +1,778 @@
+use noble::asm::Syntax;
+use noble::config::Config;
+use noble::lint::{self, Level};
+use noble::parse;
+use noble::target::{self, TargetSpec};
+use std::env;
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Command {
+    Build,
+    Run,
+    // tokenize, parse and type-check only, writing nothing but diagnostics
+    Check,
+    Fmt,
+    Repl,
+    Lsp,
+    // build again whenever the input changes
+    Watch,
+    // build and run every program in the input directory, checking exit codes
+    Test,
+    // create a project directory with a noble.toml and src/main.nbl
+    New,
+    // run a program in the interpreter, stopping where the user asks
+    Debug,
+}
+
+// What `build` leaves behind
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Emit {
+    Asm,
+    Exe,
+    // a flat binary of the program and the runtime, encoded without an assembler, for
+    // freestanding targets
+    Bin,
+    // an object file of the program and another of the runtime, encoded without an
+    // assembler, for a system linker; needs the obj feature
+    Obj,
+    // the token stream, without parsing; written to stdout unless --output is given
+    Tokens(TokenFormat),
+    // the concrete parse tree, which compiling otherwise never builds in full; also
+    // written to stdout unless --output is given
+    ParseTree,
+    // the control-flow graph of the generated code in DOT, after optimization; written
+    // to stdout unless --output is given
+    Cfg,
+    // the binary operators by precedence and associativity; needs no input file
+    Precedence,
+    // the grammar of the language from the grammar module; needs no input file either
+    GrammarEbnf,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TokenFormat {
+    // one JSON object per line
+    Json,
+    // one `line:column start..end Type "value"` per line
+    Text,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    // Auto colors only when stderr is a terminal and NO_COLOR is unset
+    pub fn enabled(self) -> bool {
+        match self {
+            ColorChoice::Always => {
+                enable_ansi_escapes();
+                true
+            }
+            ColorChoice::Never => false,
+            ColorChoice::Auto => {
+                env::var_os("NO_COLOR").is_none()
+                    && io::stderr().is_terminal()
+                    && enable_ansi_escapes()
+            }
+        }
+    }
+}
+
+// Windows consoles print escape sequences literally unless virtual terminal
+// processing is switched on for the handle
+#[cfg(windows)]
+fn enable_ansi_escapes() -> bool {
+    use std::ffi::c_void;
+
+    const STD_ERROR_HANDLE: u32 = -12i32 as u32;
+    const ENABLE_VIRTUAL_TERMINAL_PROCESSING: u32 = 0x0004;
+
+    #[link(name = "kernel32")]
+    unsafe extern "system" {
+        fn GetStdHandle(std_handle: u32) -> *mut c_void;
+        fn GetConsoleMode(console: *mut c_void, mode: *mut u32) -> i32;
+        fn SetConsoleMode(console: *mut c_void, mode: u32) -> i32;
+    }
+
+    // SAFETY: plain Win32 calls on the process's own stderr handle
+    unsafe {
+        let console = GetStdHandle(STD_ERROR_HANDLE);
+        let mut mode = 0;
+        GetConsoleMode(console, &mut mode) != 0
+            && SetConsoleMode(console, mode | ENABLE_VIRTUAL_TERMINAL_PROCESSING) != 0
+    }
+}
+
+#[cfg(not(windows))]
+fn enable_ansi_escapes() -> bool {
+    true
+}
+
+// How errors and warnings are written to stderr
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorFormat {
+    Human,
+    // one JSON object per line
+    Json,
+    // one file:line:column: message line per diagnostic
+    Short,
+}
+
+#[derive(Clone)]
+pub struct Options {
+    pub command: Command,
+    pub input: Option<String>,
+    pub output: Option<PathBuf>,
+    pub emit: Emit,
+    pub target: &'static TargetSpec,
+    pub opt_level: u8,
+    pub print_passes: bool,
+    // leave assert statements out
+    pub release: bool,
+    // trap on integer overflow and division by zero
+    pub checked_arithmetic: bool,
+    // reject mixed i32s and f32s operands
+    pub strict_numerics: bool,
+    // deepest nesting of statements and expressions the parser accepts
+    pub max_nesting: usize,
+    // from --allow, --warn and --deny
+    pub lints: lint::Levels,
+    pub verbose: bool,
+    pub dump_tokens: bool,
+    pub dump_parse_tree: bool,
+    pub dump_ast: bool,
+    // write <output>.map next to the assembly
+    pub source_map: bool,
+    pub syntax: Syntax,
+    pub timings: bool,
+    pub entry: Option<String>,
+    pub color: ColorChoice,
+    pub error_format: ErrorFormat,
+    // the address a --emit bin image is loaded at
+    pub origin: u64,
+    // compile a generated program of this many units instead of an input file
+    pub bench_synthetic: Option<usize>,
+    // test compares each executable with the interpreter
+    pub differential: bool,
+    // run prints the program's exit code after it ends
+    pub print_exit: bool,
+    // run the checked program in the interpreter instead of building an executable
+    pub interpret: bool,
+    // with interpret, also print the top-level variables when the program stops
+    pub dump_env: bool,
+    // with interpret, log each statement as it runs to stderr
+    pub trace: bool,
+    // the noble.toml given with --config instead of the one found from the working
+    // directory
+    pub config: Option<PathBuf>,
+    // the directory holding the noble.toml in use; build and run without an input file
+    // compile the whole project
+    pub project: Option<PathBuf>,
+    // the [package] name, given to the program built from main.nbl
+    pub package: Option<String>,
+    // where out.asm and out go when --output is not given, from noble.toml
+    pub out_dir: Option<PathBuf>,
+    // the directories input files are looked up in, in order, from noble.toml
+    pub source_roots: Vec<PathBuf>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            command: Command::Build,
+            input: None,
+            output: None,
+            emit: Emit::Asm,
+            target: &target::X86_64_WINDOWS,
+            opt_level: 0,
+            print_passes: false,
+            release: false,
+            checked_arithmetic: false,
+            strict_numerics: false,
+            max_nesting: parse::DEFAULT_MAX_DEPTH,
+            lints: lint::Levels::new(),
+            verbose: false,
+            dump_tokens: false,
+            dump_parse_tree: false,
+            dump_ast: false,
+            source_map: false,
+            syntax: Syntax::Intel,
+            timings: false,
+            entry: None,
+            color: ColorChoice::Auto,
+            error_format: ErrorFormat::Human,
+            origin: 0,
+            bench_synthetic: None,
+            differential: false,
+            print_exit: false,
+            interpret: false,
+            dump_env: false,
+            trace: false,
+            config: None,
+            project: None,
+            package: None,
+            out_dir: None,
+            source_roots: Vec::new(),
+        }
+    }
+}
+
+pub enum Action {
+    Run(Box<Options>),
+    Help,
+    Version,
+}
+
+const COMMANDS: [(&str, &str); 10] = [
+    ("build", "compile a program to assembly (the default)"),
+    ("run", "build an executable and run it"),
+    (
+        "check",
+        "report the errors and warnings of a program without building it",
+    ),
+    ("fmt", "print a program in canonical layout"),
+    ("repl", "enter statements interactively"),
+    ("lsp", "serve the Language Server Protocol on stdio"),
+    ("watch", "build again each time the program is saved"),
+    (
+        "test",
+        "run every program in a directory and check its exit code",
+    ),
+    ("new", "create a project with a noble.toml and src/main.nbl"),
+    (
+        "debug",
+        "run a program in the interpreter, stopping at breakpoints",
+    ),
+];
+
+struct Flag {
+    name: &'static str,
+    short: Option<&'static str>,
+    value: Option<&'static str>,
+    help: &'static str,
+}
+
+// Every flag the driver accepts; --help is generated from this table
+const FLAGS: [Flag; 34] = [
+    Flag {
+        name: "--emit",
+        short: None,
+        value: Some("asm|exe|bin|obj|tokens|tokens-text|parse-tree|cfg|precedence|grammar-ebnf"),
+        help: "what build produces (default asm); tokens writes JSON lines",
+    },
+    Flag {
+        name: "--target",
+        short: None,
+        value: Some("x86_64-windows|x86_64-linux|aarch64-linux"),
+        help: "platform to generate code for, whatever the host (default x86_64-windows)",
+    },
+    Flag {
+        name: "--freestanding",
+        short: None,
+        value: None,
+        help: "build for a freestanding target: x86_64-linux unless --target names one",
+    },
+    Flag {
+        name: "-O",
+        short: None,
+        value: Some("0|1|2"),
+        help: "optimization level, written -O0 to -O2 (default 0)",
+    },
+    Flag {
+        name: "--print-passes",
+        short: None,
+        value: None,
+        help: "list the optimization passes that ran and how long each took",
+    },
+    Flag {
+        name: "--release",
+        short: None,
+        value: None,
+        help: "leave assert statements out of the generated code",
+    },
+    Flag {
+        name: "--checked-arithmetic",
+        short: None,
+        value: None,
+        help: "exit with 135 on integer overflow and 136 on division by zero",
+    },
+    Flag {
+        name: "--strict-numerics",
+        short: None,
+        value: None,
+        help: "reject mixed i32s and f32s operands instead of converting to f32s",
+    },
+    Flag {
+        name: "--max-nesting",
+        short: None,
+        value: Some("n"),
+        help: "deepest nesting of statements and expressions accepted (default 256)",
+    },
+    Flag {
+        name: "--allow",
+        short: None,
+        value: Some("lint"),
+        help: "do not check a lint (repeatable)",
+    },
+    Flag {
+        name: "--warn",
+        short: None,
+        value: Some("lint"),
+        help: "report a lint as a warning (repeatable)",
+    },
+    Flag {
+        name: "--deny",
+        short: None,
+        value: Some("lint"),
+        help: "report a lint as an error, failing the build (repeatable)",
+    },
+    Flag {
+        name: "--config",
+        short: None,
+        value: Some("path"),
+        help: "project settings to use instead of the nearest noble.toml",
+    },
+    Flag {
+        name: "--output",
+        short: Some("-o"),
+        value: Some("path"),
+        help: "output file (default src/out.asm, or src/out for executables)",
+    },
+    Flag {
+        name: "--origin",
+        short: None,
+        value: Some("address"),
+        help: "load address of an --emit bin image, decimal or 0x hex (default 0)",
+    },
+    Flag {
+        name: "--syntax",
+        short: None,
+        value: Some("intel|att"),
+        help: "assembly dialect (default intel)",
+    },
+    Flag {
+        name: "--entry",
+        short: None,
+        value: Some("symbol"),
+        help: "entry point symbol (default mainCRTStartup, or _start on linux)",
+    },
+    Flag {
+        name: "--source-map",
+        short: None,
+        value: None,
+        help: "also write a .map file from assembly lines to source positions",
+    },
+    Flag {
+        name: "--dump-tokens",
+        short: None,
+        value: None,
+        help: "print the token stream as --emit tokens-text writes it",
+    },
+    Flag {
+        name: "--dump-parse-tree",
+        short: None,
+        value: None,
+        help: "print the concrete parse tree as --emit parse-tree writes it",
+    },
+    Flag {
+        name: "--dump-ast",
+        short: None,
+        value: None,
+        help: "print the abstract syntax tree",
+    },
+    Flag {
+        name: "--timings",
+        short: None,
+        value: None,
+        help: "report the time spent in each stage",
+    },
+    Flag {
+        name: "--color",
+        short: None,
+        value: Some("auto|always|never"),
+        help: "color diagnostics (default auto)",
+    },
+    Flag {
+        name: "--error-format",
+        short: None,
+        value: Some("human|json|short"),
+        help: "how diagnostics are written to stderr (default human, short for watch)",
+    },
+    Flag {
+        name: "--bench-synthetic",
+        short: None,
+        value: Some("n"),
+        help: "build a generated program of n units instead of a file, with --timings",
+    },
+    Flag {
+        name: "--differential",
+        short: None,
+        value: None,
+        help: "with test, also interpret each program and compare the executable with it",
+    },
+    Flag {
+        name: "--print-exit",
+        short: None,
+        value: None,
+        help: "with run, print the program's exit code when it ends",
+    },
+    Flag {
+        name: "--interpret",
+        short: None,
+        value: None,
+        help: "with run, interpret the program instead of building an executable",
+    },
+    Flag {
+        name: "--dump-env",
+        short: None,
+        value: None,
+        help: "with run --interpret, print the top-level variables when the program ends",
+    },
+    Flag {
+        name: "--trace",
+        short: None,
+        value: None,
+        help: "with run --interpret, log each statement, the variables and values to stderr",
+    },
+    Flag {
+        name: "--verbose",
+        short: Some("-v"),
+        value: None,
+        help: "trace each compiler stage",
+    },
+    Flag {
+        name: "--help",
+        short: Some("-h"),
+        value: None,
+        help: "print this help",
+    },
+    Flag {
+        name: "--version",
+        short: Some("-V"),
+        value: None,
+        help: "print the compiler version",
+    },
+    Flag {
+        name: "--",
+        short: None,
+        value: None,
+        help: "treat the remaining argument as the input file",
+    },
+];
+
+pub fn version() -> String {
+    format!("noble {}", env!("CARGO_PKG_VERSION"))
+}
+
+pub fn usage() -> String {
+    "usage: noble [command] [options] [file]".to_string()
+}
+
+pub fn help() -> String {
+    let flags: Vec<(String, &str)> = FLAGS
+        .iter()
+        .map(|flag| {
+            let mut spelling = match flag.short {
+                Some(short) => format!("{}, {}", short, flag.name),
+                None => flag.name.to_string(),
+            };
+            if let Some(value) = flag.value {
+                // -O takes its level attached
+                let separator = if flag.name == "-O" { "" } else { " " };
+                spelling = format!("{}{}<{}>", spelling, separator, value);
+            }
+            (spelling, flag.help)
+        })
+        .collect();
+    let width = flags
+        .iter()
+        .map(|(spelling, _)| spelling.len())
+        .max()
+        .unwrap_or(0)
+        + 2;
+
+    let mut text = format!("{}\n{}\n\ncommands:\n", version(), usage());
+    for (name, help) in COMMANDS {
+        text.push_str(&format!("    {:<width$}{}\n", name, help));
+    }
+    text.push_str("\noptions:\n");
+    for (spelling, help) in flags {
+        text.push_str(&format!("    {:<width$}{}\n", spelling, help));
+    }
+    text
+}
+
+// Parses the command line over `options`, which hold what noble.toml set, so that
+// every flag given wins over the project file
+pub fn parse_args(args: &[String], mut options: Options) -> Result<Action, String> {
+    let mut args = args.iter().peekable();
+
+    if let Some(first) = args.peek()
+        && let Some(command) = command(first)
+    {
+        options.command = command;
+        args.next();
+    }
+    // watched and tested builds report a line per diagnostic unless --error-format
+    // says otherwise
+    if matches!(options.command, Command::Watch | Command::Test) {
+        options.error_format = ErrorFormat::Short;
+    }
+
+    // --freestanding asks for a kind of target and --target names one, so the two are
+    // settled once every flag is read, whatever their order
+    let mut freestanding = false;
+    let mut named_target = None;
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            match args.next() {
+                Some(file) => set_input(&mut options, file)?,
+                None => return Err("-- expects a file name".to_string()),
+            }
+            continue;
+        }
+        if !arg.starts_with('-') || arg == "-" {
+            set_input(&mut options, arg)?;
+            continue;
+        }
+
+        // -O2 carries its value, --flag=value is the same as --flag value
+        let (name, attached) = match arg.split_once('=') {
+            _ if arg.starts_with("-O") => ("-O", Some(&arg[2..])),
+            Some((name, value)) if name.starts_with("--") && name.len() > 2 => (name, Some(value)),
+            _ => (arg.as_str(), None),
+        };
+        let Some(flag) = FLAGS
+            .iter()
+            .find(|f| f.name == name || f.short == Some(name))
+        else {
+            return Err(format!("unknown flag: {}", arg));
+        };
+        let value = match (flag.value, attached) {
+            (None, Some(_)) => return Err(format!("{} takes no value", flag.name)),
+            (None, None) => "",
+            (Some(_), Some(value)) => value,
+            (Some(expected), None) => match args.next() {
+                Some(value) => value.as_str(),
+                None => return Err(format!("{} expects <{}>", flag.name, expected)),
+            },
+        };
+
+        match flag.name {
+            "--help" => return Ok(Action::Help),
+            "--version" => return Ok(Action::Version),
+            "--verbose" => options.verbose = true,
+            "--dump-tokens" => options.dump_tokens = true,
+            "--dump-parse-tree" => options.dump_parse_tree = true,
+            "--dump-ast" => options.dump_ast = true,
+            "--source-map" => options.source_map = true,
+            "--timings" => options.timings = true,
+            "--freestanding" => freestanding = true,
+            "--release" => options.release = true,
+            "--print-passes" => options.print_passes = true,
+            "--checked-arithmetic" => options.checked_arithmetic = true,
+            "--strict-numerics" => options.strict_numerics = true,
+            "--differential" => options.differential = true,
+            "--print-exit" => options.print_exit = true,
+            "--interpret" => options.interpret = true,
+            "--dump-env" => options.dump_env = true,
+            "--trace" => options.trace = true,
+            "--entry" => options.entry = Some(value.to_string()),
+            "--output" => options.output = Some(PathBuf::from(value)),
+            "--config" => options.config = Some(PathBuf::from(value)),
+            "--emit" => {
+                options.emit = match value {
+                    "asm" => Emit::Asm,
+                    "exe" => Emit::Exe,
+                    "bin" => Emit::Bin,
+                    "obj" if cfg!(feature = "obj") => Emit::Obj,
+                    "obj" => {
+                        return Err(
+                            "--emit obj needs a Noble built with --features obj".to_string()
+                        );
+                    }
+                    "tokens" => Emit::Tokens(TokenFormat::Json),
+                    "tokens-text" => Emit::Tokens(TokenFormat::Text),
+                    "parse-tree" => Emit::ParseTree,
+                    "cfg" => Emit::Cfg,
+                    "precedence" => Emit::Precedence,
+                    "grammar-ebnf" => Emit::GrammarEbnf,
+                    other => return Err(format!("unknown emit kind: {}", other)),
+                }
+            }
+            "--target" => named_target = Some(lookup_target(value)?),
+            "-O" => {
+                options.opt_level = match value {
+                    "0" => 0,
+                    "1" => 1,
+                    "2" => 2,
+                    other => return Err(format!("unknown optimization level: -O{}", other)),
+                }
+            }
+            "--allow" | "--warn" | "--deny" => {
+                let level = Level::from_name(&flag.name[2..]).unwrap();
+                options.lints.set(value, level)?;
+            }
+            "--origin" => {
+                let address = match value.strip_prefix("0x") {
+                    Some(hex) => u64::from_str_radix(hex, 16),
+                    None => value.parse(),
+                };
+                options.origin =
+                    address.map_err(|_| format!("--origin expects an address, found {}", value))?;
+            }
+            "--max-nesting" => {
+                options.max_nesting = value
+                    .parse()
+                    .map_err(|_| format!("--max-nesting expects a count, found {}", value))?;
+            }
+            "--syntax" => {
+                options.syntax = match value {
+                    "intel" => Syntax::Intel,
+                    "att" => Syntax::Att,
+                    other => return Err(format!("unknown syntax: {}", other)),
+                }
+            }
+            "--color" => {
+                options.color = match value {
+                    "auto" => ColorChoice::Auto,
+                    "always" => ColorChoice::Always,
+                    "never" => ColorChoice::Never,
+                    other => return Err(format!("unknown color choice: {}", other)),
+                }
+            }
+            "--bench-synthetic" => {
+                let units = value
+                    .parse()
+                    .map_err(|_| format!("--bench-synthetic expects a count, found {}", value))?;
+                options.bench_synthetic = Some(units);
+                options.timings = true;
+            }
+            "--error-format" => {
+                options.error_format = match value {
+                    "human" => ErrorFormat::Human,
+                    "json" => ErrorFormat::Json,
+                    "short" => ErrorFormat::Short,
+                    other => return Err(format!("unknown error format: {}", other)),
+                }
+            }
+            _ => unreachable!("flag {} has no handler", flag.name),
+        }
+    }
+    match named_target {
+        Some(target) if freestanding && !target.freestanding => {
+            return Err(format!(
+                "--freestanding conflicts with --target {}, which builds on the C runtime",
+                target.name
+            ));
+        }
+        Some(target) => options.target = target,
+        // a freestanding target from noble.toml stays
+        None if freestanding && !options.target.freestanding => {
+            options.target = &target::X86_64_LINUX
+        }
+        None => {}
+    }
+    Ok(Action::Run(Box::new(options)))
+}
+
+// Whether the command has the input it needs. Checked once noble.toml is applied,
+// since inside a project build, run and check need none.
+pub fn check_input(options: &Options) -> Result<(), String> {
+    let whole_project = options.project.is_some()
+        && matches!(
+            options.command,
+            Command::Build | Command::Run | Command::Check
+        )
+        && matches!(options.emit, Emit::Asm | Emit::Exe | Emit::Bin | Emit::Obj);
+    let needs_input = matches!(
+        options.command,
+        Command::Build
+            | Command::Run
+            | Command::Check
+            | Command::Fmt
+            | Command::Watch
+            | Command::Test
+            | Command::New
+            | Command::Debug
+    ) && !(options.command == Command::Build
+        && matches!(options.emit, Emit::Precedence | Emit::GrammarEbnf));
+    if needs_input && options.input.is_none() && options.bench_synthetic.is_none() && !whole_project
+    {
+        return Err(match options.command {
+            Command::New => "new expects a project name".to_string(),
+            _ => "missing input file".to_string(),
+        });
+    }
+    if !needs_input && let Some(input) = &options.input {
+        return Err(format!("unexpected argument: {}", input));
+    }
+    // only the interpreter sees the variables, and it runs one file
+    if options.dump_env && !options.interpret {
+        return Err("--dump-env needs --interpret".to_string());
+    }
+    if options.trace && !options.interpret {
+        return Err("--trace needs --interpret".to_string());
+    }
+    if options.interpret && options.input.is_none() && whole_project {
+        return Err("--interpret needs an input file".to_string());
+    }
+    Ok(())
+}
+
+// Sets what a noble.toml specifies; called before the command line is parsed over the
+// result
+pub fn apply_config(options: &mut Options, config: &Config) -> Result<(), String> {
+    if let Some(name) = &config.target {
+        options.target = lookup_target(name)?;
+    }
+    if let Some(level) = config.opt_level {
+        options.opt_level = level;
+    }
+    options.project = config.path.parent().map(Path::to_path_buf);
+    options.package = config.name.clone();
+    options.out_dir = Some(config.out_dir.clone());
+    options.source_roots = config.source_roots.clone();
+    options.lints = config.lints.clone();
+    Ok(())
+}
+
+fn lookup_target(name: &str) -> Result<&'static TargetSpec, String> {
+    target::lookup(name).ok_or_else(|| {
+        format!(
+            "unknown target: {}; the targets are {}",
+            name,
+            target::names().join(", ")
+        )
+    })
+}
+
+fn command(arg: &str) -> Option<Command> {
+    match arg {
+        "build" => Some(Command::Build),
+        "run" => Some(Command::Run),
+        "check" => Some(Command::Check),
+        "fmt" => Some(Command::Fmt),
+        "repl" => Some(Command::Repl),
+        "lsp" => Some(Command::Lsp),
+        "watch" => Some(Command::Watch),
+        "test" => Some(Command::Test),
+        "new" => Some(Command::New),
+        "debug" => Some(Command::Debug),
+        _ => None,
+    }
+}
+
+fn set_input(options: &mut Options, file: &str) -> Result<(), String> {
+    if options.input.is_some() {
+        return Err(format!("unexpected argument: {}", file));
+    }
+    options.input = Some(file.to_string());
+    Ok(())
+}
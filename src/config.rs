@@ -0,0 +1,314 @@
+use crate::error::CompileError;
+use crate::lint::{self, Level};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// The project file the driver looks for in the working directory and its parents
+pub const FILE_NAME: &str = "noble.toml";
+
+// Project settings from a noble.toml. Command-line flags override every one of them.
+//
+//     [package]
+//     name = "hello"
+//
+//     [build]
+//     target = "x86_64-linux"
+//     opt-level = 2
+//     out-dir = "build"
+//     source-roots = ["src", "lib"]
+//
+//     [lints]
+//     unused-variable = "deny"
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Config {
+    pub path: PathBuf,
+    // what the program built from main.nbl is called
+    pub name: Option<String>,
+    // kept as written; the driver knows which targets exist
+    pub target: Option<String>,
+    pub opt_level: Option<u8>,
+    // relative paths are resolved against the directory holding the file; they
+    // default to target/ and src/ there
+    pub out_dir: PathBuf,
+    pub source_roots: Vec<PathBuf>,
+    pub lints: lint::Levels,
+}
+
+// Just the TOML a project file needs: [tables], comments and key = value pairs whose
+// value is a string, an integer, a boolean or a one-line array of strings
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    String(String),
+    Integer(i64),
+    Bool(bool),
+    Array(Vec<String>),
+}
+
+// One key = value line, under the table it appears in ("" before the first)
+struct Setting {
+    line: usize,
+    table: String,
+    key: String,
+    value: Value,
+}
+
+impl Value {
+    fn kind(&self) -> &'static str {
+        match self {
+            Value::String(_) => "a string",
+            Value::Integer(_) => "an integer",
+            Value::Bool(_) => "a boolean",
+            Value::Array(_) => "an array",
+        }
+    }
+}
+
+// The nearest noble.toml in `start` or one of its ancestors
+pub fn find(start: &Path) -> Option<PathBuf> {
+    start
+        .ancestors()
+        .map(|dir| dir.join(FILE_NAME))
+        .find(|path| path.is_file())
+}
+
+// Reads and checks a noble.toml; unknown tables, keys and lints are errors rather than
+// being ignored, so a misspelt setting is not silently lost
+pub fn load(path: &Path) -> Result<Config, CompileError> {
+    let text = fs::read_to_string(path).map_err(|source| CompileError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    let error = |line: usize, message: String| CompileError::Config {
+        path: path.to_path_buf(),
+        line: Some(line),
+        message,
+    };
+    let root = path.parent().unwrap_or(Path::new("."));
+    let mut config = Config {
+        path: path.to_path_buf(),
+        out_dir: root.join("target"),
+        source_roots: vec![root.join("src")],
+        ..Config::default()
+    };
+    let settings = parse(&text).map_err(|(line, message)| error(line, message))?;
+    for Setting {
+        line,
+        table,
+        key,
+        value,
+    } in settings
+    {
+        let invalid = |message: String| error(line, message);
+        let mismatch = |expected: &str| {
+            invalid(format!(
+                "{} expects {}, found {}",
+                key,
+                expected,
+                value.kind()
+            ))
+        };
+        match (table.as_str(), key.as_str(), &value) {
+            ("package", "name", Value::String(name)) => config.name = Some(name.clone()),
+            ("package", "name", _) => return Err(mismatch("a string")),
+            ("build", "target", Value::String(target)) => config.target = Some(target.clone()),
+            ("build", "target", _) => return Err(mismatch("a string")),
+            ("build", "opt-level", Value::Integer(level)) => {
+                let level = u8::try_from(*level)
+                    .ok()
+                    .filter(|level| *level <= 2)
+                    .ok_or_else(|| invalid(format!("unknown optimization level: {}", level)))?;
+                config.opt_level = Some(level);
+            }
+            ("build", "opt-level", _) => return Err(mismatch("an integer")),
+            ("build", "out-dir", Value::String(dir)) => config.out_dir = root.join(dir),
+            ("build", "out-dir", _) => return Err(mismatch("a string")),
+            ("build", "source-roots", Value::Array(dirs)) => {
+                if dirs.is_empty() {
+                    return Err(invalid(
+                        "source-roots needs at least one directory".to_string(),
+                    ));
+                }
+                config.source_roots = dirs.iter().map(|dir| root.join(dir)).collect();
+            }
+            ("build", "source-roots", _) => return Err(mismatch("an array of strings")),
+            ("lints", name, Value::String(level)) => {
+                let level = Level::from_name(level).ok_or_else(|| {
+                    invalid(format!(
+                        "unknown lint level {}; use allow, warn or deny",
+                        level
+                    ))
+                })?;
+                config.lints.set(name, level).map_err(invalid)?;
+            }
+            ("lints", _, _) => return Err(mismatch("a string")),
+            ("", _, _) => {
+                return Err(invalid(format!(
+                    "{} must be in a [package], [build] or [lints] table",
+                    key
+                )));
+            }
+            _ => return Err(invalid(format!("unknown setting {}.{}", table, key))),
+        }
+    }
+    Ok(config)
+}
+
+// Every setting in file order, or the line of the first problem and what it is
+fn parse(text: &str) -> Result<Vec<Setting>, (usize, String)> {
+    let mut settings = Vec::new();
+    let mut table = String::new();
+    let mut tables = Vec::new();
+    for (index, raw) in text.lines().enumerate() {
+        let line = index + 1;
+        let invalid = |message: &str| (line, message.to_string());
+        let text = strip_comment(raw).trim();
+        if text.is_empty() {
+            continue;
+        }
+        if let Some(name) = text.strip_prefix('[') {
+            let name = name
+                .strip_suffix(']')
+                .ok_or_else(|| invalid("expected ] after the table name"))?
+                .trim();
+            if !is_bare_key(name) {
+                return Err(invalid("table names are letters, digits, - and _"));
+            }
+            if tables.iter().any(|seen| seen == name) {
+                return Err(invalid(&format!("table [{}] appears twice", name)));
+            }
+            tables.push(name.to_string());
+            table = name.to_string();
+            continue;
+        }
+        let (key, value) = text
+            .split_once('=')
+            .ok_or_else(|| invalid("expected key = value"))?;
+        let key = key.trim();
+        if !is_bare_key(key) {
+            return Err(invalid("keys are letters, digits, - and _"));
+        }
+        if settings
+            .iter()
+            .any(|setting: &Setting| setting.table == table && setting.key == key)
+        {
+            return Err(invalid(&format!("{} is set twice", key)));
+        }
+        let value = parse_value(value.trim()).map_err(|message| invalid(&message))?;
+        settings.push(Setting {
+            line,
+            table: table.clone(),
+            key: key.to_string(),
+            value,
+        });
+    }
+    Ok(settings)
+}
+
+fn parse_value(text: &str) -> Result<Value, String> {
+    if let Some(items) = text.strip_prefix('[') {
+        let items = items
+            .strip_suffix(']')
+            .ok_or("arrays must be closed with ] on the same line")?
+            .trim();
+        // a trailing comma is allowed
+        let items = items.strip_suffix(',').unwrap_or(items);
+        if items.trim().is_empty() {
+            return Ok(Value::Array(Vec::new()));
+        }
+        return split_items(items)
+            .into_iter()
+            .map(|item| match parse_value(item.trim())? {
+                Value::String(s) => Ok(s),
+                other => Err(format!(
+                    "array items must be strings, found {}",
+                    other.kind()
+                )),
+            })
+            .collect::<Result<_, _>>()
+            .map(Value::Array);
+    }
+    if text.starts_with('"') {
+        return parse_string(text).map(Value::String);
+    }
+    match text {
+        "true" => return Ok(Value::Bool(true)),
+        "false" => return Ok(Value::Bool(false)),
+        _ => {}
+    }
+    text.replace('_', "")
+        .parse()
+        .map(Value::Integer)
+        .map_err(|_| {
+            format!(
+                "expected a string, integer, boolean or array, found {}",
+                text
+            )
+        })
+}
+
+// A basic string with \" \\ \n and \t escapes
+fn parse_string(text: &str) -> Result<String, String> {
+    let mut chars = text.chars().skip(1);
+    let mut value = String::new();
+    loop {
+        match chars.next() {
+            None => return Err("unterminated string".to_string()),
+            Some('"') => break,
+            Some('\\') => match chars.next() {
+                Some('"') => value.push('"'),
+                Some('\\') => value.push('\\'),
+                Some('n') => value.push('\n'),
+                Some('t') => value.push('\t'),
+                Some(c) => return Err(format!("unknown escape \\{} in string", c)),
+                None => return Err("unterminated string".to_string()),
+            },
+            Some(c) => value.push(c),
+        }
+    }
+    if chars.next().is_some() {
+        return Err("unexpected text after the closing quote".to_string());
+    }
+    Ok(value)
+}
+
+// Splits on the commas that are outside strings
+fn split_items(text: &str) -> Vec<&str> {
+    let mut items = Vec::new();
+    let (mut start, mut quoted, mut escaped) = (0, false, false);
+    for (i, c) in text.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            ',' if !quoted => {
+                items.push(&text[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    items.push(&text[start..]);
+    items
+}
+
+// The line up to a # that is not inside a string
+fn strip_comment(line: &str) -> &str {
+    let (mut quoted, mut escaped) = (false, false);
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            '#' if !quoted => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn is_bare_key(key: &str) -> bool {
+    !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
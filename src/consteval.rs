@@ -0,0 +1,239 @@
+use crate::ast::{Ast, BinOpType, Expr, ExprKind, Stmt, Type};
+use std::fmt;
+
+// The value of an expression known at compile time
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value {
+    Int(i32),
+    Bool(bool),
+    Char(char),
+}
+
+impl Value {
+    pub fn type_(&self) -> Type {
+        match self {
+            Value::Int(_) => Type::I32S,
+            Value::Bool(_) => Type::Bool,
+            Value::Char(_) => Type::Char,
+        }
+    }
+
+    pub fn to_expr(self) -> Expr {
+        let kind = match self {
+            Value::Int(i) => ExprKind::Int(i),
+            Value::Bool(b) => ExprKind::Bool(b),
+            Value::Char(c) => ExprKind::Char(c),
+        };
+        Expr::typed(kind, self.type_())
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Int(i) => write!(f, "{}", i),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Char(c) => write!(f, "{:?}", c),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum EvalError {
+    // reads a variable, calls a function, or uses a type with no constant form
+    NotConstant,
+    Overflow {
+        left: i32,
+        op: BinOpType,
+        right: i32,
+    },
+    DivisionByZero {
+        left: i32,
+    },
+    InvalidOperands {
+        op: BinOpType,
+        left: Type,
+        right: Type,
+    },
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EvalError::NotConstant => write!(f, "not a constant expression"),
+            EvalError::Overflow { left, op, right } => {
+                write!(f, "{} {} {} overflows i32s", left, op, right)
+            }
+            EvalError::DivisionByZero { left } => write!(f, "division by zero in {} / 0", left),
+            EvalError::InvalidOperands { op, left, right } => write!(
+                f,
+                "operator {} cannot be applied to {} and {}",
+                op, left, right
+            ),
+        }
+    }
+}
+
+// Evaluates with the semantics of the generated code, except that overflow and
+// division by zero are reported instead of wrapping or trapping at run time
+pub fn evaluate(expr: &Expr) -> Result<Value, EvalError> {
+    match &expr.kind {
+        ExprKind::Int(i) => Ok(Value::Int(*i)),
+        ExprKind::Bool(b) => Ok(Value::Bool(*b)),
+        ExprKind::Char(c) => Ok(Value::Char(*c)),
+        ExprKind::Float(_)
+        | ExprKind::Ident(_)
+        | ExprKind::Call { .. }
+        | ExprKind::AddressOf(_)
+        | ExprKind::Deref(_) => Err(EvalError::NotConstant),
+        ExprKind::BinaryOp {
+            left, op, right, ..
+        } => binary_op(evaluate(left)?, op, evaluate(right)?),
+        // -x is 0 - x, which is how an overflowing negation is reported
+        ExprKind::Negate { value } => match evaluate(value)? {
+            Value::Int(i) => i.checked_neg().map(Value::Int).ok_or(EvalError::Overflow {
+                left: 0,
+                op: BinOpType::Subtract,
+                right: i,
+            }),
+            _ => Err(EvalError::NotConstant),
+        },
+        // f32s has no constant form, so only i32s(...) of an integer survives
+        ExprKind::Convert { value, to } => match (evaluate(value)?, to) {
+            (Value::Int(i), Type::I32S) => Ok(Value::Int(i)),
+            _ => Err(EvalError::NotConstant),
+        },
+    }
+}
+
+fn binary_op(left: Value, op: &BinOpType, right: Value) -> Result<Value, EvalError> {
+    match (left, right) {
+        (Value::Int(l), Value::Int(r)) => {
+            let overflow = || EvalError::Overflow {
+                left: l,
+                op: op.clone(),
+                right: r,
+            };
+            let value = match op {
+                BinOpType::Add => Value::Int(l.checked_add(r).ok_or_else(overflow)?),
+                BinOpType::Subtract => Value::Int(l.checked_sub(r).ok_or_else(overflow)?),
+                BinOpType::Multiply => Value::Int(l.checked_mul(r).ok_or_else(overflow)?),
+                BinOpType::Divide if r == 0 => return Err(EvalError::DivisionByZero { left: l }),
+                // idiv truncates toward zero like checked_div; only MIN / -1 overflows
+                BinOpType::Divide => Value::Int(l.checked_div(r).ok_or_else(overflow)?),
+                _ => Value::Bool(compare(&l, op, &r)),
+            };
+            Ok(value)
+        }
+        (Value::Char(l), Value::Char(r)) if !op.is_arithmetic() => {
+            Ok(Value::Bool(compare(&l, op, &r)))
+        }
+        (Value::Bool(l), Value::Bool(r))
+            if matches!(op, BinOpType::Equal | BinOpType::NotEqual) =>
+        {
+            Ok(Value::Bool(compare(&l, op, &r)))
+        }
+        _ => Err(EvalError::InvalidOperands {
+            op: op.clone(),
+            left: left.type_(),
+            right: right.type_(),
+        }),
+    }
+}
+
+fn compare<T: PartialOrd>(left: &T, op: &BinOpType, right: &T) -> bool {
+    match op {
+        BinOpType::Equal => left == right,
+        BinOpType::NotEqual => left != right,
+        BinOpType::LessThan => left < right,
+        BinOpType::LessThanOrEqual => left <= right,
+        BinOpType::GreaterThan => left > right,
+        BinOpType::GreaterThanOrEqual => left >= right,
+        _ => unreachable!("{} is not a comparison", op),
+    }
+}
+
+// Replaces every subexpression that evaluates cleanly with its value. Anything
+// that would overflow or divide by zero is left for run time to behave as written.
+pub fn fold(expr: &Expr) -> Expr {
+    let kind = match &expr.kind {
+        ExprKind::Call { name, args } => ExprKind::Call {
+            name: *name,
+            args: args.iter().map(fold).collect(),
+        },
+        ExprKind::Convert { value, to } => ExprKind::Convert {
+            value: Box::new(fold(value)),
+            to: to.clone(),
+        },
+        ExprKind::Negate { value } => ExprKind::Negate {
+            value: Box::new(fold(value)),
+        },
+        ExprKind::BinaryOp { left, op, right } => ExprKind::BinaryOp {
+            left: Box::new(fold(left)),
+            op: op.clone(),
+            right: Box::new(fold(right)),
+        },
+        _ => return expr.clone(),
+    };
+    let folded = Expr {
+        kind,
+        type_: expr.type_.clone(),
+    };
+    match &folded.kind {
+        ExprKind::Negate { .. } | ExprKind::BinaryOp { .. } => match evaluate(&folded) {
+            Ok(value) => value.to_expr(),
+            Err(_) => folded,
+        },
+        _ => folded,
+    }
+}
+
+// Folds the expressions of every statement in the tree, for -O1 and above. Nested
+// statements live in the same arena, so one pass over it reaches all of them.
+pub fn fold_constants(ast: &mut Ast) {
+    for node in ast.nodes.iter_mut() {
+        match &mut node.stmt {
+            Stmt::Exit(expr)
+            | Stmt::PrintInt(expr)
+            | Stmt::Assert(expr)
+            | Stmt::Expression(expr)
+            | Stmt::Return(Some(expr))
+            | Stmt::VariableDeclaration {
+                value: Some(expr), ..
+            }
+            | Stmt::VariableAssignment { value: expr, .. }
+            | Stmt::DerefAssignment { value: expr, .. }
+            | Stmt::If {
+                condition: expr, ..
+            }
+            | Stmt::While {
+                condition: expr, ..
+            }
+            | Stmt::DoWhile {
+                condition: expr, ..
+            }
+            | Stmt::Match {
+                scrutinee: expr, ..
+            } => *expr = fold(expr),
+            Stmt::For {
+                iterator_begin,
+                iterator_end,
+                step,
+                ..
+            } => {
+                *iterator_begin = fold(iterator_begin);
+                *iterator_end = fold(iterator_end);
+                if let Some(step) = step {
+                    *step = fold(step);
+                }
+            }
+            Stmt::Entry
+            | Stmt::Return(None)
+            | Stmt::VariableDeclaration { value: None, .. }
+            | Stmt::Block { .. }
+            | Stmt::FunctionDeclaration { .. }
+            | Stmt::ConstantDeclaration { .. }
+            | Stmt::Define { .. } => {}
+        }
+    }
+}
@@ -0,0 +1,257 @@
+use crate::ast::{Ast, Stmt};
+use crate::diagnostic;
+use crate::interpret::{Debugger, Interpreter};
+use crate::tokenize::Span;
+use std::collections::BTreeSet;
+use std::io::{BufRead, Write};
+
+// The session behind `noble debug`: it runs a program in the interpreter and, before the
+// statements it stops at, reads commands to set breakpoints by line, step through the
+// program and print what is in scope. It stops at the first statement, so a breakpoint
+// can be set before anything runs. Commands come from one reader and everything the
+// session says goes to one writer, so the program's read_int sees the end of its input.
+
+const HELP: &str = "\
+break N, b N   stop before line N, or the first statement after it
+delete [N]     remove the breakpoint at line N, or every breakpoint
+step, s        run to the next statement, going into calls
+next, n        run to the next statement, going over calls
+finish         run until the current call returns
+continue, c    run to the next breakpoint
+print X, p X   print variable X
+locals         print every variable in scope
+list, l        print the lines around the current statement
+quit, q        end the program here
+an empty line repeats the last command
+";
+
+#[derive(Clone, Copy)]
+enum Mode {
+    Step,
+    // stop once back at this call depth or above
+    Next(usize),
+    // stop once above this call depth
+    Finish(usize),
+    Continue,
+}
+
+pub struct Session<R, W> {
+    lines: Vec<String>,
+    // the lines where a statement the debugger stops at begins
+    statements: BTreeSet<usize>,
+    breakpoints: BTreeSet<usize>,
+    commands: R,
+    out: W,
+    mode: Mode,
+    last_command: String,
+    // the statement asked about before the current one
+    previous: Option<Span>,
+}
+
+impl<R: BufRead, W: Write> Session<R, W> {
+    pub fn new(source: &str, ast: &Ast, commands: R, out: W) -> Self {
+        let statements = ast
+            .iter()
+            .filter(|visit| {
+                visit.node.span.line > 0
+                    && !matches!(
+                        visit.node.stmt,
+                        Stmt::FunctionDeclaration { .. }
+                            | Stmt::ConstantDeclaration { .. }
+                            | Stmt::Define { .. }
+                    )
+            })
+            .map(|visit| visit.node.span.line)
+            .collect();
+        Session {
+            lines: source.lines().map(str::to_string).collect(),
+            statements,
+            breakpoints: BTreeSet::new(),
+            commands,
+            out,
+            mode: Mode::Step,
+            last_command: String::new(),
+            previous: None,
+        }
+    }
+
+    // Whether a breakpoint stops the program at `at`. It does once per arrival at the
+    // line: not again for a later statement on the same line, but again when a loop
+    // whose body shares the line comes back around to it.
+    fn at_breakpoint(&self, at: Span) -> bool {
+        self.breakpoints.contains(&at.line)
+            && !self
+                .previous
+                .is_some_and(|previous| previous.line == at.line && previous.start < at.start)
+    }
+
+    fn show(&mut self, at: Span) {
+        let text = self.lines.get(at.line - 1).map_or("", String::as_str);
+        let _ = writeln!(self.out, "{}:{} {}", at.line, at.column, text);
+    }
+
+    // Reads commands until one resumes the program, returning false to end it
+    fn prompt(&mut self, at: Span, interpreter: &Interpreter) -> bool {
+        loop {
+            let _ = write!(self.out, "(noble) ");
+            let _ = self.out.flush();
+            let mut line = String::new();
+            match self.commands.read_line(&mut line) {
+                Ok(0) | Err(_) => {
+                    let _ = writeln!(self.out);
+                    return false;
+                }
+                Ok(_) => {}
+            }
+            let line = match line.trim() {
+                "" => self.last_command.clone(),
+                command => command.to_string(),
+            };
+            self.last_command = line.clone();
+            let mut words = line.split_whitespace();
+            let (command, argument) = (words.next().unwrap_or(""), words.next());
+            match command {
+                "" => {}
+                "step" | "s" => self.mode = Mode::Step,
+                "next" | "n" => self.mode = Mode::Next(interpreter.depth()),
+                "finish" if interpreter.depth() == 0 => {
+                    let _ = writeln!(self.out, "not in a call");
+                    continue;
+                }
+                "finish" => self.mode = Mode::Finish(interpreter.depth()),
+                "continue" | "c" => self.mode = Mode::Continue,
+                "quit" | "q" => return false,
+                "break" | "b" => {
+                    self.set_breakpoint(argument);
+                    continue;
+                }
+                "delete" => {
+                    self.delete_breakpoint(argument);
+                    continue;
+                }
+                "print" | "p" => {
+                    self.print(argument, interpreter);
+                    continue;
+                }
+                "locals" => {
+                    let variables = interpreter.variables();
+                    if variables.is_empty() {
+                        let _ = writeln!(self.out, "no variables in scope");
+                    }
+                    for (name, value) in variables {
+                        let _ = writeln!(self.out, "{} = {}", name, value);
+                    }
+                    continue;
+                }
+                "list" | "l" => {
+                    self.list(at.line);
+                    continue;
+                }
+                "help" | "h" => {
+                    let _ = write!(self.out, "{}", HELP);
+                    continue;
+                }
+                other => {
+                    let _ = writeln!(self.out, "unknown command: {}; try help", other);
+                    continue;
+                }
+            }
+            return true;
+        }
+    }
+
+    fn set_breakpoint(&mut self, argument: Option<&str>) {
+        let Some(line) = argument.and_then(|argument| argument.parse::<usize>().ok()) else {
+            let _ = writeln!(self.out, "break expects a line number");
+            return;
+        };
+        match self.statements.range(line..).next() {
+            Some(&line) => {
+                self.breakpoints.insert(line);
+                let _ = writeln!(self.out, "breakpoint at line {}", line);
+            }
+            None => {
+                let _ = writeln!(self.out, "no statement at or after line {}", line);
+            }
+        }
+    }
+
+    fn delete_breakpoint(&mut self, argument: Option<&str>) {
+        match argument {
+            None => self.breakpoints.clear(),
+            Some(argument) => match argument.parse::<usize>() {
+                Ok(line) if self.breakpoints.remove(&line) => {}
+                Ok(line) => {
+                    let _ = writeln!(self.out, "no breakpoint at line {}", line);
+                }
+                Err(_) => {
+                    let _ = writeln!(self.out, "delete expects a line number");
+                }
+            },
+        }
+    }
+
+    // A ref parameter is printed by what it points at, and can be asked for as x or *x
+    fn print(&mut self, argument: Option<&str>, interpreter: &Interpreter) {
+        let Some(name) = argument else {
+            let _ = writeln!(self.out, "print expects a variable");
+            return;
+        };
+        let variables = interpreter.variables();
+        let bare = name.trim_start_matches('*');
+        let found = variables
+            .iter()
+            .find(|(variable, _)| variable.trim_start_matches('*') == bare);
+        match found {
+            Some((variable, value)) => {
+                let _ = writeln!(self.out, "{} = {}", variable, value);
+            }
+            None => {
+                let names = variables
+                    .iter()
+                    .map(|(variable, _)| variable.trim_start_matches('*'));
+                let _ = match diagnostic::closest(bare, names) {
+                    Some(similar) => writeln!(
+                        self.out,
+                        "no variable {} in scope; did you mean {}?",
+                        bare, similar
+                    ),
+                    None => writeln!(self.out, "no variable {} in scope", bare),
+                };
+            }
+        }
+    }
+
+    // Two lines either side of `current`, which is marked, as are lines with breakpoints
+    fn list(&mut self, current: usize) {
+        let first = current.saturating_sub(2).max(1);
+        let last = (current + 2).min(self.lines.len());
+        for line in first..=last {
+            let marker = match (line == current, self.breakpoints.contains(&line)) {
+                (true, _) => "=>",
+                (false, true) => " *",
+                (false, false) => "  ",
+            };
+            let _ = writeln!(self.out, "{} {:>4} {}", marker, line, self.lines[line - 1]);
+        }
+    }
+}
+
+impl<R: BufRead, W: Write> Debugger for Session<R, W> {
+    fn before(&mut self, at: Span, interpreter: &Interpreter) -> bool {
+        let depth = interpreter.depth();
+        let stop = self.at_breakpoint(at)
+            || match self.mode {
+                Mode::Step => true,
+                Mode::Next(from) => depth <= from,
+                Mode::Finish(from) => depth < from,
+                Mode::Continue => false,
+            };
+        self.previous = Some(at);
+        if !stop {
+            return true;
+        }
+        self.show(at);
+        self.prompt(at, interpreter)
+    }
+}
@@ -0,0 +1,250 @@
+use crate::arena::Arena;
+use crate::ast::{Ast, Expr, Node, NodeId, Stmt};
+use crate::diagnostic::Diagnostic;
+use crate::diverge::Divergence;
+use crate::intern::Name;
+use crate::tokenize::Span;
+use std::collections::{HashMap, HashSet};
+
+// Definite assignment: a variable declared without a value (`i32s x;`) may only be read
+// once every path from its declaration to the read assigns it. The analysis follows the
+// structure of the program, carrying the set of variables that may still be unassigned:
+// branches join by union, a loop body may run no times, and after a statement that never
+// finishes, such as exit or return, nothing is reachable, so the set is empty.
+
+struct Declaration {
+    span: Span,
+    // the function the declaration is in, if any
+    function: Option<Name>,
+}
+
+struct Checker<'a> {
+    nodes: &'a Arena<Node>,
+    divergence: Divergence,
+    declarations: Vec<Declaration>,
+    // indices into declarations by name, innermost scope last
+    scopes: Vec<HashMap<Name, usize>>,
+    // declarations that may not have been assigned at this point
+    unassigned: HashSet<usize>,
+    // declarations already reported, so each is reported at its first bad read only
+    reported: HashSet<usize>,
+    function: Option<Name>,
+    span: Span,
+    diagnostics: Vec<Diagnostic>,
+}
+
+pub fn check(ast: &Ast) -> Vec<Diagnostic> {
+    let mut checker = Checker {
+        nodes: &ast.nodes,
+        divergence: Divergence::of(ast),
+        declarations: Vec::new(),
+        scopes: Vec::new(),
+        unassigned: HashSet::new(),
+        reported: HashSet::new(),
+        function: None,
+        span: Span::default(),
+        diagnostics: Vec::new(),
+    };
+    checker.statement(ast.root);
+    checker.diagnostics
+}
+
+impl Checker<'_> {
+    fn body(&mut self, body: &[NodeId]) {
+        self.scopes.push(HashMap::new());
+        for &id in body {
+            self.statement(id);
+        }
+        self.scopes.pop();
+    }
+
+    // The set after running `body` from `before`, leaving the current set untouched
+    fn branch(&mut self, before: &HashSet<usize>, body: NodeId) -> HashSet<usize> {
+        self.unassigned = before.clone();
+        self.statement(body);
+        std::mem::replace(&mut self.unassigned, before.clone())
+    }
+
+    fn statement(&mut self, id: NodeId) {
+        let nodes = self.nodes;
+        let node = &nodes[id];
+        let enclosing = self.span;
+        if node.span.line > 0 {
+            self.span = node.span;
+        }
+        match &node.stmt {
+            Stmt::Entry => self.body(&node.children),
+            Stmt::Exit(expr)
+            | Stmt::Return(Some(expr))
+            | Stmt::PrintInt(expr)
+            | Stmt::Assert(expr)
+            | Stmt::Expression(expr) => self.expr(expr),
+            Stmt::Return(None) => {}
+            Stmt::VariableDeclaration { name, value, .. } => {
+                if let Some(value) = value {
+                    self.expr(value);
+                }
+                let index = self.declare(*name);
+                if value.is_none() {
+                    self.unassigned.insert(index);
+                }
+            }
+            Stmt::VariableAssignment { name, value } => {
+                self.expr(value);
+                if let Some(index) = self.resolve(*name) {
+                    self.unassigned.remove(&index);
+                }
+            }
+            // writes what the reference points at, so the reference itself is read
+            Stmt::DerefAssignment { name, value } => {
+                self.expr(value);
+                self.read(*name);
+            }
+            Stmt::Block { body } => self.body(body),
+            Stmt::If {
+                condition,
+                body,
+                else_body,
+            } => {
+                self.expr(condition);
+                let before = self.unassigned.clone();
+                let then = self.branch(&before, *body);
+                let otherwise = match else_body {
+                    Some(else_body) => self.branch(&before, *else_body),
+                    None => before,
+                };
+                self.unassigned = &then | &otherwise;
+            }
+            Stmt::Match {
+                scrutinee,
+                arms,
+                else_body,
+            } => {
+                self.expr(scrutinee);
+                let before = self.unassigned.clone();
+                let mut after = match else_body {
+                    Some(else_body) => self.branch(&before, *else_body),
+                    None => before.clone(),
+                };
+                for (_, body) in arms {
+                    after.extend(self.branch(&before, *body));
+                }
+                self.unassigned = after;
+            }
+            // the body may not run at all, and a variable it assigns is no less assigned
+            // when the condition is checked again, so one pass over it is enough
+            Stmt::While { condition, body } => {
+                self.expr(condition);
+                let before = self.unassigned.clone();
+                self.branch(&before, *body);
+            }
+            // the step is only evaluated once the body has run
+            Stmt::For {
+                iterator_name,
+                iterator_begin,
+                iterator_end,
+                step,
+                body,
+                ..
+            } => {
+                self.expr(iterator_begin);
+                self.expr(iterator_end);
+                let before = self.unassigned.clone();
+                self.scopes.push(HashMap::new());
+                self.declare(*iterator_name);
+                let after = self.branch(&before, *body);
+                self.scopes.pop();
+                if let Some(step) = step {
+                    self.unassigned = after;
+                    self.expr(step);
+                    self.unassigned = before;
+                }
+            }
+            // the condition is outside the body's scope, so it cannot see the body's
+            // variables
+            Stmt::DoWhile { body, condition } => {
+                self.statement(*body);
+                self.expr(condition);
+            }
+            // checked where it is declared, since it can only be called after that:
+            // a variable outside it must be assigned by then. The body does not change
+            // what is assigned after the declaration.
+            Stmt::FunctionDeclaration {
+                name, params, body, ..
+            } => {
+                let before = self.unassigned.clone();
+                let enclosing = self.function.replace(*name);
+                self.scopes.push(HashMap::new());
+                for (param, _) in params {
+                    self.declare(*param);
+                }
+                self.statement(*body);
+                self.scopes.pop();
+                self.function = enclosing;
+                self.unassigned = before;
+            }
+            Stmt::ConstantDeclaration { .. } | Stmt::Define { .. } => {}
+        }
+        if !self.divergence.statement_finishes(nodes, id) {
+            self.unassigned.clear();
+        }
+        self.span = enclosing;
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        for visit in expr.walk() {
+            if let Some(name) = visit.expr.variable() {
+                self.read(name);
+            }
+        }
+    }
+
+    fn read(&mut self, name: Name) {
+        let Some(index) = self.resolve(name) else {
+            return;
+        };
+        if !self.unassigned.contains(&index) || !self.reported.insert(index) {
+            return;
+        }
+        let declaration = &self.declarations[index];
+        let note = if let Some(function) = self.function
+            && declaration.function != self.function
+        {
+            format!(
+                "it is declared without a value at line {}; a function is checked where it \
+                 is declared, so assign {} before declaring {}",
+                declaration.span.line, name, function
+            )
+        } else {
+            format!(
+                "it is declared without a value at line {}, and some path from there to \
+                 here does not assign it",
+                declaration.span.line
+            )
+        };
+        self.diagnostics.push(
+            Diagnostic::new(
+                format!("TypeError: {} may be read before it is assigned", name),
+                self.span,
+            )
+            .with_note(note),
+        );
+    }
+
+    fn declare(&mut self, name: Name) -> usize {
+        self.declarations.push(Declaration {
+            span: self.span,
+            function: self.function,
+        });
+        let index = self.declarations.len() - 1;
+        self.scopes.last_mut().unwrap().insert(name, index);
+        index
+    }
+
+    fn resolve(&self, name: Name) -> Option<usize> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(&name).copied())
+    }
+}
@@ -0,0 +1,136 @@
+use crate::tokenize::Span;
+use std::any::Any;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+    // extra information attached to another diagnostic
+    Note,
+}
+
+// An error message tied to the source location it concerns. Displays as the
+// bare message so command-line output reads the same as plain string errors.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub message: String,
+    pub span: Span,
+    pub severity: Severity,
+    // notes that elaborate on this diagnostic
+    pub children: Vec<Diagnostic>,
+    // a specific code such as a lint's, reported instead of the message's category
+    pub code: Option<&'static str>,
+}
+
+impl Diagnostic {
+    pub fn new(message: impl Into<String>, span: Span) -> Self {
+        Self {
+            message: message.into(),
+            span,
+            severity: Severity::Error,
+            children: Vec::new(),
+            code: None,
+        }
+    }
+
+    pub fn warning(message: impl Into<String>, span: Span) -> Self {
+        Self {
+            severity: Severity::Warning,
+            ..Self::new(message, span)
+        }
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.children.push(Self {
+            severity: Severity::Note,
+            ..Self::new(note, Span::default())
+        });
+        self
+    }
+
+    pub fn with_code(mut self, code: &'static str) -> Self {
+        self.code = Some(code);
+        self
+    }
+
+    // The code, or else the category prefix of the message ("ParseError", "Warning",
+    // ...), and the rest of the message
+    pub fn code_and_text(&self) -> (String, &str) {
+        let (category, text) = match self.message.split_once(": ") {
+            Some((prefix, text)) if prefix.ends_with("Error") || prefix == "Warning" => {
+                (prefix.replace(' ', ""), text)
+            }
+            _ => (format!("{:?}", self.severity), self.message.as_str()),
+        };
+        (self.code.map_or(category, str::to_string), text)
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for Diagnostic {}
+
+// A panic caught by a session that outlives one compilation, such as watch or the
+// language server, reported as the bug in the compiler it is along with what it said
+pub fn internal_error(payload: &(dyn Any + Send)) -> Diagnostic {
+    let what = match (
+        payload.downcast_ref::<&str>(),
+        payload.downcast_ref::<String>(),
+    ) {
+        (Some(message), _) => message,
+        (None, Some(message)) => message.as_str(),
+        (None, None) => "no message",
+    };
+    Diagnostic::new(
+        format!("InternalError: the compiler panicked: {}", what),
+        Span::default(),
+    )
+    .with_note("this is a bug in Noble rather than in the program; please report it")
+}
+
+// The candidate most like a name that was not found, for a "did you mean" note: one at
+// most a third of the name's length in edits away, and fewer edits than the name has
+// characters, so that `x` does not suggest `y`. Ties go to the first in sorted order.
+pub fn closest<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+    let length = name.chars().count();
+    let limit = (length / 3).max(1);
+    candidates
+        .into_iter()
+        .filter(|candidate| *candidate != name)
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|&(distance, _)| distance <= limit && distance < length)
+        .min()
+        .map(|(_, candidate)| candidate)
+}
+
+// Insertions, deletions, substitutions and swaps of two neighbouring characters needed
+// to turn one string into the other (optimal string alignment), so `whiel` is one edit
+// from `while`
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    // rows[i][j] is the distance between the first i characters of a and j of b
+    let mut rows = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in rows.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    rows[0] = (0..=b.len()).collect();
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let substitution = usize::from(a[i - 1] != b[j - 1]);
+            let mut distance = (rows[i - 1][j] + 1)
+                .min(rows[i][j - 1] + 1)
+                .min(rows[i - 1][j - 1] + substitution);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                distance = distance.min(rows[i - 2][j - 2] + 1);
+            }
+            rows[i][j] = distance;
+        }
+    }
+    rows[a.len()][b.len()]
+}
@@ -0,0 +1,112 @@
+use crate::arena::Arena;
+use crate::ast::{Ast, Expr, ExprKind, Node, NodeId, Stmt};
+use crate::consteval::{self, Value};
+use crate::intern::Name;
+use std::collections::HashSet;
+
+// Divergence: whether control can go on past a statement. exit and return never finish,
+// and neither does `while true`, which has no break to end it, or an if or match none of
+// whose branches finish. Such a statement has type never, like Rust's `!`: what follows
+// it is unreachable, and it counts as returning on every path. A function whose every
+// path ends at an exit never returns, so a call to it is never too.
+
+#[derive(Debug, Clone, Default)]
+pub struct Divergence {
+    // the functions that end the program whenever they are called
+    exits: HashSet<Name>,
+}
+
+impl Divergence {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // The divergence of every function of a checked program
+    pub fn of(ast: &Ast) -> Self {
+        let mut divergence = Self::new();
+        for visit in ast.iter() {
+            if let Stmt::FunctionDeclaration { name, body, .. } = &visit.node.stmt {
+                divergence.declare(&ast.nodes, *name, *body);
+            }
+        }
+        divergence
+    }
+
+    // Records whether the function with this body ever returns. Only the functions
+    // declared before it are known, so a call to itself does not make it diverge.
+    pub fn declare(&mut self, nodes: &Arena<Node>, name: Name, body: NodeId) {
+        if !self.statement_finishes(nodes, body) && !contains_return(nodes, &[body]) {
+            self.exits.insert(name);
+        }
+    }
+
+    pub fn never_returns(&self, name: Name) -> bool {
+        self.exits.contains(&name)
+    }
+
+    // Whether control can fall off the end of these statements
+    pub fn finishes(&self, nodes: &Arena<Node>, body: &[NodeId]) -> bool {
+        body.iter().all(|&id| self.statement_finishes(nodes, id))
+    }
+
+    // Loops other than do-while may run zero times, so only their condition can keep
+    // them from finishing. Expressions have no short-circuit operators, so a call in
+    // one that is evaluated at all is always made; an assert's condition may not be,
+    // and neither may a for loop's step, which waits for the body to run.
+    pub fn statement_finishes(&self, nodes: &Arena<Node>, id: NodeId) -> bool {
+        let stmt = &nodes[id].stmt;
+        let evaluated = match stmt {
+            Stmt::Assert(_) => vec![],
+            Stmt::For {
+                iterator_begin,
+                iterator_end,
+                ..
+            } => vec![iterator_begin, iterator_end],
+            _ => stmt.expressions(),
+        };
+        if evaluated.iter().any(|e| self.calls(e)) {
+            return false;
+        }
+        match stmt {
+            Stmt::Exit(_) | Stmt::Return(_) => false,
+            Stmt::If {
+                body, else_body, ..
+            } => {
+                self.statement_finishes(nodes, *body)
+                    || else_body.is_none_or(|id| self.statement_finishes(nodes, id))
+            }
+            Stmt::Match {
+                arms, else_body, ..
+            } => {
+                arms.iter()
+                    .any(|&(_, body)| self.statement_finishes(nodes, body))
+                    || else_body.is_none_or(|id| self.statement_finishes(nodes, id))
+            }
+            Stmt::Block { body } => self.finishes(nodes, body),
+            Stmt::While { condition, .. } => !always_true(condition),
+            Stmt::DoWhile { body, condition } => {
+                self.statement_finishes(nodes, *body) && !always_true(condition)
+            }
+            _ => true,
+        }
+    }
+
+    // Whether evaluating the expression calls a function that never returns
+    fn calls(&self, expr: &Expr) -> bool {
+        expr.walk().any(|visit| {
+            matches!(&visit.expr.kind, ExprKind::Call { name, .. } if self.never_returns(*name))
+        })
+    }
+}
+
+fn always_true(condition: &Expr) -> bool {
+    matches!(consteval::evaluate(condition), Ok(Value::Bool(true)))
+}
+
+// Whether a return statement appears anywhere in these statements
+fn contains_return(nodes: &Arena<Node>, body: &[NodeId]) -> bool {
+    body.iter().any(|&id| match &nodes[id].stmt {
+        Stmt::Return(_) => true,
+        _ => contains_return(nodes, &nodes[id].child_ids()),
+    })
+}
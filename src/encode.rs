@@ -0,0 +1,883 @@
+use crate::asm::{Line, Operand, Size, Syntax};
+#[cfg(feature = "obj")]
+use crate::target::{Arch, ObjectFormat, TargetSpec};
+use std::collections::HashMap;
+
+// Machine code for the instructions the generator and the runtime modules write, so a
+// program can be turned into a flat binary or an object file without an assembler.
+// Only the forms Noble emits are covered; anything else is an error rather than a
+// guess. Every branch and call takes a 32-bit displacement and every symbol is
+// addressed rip-relative, so an instruction's length never depends on where its labels
+// end up and one pass lays the program out.
+
+// Where a symbol's address is patched in once every label is placed
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FixupKind {
+    // a 32-bit displacement from the end of the instruction, for branches, calls and
+    // rip-relative memory operands
+    Relative,
+    // the 64-bit address at which the image is loaded plus the symbol's offset, for
+    // jump tables
+    Absolute,
+}
+
+struct Fixup {
+    section: usize,
+    // where the field is, and where the instruction it belongs to ends
+    at: usize,
+    end: usize,
+    symbol: String,
+    addend: i64,
+    kind: FixupKind,
+}
+
+// One encoded instruction, with the fields that still name a symbol
+struct Encoded {
+    bytes: Vec<u8>,
+    // (offset in bytes, symbol, addend, kind)
+    fixups: Vec<(usize, String, i64, FixupKind)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Width {
+    Byte,
+    Dword,
+    Qword,
+    Xmm,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Arg {
+    Register(u8, Width),
+    Immediate(i64),
+    Memory {
+        size: Option<Size>,
+        base: Base,
+        offset: i64,
+    },
+    Label(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Base {
+    Register(u8),
+    Symbol(String),
+}
+
+// The sections in the order they are laid out in the image
+const SECTIONS: [&str; 3] = [".text", ".data", ".bss"];
+
+// A program encoded section by section, before its symbols have addresses
+struct Assembly {
+    sections: Vec<Vec<u8>>,
+    // (section, offset) of every label and data name
+    symbols: HashMap<String, (usize, usize)>,
+    // the same names in the order they were defined
+    defined: Vec<String>,
+    globals: Vec<String>,
+    externs: Vec<String>,
+    fixups: Vec<Fixup>,
+}
+
+// Encodes a freestanding program together with the runtime module it calls into one
+// image meant to be loaded at `origin`. Execution starts at the first byte, which is the
+// entry point the generator puts first. Sections follow each other 16-byte aligned, and
+// .bss is written out as zeros so the image needs no loader.
+pub fn flat_binary(lines: &[Line], origin: u64) -> Result<Vec<u8>, String> {
+    let assembly = assemble(lines)?;
+
+    // lay the sections out one after another
+    let mut bases = Vec::new();
+    let mut image = Vec::new();
+    for section in &assembly.sections {
+        image.resize(image.len().next_multiple_of(16), 0);
+        bases.push(image.len());
+        image.extend(section);
+    }
+
+    for fixup in &assembly.fixups {
+        let Some(&(section, offset)) = assembly.symbols.get(&fixup.symbol) else {
+            return Err(if assembly.externs.contains(&fixup.symbol) {
+                format!(
+                    "{} is an external function, which a flat binary cannot link to",
+                    fixup.symbol
+                )
+            } else {
+                format!("undefined symbol {}", fixup.symbol)
+            });
+        };
+        let target = (bases[section] + offset) as i64 + fixup.addend;
+        let at = bases[fixup.section] + fixup.at;
+        match fixup.kind {
+            FixupKind::Relative => {
+                let end = (bases[fixup.section] + fixup.end) as i64;
+                let displacement = i32::try_from(target - end).map_err(|_| {
+                    format!("{} is out of reach of a 32-bit displacement", fixup.symbol)
+                })?;
+                image[at..at + 4].copy_from_slice(&displacement.to_le_bytes());
+            }
+            FixupKind::Absolute => {
+                let address = origin.wrapping_add(target as u64);
+                image[at..at + 8].copy_from_slice(&address.to_le_bytes());
+            }
+        }
+    }
+    Ok(image)
+}
+
+// Encodes a program, or the runtime module, into a relocatable object file in the
+// target's format for a system linker. Displacements within a section are filled in
+// here; those that reach into another section or outside the object, and the absolute
+// addresses in jump tables, become relocations. Every label is in the symbol table,
+// local unless the program declared it global.
+#[cfg(feature = "obj")]
+pub fn object_file(lines: &[Line], target: &TargetSpec) -> Result<Vec<u8>, String> {
+    use object::write::{Object, Relocation, StandardSection, Symbol, SymbolSection};
+    use object::{
+        Architecture, BinaryFormat, Endianness, RelocationEncoding, RelocationFlags,
+        RelocationKind, SectionKind, SymbolFlags, SymbolKind, SymbolScope,
+    };
+
+    let format = match target.object_format {
+        ObjectFormat::Coff => BinaryFormat::Coff,
+        ObjectFormat::Elf => BinaryFormat::Elf,
+    };
+    let architecture = match target.arch {
+        Arch::X86_64 => Architecture::X86_64,
+        Arch::Aarch64 => return Err(format!("cannot encode instructions for {}", target.name)),
+    };
+    let mut assembly = assemble(lines)?;
+    let mut object = Object::new(format, architecture, Endianness::Little);
+    if format == BinaryFormat::Elf {
+        // an empty note that tells the linker the stack need not be executable
+        object.add_section(Vec::new(), b".note.GNU-stack".to_vec(), SectionKind::Other);
+    }
+
+    // displacements to a label in the same section never change, so they need no
+    // relocation
+    let mut relocations = Vec::new();
+    for fixup in std::mem::take(&mut assembly.fixups) {
+        match assembly.symbols.get(&fixup.symbol) {
+            Some(&(section, offset))
+                if section == fixup.section && fixup.kind == FixupKind::Relative =>
+            {
+                let displacement = offset as i64 + fixup.addend - fixup.end as i64;
+                let displacement = i32::try_from(displacement).map_err(|_| {
+                    format!("{} is out of reach of a 32-bit displacement", fixup.symbol)
+                })?;
+                let section = &mut assembly.sections[section];
+                section[fixup.at..fixup.at + 4].copy_from_slice(&displacement.to_le_bytes());
+            }
+            Some(_) => relocations.push(fixup),
+            None if assembly.externs.contains(&fixup.symbol) => relocations.push(fixup),
+            None => return Err(format!("undefined symbol {}", fixup.symbol)),
+        }
+    }
+
+    let kinds = [
+        (StandardSection::Text, SymbolKind::Text),
+        (StandardSection::Data, SymbolKind::Data),
+        (StandardSection::UninitializedData, SymbolKind::Data),
+    ];
+    let mut ids = Vec::new();
+    for (bytes, (standard, _)) in assembly.sections.iter().zip(kinds) {
+        let id = object.section_id(standard);
+        if standard == StandardSection::UninitializedData {
+            object.append_section_bss(id, bytes.len() as u64, 16);
+        } else {
+            object.append_section_data(id, bytes, 16);
+        }
+        ids.push(id);
+    }
+
+    let mut symbols = HashMap::new();
+    for name in &assembly.defined {
+        let (section, offset) = assembly.symbols[name];
+        let scope = if assembly.globals.contains(name) {
+            SymbolScope::Dynamic
+        } else {
+            SymbolScope::Compilation
+        };
+        let id = object.add_symbol(Symbol {
+            name: name.as_bytes().to_vec(),
+            value: offset as u64,
+            size: 0,
+            kind: kinds[section].1,
+            scope,
+            weak: false,
+            section: SymbolSection::Section(ids[section]),
+            flags: SymbolFlags::None,
+        });
+        symbols.insert(name.clone(), id);
+    }
+    for name in &assembly.externs {
+        let id = object.add_symbol(Symbol {
+            name: name.as_bytes().to_vec(),
+            value: 0,
+            size: 0,
+            kind: SymbolKind::Text,
+            scope: SymbolScope::Dynamic,
+            weak: false,
+            section: SymbolSection::Undefined,
+            flags: SymbolFlags::None,
+        });
+        symbols.insert(name.clone(), id);
+    }
+
+    for fixup in relocations {
+        // the field holds the symbol's address less the end of the instruction, which
+        // a relocation expresses relative to the field itself
+        let (kind, size, addend) = match fixup.kind {
+            FixupKind::Relative => (
+                RelocationKind::Relative,
+                32,
+                fixup.addend - (fixup.end - fixup.at) as i64,
+            ),
+            FixupKind::Absolute => (RelocationKind::Absolute, 64, fixup.addend),
+        };
+        object
+            .add_relocation(
+                ids[fixup.section],
+                Relocation {
+                    offset: fixup.at as u64,
+                    symbol: symbols[&fixup.symbol],
+                    addend,
+                    flags: RelocationFlags::Generic {
+                        kind,
+                        encoding: RelocationEncoding::Generic,
+                        size,
+                    },
+                },
+            )
+            .map_err(|e| e.to_string())?;
+    }
+    object.write().map_err(|e| e.to_string())
+}
+
+// Encodes every instruction and data line, recording where each name is defined and
+// every field that waits on a symbol's address
+fn assemble(lines: &[Line]) -> Result<Assembly, String> {
+    let mut assembly = Assembly {
+        sections: vec![Vec::new(); SECTIONS.len()],
+        symbols: HashMap::new(),
+        defined: Vec::new(),
+        globals: Vec::new(),
+        externs: Vec::new(),
+        fixups: Vec::new(),
+    };
+    let mut current = 0;
+
+    for line in lines {
+        match line {
+            Line::Section(name) => {
+                current = SECTIONS
+                    .iter()
+                    .position(|section| section == name)
+                    .ok_or_else(|| format!("unknown section {}", name))?;
+            }
+            Line::Label(name) => assembly.define(name, current)?,
+            Line::Instruction { mnemonic, operands } => {
+                let args = operands
+                    .iter()
+                    .map(arg)
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|e| format!("{} in {}", e, line.render(Syntax::Intel).trim()))?;
+                let encoded = encode(mnemonic, &args)
+                    .map_err(|e| format!("{}: {}", line.render(Syntax::Intel).trim(), e))?;
+                let start = assembly.sections[current].len();
+                let end = start + encoded.bytes.len();
+                for (at, symbol, addend, kind) in encoded.fixups {
+                    assembly.fixups.push(Fixup {
+                        section: current,
+                        at: start + at,
+                        end,
+                        symbol,
+                        addend,
+                        kind,
+                    });
+                }
+                assembly.sections[current].extend(encoded.bytes);
+            }
+            Line::Quad(label) => {
+                let at = assembly.sections[current].len();
+                assembly.fixups.push(Fixup {
+                    section: current,
+                    at,
+                    end: at + 8,
+                    symbol: label.clone(),
+                    addend: 0,
+                    kind: FixupKind::Absolute,
+                });
+                assembly.sections[current].extend([0; 8]);
+            }
+            Line::Asciz { name, text } => {
+                assembly.define(name, current)?;
+                assembly.sections[current].extend(text.as_bytes());
+                assembly.sections[current].push(0);
+            }
+            Line::Reserve { name, size, count } => {
+                assembly.define(name, current)?;
+                let length = assembly.sections[current].len() + size.bytes() * count;
+                assembly.sections[current].resize(length, 0);
+            }
+            Line::Extern(name) => assembly.externs.push(name.clone()),
+            Line::Global(name) => assembly.globals.push(name.clone()),
+            Line::Comment(_) | Line::Blank | Line::Location { .. } => {}
+        }
+    }
+    Ok(assembly)
+}
+
+impl Assembly {
+    // Puts a name at the current end of a section
+    fn define(&mut self, name: &str, section: usize) -> Result<(), String> {
+        let offset = self.sections[section].len();
+        match self.symbols.insert(name.to_string(), (section, offset)) {
+            Some(_) => Err(format!("symbol {} is defined twice", name)),
+            None => {
+                self.defined.push(name.to_string());
+                Ok(())
+            }
+        }
+    }
+}
+
+fn arg(operand: &Operand) -> Result<Arg, String> {
+    Ok(match operand {
+        Operand::Register(name) => {
+            let (number, width) =
+                register(name).ok_or_else(|| format!("unknown register {}", name))?;
+            Arg::Register(number, width)
+        }
+        Operand::Immediate(value) => Arg::Immediate(*value),
+        Operand::Memory { size, base, offset } => Arg::Memory {
+            size: *size,
+            base: match register(base) {
+                Some((number, Width::Qword)) => Base::Register(number),
+                Some(_) => return Err(format!("{} cannot be an address", base)),
+                None => Base::Symbol(base.clone()),
+            },
+            offset: *offset,
+        },
+        Operand::Label(name) => Arg::Label(name.clone()),
+    })
+}
+
+// The register's number in the encoding and its width
+fn register(name: &str) -> Option<(u8, Width)> {
+    const LEGACY: [[&str; 3]; 8] = [
+        ["al", "eax", "rax"],
+        ["cl", "ecx", "rcx"],
+        ["dl", "edx", "rdx"],
+        ["bl", "ebx", "rbx"],
+        ["spl", "esp", "rsp"],
+        ["bpl", "ebp", "rbp"],
+        ["sil", "esi", "rsi"],
+        ["dil", "edi", "rdi"],
+    ];
+    const WIDTHS: [Width; 3] = [Width::Byte, Width::Dword, Width::Qword];
+    for (number, names) in LEGACY.iter().enumerate() {
+        if let Some(i) = names.iter().position(|n| *n == name) {
+            return Some((number as u8, WIDTHS[i]));
+        }
+    }
+    if let Some(number) = name.strip_prefix("xmm") {
+        return number
+            .parse()
+            .ok()
+            .filter(|n| *n < 16)
+            .map(|n| (n, Width::Xmm));
+    }
+    let rest = name.strip_prefix('r')?;
+    let digits = rest.trim_end_matches(['b', 'd']);
+    let number: u8 = digits.parse().ok().filter(|n| (8..16).contains(n))?;
+    let width = match &rest[digits.len()..] {
+        "b" => Width::Byte,
+        "d" => Width::Dword,
+        "" => Width::Qword,
+        _ => return None,
+    };
+    Some((number, width))
+}
+
+// The condition code of a jcc, setcc or cmovcc suffix
+fn condition(suffix: &str) -> Option<u8> {
+    Some(match suffix {
+        "o" => 0x0,
+        "no" => 0x1,
+        "b" | "c" | "nae" => 0x2,
+        "ae" | "nb" | "nc" => 0x3,
+        "e" | "z" => 0x4,
+        "ne" | "nz" => 0x5,
+        "be" | "na" => 0x6,
+        "a" | "nbe" => 0x7,
+        "s" => 0x8,
+        "ns" => 0x9,
+        "p" | "pe" => 0xa,
+        "np" | "po" => 0xb,
+        "l" | "nge" => 0xc,
+        "ge" | "nl" => 0xd,
+        "le" | "ng" => 0xe,
+        "g" | "nle" => 0xf,
+        _ => return None,
+    })
+}
+
+// The /digit of the group-1 arithmetic instructions
+fn arithmetic(mnemonic: &str) -> Option<u8> {
+    Some(match mnemonic {
+        "add" => 0,
+        "or" => 1,
+        "and" => 4,
+        "sub" => 5,
+        "xor" => 6,
+        "cmp" => 7,
+        _ => return None,
+    })
+}
+
+// (mandatory prefix, opcode) of the scalar single-precision instructions that take an
+// xmm register and an xmm register or memory operand
+fn sse(mnemonic: &str) -> Option<(Option<u8>, u8)> {
+    Some(match mnemonic {
+        "addss" => (Some(0xf3), 0x58),
+        "mulss" => (Some(0xf3), 0x59),
+        "subss" => (Some(0xf3), 0x5c),
+        "minss" => (Some(0xf3), 0x5d),
+        "divss" => (Some(0xf3), 0x5e),
+        "maxss" => (Some(0xf3), 0x5f),
+        "sqrtss" => (Some(0xf3), 0x51),
+        "ucomiss" => (None, 0x2e),
+        "comiss" => (None, 0x2f),
+        "xorps" => (None, 0x57),
+        _ => return None,
+    })
+}
+
+fn encode(mnemonic: &str, args: &[Arg]) -> Result<Encoded, String> {
+    use Arg::{Immediate, Label, Memory, Register};
+
+    let mut out = Encoder::default();
+    match (mnemonic, args) {
+        ("ret", []) => out.bytes(&[0xc3]),
+        ("cdq", []) => out.bytes(&[0x99]),
+        ("cqo", []) => out.bytes(&[0x48, 0x99]),
+        ("syscall", []) => out.bytes(&[0x0f, 0x05]),
+        ("nop", []) => out.bytes(&[0x90]),
+
+        (_, [dst, src]) if let Some(digit) = arithmetic(mnemonic) => match (dst, src) {
+            (_, Register(reg, width)) if is_rm(dst) => {
+                let opcode = if *width == Width::Byte { 0 } else { 1 };
+                out.modrm(None, *width, &[digit * 8 + opcode], *reg, dst, &[src])?;
+            }
+            (Register(reg, width), Memory { .. }) => {
+                let opcode = if *width == Width::Byte { 2 } else { 3 };
+                out.modrm(None, *width, &[digit * 8 + opcode], *reg, src, &[dst])?;
+            }
+            (_, Immediate(value)) if is_rm(dst) => {
+                let width = rm_width(dst)?;
+                if width == Width::Byte {
+                    out.modrm(None, width, &[0x80], digit, dst, &[])?;
+                    out.immediate(*value, 1, width)?;
+                } else if i8::try_from(*value).is_ok() {
+                    out.modrm(None, width, &[0x83], digit, dst, &[])?;
+                    out.immediate(*value, 1, width)?;
+                } else {
+                    out.modrm(None, width, &[0x81], digit, dst, &[])?;
+                    out.immediate(*value, 4, width)?;
+                }
+            }
+            _ => return Err(unsupported()),
+        },
+
+        ("test", [dst, Register(reg, width)]) if is_rm(dst) => {
+            let opcode = if *width == Width::Byte { 0x84 } else { 0x85 };
+            out.modrm(None, *width, &[opcode], *reg, dst, &[&args[1]])?;
+        }
+        ("test", [dst, Immediate(value)]) if is_rm(dst) => {
+            let width = rm_width(dst)?;
+            let (opcode, size) = if width == Width::Byte {
+                (0xf6, 1)
+            } else {
+                (0xf7, 4)
+            };
+            out.modrm(None, width, &[opcode], 0, dst, &[])?;
+            out.immediate(*value, size, width)?;
+        }
+
+        ("mov", [dst, Register(reg, width)]) if is_rm(dst) && *width != Width::Xmm => {
+            let opcode = if *width == Width::Byte { 0x88 } else { 0x89 };
+            out.modrm(None, *width, &[opcode], *reg, dst, &[&args[1]])?;
+        }
+        ("mov", [Register(reg, width), src @ Memory { .. }]) if *width != Width::Xmm => {
+            let opcode = if *width == Width::Byte { 0x8a } else { 0x8b };
+            out.modrm(None, *width, &[opcode], *reg, src, &[&args[0]])?;
+        }
+        ("mov", [Register(reg, width), Immediate(value)]) => match width {
+            Width::Byte => {
+                out.rex(false, 0, 0, *reg, byte_needs_rex(&[&args[0]]));
+                out.bytes(&[0xb0 + (reg & 7)]);
+                out.immediate(*value, 1, *width)?;
+            }
+            Width::Dword => {
+                out.rex(false, 0, 0, *reg, false);
+                out.bytes(&[0xb8 + (reg & 7)]);
+                out.immediate(*value, 4, *width)?;
+            }
+            Width::Qword if i32::try_from(*value).is_ok() => {
+                out.modrm(None, *width, &[0xc7], 0, &args[0], &[])?;
+                out.immediate(*value, 4, *width)?;
+            }
+            Width::Qword => {
+                out.rex(true, 0, 0, *reg, false);
+                out.bytes(&[0xb8 + (reg & 7)]);
+                out.bytes(&value.to_le_bytes());
+            }
+            Width::Xmm => return Err(unsupported()),
+        },
+        ("mov", [dst @ Memory { .. }, Immediate(value)]) => {
+            let width = rm_width(dst)?;
+            let (opcode, size) = if width == Width::Byte {
+                (0xc6, 1)
+            } else {
+                (0xc7, 4)
+            };
+            out.modrm(None, width, &[opcode], 0, dst, &[])?;
+            out.immediate(*value, size, width)?;
+        }
+
+        (
+            "lea",
+            [
+                Register(reg, width @ (Width::Dword | Width::Qword)),
+                src @ Memory { .. },
+            ],
+        ) => {
+            out.modrm(None, *width, &[0x8d], *reg, src, &[])?;
+        }
+        ("movzx" | "movsx", [Register(reg, width @ (Width::Dword | Width::Qword)), src])
+            if is_rm(src) && rm_width(src)? == Width::Byte =>
+        {
+            let opcode = if mnemonic == "movzx" { 0xb6 } else { 0xbe };
+            out.modrm(None, *width, &[0x0f, opcode], *reg, src, &[src])?;
+        }
+
+        ("imul", [Register(reg, width), src]) if is_rm(src) && *width != Width::Byte => {
+            out.modrm(None, *width, &[0x0f, 0xaf], *reg, src, &[])?;
+        }
+        ("imul", [Register(reg, width), src, Immediate(value)]) if is_rm(src) => {
+            if i8::try_from(*value).is_ok() {
+                out.modrm(None, *width, &[0x6b], *reg, src, &[])?;
+                out.immediate(*value, 1, *width)?;
+            } else {
+                out.modrm(None, *width, &[0x69], *reg, src, &[])?;
+                out.immediate(*value, 4, *width)?;
+            }
+        }
+        ("not" | "neg" | "mul" | "imul" | "div" | "idiv", [operand]) if is_rm(operand) => {
+            let digit = match mnemonic {
+                "not" => 2,
+                "neg" => 3,
+                "mul" => 4,
+                "imul" => 5,
+                "div" => 6,
+                _ => 7,
+            };
+            let width = rm_width(operand)?;
+            let opcode = if width == Width::Byte { 0xf6 } else { 0xf7 };
+            out.modrm(None, width, &[opcode], digit, operand, &[operand])?;
+        }
+        ("inc" | "dec", [operand]) if is_rm(operand) => {
+            let digit = if mnemonic == "inc" { 0 } else { 1 };
+            let width = rm_width(operand)?;
+            let opcode = if width == Width::Byte { 0xfe } else { 0xff };
+            out.modrm(None, width, &[opcode], digit, operand, &[operand])?;
+        }
+        ("shl" | "sal" | "shr" | "sar", [operand, count]) if is_rm(operand) => {
+            let digit = match mnemonic {
+                "shr" => 5,
+                "sar" => 7,
+                _ => 4,
+            };
+            let width = rm_width(operand)?;
+            let byte = width == Width::Byte;
+            match count {
+                Immediate(value) => {
+                    out.modrm(
+                        None,
+                        width,
+                        &[if byte { 0xc0 } else { 0xc1 }],
+                        digit,
+                        operand,
+                        &[operand],
+                    )?;
+                    out.immediate(*value, 1, width)?;
+                }
+                Register(1, Width::Byte) => {
+                    out.modrm(
+                        None,
+                        width,
+                        &[if byte { 0xd2 } else { 0xd3 }],
+                        digit,
+                        operand,
+                        &[operand],
+                    )?;
+                }
+                _ => return Err(unsupported()),
+            }
+        }
+
+        ("push", [Register(reg, Width::Qword)]) => {
+            out.rex(false, 0, 0, *reg, false);
+            out.bytes(&[0x50 + (reg & 7)]);
+        }
+        ("pop", [Register(reg, Width::Qword)]) => {
+            out.rex(false, 0, 0, *reg, false);
+            out.bytes(&[0x58 + (reg & 7)]);
+        }
+        // push and pop are 64-bit whatever the operand says
+        ("push", [operand @ Memory { .. }]) => {
+            out.modrm(None, Width::Dword, &[0xff], 6, operand, &[])?
+        }
+        ("pop", [operand @ Memory { .. }]) => {
+            out.modrm(None, Width::Dword, &[0x8f], 0, operand, &[])?
+        }
+        ("push", [Immediate(value)]) => {
+            if i8::try_from(*value).is_ok() {
+                out.bytes(&[0x6a]);
+                out.immediate(*value, 1, Width::Qword)?;
+            } else {
+                out.bytes(&[0x68]);
+                out.immediate(*value, 4, Width::Qword)?;
+            }
+        }
+
+        ("call", [Label(target)]) => out.branch(&[0xe8], target),
+        ("jmp", [Label(target)]) => out.branch(&[0xe9], target),
+        ("call" | "jmp", [operand]) if is_rm(operand) => {
+            let digit = if mnemonic == "call" { 2 } else { 4 };
+            out.modrm(None, Width::Dword, &[0xff], digit, operand, &[])?;
+        }
+        (_, [Label(target)]) if let Some(code) = mnemonic.strip_prefix('j').and_then(condition) => {
+            out.branch(&[0x0f, 0x80 + code], target)
+        }
+        (_, [operand])
+            if is_rm(operand)
+                && let Some(code) = mnemonic.strip_prefix("set").and_then(condition) =>
+        {
+            if rm_width(operand)? != Width::Byte {
+                return Err(unsupported());
+            }
+            out.modrm(
+                None,
+                Width::Byte,
+                &[0x0f, 0x90 + code],
+                0,
+                operand,
+                &[operand],
+            )?;
+        }
+        (_, [Register(reg, width), src])
+            if is_rm(src)
+                && *width != Width::Byte
+                && let Some(code) = mnemonic.strip_prefix("cmov").and_then(condition) =>
+        {
+            out.modrm(None, *width, &[0x0f, 0x40 + code], *reg, src, &[])?;
+        }
+
+        ("movd", [Register(xmm, Width::Xmm), src])
+            if is_rm(src) && rm_width(src)? == Width::Dword =>
+        {
+            out.modrm(Some(0x66), Width::Dword, &[0x0f, 0x6e], *xmm, src, &[])?;
+        }
+        ("movd", [dst, Register(xmm, Width::Xmm)])
+            if is_rm(dst) && rm_width(dst)? == Width::Dword =>
+        {
+            out.modrm(Some(0x66), Width::Dword, &[0x0f, 0x7e], *xmm, dst, &[])?;
+        }
+        ("movss", [Register(xmm, Width::Xmm), src]) if is_xmm_or_memory(src) => {
+            out.modrm(Some(0xf3), Width::Dword, &[0x0f, 0x10], *xmm, src, &[])?;
+        }
+        ("movss", [dst @ Memory { .. }, Register(xmm, Width::Xmm)]) => {
+            out.modrm(Some(0xf3), Width::Dword, &[0x0f, 0x11], *xmm, dst, &[])?;
+        }
+        (_, [Register(xmm, Width::Xmm), src])
+            if is_xmm_or_memory(src)
+                && let Some((prefix, opcode)) = sse(mnemonic) =>
+        {
+            out.modrm(prefix, Width::Dword, &[0x0f, opcode], *xmm, src, &[])?;
+        }
+        ("cvtsi2ss", [Register(xmm, Width::Xmm), src]) if is_rm(src) => {
+            let width = rm_width(src)?;
+            out.modrm(Some(0xf3), width, &[0x0f, 0x2a], *xmm, src, &[])?;
+        }
+        ("cvttss2si" | "cvtss2si", [Register(reg, width), src]) if is_xmm_or_memory(src) => {
+            let opcode = if mnemonic == "cvttss2si" { 0x2c } else { 0x2d };
+            out.modrm(Some(0xf3), *width, &[0x0f, opcode], *reg, src, &[])?;
+        }
+
+        _ => return Err(unsupported()),
+    }
+    Ok(out.finish())
+}
+
+fn unsupported() -> String {
+    "this form of the instruction cannot be encoded".to_string()
+}
+
+// A general-purpose register or memory, which a ModRM byte can name
+fn is_rm(arg: &Arg) -> bool {
+    matches!(arg, Arg::Memory { .. })
+        || matches!(arg, Arg::Register(_, width) if *width != Width::Xmm)
+}
+
+fn is_xmm_or_memory(arg: &Arg) -> bool {
+    matches!(arg, Arg::Memory { .. } | Arg::Register(_, Width::Xmm))
+}
+
+fn rm_width(arg: &Arg) -> Result<Width, String> {
+    match arg {
+        Arg::Register(_, width) => Ok(*width),
+        Arg::Memory {
+            size: Some(size), ..
+        } => Ok(match size {
+            Size::Byte => Width::Byte,
+            Size::Dword => Width::Dword,
+            Size::Qword => Width::Qword,
+        }),
+        _ => Err("the operand size is not given".to_string()),
+    }
+}
+
+// spl, bpl, sil and dil are only reachable with a REX prefix, without which their
+// numbers mean ah, ch, dh and bh
+fn byte_needs_rex(args: &[&Arg]) -> bool {
+    args.iter()
+        .any(|arg| matches!(arg, Arg::Register(4..=7, Width::Byte)))
+}
+
+#[derive(Default)]
+struct Encoder {
+    bytes: Vec<u8>,
+    fixups: Vec<(usize, String, i64, FixupKind)>,
+}
+
+impl Encoder {
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.bytes.extend(bytes);
+    }
+
+    fn rex(&mut self, wide: bool, reg: u8, index: u8, base: u8, force: bool) {
+        let rex = 0x40
+            | (wide as u8) << 3
+            | (reg >> 3 & 1) << 2
+            | (index >> 3 & 1) << 1
+            | (base >> 3 & 1);
+        if rex != 0x40 || force {
+            self.bytes.push(rex);
+        }
+    }
+
+    // Prefix, REX, opcode and the ModRM byte with its SIB and displacement, for an
+    // instruction whose operand size is `width` and whose reg field holds `reg` (a
+    // register number or an opcode extension). `byte_operands` are the operands that
+    // may be byte registers needing a REX prefix.
+    fn modrm(
+        &mut self,
+        prefix: Option<u8>,
+        width: Width,
+        opcode: &[u8],
+        reg: u8,
+        rm: &Arg,
+        byte_operands: &[&Arg],
+    ) -> Result<(), String> {
+        let mut byte_args: Vec<&Arg> = byte_operands.to_vec();
+        byte_args.push(rm);
+        let force = byte_needs_rex(&byte_args);
+        let wide = width == Width::Qword;
+        self.bytes.extend(prefix);
+        match rm {
+            Arg::Register(number, _) => {
+                self.rex(wide, reg, 0, *number, force);
+                self.bytes(opcode);
+                self.bytes.push(0xc0 | (reg & 7) << 3 | (number & 7));
+            }
+            Arg::Memory {
+                base: Base::Symbol(symbol),
+                offset,
+                ..
+            } => {
+                self.rex(wide, reg, 0, 0, force);
+                self.bytes(opcode);
+                // mod 00 with r/m 101 is [rip+disp32]
+                self.bytes.push((reg & 7) << 3 | 0b101);
+                self.fixups.push((
+                    self.bytes.len(),
+                    symbol.clone(),
+                    *offset,
+                    FixupKind::Relative,
+                ));
+                self.bytes.extend([0; 4]);
+            }
+            Arg::Memory {
+                base: Base::Register(base),
+                offset,
+                ..
+            } => {
+                self.rex(wide, reg, 0, *base, force);
+                self.bytes(opcode);
+                let displacement = i32::try_from(*offset)
+                    .map_err(|_| "the displacement is too large".to_string())?;
+                // rbp and r13 have no form without a displacement
+                let mode = match displacement {
+                    0 if base & 7 != 5 => 0b00,
+                    d if i8::try_from(d).is_ok() => 0b01,
+                    _ => 0b10,
+                };
+                self.bytes.push(mode << 6 | (reg & 7) << 3 | (base & 7));
+                // rsp and r12 as a base need a SIB byte
+                if base & 7 == 4 {
+                    self.bytes.push(0x24);
+                }
+                match mode {
+                    0b01 => self.bytes.push(displacement as i8 as u8),
+                    0b10 => self.bytes.extend(displacement.to_le_bytes()),
+                    _ => {}
+                }
+            }
+            _ => return Err(unsupported()),
+        }
+        Ok(())
+    }
+
+    // An immediate of `size` bytes; it must fit signed, or unsigned in the operand size
+    fn immediate(&mut self, value: i64, size: usize, width: Width) -> Result<(), String> {
+        let operand_bits = match width {
+            Width::Byte => 8,
+            Width::Dword => 32,
+            _ => 64,
+        };
+        let fits_signed = value >= -(1 << (size * 8 - 1)) && value < 1 << (size * 8 - 1);
+        let fits_unsigned =
+            size * 8 == operand_bits && value >= 0 && (value as u64) < 1u64 << (size * 8);
+        if !fits_signed && !fits_unsigned {
+            return Err(format!("{} does not fit in {} bytes", value, size));
+        }
+        self.bytes.extend(&value.to_le_bytes()[..size]);
+        Ok(())
+    }
+
+    fn branch(&mut self, opcode: &[u8], target: &str) {
+        self.bytes(opcode);
+        self.fixups
+            .push((self.bytes.len(), target.to_string(), 0, FixupKind::Relative));
+        self.bytes.extend([0; 4]);
+    }
+
+    fn finish(self) -> Encoded {
+        Encoded {
+            bytes: self.bytes,
+            fixups: self.fixups,
+        }
+    }
+}
@@ -0,0 +1,129 @@
+use crate::diagnostic::Diagnostic;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+// Everything that can stop a compilation, one variant per stage
+#[derive(Debug)]
+pub enum CompileError {
+    // reading the source or creating the output file
+    Io {
+        path: PathBuf,
+        source: io::Error,
+    },
+    Tokenize(Diagnostic),
+    Parse(Vec<Diagnostic>),
+    Type(Vec<Diagnostic>),
+    // lints set to deny that fired
+    Lint(Vec<Diagnostic>),
+    // a noble.toml that could not be understood, with the line at fault when known
+    Config {
+        path: PathBuf,
+        line: Option<usize>,
+        message: String,
+    },
+    // a target the code generator cannot write code for
+    Target(String),
+    // an instruction or symbol the flat binary encoder cannot handle
+    Encode(String),
+    // writing the generated assembly
+    Codegen(io::Error),
+    // the assembler, linker or the built program could not be run, or failed
+    Toolchain {
+        program: String,
+        message: String,
+    },
+}
+
+impl CompileError {
+    // Errors in the program are 1, problems with the environment are 2
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            CompileError::Tokenize(_)
+            | CompileError::Parse(_)
+            | CompileError::Type(_)
+            | CompileError::Lint(_) => 1,
+            CompileError::Io { .. }
+            | CompileError::Config { .. }
+            | CompileError::Target(_)
+            | CompileError::Encode(_)
+            | CompileError::Codegen(_)
+            | CompileError::Toolchain { .. } => 2,
+        }
+    }
+
+    // The located problems in the program, empty for environment errors
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        match self {
+            CompileError::Tokenize(diagnostic) => std::slice::from_ref(diagnostic),
+            CompileError::Parse(diagnostics)
+            | CompileError::Type(diagnostics)
+            | CompileError::Lint(diagnostics) => diagnostics,
+            CompileError::Io { .. }
+            | CompileError::Config { .. }
+            | CompileError::Target(_)
+            | CompileError::Encode(_)
+            | CompileError::Codegen(_)
+            | CompileError::Toolchain { .. } => &[],
+        }
+    }
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompileError::Io { path, source } => {
+                write!(f, "IOError: {}: {}", path.display(), source)
+            }
+            CompileError::Tokenize(diagnostic) => write!(f, "{}", diagnostic),
+            CompileError::Parse(diagnostics)
+            | CompileError::Type(diagnostics)
+            | CompileError::Lint(diagnostics) => {
+                for (i, diagnostic) in diagnostics.iter().enumerate() {
+                    if i > 0 {
+                        writeln!(f)?;
+                    }
+                    write!(f, "{}", diagnostic)?;
+                }
+                Ok(())
+            }
+            CompileError::Config {
+                path,
+                line: Some(line),
+                message,
+            } => write!(f, "ConfigError: {}:{}: {}", path.display(), line, message),
+            CompileError::Config {
+                path,
+                line: None,
+                message,
+            } => write!(f, "ConfigError: {}: {}", path.display(), message),
+            CompileError::Target(message) => write!(f, "TargetError: {}", message),
+            CompileError::Encode(message) => write!(f, "EncodeError: {}", message),
+            CompileError::Codegen(source) => {
+                write!(f, "CodegenError: unable to write assembly: {}", source)
+            }
+            CompileError::Toolchain { program, message } => {
+                write!(f, "ToolchainError: {}: {}", program, message)
+            }
+        }
+    }
+}
+
+impl Error for CompileError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CompileError::Io { source, .. } | CompileError::Codegen(source) => Some(source),
+            CompileError::Tokenize(diagnostic) => Some(diagnostic),
+            CompileError::Parse(diagnostics)
+            | CompileError::Type(diagnostics)
+            | CompileError::Lint(diagnostics) => {
+                diagnostics.first().map(|d| d as &(dyn Error + 'static))
+            }
+            CompileError::Config { .. }
+            | CompileError::Target(_)
+            | CompileError::Encode(_)
+            | CompileError::Toolchain { .. } => None,
+        }
+    }
+}
@@ -0,0 +1,488 @@
+use crate::arena::Arena;
+use crate::ast::{Ast, BinOpType, Expr, ExprKind, Linkage, Node, NodeId, Stmt, Type};
+use crate::diagnostic::Diagnostic;
+use crate::tokenize::{CONTEXTUAL_KEYWORDS, TokenType, Trivia, TriviaKind, tokenize_lossless};
+
+const INDENT: &str = "    ";
+
+// Canonical layout for Noble source: one statement per line, four-space
+// indentation per brace level, single spaces around operators. Comments are
+// kept, as is a single blank line wherever the input had one or more.
+pub fn format_source(source: &str) -> Result<String, Diagnostic> {
+    let lossless = tokenize_lossless(source)?;
+    let mut formatter = Formatter {
+        out: String::new(),
+        depth: 0,
+        line_start: true,
+        pending_newline: false,
+        previous: None,
+        prefix: false,
+        opener: None,
+        blocks: Vec::new(),
+        closed_block: None,
+        defined_name: false,
+        contextual_keyword: false,
+    };
+
+    for token in &lossless.tokens {
+        let kind = token.token.token_type;
+        formatter.trivia(&token.leading_trivia, Some(kind));
+        formatter.token(kind, &token.text);
+    }
+    formatter.trivia(&lossless.trailing_trivia, None);
+
+    let mut out = formatter.out.trim_end().to_string();
+    if !out.is_empty() {
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+struct Formatter {
+    out: String,
+    depth: usize,
+    line_start: bool,
+    // a statement or brace ended; the next token starts a new line
+    pending_newline: bool,
+    previous: Option<TokenType>,
+    // the previous token was a unary `&`, `*` or `-`, which sits against its operand
+    prefix: bool,
+    // keyword that introduced the block about to open, e.g. `if` or `do`
+    opener: Option<TokenType>,
+    // openers of the braces currently open
+    blocks: Vec<Option<TokenType>>,
+    // opener of the block the last `}` closed
+    closed_block: Option<TokenType>,
+    // the previous token was the name after `define`, which its expression follows
+    // rather than calls or operates on
+    defined_name: bool,
+    // the previous token was `step` after the bounds of a for loop, a keyword rather than
+    // a name there
+    contextual_keyword: bool,
+}
+
+impl Formatter {
+    // Comments and line breaks before `next`, or before the end of the file
+    fn trivia(&mut self, trivia: &[Trivia], next: Option<TokenType>) {
+        let mut newlines = 0;
+        let mut commented = false;
+        for piece in trivia {
+            match piece.kind {
+                TriviaKind::Whitespace => newlines += piece.text.matches('\n').count(),
+                TriviaKind::LineComment => {
+                    // a comment on the same line as code stays there
+                    if newlines == 0 && !self.line_start {
+                        self.out.push(' ');
+                    } else {
+                        self.break_line(newlines);
+                        self.indent();
+                    }
+                    self.out.push_str(piece.text.trim_end());
+                    self.line_start = false;
+                    self.pending_newline = true;
+                    newlines = 0;
+                    commented = true;
+                }
+            }
+        }
+        if self.pending_newline && (commented || !self.continues_line(next)) {
+            self.break_line(newlines);
+        }
+        self.pending_newline = false;
+    }
+
+    fn token(&mut self, kind: TokenType, text: &str) {
+        // where the parser reads it as a keyword: once the bound before it is complete
+        let contextual_keyword = kind == TokenType::Ident
+            && CONTEXTUAL_KEYWORDS.contains(&text)
+            && self.opener == Some(TokenType::For)
+            && self.ends_operand();
+        if kind == TokenType::RBrace {
+            self.depth = self.depth.saturating_sub(1);
+        }
+
+        if self.line_start {
+            self.indent();
+        } else if self.needs_space(kind) {
+            self.out.push(' ');
+        }
+        self.out.push_str(text);
+        self.line_start = false;
+        // `*` only multiplies, and `-` only subtracts, when an operand comes before it
+        self.prefix = kind == TokenType::Amp
+            || (matches!(kind, TokenType::Star | TokenType::Minus)
+                && (self.defined_name || !self.ends_operand()));
+        self.defined_name = kind == TokenType::Ident && self.previous == Some(TokenType::Define);
+        self.contextual_keyword = contextual_keyword;
+
+        match kind {
+            TokenType::LBrace => {
+                self.blocks.push(self.opener.take());
+                self.depth += 1;
+                self.pending_newline = true;
+            }
+            TokenType::RBrace => {
+                self.closed_block = self.blocks.pop().flatten();
+                self.pending_newline = true;
+            }
+            TokenType::Semi => {
+                self.opener = None;
+                self.pending_newline = true;
+            }
+            TokenType::If
+            | TokenType::Else
+            | TokenType::Do
+            | TokenType::While
+            | TokenType::For
+            | TokenType::Fn
+            | TokenType::Match
+            | TokenType::FatArrow => self.opener = Some(kind),
+            _ => {}
+        }
+        self.previous = Some(kind);
+    }
+
+    // Ends the current line unless the next token continues it; `newlines` is
+    // how many line breaks the source had, so blank lines survive as one
+    fn break_line(&mut self, newlines: usize) {
+        if self.line_start {
+            return;
+        }
+        self.pending_newline = false;
+        self.trim_line();
+        self.out.push('\n');
+        if newlines > 1 && self.previous != Some(TokenType::LBrace) {
+            self.out.push('\n');
+        }
+        self.line_start = true;
+    }
+
+    fn indent(&mut self) {
+        for _ in 0..self.depth {
+            self.out.push_str(INDENT);
+        }
+    }
+
+    fn trim_line(&mut self) {
+        let trimmed = self.out.trim_end_matches(' ').len();
+        self.out.truncate(trimmed);
+    }
+
+    // `} else` of an if and the `} while` of a do-while stay on the closing brace's line
+    fn continues_line(&self, next: Option<TokenType>) -> bool {
+        self.previous == Some(TokenType::RBrace)
+            && matches!(
+                (self.closed_block, next),
+                (Some(TokenType::If | TokenType::Else), Some(TokenType::Else))
+                    | (Some(TokenType::Do), Some(TokenType::While))
+            )
+    }
+
+    fn ends_operand(&self) -> bool {
+        !self.contextual_keyword
+            && matches!(
+                self.previous,
+                Some(
+                    TokenType::Ident
+                        | TokenType::IntLit
+                        | TokenType::FloatLit
+                        | TokenType::CharLit
+                        | TokenType::BoolLit
+                        | TokenType::RParen
+                )
+            )
+    }
+
+    fn needs_space(&self, kind: TokenType) -> bool {
+        // `- -x` would read back as `--` followed by x
+        if self.prefix {
+            return self.previous == Some(TokenType::Minus) && kind == TokenType::Minus;
+        }
+        match (self.previous, kind) {
+            _ if self.defined_name => kind != TokenType::Semi,
+            _ if self.contextual_keyword => true,
+            (_, TokenType::Semi)
+            | (_, TokenType::Comma)
+            | (_, TokenType::RParen)
+            | (_, TokenType::PlusPlus | TokenType::MinusMinus)
+            | (Some(TokenType::LParen), _) => false,
+            // calls and conversions: f(x), print_int(x), f32s(x)
+            (
+                Some(TokenType::Ident | TokenType::PrintInt | TokenType::I32S | TokenType::F32S),
+                TokenType::LParen,
+            ) => false,
+            _ => true,
+        }
+    }
+}
+
+// Source text for an AST, already in the layout format_source gives. Parsing it gives
+// the same tree back, apart from spans; comments and blank lines were not kept in the
+// tree, so they are not in the text.
+pub fn unparse(ast: &Ast) -> String {
+    let mut printer = Printer {
+        nodes: &ast.nodes,
+        out: String::new(),
+        depth: 0,
+    };
+    printer.body(&ast.root().children);
+    printer.out
+}
+
+struct Printer<'a> {
+    nodes: &'a Arena<Node>,
+    out: String,
+    depth: usize,
+}
+
+impl Printer<'_> {
+    fn line(&mut self, text: &str) {
+        for _ in 0..self.depth {
+            self.out.push_str(INDENT);
+        }
+        self.out.push_str(text);
+        self.out.push('\n');
+    }
+
+    fn body(&mut self, body: &[NodeId]) {
+        for &id in body {
+            self.statement(id);
+        }
+    }
+
+    fn indented(&mut self, body: &[NodeId]) {
+        self.depth += 1;
+        self.body(body);
+        self.depth -= 1;
+    }
+
+    // `opening {`, the statements of the block one level in, then `closing` on the
+    // brace's line
+    fn block(&mut self, opening: &str, block: NodeId, closing: &str) {
+        let nodes = self.nodes;
+        self.line(&format!("{}{{", opening));
+        self.indented(nodes[block].block());
+        self.line(&format!("}}{}", closing));
+    }
+
+    fn statement(&mut self, id: NodeId) {
+        let nodes = self.nodes;
+        match &nodes[id].stmt {
+            Stmt::Entry => self.body(&nodes[id].children),
+            Stmt::Exit(expr) => self.line(&format!("exit {};", source(expr))),
+            Stmt::PrintInt(expr) => self.line(&format!("print_int({});", source(expr))),
+            Stmt::Assert(expr) => self.line(&format!("assert {};", source(expr))),
+            Stmt::VariableDeclaration {
+                name,
+                type_,
+                value: Some(value),
+            } => self.line(&format!("{} {} = {};", type_, name, source(value))),
+            Stmt::VariableDeclaration {
+                name,
+                type_,
+                value: None,
+            } => self.line(&format!("{} {};", type_, name)),
+            Stmt::VariableAssignment { name, value } => {
+                self.line(&format!("{} = {};", name, source(value)))
+            }
+            Stmt::DerefAssignment { name, value } => {
+                self.line(&format!("*{} = {};", name, source(value)))
+            }
+            Stmt::For {
+                iterator_name,
+                type_,
+                iterator_begin,
+                iterator_end,
+                inclusive,
+                step,
+                body,
+            } => {
+                // i32s is the iterator's type when none is written
+                let declared = match type_ {
+                    Type::I32S => String::new(),
+                    type_ => format!("{} ", type_),
+                };
+                let step = match step {
+                    Some(step) => format!("step {} ", source(step)),
+                    None => String::new(),
+                };
+                let opening = format!(
+                    "for {}{} in {} {} {} {}",
+                    declared,
+                    iterator_name,
+                    source(iterator_begin),
+                    if *inclusive { "to" } else { "until" },
+                    source(iterator_end),
+                    step
+                );
+                self.block(&opening, *body, "");
+            }
+            Stmt::If { .. } => self.if_chain(id),
+            Stmt::Block { .. } => self.block("", id, ""),
+            Stmt::While { condition, body } => {
+                self.block(&format!("while {} ", source(condition)), *body, "")
+            }
+            Stmt::Match {
+                scrutinee,
+                arms,
+                else_body,
+            } => {
+                self.line(&format!("match {} {{", source(scrutinee)));
+                self.depth += 1;
+                for (value, body) in arms {
+                    self.block(&format!("{} => ", value), *body, "");
+                }
+                if let Some(else_body) = else_body {
+                    self.block("else => ", *else_body, "");
+                }
+                self.depth -= 1;
+                self.line("}");
+            }
+            Stmt::DoWhile { body, condition } => {
+                self.block("do ", *body, &format!(" while {};", source(condition)))
+            }
+            Stmt::FunctionDeclaration {
+                name,
+                params,
+                return_type,
+                body,
+                linkage,
+            } => {
+                let params: Vec<String> = params
+                    .iter()
+                    .map(|(name, type_)| format!("{} {}", type_, name))
+                    .collect();
+                let signature = format!(
+                    "{}fn {}({}) -> {}",
+                    match linkage {
+                        Linkage::Internal => "",
+                        Linkage::Extern => "extern ",
+                        Linkage::Export => "export ",
+                    },
+                    name,
+                    params.join(", "),
+                    return_type
+                );
+                match linkage {
+                    Linkage::Extern => self.line(&format!("{};", signature)),
+                    _ => self.block(&format!("{} ", signature), *body, ""),
+                }
+            }
+            Stmt::Return(Some(expr)) => self.line(&format!("return {};", source(expr))),
+            Stmt::Return(None) => self.line("return;"),
+            Stmt::Expression(expr) => self.line(&format!("{};", source(expr))),
+            Stmt::ConstantDeclaration { name, value } => {
+                self.line(&format!("const {} = {};", name, source(&value.to_expr())))
+            }
+            Stmt::Define { name, value } => {
+                self.line(&format!("define {} {};", name, source(value)))
+            }
+        }
+    }
+
+    // An if and its else ifs, each `} else` on the line of the brace it follows
+    fn if_chain(&mut self, mut id: NodeId) {
+        let nodes = self.nodes;
+        let mut opening = "";
+        while let Stmt::If {
+            condition,
+            body,
+            else_body,
+        } = &nodes[id].stmt
+        {
+            self.line(&format!("{}if {} {{", opening, source(condition)));
+            self.indented(nodes[*body].block());
+            match else_body.map(|else_id| (else_id, &nodes[else_id].stmt)) {
+                None => break,
+                Some((else_id, Stmt::If { .. })) => {
+                    id = else_id;
+                    opening = "} else ";
+                    continue;
+                }
+                Some((_, Stmt::Block { body })) => {
+                    self.line("} else {");
+                    self.indented(body);
+                }
+                // the parser only puts an if or a block after else
+                Some((else_id, _)) => {
+                    self.line("} else {");
+                    self.indented(&[else_id]);
+                }
+            }
+            break;
+        }
+        self.line("}");
+    }
+}
+
+// An expression as source, with parentheses only where precedence needs them
+pub fn source(expr: &Expr) -> String {
+    let mut out = String::new();
+    write_expr(&mut out, expr, 0);
+    out
+}
+
+// Binding strength as the parser climbs it: equality, comparison, add, multiply
+fn level(op: &BinOpType) -> usize {
+    match op {
+        BinOpType::Equal | BinOpType::NotEqual => 1,
+        BinOpType::LessThan
+        | BinOpType::LessThanOrEqual
+        | BinOpType::GreaterThan
+        | BinOpType::GreaterThanOrEqual => 2,
+        BinOpType::Add | BinOpType::Subtract => 3,
+        BinOpType::Multiply | BinOpType::Divide => 4,
+    }
+}
+
+// Anything that is not a binary operation is a primary, which binds tightest
+const PRIMARY: usize = 5;
+
+// Writes expr, parenthesized if it binds more loosely than `min`
+fn write_expr(out: &mut String, expr: &Expr, min: usize) {
+    match &expr.kind {
+        ExprKind::BinaryOp { left, op, right } => {
+            let level = level(op);
+            if level < min {
+                out.push('(');
+            }
+            // every operator is left-associative, so a right operand at the same
+            // level needs parentheses
+            write_expr(out, left, level);
+            out.push_str(&format!(" {} ", op));
+            write_expr(out, right, level + 1);
+            if level < min {
+                out.push(')');
+            }
+        }
+        ExprKind::Int(value) => out.push_str(&value.to_string()),
+        ExprKind::Float(value) => out.push_str(&format!("{:?}", value)),
+        ExprKind::Bool(value) => out.push_str(&value.to_string()),
+        ExprKind::Char(value) => out.push_str(&format!("'{}'", value)),
+        ExprKind::Ident(name) => out.push_str(&name.text()),
+        ExprKind::AddressOf(name) => out.push_str(&format!("&{}", name)),
+        ExprKind::Deref(name) => out.push_str(&format!("*{}", name)),
+        ExprKind::Call { name, args } => {
+            let args: Vec<String> = args.iter().map(source).collect();
+            out.push_str(&format!("{}({})", name, args.join(", ")));
+        }
+        ExprKind::Convert { value, to } => out.push_str(&format!("{}({})", to, source(value))),
+        // a negative literal is written with its own `-`, so it is parenthesized too
+        ExprKind::Negate { value } => {
+            out.push('-');
+            let signed = match value.kind {
+                ExprKind::Int(int) => int < 0,
+                ExprKind::Float(float) => float.is_sign_negative(),
+                ExprKind::Negate { .. } => true,
+                _ => false,
+            };
+            if signed {
+                out.push('(');
+                write_expr(out, value, 0);
+                out.push(')');
+            } else {
+                write_expr(out, value, PRIMARY);
+            }
+        }
+    }
+}
@@ -0,0 +1,884 @@
+use crate::ast::{BinOpType, Type};
+use crate::parse::{Associativity, INFIX_OPERATORS, LEVELS};
+use std::collections::HashSet;
+
+// Random Noble programs for differential and crash fuzzing. Every program is valid: it
+// parses, type checks and runs to its end, so a build that fails, a panic or a run that
+// differs from the interpreter is a bug in the compiler rather than in the program. The
+// same seed always gives the same programs, so a failure can be reproduced from it.
+//
+// Binary operators are drawn from parse::INFIX_OPERATORS and grouped by their levels, so
+// a new operator is generated as soon as it parses. Division is by a non-zero literal,
+// loops run a few times and functions only call the ones before them, so nothing traps
+// or runs away.
+
+const TYPES: [Type; 4] = [Type::I32S, Type::F32S, Type::Bool, Type::Char];
+
+// The precedence of a primary expression, above every operator level
+const PRIMARY: u8 = LEVELS.len() as u8 + 1;
+
+// What a name in scope stands for
+#[derive(Clone)]
+enum Meaning {
+    Variable {
+        mutable: bool,
+    },
+    // a ref parameter, read as *p and written by *p = ...
+    Reference,
+    Constant,
+    // the declarations its expression uses, which must be the ones in scope wherever it
+    // is used, and whether it makes calls
+    Define {
+        uses: Vec<(String, usize)>,
+        calls: bool,
+    },
+}
+
+#[derive(Clone)]
+struct Binding {
+    name: String,
+    type_: Type,
+    meaning: Meaning,
+    // tells apart declarations of the same name
+    id: usize,
+}
+
+struct Function {
+    name: String,
+    // Type::Ref for a ref parameter
+    params: Vec<Type>,
+    return_type: Type,
+}
+
+pub struct Generator {
+    state: u64,
+    statement_depth: usize,
+    expression_depth: usize,
+    identifiers: usize,
+    // the bindings of each enclosing scope, innermost last
+    scopes: Vec<Vec<Binding>>,
+    // the functions declared so far, which later code may call
+    functions: Vec<Function>,
+    // the return type of the function being generated
+    returns: Option<Type>,
+    // whether expressions may call functions, which could change what they read
+    calls: bool,
+    // the declarations the expressions generated since it was cleared use, and whether
+    // they make calls
+    used: Vec<(String, usize)>,
+    called: bool,
+    // numbers declarations, and names functions and loop counters
+    counter: usize,
+    out: String,
+    indent: usize,
+}
+
+impl Generator {
+    pub fn new(seed: u64) -> Self {
+        // splitmix64, so that nearby seeds start far apart; xorshift needs a non-zero state
+        let mut z = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        Generator {
+            state: (z ^ (z >> 31)).max(1),
+            statement_depth: 3,
+            expression_depth: 4,
+            identifiers: 6,
+            scopes: Vec::new(),
+            functions: Vec::new(),
+            returns: None,
+            calls: true,
+            used: Vec::new(),
+            called: false,
+            counter: 0,
+            out: String::new(),
+            indent: 0,
+        }
+    }
+
+    // How deeply blocks may nest inside one another
+    pub fn statement_depth(mut self, depth: usize) -> Self {
+        self.statement_depth = depth;
+        self
+    }
+
+    // How deeply operators, calls and conversions may nest inside one another
+    pub fn expression_depth(mut self, depth: usize) -> Self {
+        self.expression_depth = depth;
+        self
+    }
+
+    // How many names variables, parameters, constants and defines share. Fewer names
+    // means more shadowing.
+    pub fn identifiers(mut self, count: usize) -> Self {
+        self.identifiers = count.max(1);
+        self
+    }
+
+    // The next program, different each time
+    pub fn program(&mut self) -> String {
+        self.scopes = vec![Vec::new()];
+        self.functions.clear();
+        self.counter = 0;
+        self.out.clear();
+        for _ in 0..3 + self.below(8) {
+            if self.chance(25) {
+                self.function();
+            } else {
+                self.statement(0);
+            }
+        }
+        if self.chance(70) {
+            let status = self.expression(&Type::I32S);
+            self.line(&format!("exit {};", status));
+        }
+        std::mem::take(&mut self.out)
+    }
+
+    fn next(&mut self) -> u64 {
+        // xorshift64*
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn chance(&mut self, percent: u64) -> bool {
+        self.next() % 100 < percent
+    }
+
+    fn between(&mut self, low: i32, high: i32) -> i32 {
+        low + self.below((high - low + 1) as usize) as i32
+    }
+
+    fn pick<T: Clone>(&mut self, items: &[T]) -> T {
+        items[self.below(items.len())].clone()
+    }
+
+    fn fresh(&mut self, prefix: &str) -> String {
+        self.counter += 1;
+        format!("{}{}", prefix, self.counter)
+    }
+
+    fn line(&mut self, text: &str) {
+        for _ in 0..self.indent {
+            self.out.push_str("    ");
+        }
+        self.out.push_str(text);
+        self.out.push('\n');
+    }
+
+    fn enter(&mut self, bindings: Vec<Binding>) {
+        self.scopes.push(bindings);
+        self.indent += 1;
+    }
+
+    fn leave(&mut self, close: &str) {
+        self.exit_scope();
+        self.line(close);
+    }
+
+    fn exit_scope(&mut self) {
+        self.indent -= 1;
+        self.scopes.pop();
+    }
+
+    fn statements(&mut self, depth: usize) {
+        for _ in 0..self.below(4) {
+            self.statement(depth);
+        }
+    }
+
+    fn bind(&mut self, name: String, type_: Type, meaning: Meaning) -> Binding {
+        self.counter += 1;
+        Binding {
+            name,
+            type_,
+            meaning,
+            id: self.counter,
+        }
+    }
+
+    fn declare(&mut self, binding: Binding) {
+        self.scopes.last_mut().unwrap().push(binding);
+    }
+
+    // A name from the pool not declared in the innermost scope, if one is left
+    fn unused_name(&mut self, taken: &[String]) -> Option<String> {
+        let scope = self.scopes.last().unwrap();
+        let free: Vec<String> = (0..self.identifiers)
+            .map(|i| format!("v{}", i))
+            .filter(|name| !scope.iter().any(|binding| binding.name == *name))
+            .filter(|name| !taken.contains(name))
+            .collect();
+        (!free.is_empty()).then(|| self.pick(&free))
+    }
+
+    // The binding each name in scope refers to, an inner one hiding an outer one
+    fn visible(&self) -> Vec<Binding> {
+        let mut seen = HashSet::new();
+        let mut visible = Vec::new();
+        for binding in self
+            .scopes
+            .iter()
+            .rev()
+            .flat_map(|scope| scope.iter().rev())
+        {
+            if seen.insert(binding.name.clone()) {
+                visible.push(binding.clone());
+            }
+        }
+        visible
+    }
+
+    // A define can be used where every name its expression uses still means the same
+    fn usable(&self, binding: &Binding, visible: &[Binding]) -> bool {
+        match &binding.meaning {
+            Meaning::Define { uses, calls } => {
+                (self.calls || !calls)
+                    && uses.iter().all(|(name, id)| {
+                        visible
+                            .iter()
+                            .any(|other| other.name == *name && other.id == *id)
+                    })
+            }
+            _ => true,
+        }
+    }
+
+    fn assignable(&self, type_: Option<&Type>) -> Vec<Binding> {
+        self.visible()
+            .into_iter()
+            .filter(|binding| matches!(binding.meaning, Meaning::Variable { mutable: true }))
+            .filter(|binding| type_.is_none_or(|type_| binding.type_ == *type_))
+            .collect()
+    }
+
+    fn statement(&mut self, depth: usize) {
+        let nested = depth < self.statement_depth;
+        loop {
+            let done = match self.below(if nested { 24 } else { 16 }) {
+                0..=2 => self.declaration(),
+                3..=4 => self.assignment(),
+                5 => self.increment(),
+                6 => self.parallel_assignment(),
+                7 => self.deref_assignment(),
+                8 => self.call_statement(),
+                9 => {
+                    let value = self.expression(&Type::I32S);
+                    self.line(&format!("print_int({});", value));
+                    true
+                }
+                10 => self.assertion(),
+                11 => self.constant(),
+                12 => self.define(),
+                13 => self.early_exit(),
+                14 => {
+                    let comment = format!("// {}", self.fresh("note "));
+                    self.line(&comment);
+                    true
+                }
+                15 => self.early_return(),
+                16 => {
+                    self.line("{");
+                    self.enter(Vec::new());
+                    self.statements(depth + 1);
+                    self.leave("}");
+                    true
+                }
+                17..=18 => self.if_(depth),
+                19 => self.while_(depth),
+                20 => self.do_while(depth),
+                21..=22 => self.for_(depth),
+                _ => self.match_(depth),
+            };
+            if done {
+                return;
+            }
+        }
+    }
+
+    // `T a = e;`, sometimes with a second variable declared without a value and assigned
+    // right after
+    fn declaration(&mut self) -> bool {
+        let Some(name) = self.unused_name(&[]) else {
+            return false;
+        };
+        let type_ = self.pick(&TYPES);
+        let value = self.expression(&type_);
+        // a name nothing in scope has, since the assignment after it must not read it
+        let second = if self.chance(20) {
+            let mut taken: Vec<String> = self
+                .visible()
+                .into_iter()
+                .map(|binding| binding.name)
+                .collect();
+            taken.push(name.clone());
+            self.unused_name(&taken)
+        } else {
+            None
+        };
+        match &second {
+            Some(other) => self.line(&format!("{} {} = {}, {};", type_, name, value, other)),
+            None => self.line(&format!("{} {} = {};", type_, name, value)),
+        }
+        let binding = self.bind(name, type_.clone(), Meaning::Variable { mutable: true });
+        self.declare(binding);
+        if let Some(other) = second {
+            let value = self.expression(&type_);
+            self.line(&format!("{} = {};", other, value));
+            let binding = self.bind(other, type_, Meaning::Variable { mutable: true });
+            self.declare(binding);
+        }
+        true
+    }
+
+    fn assignment(&mut self) -> bool {
+        let targets = self.assignable(None);
+        if targets.is_empty() {
+            return false;
+        }
+        let target = self.pick(&targets);
+        let value = self.expression(&target.type_);
+        self.line(&format!("{} = {};", target.name, value));
+        true
+    }
+
+    fn increment(&mut self) -> bool {
+        let targets: Vec<Binding> = self
+            .assignable(None)
+            .into_iter()
+            .filter(|binding| matches!(binding.type_, Type::I32S | Type::F32S | Type::Char))
+            .collect();
+        if targets.is_empty() {
+            return false;
+        }
+        let target = self.pick(&targets);
+        let operator = if self.chance(50) { "++" } else { "--" };
+        self.line(&format!("{}{};", target.name, operator));
+        true
+    }
+
+    // `a, b = e, f;`, where f sees a as it was before the statement
+    fn parallel_assignment(&mut self) -> bool {
+        let targets = self.assignable(None);
+        if targets.len() < 2 {
+            return false;
+        }
+        let first = self.pick(&targets);
+        let rest: Vec<Binding> = targets
+            .into_iter()
+            .filter(|binding| binding.name != first.name)
+            .collect();
+        let second = self.pick(&rest);
+        let a = self.expression(&first.type_);
+        let b = self.expression(&second.type_);
+        self.line(&format!("{}, {} = {}, {};", first.name, second.name, a, b));
+        true
+    }
+
+    fn deref_assignment(&mut self) -> bool {
+        let targets: Vec<Binding> = self
+            .visible()
+            .into_iter()
+            .filter(|binding| matches!(binding.meaning, Meaning::Reference))
+            .collect();
+        if targets.is_empty() {
+            return false;
+        }
+        let target = self.pick(&targets);
+        let value = self.expression(&target.type_);
+        self.line(&format!("*{} = {};", target.name, value));
+        true
+    }
+
+    fn call_statement(&mut self) -> bool {
+        let callable: Vec<usize> = (0..self.functions.len())
+            .filter(|&f| self.arguments_possible(f))
+            .collect();
+        if callable.is_empty() {
+            return false;
+        }
+        let f = self.pick(&callable);
+        let call = self.call(f);
+        self.line(&format!("{};", call));
+        true
+    }
+
+    // `assert e == e;`, which holds since e makes no calls that could change what it reads
+    fn assertion(&mut self) -> bool {
+        let type_ = self.pick(&[Type::I32S, Type::Bool, Type::Char]);
+        self.calls = false;
+        let (value, precedence) = self.operand(&type_, self.expression_depth);
+        self.calls = true;
+        let value = match precedence < PRIMARY {
+            true => format!("({})", value),
+            false => value,
+        };
+        self.line(&format!("assert {} == {};", value, value));
+        true
+    }
+
+    // `const k = e;`, where e is literals and constants, small enough not to overflow
+    fn constant(&mut self) -> bool {
+        let Some(name) = self.unused_name(&[]) else {
+            return false;
+        };
+        let type_ = self.pick(&[Type::I32S, Type::Bool, Type::Char]);
+        let constants: Vec<Binding> = self
+            .visible()
+            .into_iter()
+            .filter(|binding| matches!(binding.meaning, Meaning::Constant))
+            .filter(|binding| binding.type_ == type_)
+            .collect();
+        let value = match type_ {
+            _ if !constants.is_empty() && self.chance(30) => self.pick(&constants).name,
+            Type::I32S => {
+                let (a, b) = (self.between(-100, 100), self.between(-100, 100));
+                let op = self.pick(&["+", "-", "*"]);
+                format!("{} {} {}", a, op, b)
+            }
+            Type::Bool => {
+                let (a, b) = (self.between(0, 3), self.between(0, 3));
+                let op = self.pick(&["==", "!=", "<", ">="]);
+                format!("{} {} {}", a, op, b)
+            }
+            _ => self.literal(&type_),
+        };
+        self.line(&format!("const {} = {};", name, value));
+        let binding = self.bind(name, type_, Meaning::Constant);
+        self.declare(binding);
+        true
+    }
+
+    fn define(&mut self) -> bool {
+        let Some(name) = self.unused_name(&[]) else {
+            return false;
+        };
+        let type_ = self.pick(&TYPES);
+        self.used.clear();
+        self.called = false;
+        let value = self.expression(&type_);
+        let uses = std::mem::take(&mut self.used);
+        self.line(&format!("define {} {};", name, value));
+        let meaning = Meaning::Define {
+            uses,
+            calls: self.called,
+        };
+        let binding = self.bind(name, type_, meaning);
+        self.declare(binding);
+        true
+    }
+
+    fn early_exit(&mut self) -> bool {
+        if !self.chance(20) {
+            return false;
+        }
+        let condition = self.expression(&Type::Bool);
+        let status = self.expression(&Type::I32S);
+        self.line(&format!("if {} {{ exit {}; }}", condition, status));
+        true
+    }
+
+    fn early_return(&mut self) -> bool {
+        let Some(return_type) = self.returns.clone() else {
+            return false;
+        };
+        let condition = self.expression(&Type::Bool);
+        match return_type {
+            Type::Void => self.line(&format!("if {} {{ return; }}", condition)),
+            _ => {
+                let value = self.expression(&return_type);
+                self.line(&format!("if {} {{ return {}; }}", condition, value));
+            }
+        }
+        true
+    }
+
+    fn if_(&mut self, depth: usize) -> bool {
+        let condition = self.expression(&Type::Bool);
+        self.line(&format!("if {} {{", condition));
+        loop {
+            self.enter(Vec::new());
+            self.statements(depth + 1);
+            match self.below(4) {
+                0 => {
+                    // the condition is outside the block before it
+                    self.exit_scope();
+                    let condition = self.expression(&Type::Bool);
+                    self.line(&format!("}} else if {} {{", condition));
+                }
+                1 => {
+                    self.leave("} else {");
+                    self.enter(Vec::new());
+                    self.statements(depth + 1);
+                    self.leave("}");
+                    return true;
+                }
+                _ => {
+                    self.leave("}");
+                    return true;
+                }
+            }
+        }
+    }
+
+    // A counter outside the identifier pool, so the body cannot touch it
+    fn while_(&mut self, depth: usize) -> bool {
+        let counter = self.fresh("w");
+        let limit = self.between(0, 4);
+        self.line(&format!("i32s {} = 0;", counter));
+        self.line(&format!("while {} < {} {{", counter, limit));
+        self.enter(Vec::new());
+        self.statements(depth + 1);
+        self.line(&format!("{} = {} + 1;", counter, counter));
+        self.leave("}");
+        true
+    }
+
+    fn do_while(&mut self, depth: usize) -> bool {
+        let counter = self.fresh("w");
+        let limit = self.between(0, 4);
+        self.line(&format!("i32s {} = 0;", counter));
+        self.line("do {");
+        self.enter(Vec::new());
+        self.statements(depth + 1);
+        self.line(&format!("{}++;", counter));
+        self.leave(&format!("}} while {} < {};", counter, limit));
+        true
+    }
+
+    fn for_(&mut self, depth: usize) -> bool {
+        let Some(name) = self.unused_name(&[]) else {
+            return false;
+        };
+        let float = self.chance(25);
+        let (begin, end, step) = if float {
+            let begin = self.between(-4, 4);
+            let end = begin + self.between(-1, 6);
+            let step = self.pick(&["", " step 0.5", " step 1.5"]);
+            (format!("{}.0", begin), format!("{}.", end), step)
+        } else {
+            let begin = self.between(-3, 3);
+            let end = begin + self.between(-1, 5);
+            let step = self.pick(&["", "", " step 2", " step 3"]);
+            (begin.to_string(), end.to_string(), step)
+        };
+        let type_ = match float {
+            true => "f32s ",
+            false => self.pick(&["", "i32s "]),
+        };
+        let range = if self.chance(50) { "to" } else { "until" };
+        self.line(&format!(
+            "for {}{} in {} {} {}{} {{",
+            type_, name, begin, range, end, step
+        ));
+        let iterator_type = if float { Type::F32S } else { Type::I32S };
+        let iterator = self.bind(name, iterator_type, Meaning::Variable { mutable: false });
+        self.enter(vec![iterator]);
+        self.statements(depth + 1);
+        self.leave("}");
+        true
+    }
+
+    fn match_(&mut self, depth: usize) -> bool {
+        let scrutinee = self.expression(&Type::I32S);
+        self.line(&format!("match {} {{", scrutinee));
+        self.indent += 1;
+        let mut values: Vec<i32> = (0..10).collect();
+        for _ in 0..1 + self.below(3) {
+            let value = values.remove(self.below(values.len()));
+            self.line(&format!("{} => {{", value));
+            self.enter(Vec::new());
+            self.statements(depth + 1);
+            self.leave("}");
+        }
+        // an integer match must be exhaustive
+        self.line("else => {");
+        self.enter(Vec::new());
+        self.statements(depth + 1);
+        self.leave("}");
+        self.indent -= 1;
+        self.line("}");
+        true
+    }
+
+    // A function at the top level: extern ones are declared but never called, since
+    // nothing defines them
+    fn function(&mut self) {
+        let name = self.fresh("f");
+        let linkage = match self.below(8) {
+            0 => "extern ",
+            1 | 2 => "export ",
+            _ => "",
+        };
+        let mut params = Vec::new();
+        let mut bindings: Vec<Binding> = Vec::new();
+        for _ in 0..self.below(4) {
+            let taken: Vec<String> = bindings
+                .iter()
+                .map(|binding| binding.name.clone())
+                .collect();
+            self.scopes.push(Vec::new());
+            let unused = self.unused_name(&taken);
+            self.scopes.pop();
+            let Some(param) = unused else {
+                break;
+            };
+            let type_ = self.pick(&TYPES);
+            let binding = match self.chance(30) {
+                true => {
+                    params.push(Type::Ref(Box::new(type_.clone())));
+                    self.bind(param, type_, Meaning::Reference)
+                }
+                false => {
+                    params.push(type_.clone());
+                    self.bind(param, type_, Meaning::Variable { mutable: false })
+                }
+            };
+            bindings.push(binding);
+        }
+        let return_type = match self.below(5) {
+            0 => Type::Void,
+            _ => self.pick(&TYPES),
+        };
+        let list: Vec<String> = params
+            .iter()
+            .zip(&bindings)
+            .map(|(type_, binding)| format!("{} {}", type_, binding.name))
+            .collect();
+        let header = format!(
+            "{}fn {}({}) -> {}",
+            linkage,
+            name,
+            list.join(", "),
+            return_type
+        );
+        if linkage == "extern " {
+            self.line(&format!("{};", header));
+            return;
+        }
+        self.returns = Some(return_type.clone());
+        if return_type != Type::Void && self.chance(25) {
+            self.scopes.push(bindings);
+            let value = self.expression(&return_type);
+            self.scopes.pop();
+            self.line(&format!("{} = {};", header, value));
+        } else {
+            self.line(&format!("{} {{", header));
+            self.enter(bindings);
+            self.statements(0);
+            if return_type != Type::Void {
+                let value = self.expression(&return_type);
+                self.line(&format!("return {};", value));
+            }
+            self.leave("}");
+        }
+        self.returns = None;
+        self.functions.push(Function {
+            name,
+            params,
+            return_type,
+        });
+    }
+
+    fn expression(&mut self, type_: &Type) -> String {
+        self.operand(type_, self.expression_depth).0
+    }
+
+    // An expression of the type and the precedence it binds at
+    fn operand(&mut self, type_: &Type, depth: usize) -> (String, u8) {
+        if depth == 0 || self.chance(30) {
+            return (self.leaf(type_), PRIMARY);
+        }
+        let depth = depth - 1;
+        match self.below(10) {
+            0..=4 => self.binary(type_, depth),
+            5 if matches!(type_, Type::I32S | Type::F32S) => {
+                let (value, precedence) = self.operand(type_, depth);
+                // `- -x` must not become `--x`
+                if precedence < PRIMARY || value.starts_with('-') {
+                    (format!("-({})", value), PRIMARY)
+                } else {
+                    (format!("-{}", value), PRIMARY)
+                }
+            }
+            6 if *type_ == Type::I32S => {
+                let value = self.operand(&Type::F32S, depth).0;
+                (format!("i32s({})", value), PRIMARY)
+            }
+            6 if *type_ == Type::F32S => {
+                let value = self.operand(&Type::I32S, depth).0;
+                (format!("f32s({})", value), PRIMARY)
+            }
+            7 | 8 if self.calls => {
+                let callable: Vec<usize> = (0..self.functions.len())
+                    .filter(|&f| self.functions[f].return_type == *type_)
+                    .filter(|&f| self.arguments_possible(f))
+                    .collect();
+                if callable.is_empty() {
+                    return (self.leaf(type_), PRIMARY);
+                }
+                let f = self.pick(&callable);
+                (self.call_at(f, depth), PRIMARY)
+            }
+            9 => {
+                let value = self.operand(type_, depth).0;
+                (format!("({})", value), PRIMARY)
+            }
+            _ => (self.leaf(type_), PRIMARY),
+        }
+    }
+
+    // Two operands and an operator giving the type, parenthesized only where the
+    // operator's level needs it
+    fn binary(&mut self, type_: &Type, depth: usize) -> (String, u8) {
+        let operators: Vec<usize> = (0..INFIX_OPERATORS.len())
+            .filter(|&i| {
+                let op = &INFIX_OPERATORS[i].op;
+                match type_ {
+                    Type::Bool => !op.is_arithmetic(),
+                    Type::I32S | Type::F32S | Type::Char => op.is_arithmetic(),
+                    _ => false,
+                }
+            })
+            .collect();
+        let operator = &INFIX_OPERATORS[self.pick(&operators)];
+        let operand_type = match operator.op {
+            _ if operator.op.is_arithmetic() => type_.clone(),
+            BinOpType::Equal | BinOpType::NotEqual => self.pick(&TYPES),
+            _ => self.pick(&[Type::I32S, Type::F32S, Type::Char]),
+        };
+        let left = self.operand(&operand_type, depth);
+        let right = match operator.op {
+            BinOpType::Divide if operand_type != Type::F32S => {
+                (self.nonzero(&operand_type), PRIMARY)
+            }
+            _ => self.operand(&operand_type, depth),
+        };
+        let precedence = operator.precedence;
+        // the side the level groups toward may hold the same level unparenthesized
+        let (left_min, right_min) = match operator.level().associativity {
+            Associativity::Left => (precedence, precedence + 1),
+            Associativity::Right => (precedence + 1, precedence),
+        };
+        let wrap = |(text, inner): (String, u8), min: u8| match inner < min {
+            true => format!("({})", text),
+            false => text,
+        };
+        let spelling = operator.token.spelling().unwrap();
+        (
+            format!(
+                "{} {} {}",
+                wrap(left, left_min),
+                spelling,
+                wrap(right, right_min)
+            ),
+            precedence,
+        )
+    }
+
+    fn leaf(&mut self, type_: &Type) -> String {
+        let visible = self.visible();
+        let named: Vec<Binding> = visible
+            .iter()
+            .filter(|binding| binding.type_ == *type_ && self.usable(binding, &visible))
+            .cloned()
+            .collect();
+        if named.is_empty() || self.chance(35) {
+            return self.literal(type_);
+        }
+        let binding = self.pick(&named);
+        match &binding.meaning {
+            // a constant is replaced by its value, so nothing of it is left to capture
+            Meaning::Constant => {}
+            Meaning::Define { uses, calls } => {
+                self.used.extend(uses.iter().cloned());
+                self.called |= calls;
+            }
+            _ => self.used.push((binding.name.clone(), binding.id)),
+        }
+        match binding.meaning {
+            Meaning::Reference => format!("*{}", binding.name),
+            _ => binding.name,
+        }
+    }
+
+    fn literal(&mut self, type_: &Type) -> String {
+        match type_ {
+            Type::I32S => match self.below(12) {
+                0 => format!("0x{:x}", self.between(0, 4095)),
+                1 => format!("0b{:b}", self.between(0, 63)),
+                2 => format!("0o{:o}", self.between(0, 511)),
+                3 => format!("{}_000", self.between(1, 99)),
+                4 => format!("{}i32", self.between(0, 99)),
+                5 => self
+                    .pick(&["2147483647", "-2147483648", "65536", "-1"])
+                    .to_string(),
+                _ => self.between(-20, 20).to_string(),
+            },
+            Type::F32S => {
+                let (whole, tenths) = (self.between(-20, 20), self.between(0, 9));
+                match self.below(6) {
+                    0 => format!(".{}", tenths),
+                    1 => format!("{}.", whole),
+                    2 => format!("{}.{}e-1", whole, tenths),
+                    3 => format!("{}f32", whole.abs()),
+                    4 => format!("{}.{}f32", whole, tenths),
+                    _ => format!("{}.{}", whole, tenths),
+                }
+            }
+            Type::Bool => self.pick(&["true", "false"]).to_string(),
+            _ => format!("'{}'", char::from(self.between(0x20, 0x7e) as u8)),
+        }
+    }
+
+    // A literal divisor
+    fn nonzero(&mut self, type_: &Type) -> String {
+        match type_ {
+            Type::Char => format!("'{}'", char::from(self.between(0x21, 0x7e) as u8)),
+            _ => {
+                let divisor = self.between(1, 9);
+                match self.chance(25) {
+                    true => format!("-{}", divisor),
+                    false => divisor.to_string(),
+                }
+            }
+        }
+    }
+
+    // Whether every ref parameter of the function has a variable it can refer to
+    fn arguments_possible(&self, f: usize) -> bool {
+        self.functions[f].params.iter().all(|param| match param {
+            Type::Ref(inner) => !self.assignable(Some(inner)).is_empty(),
+            _ => true,
+        })
+    }
+
+    fn call(&mut self, f: usize) -> String {
+        self.call_at(f, self.expression_depth.saturating_sub(1))
+    }
+
+    fn call_at(&mut self, f: usize, depth: usize) -> String {
+        self.called = true;
+        let params = self.functions[f].params.clone();
+        let mut args = Vec::new();
+        for param in &params {
+            let arg = match param {
+                Type::Ref(inner) => {
+                    let targets = self.assignable(Some(inner));
+                    let target = self.pick(&targets);
+                    self.used.push((target.name.clone(), target.id));
+                    format!("&{}", target.name)
+                }
+                _ => self.operand(param, depth).0,
+            };
+            args.push(arg);
+        }
+        format!("{}({})", self.functions[f].name, args.join(", "))
+    }
+}
@@ -1,317 +1,1521 @@
-use crate::parse::{AbstractSyntaxTreeNode, AbstractSyntaxTreeSymbol, BinOpType, Expr};
-use std::collections::HashSet;
-use std::fs::File;
-use std::io::BufWriter;
-use std::io::Write;
+use crate::arena::Arena;
+use crate::asm::{self, Line, Size, Syntax};
+use crate::ast::{Ast, BinOpType, Expr, ExprKind, Linkage, Node, NodeId, Stmt, Type};
+use crate::intern::Name;
+use crate::optimize::PassManager;
+use crate::runtime;
+use crate::target::{ArgumentLocation, TARGETS, TargetSpec};
+use crate::tokenize::Span;
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+// Noble functions take their first four arguments in the registers Windows x64 uses,
+// on every target, and the rest on the stack. Extern and export functions use the C
+// calling convention of the target instead.
+const ARG_REGISTERS: [&str; 4] = ["rcx", "rdx", "r8", "r9"];
+const ARG_REGISTERS_32: [&str; 4] = ["ecx", "edx", "r8d", "r9d"];
+
+// Words NASM reads as part of an operand rather than as a symbol
+const ASSEMBLER_KEYWORDS: [&str; 12] = [
+    "byte", "word", "dword", "qword", "near", "far", "short", "rel", "abs", "strict", "wrt", "seg",
+];
+
+// name, parameters, body, span and the clock reading where it was declared
+type PendingFunction = (Name, Vec<(Name, Type)>, NodeId, Span, usize);
+// Where the generator was when a scope was entered, restored when it ends
+struct Scope {
+    id: usize,
+    frame_offset: i32,
+    // length of the list of replaced bindings
+    replaced: usize,
+}
+// What a declaration in an inner scope changed, undone when that scope ends: the
+// slot a local name had before, if any, or the global binding it opened
+enum Binding {
+    Local(Name, Option<(i32, Type)>),
+    Global(Name),
+}
+
+// A global variable or loop iterator in scope from one clock reading until another
+struct GlobalBinding {
+    symbol: String,
+    from: usize,
+    until: Option<usize>,
+}
+// parameter types and return type
+type Signature = (Vec<Type>, Type);
+
+// The stack frame of a routine the generator writes, from the stack pointer it was entered
+// with down: the return address if it was called, the callee-saved registers it keeps,
+// the caller's rbp, which rbp then points at, a slot for each parameter, which the
+// prologue spills there from its register or copies from the caller's stack, and one for
+// each local in scope at once, padded so that rsp ends up 16-byte aligned
+struct Frame {
+    called: bool,
+    saved: &'static [&'static str],
+    parameters: usize,
+    locals: usize,
+}
+
+impl Frame {
+    // Bytes the prologue moves rsp down by below rbp
+    fn size(&self) -> usize {
+        let above = self.arguments();
+        (above + 8 * (self.parameters + self.locals)).next_multiple_of(16) - above
+    }
+
+    // Offset from rbp of the first argument the caller left on the stack
+    fn arguments(&self) -> usize {
+        8 * (usize::from(self.called) + self.saved.len() + 1)
+    }
+
+    // Offset from rbp of the slot of parameter `i`; the locals come after the last one
+    fn parameter(i: usize) -> i32 {
+        -8 * (i as i32 + 1)
+    }
+}
 
 pub struct Generator {
-    declared_vars: HashSet<String>,
+    // source text, split into lines for the "; line N:" annotations
+    source_lines: Vec<String>,
+    lines: Vec<Line>,
+    // in order of first declaration so the .bss layout is the same on every run,
+    // each with the largest size any declaration of that name needs
+    declared_vars: Vec<(String, Size)>,
+    // position of each symbol in declared_vars
+    declared_index: HashMap<String, usize>,
+    // size of the type of each global's declaration in effect
+    global_sizes: HashMap<String, Size>,
+    // every binding of each global variable and loop iterator name, in declaration
+    // order. The clock ticks as they open and close, so a function body generated
+    // after main still sees the globals that were in scope where it was declared.
+    globals: HashMap<Name, Vec<GlobalBinding>>,
+    clock: usize,
+    // the clock reading where the function being generated was declared
+    declared_at: Option<usize>,
+    // (symbol, source name, declaration) of every global, for the source map
+    global_symbols: Vec<(String, String, Span)>,
+    // innermost scope being generated, 0 for the top level of the program, and the
+    // number of scopes entered so far
+    scope: usize,
+    scope_count: usize,
+    replaced: Vec<Binding>,
+    // rbp-relative slots of the parameters and locals of the function being generated,
+    // with the type of the value stored there
+    locals: HashMap<Name, (i32, Type)>,
+    // function whose body is being generated, and the lowest slot used so far
+    current_function: Option<Name>,
+    frame_offset: i32,
+    functions: Vec<PendingFunction>,
+    // extern functions called with the C convention, and the functions that get a
+    // C-callable wrapper under their own name
+    extern_functions: Vec<(Name, Signature)>,
+    exports: Vec<(Name, Signature)>,
+    entry: String,
+    // the calling convention, and whether the runtime is the C one or the kernel's
+    target: &'static TargetSpec,
+    // routines of the runtime module the program calls or jumps to, in order of first use
+    runtime_routines: Vec<&'static str>,
+    // (label, text) of the message printed by each runtime check that fails
+    failure_messages: Vec<(String, String)>,
+    // release builds leave asserts out
+    asserts: bool,
+    // trap on signed overflow and zero divisors instead of wrapping or faulting
+    checked_arithmetic: bool,
+    // input file name and position of the statement being generated, for failure messages
+    // and the source map
+    file: String,
+    span: Span,
+    // fresh label suffix, per generator so numbering only depends on the program
+    label_count: usize,
 }
 
 impl Generator {
-    pub fn new() -> Self {
+    pub fn new(file: &str, source: &str, entry: &str, target: &'static TargetSpec) -> Self {
         Self {
-            declared_vars: HashSet::new(),
+            source_lines: source.lines().map(|l| l.trim().to_string()).collect(),
+            lines: Vec::new(),
+            declared_vars: Vec::new(),
+            declared_index: HashMap::new(),
+            global_sizes: HashMap::new(),
+            globals: HashMap::new(),
+            clock: 0,
+            declared_at: None,
+            global_symbols: Vec::new(),
+            scope: 0,
+            scope_count: 0,
+            replaced: Vec::new(),
+            locals: HashMap::new(),
+            current_function: None,
+            frame_offset: 0,
+            functions: Vec::new(),
+            extern_functions: Vec::new(),
+            exports: Vec::new(),
+            entry: entry.to_string(),
+            target,
+            runtime_routines: Vec::new(),
+            failure_messages: Vec::new(),
+            asserts: true,
+            checked_arithmetic: false,
+            file: file.to_string(),
+            span: Span::default(),
+            label_count: 0,
         }
     }
 
-    pub fn generate_boilerplate(&mut self, writer: &mut BufWriter<&File>) {
-        write!(
-            writer,
-            "{}",
-            "bits 64\ndefault rel\n\nsegment .text\nglobal mainCRTStartup\n\nmainCRTStartup:\n"
-        )
-        .expect("Unable to write to file.");
+    pub fn generate_boilerplate(&mut self) {
+        self.lines.push(Line::Section(".text".to_string()));
+        self.lines.push(Line::Global(self.entry.clone()));
+        self.lines.push(Line::Blank);
+        self.label(&self.entry.clone());
+        // Nothing calls a freestanding program's entry point: it starts with no return
+        // address on the stack, and clears rbp to end the chain of frames a debugger
+        // walks. Every program ends in the runtime's exit, so this frame is never taken
+        // down.
+        let called = !self.target.freestanding;
+        if !called {
+            self.emit("xor ebp, ebp");
+        }
+        self.prologue(&Frame {
+            called,
+            saved: &[],
+            parameters: 0,
+            locals: 0,
+        });
     }
 
-    pub fn generate_x64(
-        &mut self,
-        ast_root: &AbstractSyntaxTreeNode,
-        writer: &mut BufWriter<&File>,
-    ) {
-        match &ast_root.symbol {
-            AbstractSyntaxTreeSymbol::AbstractSyntaxTreeSymbolEntry => {
-                ast_root
-                    .children
-                    .iter()
-                    .map(|child| self.generate_x64(child, writer))
-                    .for_each(drop);
+    pub fn set_asserts(&mut self, enabled: bool) {
+        self.asserts = enabled;
+    }
 
-                writeln!(writer, "    ret").unwrap();
+    pub fn set_checked_arithmetic(&mut self, enabled: bool) {
+        self.checked_arithmetic = enabled;
+    }
+
+    // Returns the number of bytes written
+    pub fn write(&self, writer: &mut impl Write, syntax: Syntax) -> io::Result<usize> {
+        asm::write_program(&self.lines, syntax, writer)
+    }
+
+    // The program as generated so far, for analyses such as the control-flow graph
+    pub fn lines(&self) -> &[Line] {
+        &self.lines
+    }
+
+    // For callers that rewrite the program between generation and the assembly passes
+    pub fn lines_mut(&mut self) -> &mut Vec<Line> {
+        &mut self.lines
+    }
+
+    pub fn instruction_count(&self) -> usize {
+        self.lines
+            .iter()
+            .filter(|line| matches!(line, Line::Instruction { .. }))
+            .count()
+    }
+
+    fn emit(&mut self, instruction: &str) {
+        self.lines.push(asm::parse_instruction(instruction));
+    }
+
+    fn declare_var(&mut self, name: &str, size: Size) {
+        match self.declared_index.get(name) {
+            Some(&i) => {
+                let reserved = &mut self.declared_vars[i].1;
+                if reserved.bytes() < size.bytes() {
+                    *reserved = size;
+                }
+            }
+            None => {
+                self.declared_index
+                    .insert(name.to_string(), self.declared_vars.len());
+                self.declared_vars.push((name.to_string(), size));
+            }
+        }
+        self.global_sizes.insert(name.to_string(), size);
+    }
+
+    // Shared by every construct that needs fresh labels (if/else, while, do-while, match)
+    fn next_label_id(&mut self) -> usize {
+        let current = self.label_count;
+        self.label_count += 1;
+        current
+    }
+
+    fn label(&mut self, name: &str) {
+        self.lines.push(Line::Label(name.to_string()));
+    }
+
+    fn annotate(&mut self, span: Span) {
+        if span.line == 0 {
+            return;
+        }
+        if let Some(text) = self.source_lines.get(span.line - 1) {
+            let comment = Line::Comment(format!("line {}: {}", span.line, text));
+            // a line is annotated once, even when it holds several statements or one
+            // the optimizer split in two, like a for loop with its bound hoisted
+            let previous = self
+                .lines
+                .iter()
+                .rev()
+                .find(|line| matches!(line, Line::Comment(_)));
+            if previous != Some(&comment) {
+                self.lines.push(comment);
+            }
+        }
+    }
+
+    pub fn generate_x64(&mut self, ast: &Ast) {
+        self.generate_node(&ast.nodes, ast.root);
+    }
+
+    fn generate_node(&mut self, nodes: &Arena<Node>, id: NodeId) {
+        let ast_root = &nodes[id];
+        // function bodies are annotated where they are emitted, after main
+        if !matches!(ast_root.stmt, Stmt::FunctionDeclaration { .. }) {
+            self.annotate(ast_root.span);
+        }
+        let enclosing = self.span;
+        if ast_root.span.line > 0 {
+            self.locate(ast_root.span);
+        }
+        self.generate_statement(nodes, ast_root);
+        if self.span != enclosing {
+            self.locate(enclosing);
+        }
+    }
+
+    // Attributes the instructions that follow to a source position, for the source map
+    fn locate(&mut self, span: Span) {
+        self.span = span;
+        self.lines.push(Line::Location {
+            line: span.line,
+            column: span.column,
+        });
+    }
+
+    // Runs the assembly passes of the -O level over everything generated so far
+    pub fn optimize(&mut self, passes: &mut PassManager) {
+        passes.run_asm(&mut self.lines);
+    }
+
+    // (assembly line, source line, source column) of each instruction written by write()
+    pub fn source_map(&self, syntax: Syntax) -> Vec<(usize, usize, usize)> {
+        asm::source_locations(&self.lines, syntax)
+    }
+
+    // (assembly symbol, source name, declaration) of each global variable and loop iterator
+    pub fn global_symbols(&self) -> &[(String, String, Span)] {
+        &self.global_symbols
+    }
+
+    fn generate_statement(&mut self, nodes: &Arena<Node>, ast_root: &Node) {
+        match &ast_root.stmt {
+            Stmt::Entry => {
+                for &child in &ast_root.children {
+                    self.generate_node(nodes, child);
+                }
+
+                // falling off the end of the program exits with 0
+                self.emit("mov eax, 0");
+                // every exit statement jumps here with its status in eax, from any depth
+                // of loops and calls; the runtime ends the process without returning
+                self.label("program.exit");
+                self.call_runtime(runtime::EXIT);
+
+                // function bodies live after main so control never falls into them
+                let functions = std::mem::take(&mut self.functions);
+                for (name, params, body, span, declared_at) in functions {
+                    self.declared_at = Some(declared_at);
+                    self.generate_function(nodes, name, &params, body, span);
+                }
+                self.declared_at = None;
+                for (name, signature) in std::mem::take(&mut self.exports) {
+                    self.generate_export(name, &signature);
+                }
+                self.generate_externs_and_data();
 
                 if !self.declared_vars.is_empty() {
-                    writeln!(writer, "\nsegment .bss").unwrap();
-                    for var in &self.declared_vars {
-                        writeln!(writer, "{} resd 1", var).unwrap();
+                    self.lines.push(Line::Blank);
+                    self.lines.push(Line::Section(".bss".to_string()));
+                    for (var, size) in &self.declared_vars {
+                        self.lines.push(Line::Reserve {
+                            name: var.clone(),
+                            size: *size,
+                            count: 1,
+                        });
                     }
                 }
             }
 
-            AbstractSyntaxTreeSymbol::AbstractSyntaxTreeSymbolExit(expr) => match expr {
-                Expr::Int(i) => {
-                    writeln!(writer, "    mov eax, {}", i).unwrap();
+            Stmt::Exit(expr) => {
+                match &expr.kind {
+                    ExprKind::Call { name, args } => self.generate_call(*name, args),
+                    ExprKind::BinaryOp { left, op, right } => {
+                        self.generate_binary_op(left, op, right)
+                    }
+                    _ => self.generate_expr_into_register(expr, "eax"),
                 }
-                Expr::Ident(j) => {
-                    writeln!(writer, "    mov eax, dword [{}]", j).expect("Idek");
+                self.emit("jmp program.exit");
+            }
+
+            Stmt::Return(expr) => {
+                if let Some(expr) = expr {
+                    self.generate_expr_into_register(expr, "eax");
                 }
-                Expr::Float(f) => {
-                    let bits = f.to_bits();
-                    writeln!(writer, "    mov eax, {}", bits).unwrap();
+                let function = self.current_function.expect("return outside of a function");
+                self.emit(&format!("jmp fn.{}.return", function));
+            }
+
+            Stmt::Expression(expr) => {
+                // the result is left in eax and ignored
+                self.generate_expr_into_register(expr, "eax");
+            }
+
+            Stmt::PrintInt(expr) => {
+                self.generate_expr_into_register(expr, "eax");
+                self.call_runtime(runtime::PRINT_INT);
+            }
+
+            Stmt::Assert(expr) => {
+                if self.asserts {
+                    self.generate_assert(expr);
                 }
-                Expr::Bool(b) => {
-                    let val = if *b { 1 } else { 0 };
-                    writeln!(writer, "    mov eax, {}", val).unwrap();
+            }
+
+            Stmt::VariableDeclaration { name, type_, value } => {
+                // `i32s x = x + 1;` reads the x it shadows, so a value that names the
+                // variable is worked out before the new one is bound
+                let reads_shadowed = value.as_ref().is_some_and(|value| {
+                    value
+                        .walk()
+                        .any(|visit| visit.expr.variable() == Some(*name))
+                });
+                if reads_shadowed {
+                    self.generate_expr_into_register(value.as_ref().unwrap(), "eax");
                 }
-                Expr::Char(c) => {
-                    writeln!(writer, "    mov eax, {}", *c as u32).unwrap();
+                let size = storage_size(type_);
+                if self.current_function.is_none() {
+                    let symbol = self.global_symbol(*name);
+                    self.declare_var(&symbol, size);
+                    self.bind_global(*name, symbol);
+                } else {
+                    let offset = self.allocate_local();
+                    self.bind_local(*name, (offset, type_.clone()));
                 }
-                Expr::BinaryOp { left, op, right } => {
-                    self.generate_binary_op(left, op, right, writer);
+                match value {
+                    Some(_) if reads_shadowed => self.store_variable(*name),
+                    Some(value) => self.match_variable_helper(*name, value),
+                    None => {}
                 }
-            },
+            }
 
-            AbstractSyntaxTreeSymbol::AbstractSyntaxTreeSymbolVariableDeclaration {
-                name,
-                type_: _type_,
-                value,
-            } => {
-                self.declared_vars.insert(name.clone());
-                self.match_variable_helper(name, value, writer);
+            Stmt::VariableAssignment { name, value } => {
+                self.match_variable_helper(*name, value);
             }
 
-            AbstractSyntaxTreeSymbol::AbstractSyntaxTreeSymbolVariableAssignment {
-                name,
-                value,
-            } => {
-                self.match_variable_helper(name, value, writer);
+            Stmt::DerefAssignment { name, value } => {
+                self.generate_expr_into_register(value, "eax");
+                self.load_variable("ebx", *name);
+                let size = self.pointee_size(*name);
+                self.emit(&format!(
+                    "mov {} [rbx], {}",
+                    size.keyword(),
+                    sized_register("eax", size)
+                ));
             }
 
-            AbstractSyntaxTreeSymbol::AbstractSyntaxTreeSymbolFor {
+            Stmt::For {
                 iterator_name,
+                type_,
                 iterator_begin,
                 iterator_end,
+                inclusive,
+                step,
                 body,
             } => {
-                self.declared_vars.insert(iterator_name.clone());
-
-                let loop_label = format!("loop_begin_{}", iterator_name);
-                let end_label = format!("loop_end_{}", iterator_name);
+                let id = self.next_label_id();
+                let scope = self.enter_scope();
+                // each loop gets its own slot so it cannot clobber an outer variable
+                let offset = self
+                    .current_function
+                    .is_some()
+                    .then(|| self.allocate_local());
+                let address = match offset {
+                    Some(offset) => format!("rbp{}", offset),
+                    None => {
+                        let symbol = self.global_symbol(*iterator_name);
+                        self.declare_var(&symbol, Size::Dword);
+                        symbol
+                    }
+                };
+                let slot = format!("dword [{}]", address);
+                let float = *type_ == Type::F32S;
 
-                self.generate_expr_into_register(iterator_begin, "eax", writer);
-                writeln!(writer, "    mov dword [{}], eax", iterator_name).unwrap();
+                let loop_label = format!("loop_begin.{}", id);
+                let end_label = format!("loop_end.{}", id);
 
-                writeln!(writer, "{}:", loop_label).unwrap();
+                self.generate_expr_into_register(iterator_begin, "eax");
+                self.emit(&format!("mov {}, eax", slot));
 
-                writeln!(writer, "    mov eax, dword [{}]", iterator_name).unwrap();
-                self.generate_expr_into_register(iterator_end, "ebx", writer);
-                writeln!(writer, "    cmp eax, ebx").unwrap();
-                writeln!(writer, "    jg {}", end_label).unwrap();
+                self.label(&loop_label);
 
-                for stmt in body {
-                    self.generate_x64(stmt, writer);
+                // the bound is evaluated before every iteration, outside the iterator's
+                // scope, and first since computing it may use eax
+                self.generate_expr_into_register(iterator_end, "ebx");
+                self.emit(&format!("mov eax, {}", slot));
+                // "to" runs while i <= end, "until" while i < end
+                if float {
+                    // comparing the bound with the iterator sets CF when the bound is
+                    // smaller or either is NaN, so a NaN ends the loop
+                    self.emit("movd xmm0, eax");
+                    self.emit("movd xmm1, ebx");
+                    self.emit("comiss xmm1, xmm0");
+                    let exit_jump = if *inclusive { "jb" } else { "jbe" };
+                    self.emit(&format!("{} {}", exit_jump, end_label));
+                } else {
+                    self.emit("cmp eax, ebx");
+                    let exit_jump = if *inclusive { "jg" } else { "jge" };
+                    self.emit(&format!("{} {}", exit_jump, end_label));
+                }
+                // the iterator is only in scope for the body, not the step after it
+                let iteration = self.enter_scope();
+                match offset {
+                    Some(offset) => self.bind_local(*iterator_name, (offset, type_.clone())),
+                    None => self.bind_global(*iterator_name, address.clone()),
                 }
 
-                writeln!(writer, "    mov eax, dword [{}]", iterator_name).unwrap();
-                writeln!(writer, "    inc eax").unwrap();
-                writeln!(writer, "    mov dword [{}], eax", iterator_name).unwrap();
+                self.generate_node(nodes, *body);
+                self.exit_scope(iteration);
+
+                match (step, float) {
+                    (None, false) => {
+                        self.emit(&format!("mov eax, {}", slot));
+                        self.emit("inc eax");
+                    }
+                    // the iterator wraps like inc does, even with checked arithmetic
+                    (Some(step), false) => {
+                        self.generate_expr_into_register(step, "ebx");
+                        self.emit(&format!("mov eax, {}", slot));
+                        self.emit("add eax, ebx");
+                    }
+                    (step, true) => {
+                        match step {
+                            Some(step) => self.generate_expr_into_register(step, "ebx"),
+                            None => self.emit(&format!("mov ebx, {}", 1.0f32.to_bits())),
+                        }
+                        self.emit(&format!("mov eax, {}", slot));
+                        self.generate_float_arithmetic(&BinOpType::Add);
+                    }
+                }
+                self.emit(&format!("mov {}, eax", slot));
 
-                writeln!(writer, "    jmp {}", loop_label).unwrap();
+                self.emit(&format!("jmp {}", loop_label));
 
-                writeln!(writer, "{}:", end_label).unwrap();
+                self.label(&end_label);
+                self.exit_scope(scope);
             }
 
-            AbstractSyntaxTreeSymbol::AbstractSyntaxTreeSymbolIf {
+            Stmt::If {
                 condition,
                 body,
                 else_body,
             } => {
-                self.generate_if(condition, body, else_body, writer);
+                self.generate_if(nodes, condition, *body, *else_body);
+            }
+
+            Stmt::While { condition, body } => {
+                let id = self.next_label_id();
+                let loop_label = format!("while_begin.{}", id);
+                let end_label = format!("while_end.{}", id);
+
+                self.label(&loop_label);
+                self.generate_expr_into_register(condition, "eax");
+                self.emit("cmp eax, 0");
+                self.emit(&format!("je {}", end_label));
+
+                self.generate_node(nodes, *body);
+
+                self.emit(&format!("jmp {}", loop_label));
+                self.label(&end_label);
             }
 
-            AbstractSyntaxTreeSymbol::AbstractSyntaxTreeSymbolBlock { body } => {
-                for stmt in body {
-                    self.generate_x64(stmt, writer);
+            Stmt::DoWhile { body, condition } => {
+                let loop_label = format!("do_begin.{}", self.next_label_id());
+
+                self.label(&loop_label);
+                self.generate_node(nodes, *body);
+
+                // condition is checked at the bottom, after the first pass
+                self.generate_expr_into_register(condition, "eax");
+                self.emit("cmp eax, 0");
+                self.emit(&format!("jne {}", loop_label));
+            }
+
+            Stmt::Match {
+                scrutinee,
+                arms,
+                else_body,
+            } => {
+                self.generate_match(nodes, scrutinee, arms, *else_body);
+            }
+
+            Stmt::Block { body } => self.generate_body(nodes, body),
+
+            Stmt::FunctionDeclaration {
+                name,
+                params,
+                return_type,
+                body,
+                linkage,
+            } => {
+                let signature = (
+                    params.iter().map(|(_, type_)| type_.clone()).collect(),
+                    return_type.clone(),
+                );
+                match linkage {
+                    Linkage::Extern => {
+                        self.extern_functions.push((*name, signature));
+                        return;
+                    }
+                    Linkage::Export => self.exports.push((*name, signature)),
+                    Linkage::Internal => {}
                 }
+                self.functions
+                    .push((*name, params.clone(), *body, ast_root.span, self.clock));
+            }
+
+            // every use already holds the value, so nothing is stored
+            Stmt::ConstantDeclaration { .. } | Stmt::Define { .. } => {}
+        }
+    }
+
+    // Where a variable is stored and how many bytes it takes
+    fn address(&self, name: Name) -> (String, Size) {
+        match self.locals.get(&name) {
+            Some((offset, type_)) => (format!("rbp{}", offset), storage_size(type_)),
+            None => {
+                let storage = self
+                    .global(name)
+                    .map_or_else(|| name.to_string(), str::to_string);
+                let size = self
+                    .global_sizes
+                    .get(&storage)
+                    .copied()
+                    .unwrap_or(Size::Dword);
+                (storage, size)
+            }
+        }
+    }
+
+    // The memory operand holding a variable, sized to its type
+    fn variable(&self, name: Name) -> (String, Size) {
+        let (address, size) = self.address(name);
+        (format!("{} [{}]", size.keyword(), address), size)
+    }
+
+    // Loads a variable into a 32-bit register, zero-extending bytes; references
+    // fill the whole 64-bit register
+    fn load_variable(&mut self, reg: &str, name: Name) {
+        match self.variable(name) {
+            (operand, Size::Byte) => self.emit(&format!("movzx {}, {}", reg, operand)),
+            (operand, size) => {
+                self.emit(&format!("mov {}, {}", sized_register(reg, size), operand))
             }
         }
     }
 
-    fn match_variable_helper(
+    // Stores the part of eax (or rax) a variable's size covers
+    fn store_variable(&mut self, name: Name) {
+        let (operand, size) = self.variable(name);
+        self.emit(&format!("mov {}, {}", operand, sized_register("eax", size)));
+    }
+
+    // Loads the value a reference points to into a 32-bit register, going through
+    // the register's 64-bit form for the address
+    fn load_through(&mut self, reg: &str, name: Name) {
+        self.load_variable(reg, name);
+        let pointer = sized_register(reg, Size::Qword);
+        match self.pointee_size(name) {
+            Size::Byte => self.emit(&format!("movzx {}, byte [{}]", reg, pointer)),
+            size => self.emit(&format!("mov {}, {} [{}]", reg, size.keyword(), pointer)),
+        }
+    }
+
+    fn pointee_size(&self, name: Name) -> Size {
+        match self.locals.get(&name) {
+            Some((_, Type::Ref(inner))) => storage_size(inner),
+            // the type checker only lets references be dereferenced
+            _ => unreachable!("{} is not a reference", name),
+        }
+    }
+
+    fn generate_function(
         &mut self,
-        name: &String,
-        value: &Expr,
-        writer: &mut BufWriter<&File>,
+        nodes: &Arena<Node>,
+        name: Name,
+        params: &[(Name, Type)],
+        body: NodeId,
+        span: Span,
     ) {
-        match value {
-            Expr::Int(i) => {
-                writeln!(writer, "    mov dword [{}], {}", name, i).unwrap();
+        self.lines.push(Line::Blank);
+        self.label(&format!("fn.{}", name));
+        self.annotate(span);
+        let enclosing = self.span;
+        self.locate(span);
+        let frame = Frame {
+            called: true,
+            saved: &[],
+            parameters: params.len(),
+            locals: frame_slots(nodes, &[body]),
+        };
+        self.prologue(&frame);
+
+        for (i, (param, type_)) in params.iter().enumerate() {
+            self.locals
+                .insert(*param, (Frame::parameter(i), type_.clone()));
+            if i < ARG_REGISTERS_32.len() {
+                let (slot, size) = self.variable(*param);
+                let register = sized_register(ARG_REGISTERS_32[i], size);
+                self.emit(&format!("mov {}, {}", slot, register));
+            } else {
+                let incoming = frame.arguments() + 8 * (i - ARG_REGISTERS_32.len());
+                let (slot, size) = self.variable(*param);
+                let wide = size == Size::Qword;
+                let register = if wide { "rax" } else { "eax" };
+                let source = if wide { "qword" } else { "dword" };
+                self.emit(&format!("mov {}, {} [rbp+{}]", register, source, incoming));
+                self.emit(&format!("mov {}, {}", slot, sized_register("eax", size)));
             }
-            Expr::Ident(ident) => {
-                writeln!(writer, "    mov eax, dword [{}]", ident).unwrap();
-                writeln!(writer, "    mov dword [{}], eax", name).unwrap();
+        }
+
+        self.current_function = Some(name);
+        self.frame_offset = -8 * params.len() as i32;
+        self.generate_node(nodes, body);
+        self.current_function = None;
+
+        // every return jumps here with its value in eax
+        self.label(&format!("fn.{}.return", name));
+        self.epilogue(&frame);
+        self.locals.clear();
+        self.locate(enclosing);
+    }
+
+    // A body is a scope of its own: the slots of the locals declared in it are free
+    // for reuse once it ends, and an outer variable it shadows is visible again
+    fn generate_body(&mut self, nodes: &Arena<Node>, body: &[NodeId]) {
+        let scope = self.enter_scope();
+        for &stmt in body {
+            self.generate_node(nodes, stmt);
+        }
+        self.exit_scope(scope);
+    }
+
+    fn enter_scope(&mut self) -> Scope {
+        self.scope_count += 1;
+        let id = std::mem::replace(&mut self.scope, self.scope_count);
+        Scope {
+            id,
+            frame_offset: self.frame_offset,
+            replaced: self.replaced.len(),
+        }
+    }
+
+    fn exit_scope(&mut self, scope: Scope) {
+        for binding in self.replaced.drain(scope.replaced..).rev() {
+            match binding {
+                Binding::Local(name, Some(slot)) => {
+                    self.locals.insert(name, slot);
+                }
+                Binding::Local(name, None) => {
+                    self.locals.remove(&name);
+                }
+                Binding::Global(name) => {
+                    let bindings = self.globals.get_mut(&name).into_iter().flatten();
+                    if let Some(open) = bindings.rev().find(|b| b.until.is_none()) {
+                        open.until = Some(self.clock);
+                    }
+                    self.clock += 1;
+                }
             }
-            Expr::Float(f) => {
+        }
+        self.scope = scope.id;
+        self.frame_offset = scope.frame_offset;
+    }
+
+    fn bind_local(&mut self, name: Name, slot: (i32, Type)) {
+        let previous = self.locals.insert(name, slot);
+        self.replaced.push(Binding::Local(name, previous));
+    }
+
+    fn bind_global(&mut self, name: Name, symbol: String) {
+        self.globals.entry(name).or_default().push(GlobalBinding {
+            symbol,
+            from: self.clock,
+            until: None,
+        });
+        self.clock += 1;
+        self.replaced.push(Binding::Global(name));
+    }
+
+    // The symbol of the global a name refers to: the innermost binding in scope now,
+    // or where the function being generated was declared
+    fn global(&self, name: Name) -> Option<&str> {
+        let at = self.declared_at.unwrap_or(self.clock);
+        self.globals
+            .get(&name)?
+            .iter()
+            .rev()
+            .find(|b| b.from <= at && b.until.is_none_or(|until| until > at))
+            .map(|b| b.symbol.as_str())
+    }
+
+    // The assembly symbol of a global declared in the current scope. Like every symbol
+    // the generator makes up (fn.<name>, fn.<name>.return, loop_begin.<n>) it has a '.'
+    // in it, which no source name can, so a variable called rax, loop_begin_0 or byte
+    // cannot collide with a label, register or assembler keyword. The scope keeps a
+    // binding that shadows another from aliasing its storage.
+    fn global_symbol(&mut self, name: Name) -> String {
+        let symbol = format!("v.{}.{}", self.scope, name);
+        self.global_symbols
+            .push((symbol.clone(), name.to_string(), self.span));
+        symbol
+    }
+
+    fn allocate_local(&mut self) -> i32 {
+        self.frame_offset -= 8;
+        self.frame_offset
+    }
+
+    // Falls through when the condition holds, otherwise reports file:line and exits
+    fn generate_assert(&mut self, condition: &Expr) {
+        let ok_label = format!("assert_ok.{}", self.next_label_id());
+        self.generate_expr_into_register(condition, "eax");
+        self.emit("cmp eax, 0");
+        self.emit(&format!("jne {}", ok_label));
+        self.generate_failure(runtime::ASSERT_FAIL, "assertion failed");
+        self.label(&ok_label);
+    }
+
+    // Hands "file:line: what" to a failure handler, which never returns; checks
+    // jump over this when they pass
+    fn generate_failure(&mut self, handler: &'static str, what: &str) {
+        let message_label = format!("failure_message.{}", self.failure_messages.len());
+        let message = format!("{}:{}: {}\n", self.file, self.span.line, what);
+        if self.target.freestanding {
+            self.emit(&format!("lea rsi, [{}]", message_label));
+            self.emit(&format!("mov edx, {}", message.len()));
+        } else {
+            self.emit(&format!("lea rcx, [{}]", message_label));
+        }
+        self.emit(&format!("jmp {}", handler));
+        self.failure_messages.push((message_label, message));
+        self.use_routine(handler);
+    }
+
+    fn call_runtime(&mut self, routine: &'static str) {
+        self.use_routine(routine);
+        self.emit(&format!("call {}", routine));
+    }
+
+    fn use_routine(&mut self, routine: &'static str) {
+        if !self.runtime_routines.contains(&routine) {
+            self.runtime_routines.push(routine);
+        }
+    }
+
+    // The routines themselves are in the separate runtime module; this program only
+    // declares the ones it uses, along with its extern functions
+    fn generate_externs_and_data(&mut self) {
+        let mut externs: Vec<String> = self
+            .runtime_routines
+            .iter()
+            .map(|r| r.to_string())
+            .collect();
+        for (name, _) in &self.extern_functions {
+            externs.push(name.to_string());
+        }
+        for (i, name) in externs.into_iter().enumerate() {
+            self.lines.insert(i, Line::Extern(name));
+        }
+
+        let mut data = Vec::new();
+        for (name, text) in &self.failure_messages {
+            data.push(Line::Asciz {
+                name: name.clone(),
+                text: text.clone(),
+            });
+        }
+        if !data.is_empty() {
+            self.lines.push(Line::Blank);
+            self.lines.push(Line::Section(".data".to_string()));
+            self.lines.extend(data);
+        }
+    }
+
+    fn generate_call(&mut self, name: Name, args: &[Expr]) {
+        if name == "read_int" {
+            self.call_runtime(runtime::READ_INT);
+            return;
+        }
+        if let Some((_, signature)) = self.extern_functions.iter().find(|(n, _)| *n == name) {
+            let signature = signature.clone();
+            self.generate_extern_call(name, args, &signature);
+            return;
+        }
+
+        // Arguments are evaluated left to right. Those passed in registers wait on the
+        // stack, the last on top, and the rest go straight to their slots below them, so
+        // popping the registers in reverse leaves the first stack argument on top.
+        let spilled = args.len().saturating_sub(ARG_REGISTERS.len());
+        if spilled > 0 {
+            self.emit(&format!("sub rsp, {}", 8 * spilled));
+        }
+        for (i, arg) in args.iter().enumerate() {
+            self.generate_expr_into_register(arg, "eax");
+            if i < ARG_REGISTERS.len() {
+                self.emit("push rax");
+            } else {
+                self.emit(&format!("mov qword [rsp+{}], rax", 8 * i));
+            }
+        }
+        for reg in ARG_REGISTERS.iter().take(args.len()).rev() {
+            self.emit(&format!("pop {}", reg));
+        }
+
+        self.emit(&format!("call fn.{}", name));
+
+        if spilled > 0 {
+            self.emit(&format!("add rsp, {}", 8 * spilled));
+        }
+    }
+
+    // Arguments are evaluated left to right onto the stack, the last on top, then moved
+    // to where the C convention expects them on a 16-byte aligned stack
+    fn generate_extern_call(
+        &mut self,
+        name: Name,
+        args: &[Expr],
+        (params, return_type): &Signature,
+    ) {
+        for arg in args {
+            self.generate_expr_into_register(arg, "eax");
+            self.emit("push rax");
+        }
+        let slot = |i: usize| 8 * (args.len() - 1 - i);
+        let convention = self.target.abi.calling_convention();
+        let locations = convention.arguments(params.iter().map(|type_| *type_ == Type::F32S));
+        let stack_bytes = 8 * locations
+            .iter()
+            .filter(|location| matches!(location, ArgumentLocation::Stack(_)))
+            .count();
+        // Windows callees may use the 32 bytes of shadow space above the return address
+        let shadow = convention.shadow_space;
+
+        // rbx is callee-saved in every convention, so it still points at the
+        // evaluated arguments when the callee returns
+        self.emit("mov rbx, rsp");
+        let frame = convention.call_frame(stack_bytes);
+        if frame > 0 {
+            self.emit(&format!("sub rsp, {}", frame));
+        }
+        self.emit(&format!("and rsp, -{}", convention.stack_alignment));
+        for (i, location) in locations.iter().enumerate() {
+            if let ArgumentLocation::Stack(offset) = location {
+                self.emit(&format!("mov rax, qword [rbx+{}]", slot(i)));
+                self.emit(&format!("mov qword [rsp+{}], rax", shadow + offset));
+            }
+        }
+        let mut floats = 0;
+        for (i, location) in locations.iter().enumerate() {
+            match location {
+                ArgumentLocation::Register(reg) => {
+                    self.emit(&format!("mov {}, qword [rbx+{}]", reg, slot(i)))
+                }
+                ArgumentLocation::Vector(k) => {
+                    self.emit(&format!("mov eax, dword [rbx+{}]", slot(i)));
+                    self.emit(&format!("movd xmm{}, eax", k));
+                    floats += 1;
+                }
+                ArgumentLocation::Stack(_) => {}
+            }
+        }
+        // variadic System V callees such as printf read the number of vector registers in al
+        if convention.vector_count_in_al {
+            self.emit(&format!("mov eax, {}", floats));
+        }
+        self.emit(&format!("call {}", name));
+        self.emit("mov rsp, rbx");
+        if !args.is_empty() {
+            self.emit(&format!("add rsp, {}", 8 * args.len()));
+        }
+
+        match return_type {
+            Type::F32S => self.emit("movd eax, xmm0"),
+            // C only sets the low byte of a bool or char
+            Type::Bool | Type::Char => self.emit("movzx eax, al"),
+            _ => {}
+        }
+    }
+
+    // A global label under the function's own name that C code can call: it moves the
+    // arguments from where the C convention put them to where a Noble call would, and
+    // keeps rbx, which Noble code uses freely but C callers expect preserved
+    fn generate_export(&mut self, name: Name, (params, return_type): &Signature) {
+        self.lines.push(Line::Blank);
+        self.lines.push(Line::Global(name.to_string()));
+        self.label(&name.to_string());
+        let frame = Frame {
+            called: true,
+            saved: &["rbx"],
+            parameters: 0,
+            locals: 0,
+        };
+        self.prologue(&frame);
+
+        // on Windows the stack arguments come after the shadow space
+        let convention = self.target.abi.calling_convention();
+        let stack_base = frame.arguments() + convention.shadow_space;
+        let locations = convention.arguments(params.iter().map(|type_| *type_ == Type::F32S));
+        for location in locations.iter().rev() {
+            match location {
+                ArgumentLocation::Register(reg) => self.emit(&format!("push {}", reg)),
+                ArgumentLocation::Vector(k) => {
+                    self.emit(&format!("movd eax, xmm{}", k));
+                    self.emit("push rax");
+                }
+                ArgumentLocation::Stack(offset) => {
+                    self.emit(&format!("push qword [rbp+{}]", stack_base + offset))
+                }
+            }
+        }
+        for reg in ARG_REGISTERS.iter().take(params.len()) {
+            self.emit(&format!("pop {}", reg));
+        }
+        self.emit(&format!("call fn.{}", name));
+
+        if *return_type == Type::F32S {
+            self.emit("movd xmm0, eax");
+        }
+        self.epilogue(&frame);
+    }
+
+    fn prologue(&mut self, frame: &Frame) {
+        for register in frame.saved {
+            self.emit(&format!("push {}", register));
+        }
+        self.emit("push rbp");
+        self.emit("mov rbp, rsp");
+        if frame.size() > 0 {
+            self.emit(&format!("sub rsp, {}", frame.size()));
+        }
+    }
+
+    fn epilogue(&mut self, frame: &Frame) {
+        self.emit("mov rsp, rbp");
+        self.emit("pop rbp");
+        for register in frame.saved.iter().rev() {
+            self.emit(&format!("pop {}", register));
+        }
+        self.emit("ret");
+    }
+
+    fn match_variable_helper(&mut self, name: Name, value: &Expr) {
+        let (target, _) = self.variable(name);
+        match &value.kind {
+            ExprKind::Int(i) => {
+                self.emit(&format!("mov {}, {}", target, i));
+            }
+            ExprKind::Ident(ident) => {
+                self.load_variable("eax", *ident);
+                self.store_variable(name);
+            }
+            ExprKind::Call { name: callee, args } => {
+                self.generate_call(*callee, args);
+                self.store_variable(name);
+            }
+            ExprKind::AddressOf(_)
+            | ExprKind::Deref(_)
+            | ExprKind::Convert { .. }
+            | ExprKind::Negate { .. } => {
+                self.generate_expr_into_register(value, "eax");
+                self.store_variable(name);
+            }
+            ExprKind::Float(f) => {
                 let bits = f.to_bits();
-                writeln!(writer, "    mov dword [{}], {}", name, bits).unwrap();
+                self.emit(&format!("mov {}, {}", target, bits));
             }
-            Expr::Bool(b) => {
+            ExprKind::Bool(b) => {
                 let val = if *b { 1 } else { 0 };
-                writeln!(writer, "    mov dword [{}], {}", name, val).unwrap();
+                self.emit(&format!("mov {}, {}", target, val));
+            }
+            ExprKind::Char(c) => {
+                self.emit(&format!("mov {}, {}", target, *c as u32));
             }
-            Expr::Char(c) => {
-                writeln!(writer, "    mov dword [{}], {}", name, *c as u32).unwrap();
+            // x = x + 1 and x = x - 1, which x++ and x-- lower to, change the variable in
+            // place; inc and dec set the overflow flag like add and sub
+            ExprKind::BinaryOp { left, op, right }
+                if *type_of(left) == Type::I32S
+                    && matches!(left.kind, ExprKind::Ident(ident) if ident == name)
+                    && matches!(right.kind, ExprKind::Int(1))
+                    && matches!(op, BinOpType::Add | BinOpType::Subtract) =>
+            {
+                let mnemonic = match op {
+                    BinOpType::Add => "inc",
+                    _ => "dec",
+                };
+                self.emit(&format!("{} {}", mnemonic, target));
+                self.check_overflow();
             }
-            Expr::BinaryOp { left, op, right } => {
-                self.generate_binary_op(left, op, right, writer);
-                writeln!(writer, "    mov dword [{}], eax", name).unwrap();
+            ExprKind::BinaryOp { left, op, right } => {
+                self.generate_binary_op(left, op, right);
+                self.store_variable(name);
             }
         }
     }
 
-    fn generate_expr_into_register(
-        &mut self,
-        expr: &Expr,
-        reg: &str,
-        writer: &mut BufWriter<&File>,
-    ) {
-        match expr {
-            Expr::Int(i) => {
-                writeln!(writer, "    mov {}, {}", reg, i).unwrap();
+    fn generate_expr_into_register(&mut self, expr: &Expr, reg: &str) {
+        match &expr.kind {
+            ExprKind::Int(i) => {
+                self.emit(&format!("mov {}, {}", reg, i));
+            }
+            ExprKind::Ident(name) => {
+                self.load_variable(reg, *name);
+            }
+            ExprKind::Call { name, args } => {
+                self.generate_call(*name, args);
+                self.emit(&format!("mov {}, eax", reg));
+            }
+            ExprKind::AddressOf(name) => {
+                let (address, _) = self.address(*name);
+                let wide = sized_register(reg, Size::Qword);
+                self.emit(&format!("lea {}, [{}]", wide, address));
+            }
+            ExprKind::Deref(name) => {
+                self.load_through(reg, *name);
+            }
+            ExprKind::Convert { value, to } => {
+                self.generate_expr_into_register(value, reg);
+                match (type_of(value), to) {
+                    (Type::I32S, Type::F32S) => {
+                        self.emit(&format!("cvtsi2ss xmm0, {}", reg));
+                        self.emit(&format!("movd {}, xmm0", reg));
+                    }
+                    // truncates toward zero, like the integer division
+                    (Type::F32S, Type::I32S) => {
+                        self.emit(&format!("movd xmm0, {}", reg));
+                        self.emit(&format!("cvttss2si {}, xmm0", reg));
+                    }
+                    _ => {}
+                }
             }
-            Expr::Ident(name) => {
-                writeln!(writer, "    mov {}, dword [{}]", reg, name).unwrap();
+            ExprKind::Negate { value } => {
+                self.generate_expr_into_register(value, reg);
+                if *type_of(expr) == Type::F32S {
+                    // flip the sign bit
+                    self.emit(&format!("xor {}, -2147483648", reg));
+                } else {
+                    self.emit(&format!("neg {}", reg));
+                    self.check_overflow();
+                }
             }
-            Expr::Float(f) => {
+            ExprKind::Float(f) => {
                 let bits = f.to_bits();
-                writeln!(writer, "    mov {}, {}", reg, bits).unwrap();
+                self.emit(&format!("mov {}, {}", reg, bits));
             }
-            Expr::Bool(b) => {
+            ExprKind::Bool(b) => {
                 let val = if *b { 1 } else { 0 };
-                writeln!(writer, "    mov {}, {}", reg, val).unwrap();
+                self.emit(&format!("mov {}, {}", reg, val));
             }
-            Expr::Char(c) => {
-                writeln!(writer, "    mov {}, {}", reg, *c as u32).unwrap();
+            ExprKind::Char(c) => {
+                self.emit(&format!("mov {}, {}", reg, *c as u32));
             }
-            Expr::BinaryOp { left, op, right } => {
-                self.generate_binary_op(left, op, right, writer);
-                writeln!(writer, "    mov {}, eax", reg).unwrap();
+            ExprKind::BinaryOp { left, op, right } => {
+                self.generate_binary_op(left, op, right);
+                self.emit(&format!("mov {}, eax", reg));
             }
         }
     }
 
-    fn generate_binary_op(
-        &mut self,
-        left: &Expr,
-        op: &BinOpType,
-        right: &Expr,
-        writer: &mut BufWriter<&File>,
-    ) {
+    fn generate_binary_op(&mut self, left: &Expr, op: &BinOpType, right: &Expr) {
         // Eval left into eax
-        self.generate_expr_into_register(left, "eax", writer);
+        self.generate_expr_into_register(left, "eax");
 
         // Push eax (save left value)
-        writeln!(writer, "    push rax").unwrap();
+        self.emit("push rax");
 
         // Eval right into ebx
-        self.generate_expr_into_register(right, "ebx", writer);
+        self.generate_expr_into_register(right, "ebx");
 
         // Restore left into eax
-        writeln!(writer, "    pop rax").unwrap();
+        self.emit("pop rax");
+
+        // the checker promotes a mixed operation's i32s operand, so both have this type
+        if *type_of(left) == Type::F32S {
+            if op.is_arithmetic() {
+                self.generate_float_arithmetic(op);
+            } else {
+                self.generate_float_comparison(op);
+            }
+            return;
+        }
 
         match op {
             BinOpType::Add => {
-                writeln!(writer, "    add eax, ebx").unwrap();
+                self.emit("add eax, ebx");
+                self.check_overflow();
             }
             BinOpType::Subtract => {
-                writeln!(writer, "    sub eax, ebx").unwrap();
+                self.emit("sub eax, ebx");
+                self.check_overflow();
             }
             BinOpType::Multiply => {
-                writeln!(writer, "    imul eax, ebx").unwrap();
-            }
-            BinOpType::Divide => {
-                writeln!(writer, "    cdq").unwrap(); // sign-extend eax into edx:eax
-                writeln!(writer, "    idiv ebx").unwrap(); // eax = eax / ebx
+                self.emit("imul eax, ebx");
+                self.check_overflow();
             }
+            BinOpType::Divide => self.generate_division(right),
+            _ => {}
+        }
+        // a char wraps at 8 bits, like the byte it is kept in, before it is compared
+        if op.is_arithmetic() && *type_of(left) == Type::Char {
+            self.emit("movzx eax, al");
+        }
 
+        match op {
             // set eax to 1 or 0 on comparisons
             BinOpType::LessThan => {
-                writeln!(writer, "    cmp eax, ebx").unwrap();
-                writeln!(writer, "    setl al").unwrap();
-                writeln!(writer, "    movzx eax, al").unwrap();
+                self.emit("cmp eax, ebx");
+                self.emit("setl al");
+                self.emit("movzx eax, al");
             }
             BinOpType::LessThanOrEqual => {
-                writeln!(writer, "    cmp eax, ebx").unwrap();
-                writeln!(writer, "    setle al").unwrap();
-                writeln!(writer, "    movzx eax, al").unwrap();
+                self.emit("cmp eax, ebx");
+                self.emit("setle al");
+                self.emit("movzx eax, al");
             }
             BinOpType::GreaterThan => {
-                writeln!(writer, "    cmp eax, ebx").unwrap();
-                writeln!(writer, "    setg al").unwrap();
-                writeln!(writer, "    movzx eax, al").unwrap();
+                self.emit("cmp eax, ebx");
+                self.emit("setg al");
+                self.emit("movzx eax, al");
             }
             BinOpType::GreaterThanOrEqual => {
-                writeln!(writer, "    cmp eax, ebx").unwrap();
-                writeln!(writer, "    setge al").unwrap();
-                writeln!(writer, "    movzx eax, al").unwrap();
+                self.emit("cmp eax, ebx");
+                self.emit("setge al");
+                self.emit("movzx eax, al");
             }
             BinOpType::Equal => {
-                writeln!(writer, "    cmp eax, ebx").unwrap();
-                writeln!(writer, "    sete al").unwrap();
-                writeln!(writer, "    movzx eax, al").unwrap();
+                self.emit("cmp eax, ebx");
+                self.emit("sete al");
+                self.emit("movzx eax, al");
             }
             BinOpType::NotEqual => {
-                writeln!(writer, "    cmp eax, ebx").unwrap();
-                writeln!(writer, "    setne al").unwrap();
-                writeln!(writer, "    movzx eax, al").unwrap();
+                self.emit("cmp eax, ebx");
+                self.emit("setne al");
+                self.emit("movzx eax, al");
+            }
+            _ => {}
+        }
+    }
+
+    // Applies op to the f32s bit patterns in eax and ebx, leaving the result's bits in eax
+    fn generate_float_arithmetic(&mut self, op: &BinOpType) {
+        let mnemonic = match op {
+            BinOpType::Add => "addss",
+            BinOpType::Subtract => "subss",
+            BinOpType::Multiply => "mulss",
+            BinOpType::Divide => "divss",
+            _ => unreachable!("{} is not arithmetic", op),
+        };
+        self.emit("movd xmm0, eax");
+        self.emit("movd xmm1, ebx");
+        self.emit(&format!("{} xmm0, xmm1", mnemonic));
+        self.emit("movd eax, xmm0");
+    }
+
+    // Compares the f32s bit patterns in eax and ebx, leaving 1 or 0 in eax. A NaN
+    // operand makes every comparison false except !=, as IEEE 754 requires.
+    fn generate_float_comparison(&mut self, op: &BinOpType) {
+        self.emit("movd xmm0, eax");
+        self.emit("movd xmm1, ebx");
+        match op {
+            // seta and setae are false when the operands are unordered, so the
+            // less-than forms swap the operands rather than use setb/setbe
+            BinOpType::GreaterThan => {
+                self.emit("comiss xmm0, xmm1");
+                self.emit("seta al");
+            }
+            BinOpType::GreaterThanOrEqual => {
+                self.emit("comiss xmm0, xmm1");
+                self.emit("setae al");
+            }
+            BinOpType::LessThan => {
+                self.emit("comiss xmm1, xmm0");
+                self.emit("seta al");
+            }
+            BinOpType::LessThanOrEqual => {
+                self.emit("comiss xmm1, xmm0");
+                self.emit("setae al");
+            }
+            // unordered sets ZF as well as PF, so equality also has to check parity
+            BinOpType::Equal => {
+                self.emit("ucomiss xmm0, xmm1");
+                self.emit("sete al");
+                self.emit("setnp cl");
+                self.emit("and al, cl");
+            }
+            BinOpType::NotEqual => {
+                self.emit("ucomiss xmm0, xmm1");
+                self.emit("setne al");
+                self.emit("setp cl");
+                self.emit("or al, cl");
+            }
+            _ => unreachable!("{} is not a comparison", op),
+        }
+        self.emit("movzx eax, al");
+    }
+
+    // With --checked-arithmetic, the add, sub or imul just emitted must not have overflowed
+    fn check_overflow(&mut self) {
+        if !self.checked_arithmetic {
+            return;
+        }
+        let ok_label = format!("no_overflow.{}", self.next_label_id());
+        self.emit(&format!("jno {}", ok_label));
+        self.generate_failure(runtime::OVERFLOW_FAIL, "integer overflow");
+        self.label(&ok_label);
+    }
+
+    // Divides eax by ebx, truncating toward zero. idiv faults on i32 MIN / -1, whose
+    // quotient doesn't fit, so unless arithmetic is checked a divisor that may be -1
+    // negates the dividend instead, wrapping MIN to itself like the other operators. A
+    // zero divisor still faults.
+    fn generate_division(&mut self, divisor: &Expr) {
+        let mut done_label = None;
+        if self.checked_arithmetic {
+            self.check_divisor();
+        } else if let ExprKind::Int(divisor) = divisor.kind {
+            if divisor == -1 {
+                self.emit("neg eax");
+                return;
             }
+        } else {
+            let id = self.next_label_id();
+            let divide_label = format!("divide.{}", id);
+            self.emit("cmp ebx, -1");
+            self.emit(&format!("jne {}", divide_label));
+            self.emit("neg eax");
+            let done = format!("divided.{}", id);
+            self.emit(&format!("jmp {}", done));
+            self.label(&divide_label);
+            done_label = Some(done);
+        }
+        self.emit("cdq"); // sign-extend eax into edx:eax
+        self.emit("idiv ebx"); // eax = eax / ebx
+        if let Some(done) = done_label {
+            self.label(&done);
         }
     }
 
+    // idiv faults on a zero divisor and on i32 MIN / -1; with --checked-arithmetic both
+    // are reported before the division instead
+    fn check_divisor(&mut self) {
+        let id = self.next_label_id();
+        let nonzero_label = format!("divisor_nonzero.{}", id);
+        let ok_label = format!("divisor_ok.{}", id);
+        self.emit("cmp ebx, 0");
+        self.emit(&format!("jne {}", nonzero_label));
+        self.generate_failure(runtime::DIVISION_BY_ZERO_FAIL, "division by zero");
+        self.label(&nonzero_label);
+        self.emit("cmp ebx, -1");
+        self.emit(&format!("jne {}", ok_label));
+        self.emit(&format!("cmp eax, {}", i32::MIN));
+        self.emit(&format!("jne {}", ok_label));
+        self.generate_failure(runtime::OVERFLOW_FAIL, "integer overflow");
+        self.label(&ok_label);
+    }
+
+    fn generate_match(
+        &mut self,
+        nodes: &Arena<Node>,
+        scrutinee: &Expr,
+        arms: &[(i32, NodeId)],
+        else_body: Option<NodeId>,
+    ) {
+        let id = self.next_label_id();
+        let else_label = format!("match_else.{}", id);
+        let end_label = format!("match_end.{}", id);
+        let case_labels: Vec<String> = (0..arms.len())
+            .map(|i| format!("match_case.{}.{}", id, i))
+            .collect();
+
+        self.generate_expr_into_register(scrutinee, "eax");
+
+        // the first arm wins when a value repeats, matching the compare chain
+        let target = |value: i64| {
+            arms.iter()
+                .position(|(v, _)| *v as i64 == value)
+                .map_or(else_label.clone(), |i| case_labels[i].clone())
+        };
+
+        let min = arms.iter().map(|(v, _)| *v as i64).min().unwrap_or(0);
+        let max = arms.iter().map(|(v, _)| *v as i64).max().unwrap_or(0);
+        let range = max - min + 1;
+
+        if arms.len() >= 4 && range <= 2 * arms.len() as i64 {
+            // dense: index a table of case addresses by (value - min)
+            let table_label = format!("match_table.{}", id);
+            self.emit(&format!("sub eax, {}", min));
+            self.emit(&format!("cmp eax, {}", range - 1));
+            self.emit(&format!("ja {}", else_label));
+            self.emit(&format!("lea rdx, [{}]", table_label));
+            self.emit("shl rax, 3");
+            self.emit("add rdx, rax");
+            self.emit("jmp qword [rdx]");
+            self.label(&table_label);
+            for value in min..=max {
+                self.lines.push(Line::Quad(target(value)));
+            }
+        } else {
+            // sparse: compare against each arm in turn
+            for (i, (value, _)) in arms.iter().enumerate() {
+                self.emit(&format!("cmp eax, {}", value));
+                self.emit(&format!("je {}", case_labels[i]));
+            }
+            self.emit(&format!("jmp {}", else_label));
+        }
+
+        for (i, &(_, body)) in arms.iter().enumerate() {
+            self.label(&case_labels[i]);
+            self.generate_node(nodes, body);
+            self.emit(&format!("jmp {}", end_label));
+        }
+
+        self.label(&else_label);
+        if let Some(else_body) = else_body {
+            self.generate_node(nodes, else_body);
+        }
+        self.label(&end_label);
+    }
+
     fn generate_if(
         &mut self,
+        nodes: &Arena<Node>,
         condition: &Expr,
-        body: &Vec<AbstractSyntaxTreeNode>,
-        else_body: &Option<Box<AbstractSyntaxTreeNode>>,
-        writer: &mut BufWriter<&File>,
+        body: NodeId,
+        else_body: Option<NodeId>,
     ) {
-        static mut LABEL_COUNT: usize = 0;
-        let id = unsafe {
-            let current = LABEL_COUNT;
-            LABEL_COUNT += 1;
-            current
-        };
+        let id = self.next_label_id();
 
-        let else_label = format!("else_{}", id);
-        let end_label = format!("endif_{}", id);
+        let else_label = format!("else.{}", id);
+        let end_label = format!("endif.{}", id);
 
-        self.generate_expr_into_register(condition, "eax", writer);
+        self.generate_expr_into_register(condition, "eax");
 
         // Compare eax with 0 (false)
-        writeln!(writer, "    cmp eax, 0").unwrap();
+        self.emit("cmp eax, 0");
 
         // Jump if false → else or end if no else
         if else_body.is_some() {
-            writeln!(writer, "    je {}", else_label).unwrap();
+            self.emit(&format!("je {}", else_label));
         } else {
-            writeln!(writer, "    je {}", end_label).unwrap();
+            self.emit(&format!("je {}", end_label));
         }
 
         // IF BODY
-        for stmt in body {
-            self.generate_x64(stmt, writer);
-        }
+        self.generate_node(nodes, body);
 
         // End of IF always jumps to end_label if else exists
         if else_body.is_some() {
-            writeln!(writer, "    jmp {}", end_label).unwrap();
+            self.emit(&format!("jmp {}", end_label));
         }
 
         // ELSE or ELSE IF
         if let Some(else_ast) = else_body {
-            writeln!(writer, "{}:", else_label).unwrap();
-            self.generate_x64(else_ast, writer);
+            self.label(&else_label);
+            self.generate_node(nodes, else_ast);
         }
 
-        writeln!(writer, "{}:", end_label).unwrap();
+        self.label(&end_label);
+    }
+}
+
+// Stack slots statements need at their deepest point: one per declaration and for-loop
+// iterator in scope. Nested blocks release theirs when they end, so siblings share.
+fn frame_slots(nodes: &Arena<Node>, statements: &[NodeId]) -> usize {
+    let mut declared = 0;
+    let mut peak = 0;
+    for &id in statements {
+        let nested = match &nodes[id].stmt {
+            Stmt::VariableDeclaration { .. } => {
+                declared += 1;
+                0
+            }
+            Stmt::Block { body } => frame_slots(nodes, body),
+            Stmt::For { body, .. } => 1 + frame_slots(nodes, &[*body]),
+            Stmt::While { body, .. } | Stmt::DoWhile { body, .. } => frame_slots(nodes, &[*body]),
+            Stmt::If {
+                body, else_body, ..
+            } => frame_slots(nodes, &[*body]).max(frame_slots(nodes, else_body.as_slice())),
+            Stmt::Match {
+                arms, else_body, ..
+            } => arms
+                .iter()
+                .map(|(_, body)| body)
+                .chain(else_body)
+                .map(|&body| frame_slots(nodes, &[body]))
+                .max()
+                .unwrap_or(0),
+            // a nested function's locals live in its own frame
+            _ => 0,
+        };
+        peak = peak.max(declared + nested);
+    }
+    peak
+}
+
+// What the assembly already uses a name for, if anything. Extern and export functions
+// keep their names there, and these are the only symbols a source name can spell: the
+// generator's own all have a '.' in them.
+pub fn reserved_symbol(name: &str) -> Option<&'static str> {
+    if asm::is_register(name) {
+        Some("a register")
+    } else if ASSEMBLER_KEYWORDS.contains(&name) {
+        Some("an assembler keyword")
+    } else if name.starts_with(runtime::PREFIX) {
+        Some("reserved for the runtime")
+    } else if TARGETS.iter().any(|target| target.entry == name) {
+        Some("an entry point")
+    } else {
+        None
+    }
+}
+
+// The type the checker resolved for an expression. Code is only generated for programs
+// that type checked, so every expression has one.
+fn type_of(expr: &Expr) -> &Type {
+    expr.type_
+        .as_ref()
+        .expect("code generated for an expression the type checker has not seen")
+}
+
+// Bytes a variable of this type occupies in memory
+fn storage_size(type_: &Type) -> Size {
+    match type_ {
+        Type::Bool => Size::Byte,
+        Type::Ref(_) => Size::Qword,
+        _ => Size::Dword,
+    }
+}
+
+// The byte, dword or qword form of a general-purpose register named by its dword form
+fn sized_register(reg: &str, size: Size) -> String {
+    // r8d..r15d, then the legacy eax, ebx, ecx and edx
+    let numbered = reg.strip_prefix('r').and_then(|r| r.strip_suffix('d'));
+    match (numbered, size) {
+        (_, Size::Dword) => reg.to_string(),
+        (Some(number), Size::Byte) => format!("r{}b", number),
+        (Some(number), Size::Qword) => format!("r{}", number),
+        (None, Size::Byte) => format!("{}l", &reg[1..2]),
+        (None, Size::Qword) => format!("r{}", &reg[1..]),
     }
 }
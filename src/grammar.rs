@@ -0,0 +1,434 @@
+use crate::parse::{Associativity, INFIX_OPERATORS, LEVELS};
+
+// The language's grammar as data. The parser is written by hand and does not read this
+// table; it is what `--emit grammar-ebnf`, docs/grammar.md and the README print, and
+// tests/grammar.rs checks it against the tokenizer's keywords and the parser's
+// precedence tables, so a change to one that the other does not follow fails there.
+
+// One rule of the grammar: its name, what it produces and a note on what the productions
+// alone do not say
+pub struct Definition {
+    pub name: &'static str,
+    pub production: Symbol,
+    pub note: Option<&'static str>,
+}
+
+pub enum Symbol {
+    // a token spelled exactly so
+    Token(&'static str),
+    // another rule, by name
+    Rule(&'static str),
+    // a token described in words, for what the tokenizer decides character by character
+    Prose(&'static str),
+    // nothing at all
+    Empty,
+    Sequence(&'static [Symbol]),
+    Choice(&'static [Symbol]),
+    Optional(&'static Symbol),
+    ZeroOrMore(&'static Symbol),
+    OneOrMore(&'static Symbol),
+    // the binary operators of one precedence level, from INFIX_OPERATORS, chained over
+    // the next level as LEVELS says they associate; levels count from 1
+    Level(u8),
+}
+
+use Symbol::*;
+
+const EXPR: Symbol = Rule("Expr");
+const IDENT: Symbol = Rule("Ident");
+const BLOCK: Symbol = Rule("Block");
+const SEMI: Symbol = Token(";");
+
+// Every rule, the start rule first
+pub const RULES: [Definition; 41] = [
+    Definition {
+        name: "Entry Point",
+        production: ZeroOrMore(&Rule("Stmt")),
+        note: None,
+    },
+    Definition {
+        name: "Stmt",
+        production: Choice(&[
+            Rule("Exit"),
+            Rule("PrintInt"),
+            Rule("VariableDec"),
+            Rule("VariableAsm"),
+            Rule("ParallelAsm"),
+            Rule("For"),
+            Rule("While"),
+            Rule("DoWhile"),
+            Rule("If"),
+            Rule("Match"),
+            Rule("Function"),
+            Rule("Return"),
+            Rule("ExprStmt"),
+            Rule("Assert"),
+            Rule("Const"),
+            Rule("Define"),
+            Rule("DerefAsm"),
+            BLOCK,
+        ]),
+        note: None,
+    },
+    Definition {
+        name: "VariableDec",
+        production: Sequence(&[
+            Rule("Type"),
+            Rule("Declarator"),
+            ZeroOrMore(&Sequence(&[Token(","), Rule("Declarator")])),
+            SEMI,
+        ]),
+        note: None,
+    },
+    Definition {
+        name: "Declarator",
+        production: Sequence(&[IDENT, Optional(&Sequence(&[Token("="), EXPR]))]),
+        note: Some("without a value, it must be assigned before it is read"),
+    },
+    Definition {
+        name: "VariableAsm",
+        production: Sequence(&[
+            IDENT,
+            Choice(&[Sequence(&[Token("="), EXPR]), Token("++"), Token("--")]),
+            SEMI,
+        ]),
+        note: Some("x++ is x = x + 1 and x-- is x = x - 1; x must be i32s, f32s or char"),
+    },
+    Definition {
+        name: "ParallelAsm",
+        production: Sequence(&[
+            IDENT,
+            OneOrMore(&Sequence(&[Token(","), IDENT])),
+            Token("="),
+            EXPR,
+            OneOrMore(&Sequence(&[Token(","), EXPR])),
+            SEMI,
+        ]),
+        note: Some("every value is read before any variable is assigned"),
+    },
+    Definition {
+        name: "DerefAsm",
+        production: Sequence(&[Token("*"), IDENT, Token("="), EXPR, SEMI]),
+        note: Some("Ident must be a reference"),
+    },
+    Definition {
+        name: "For",
+        production: Sequence(&[
+            Token("for"),
+            Optional(&Rule("Type")),
+            IDENT,
+            Token("in"),
+            EXPR,
+            Choice(&[Token("to"), Token("until")]),
+            EXPR,
+            Optional(&Sequence(&[Token("step"), EXPR])),
+            BLOCK,
+        ]),
+        note: Some("Type is i32s (the default) or f32s; the step must be positive"),
+    },
+    Definition {
+        name: "While",
+        production: Sequence(&[Token("while"), EXPR, BLOCK]),
+        note: None,
+    },
+    Definition {
+        name: "DoWhile",
+        production: Sequence(&[Token("do"), BLOCK, Token("while"), EXPR, SEMI]),
+        note: None,
+    },
+    Definition {
+        name: "If",
+        production: Sequence(&[Token("if"), EXPR, BLOCK, Rule("Else")]),
+        note: None,
+    },
+    Definition {
+        name: "Match",
+        production: Sequence(&[
+            Token("match"),
+            EXPR,
+            Token("{"),
+            ZeroOrMore(&Rule("MatchArm")),
+            Token("}"),
+        ]),
+        note: None,
+    },
+    Definition {
+        name: "MatchArm",
+        production: Sequence(&[
+            Choice(&[Rule("Int_Lit"), Token("else")]),
+            Token("=>"),
+            BLOCK,
+        ]),
+        note: None,
+    },
+    Definition {
+        name: "Else",
+        production: Choice(&[
+            Sequence(&[Token("else"), Rule("If")]),
+            Sequence(&[Token("else"), BLOCK]),
+            Empty,
+        ]),
+        note: None,
+    },
+    Definition {
+        name: "Block",
+        production: Sequence(&[Token("{"), ZeroOrMore(&Rule("Stmt")), Token("}")]),
+        note: None,
+    },
+    Definition {
+        name: "Function",
+        production: Choice(&[
+            Sequence(&[
+                Optional(&Token("export")),
+                Token("fn"),
+                Rule("Signature"),
+                Choice(&[Sequence(&[Token("="), EXPR, SEMI]), BLOCK]),
+            ]),
+            Sequence(&[Token("extern"), Token("fn"), Rule("Signature"), SEMI]),
+        ]),
+        note: None,
+    },
+    Definition {
+        name: "Signature",
+        production: Sequence(&[
+            IDENT,
+            Token("("),
+            Optional(&Rule("Params")),
+            Token(")"),
+            Token("->"),
+            Choice(&[Rule("Type"), Token("void")]),
+        ]),
+        note: None,
+    },
+    Definition {
+        name: "Return",
+        production: Sequence(&[Token("return"), Optional(&EXPR), SEMI]),
+        note: Some("only inside a function body; no value in a void function"),
+    },
+    Definition {
+        name: "ExprStmt",
+        production: Sequence(&[Rule("Call"), SEMI]),
+        note: Some("the result is discarded"),
+    },
+    Definition {
+        name: "Params",
+        production: Sequence(&[
+            Rule("Param"),
+            ZeroOrMore(&Sequence(&[Token(","), Rule("Param")])),
+        ]),
+        note: None,
+    },
+    Definition {
+        name: "Param",
+        production: Sequence(&[Optional(&Token("ref")), Rule("Type"), IDENT]),
+        note: Some("a ref parameter takes \"&\" Ident"),
+    },
+    Definition {
+        name: "Type",
+        production: Choice(&[Token("i32s"), Token("f32s"), Token("bool"), Token("char")]),
+        note: None,
+    },
+    Definition {
+        name: "Ident",
+        production: Prose(
+            "user-defined non-keyword: ASCII letters, digits and \"_\", not starting with a digit",
+        ),
+        note: None,
+    },
+    Definition {
+        name: "Exit",
+        production: Sequence(&[Token("exit"), EXPR, SEMI]),
+        note: None,
+    },
+    Definition {
+        name: "PrintInt",
+        production: Sequence(&[Token("print_int"), Token("("), EXPR, Token(")"), SEMI]),
+        note: None,
+    },
+    Definition {
+        name: "Assert",
+        production: Sequence(&[Token("assert"), EXPR, SEMI]),
+        note: Some("Expr must be bool"),
+    },
+    Definition {
+        name: "Const",
+        production: Sequence(&[Token("const"), IDENT, Token("="), EXPR, SEMI]),
+        note: Some("Expr must be a constant expression"),
+    },
+    Definition {
+        name: "Define",
+        production: Sequence(&[Token("define"), IDENT, EXPR, SEMI]),
+        note: Some("each later use of Ident stands for Expr"),
+    },
+    Definition {
+        name: "Expr",
+        production: Rule("Equality"),
+        note: None,
+    },
+    Definition {
+        name: "Equality",
+        production: Level(1),
+        note: None,
+    },
+    Definition {
+        name: "Comparison",
+        production: Level(2),
+        note: None,
+    },
+    Definition {
+        name: "Add",
+        production: Level(3),
+        note: None,
+    },
+    Definition {
+        name: "Mul",
+        production: Level(4),
+        note: Some("integer \"/\" truncates toward zero"),
+    },
+    Definition {
+        name: "Primary",
+        production: Choice(&[
+            Rule("Int_Lit"),
+            Rule("Float_Lit"),
+            Rule("Bool_Lit"),
+            Rule("Char_Lit"),
+            IDENT,
+            Rule("Call"),
+            Rule("Convert"),
+            Sequence(&[Token("&"), IDENT]),
+            Sequence(&[Token("*"), IDENT]),
+            Sequence(&[Token("-"), Rule("Primary")]),
+            Sequence(&[Token("("), EXPR, Token(")")]),
+        ]),
+        note: None,
+    },
+    Definition {
+        name: "Convert",
+        production: Sequence(&[
+            Choice(&[Token("i32s"), Token("f32s")]),
+            Token("("),
+            EXPR,
+            Token(")"),
+        ]),
+        note: Some("Expr must be i32s or f32s; f32s to i32s truncates toward zero"),
+    },
+    Definition {
+        name: "Call",
+        production: Sequence(&[
+            IDENT,
+            Token("("),
+            Optional(&Sequence(&[
+                EXPR,
+                ZeroOrMore(&Sequence(&[Token(","), EXPR])),
+            ])),
+            Token(")"),
+        ]),
+        note: Some("builtin: read_int()"),
+    },
+    Definition {
+        name: "Int_Lit",
+        production: Prose(
+            "integer literal: decimal, 0x hex, 0o octal or 0b binary, \"_\" separators \
+             allowed; a decimal literal may end in i32, or in f32 to make it a Float_Lit \
+             (3f32)",
+        ),
+        note: None,
+    },
+    Definition {
+        name: "Float_Lit",
+        production: Prose(
+            "floating point literal: digits on at least one side of \".\" (0.5, .5, 5.), \
+             optional exponent (1e5, 2.5e-3), optional f32 suffix (1.0f32)",
+        ),
+        note: None,
+    },
+    Definition {
+        name: "Bool_Lit",
+        production: Choice(&[Token("true"), Token("false")]),
+        note: None,
+    },
+    Definition {
+        name: "Char_Lit",
+        production: Prose("character literal"),
+        note: None,
+    },
+    Definition {
+        name: "Comment",
+        production: Sequence(&[Token("//"), Prose("anything up to the end of the line")]),
+        note: Some("ignored between tokens"),
+    },
+];
+
+// The grammar in EBNF, one rule per line: `?`, `*` and `+` for optional and repeated
+// parts, quoted tokens, and prose and notes between asterisks
+pub fn ebnf() -> String {
+    let mut out = String::new();
+    for rule in &RULES {
+        let name = if rule.name.contains(' ') {
+            format!("\"{}\"", rule.name)
+        } else {
+            rule.name.to_string()
+        };
+        let production = render(&rule.production, true);
+        let line = match rule.note {
+            Some(note) => format!("{:<16}→ {:<33}   *{}*", name, production, note),
+            None => format!("{:<16}→ {}", name, production),
+        };
+        out.push_str(line.trim_end());
+        out.push('\n');
+    }
+    out
+}
+
+// A symbol as EBNF. Only at the top of a rule does a choice go without parentheses.
+fn render(symbol: &Symbol, top: bool) -> String {
+    match symbol {
+        Token(spelling) => format!("\"{}\"", spelling),
+        Rule(name) => name.to_string(),
+        Prose(text) => format!("*{}*", text),
+        Empty => "ε".to_string(),
+        Sequence(symbols) => {
+            let parts: Vec<String> = symbols.iter().map(|s| render(s, false)).collect();
+            parts.join(" ")
+        }
+        Choice(symbols) => {
+            let parts: Vec<String> = symbols.iter().map(|s| render(s, false)).collect();
+            if top {
+                parts.join(" | ")
+            } else {
+                format!("({})", parts.join(" | "))
+            }
+        }
+        Optional(symbol) => format!("{}?", operand(symbol)),
+        ZeroOrMore(symbol) => format!("{}*", operand(symbol)),
+        OneOrMore(symbol) => format!("{}+", operand(symbol)),
+        Level(precedence) => {
+            let level = &LEVELS[*precedence as usize - 1];
+            let next = LEVELS
+                .get(*precedence as usize)
+                .map_or("Primary", |next| next.rule);
+            let spellings: Vec<String> = INFIX_OPERATORS
+                .iter()
+                .filter(|o| o.precedence == *precedence)
+                .filter_map(|o| o.token.spelling())
+                .map(|s| format!("\"{}\"", s))
+                .collect();
+            let operators = match spellings.as_slice() {
+                [one] => one.clone(),
+                _ => format!("({})", spellings.join(" | ")),
+            };
+            match level.associativity {
+                Associativity::Left => format!("{} ({} {})*", next, operators, next),
+                Associativity::Right => format!("{} ({} {})?", next, operators, level.rule),
+            }
+        }
+    }
+}
+
+// A symbol under a `?`, `*` or `+`, in parentheses unless it is a single one
+fn operand(symbol: &Symbol) -> String {
+    match symbol {
+        Sequence(_) | Choice(_) => format!("({})", render(symbol, true)),
+        _ => render(symbol, false),
+    }
+}
@@ -0,0 +1,98 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+// An identifier or other token text, interned: the same text always gives the same
+// Name, so names are copied and compared as a u32 rather than as a String. A Name means
+// something only on the thread that interned it, and only until that thread's table is
+// reset.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Name(u32);
+
+// The text of every name interned on a thread. It grows with the number of distinct
+// names, so commands that compile one program after another in the same process (watch,
+// repl and the language server) reset it between programs.
+#[derive(Clone, Default)]
+pub struct Interner {
+    names: HashMap<Arc<str>, Name>,
+    strings: Vec<Arc<str>>,
+}
+
+thread_local! {
+    static INTERNER: RefCell<Interner> = RefCell::new(Interner::default());
+}
+
+impl Name {
+    pub fn intern(text: &str) -> Name {
+        INTERNER.with_borrow_mut(|interner| {
+            if let Some(&name) = interner.names.get(text) {
+                return name;
+            }
+            let name = Name(interner.strings.len() as u32);
+            let text: Arc<str> = text.into();
+            interner.strings.push(text.clone());
+            interner.names.insert(text, name);
+            name
+        })
+    }
+
+    pub fn text(self) -> Arc<str> {
+        INTERNER.with_borrow(|interner| interner.strings[self.0 as usize].clone())
+    }
+
+    fn with_str<R>(self, f: impl FnOnce(&str) -> R) -> R {
+        INTERNER.with_borrow(|interner| f(&interner.strings[self.0 as usize]))
+    }
+}
+
+// A copy of this thread's table, for a thread that works on names interned here
+pub fn current() -> Interner {
+    INTERNER.with_borrow(Interner::clone)
+}
+
+// Makes `interner` this thread's table
+pub fn install(interner: Interner) {
+    INTERNER.set(interner);
+}
+
+// Forgets every name interned on this thread; a Name from before must not be used after
+pub fn reset() {
+    install(Interner::default());
+}
+
+// The number of names interned on this thread
+pub fn len() -> usize {
+    INTERNER.with_borrow(|interner| interner.strings.len())
+}
+
+impl From<&str> for Name {
+    fn from(text: &str) -> Self {
+        Name::intern(text)
+    }
+}
+
+impl PartialEq<str> for Name {
+    fn eq(&self, other: &str) -> bool {
+        self.with_str(|text| text == other)
+    }
+}
+
+impl PartialEq<&str> for Name {
+    fn eq(&self, other: &&str) -> bool {
+        self.with_str(|text| text == *other)
+    }
+}
+
+impl fmt::Display for Name {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.with_str(|text| f.write_str(text))
+    }
+}
+
+// Prints the text, so AST and token dumps read as they did with Strings
+impl fmt::Debug for Name {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.with_str(|text| fmt::Debug::fmt(text, f))
+    }
+}
@@ -0,0 +1,812 @@
+use crate::arena::Arena;
+use crate::ast::{Ast, BinOpType, Expr, ExprKind, Linkage, Node, NodeId, Stmt, Type};
+use crate::format;
+use crate::intern::Name;
+use crate::runtime::{self, FAILURE_HANDLERS};
+use crate::target::TargetSpec;
+use crate::tokenize::Span;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Write;
+use std::rc::Rc;
+
+// A tree-walking interpreter over the checked AST. It gives a program the meaning the
+// language intends without going through the code generator, so `noble test
+// --differential` can run both and compare what they did. It follows the freestanding
+// runtime where the runtimes differ, such as how read_int scans its input, and
+// evaluates call arguments left to right: a program whose result depends on that order
+// is reported as a mismatch, which is what it is to a reader of the source. `noble run
+// --interpret` runs programs in it too, and with --trace it shows them step by step;
+// `noble debug` stops them between statements through a Debugger.
+
+pub struct Outcome {
+    // what exit was given, 0 after the last statement, or the status of a failed check
+    pub status: i32,
+    // everything print_int wrote, one number per line
+    pub output: String,
+    // the top-level variables in scope when the program stopped, in the order they were
+    // declared, each with its value written out
+    pub variables: Vec<(Name, String)>,
+}
+
+impl Outcome {
+    // The status the process reports on the target: Linux keeps only the low 8 bits
+    pub fn process_status(&self, target: &TargetSpec) -> i32 {
+        if target.freestanding {
+            self.status & 0xff
+        } else {
+            self.status
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum InterpretError {
+    // the body is in another object file
+    Extern(Name),
+    // probably does not terminate
+    StepLimit(u64),
+    CallDepth(usize),
+    // the debugger ended the run
+    Stopped,
+}
+
+impl fmt::Display for InterpretError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InterpretError::Extern(name) => {
+                write!(f, "calls extern function {}", name)
+            }
+            InterpretError::StepLimit(steps) => {
+                write!(f, "still running after {} statements", steps)
+            }
+            InterpretError::CallDepth(depth) => {
+                write!(f, "calls nest more than {} deep", depth)
+            }
+            InterpretError::Stopped => write!(f, "stopped by the debugger"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Value {
+    Int(i32),
+    Float(f32),
+    Bool(bool),
+    // a byte, like the C char it is passed as
+    Char(u8),
+    // the cell a ref parameter points at
+    Ref(usize),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Int(value) => write!(f, "{}", value),
+            // always with a point or an exponent, so it reads as an f32s
+            Value::Float(value) => write!(f, "{:?}", value),
+            Value::Bool(value) => write!(f, "{}", value),
+            Value::Char(value) => write!(f, "{:?}", *value as char),
+            Value::Ref(cell) => write!(f, "ref to cell {}", cell),
+        }
+    }
+}
+
+// How a statement finished
+enum Flow {
+    Next,
+    Return(Option<Value>),
+}
+
+// Why the program stopped before its last statement
+enum Halt {
+    Exit(i32),
+    Error(InterpretError),
+}
+
+impl From<InterpretError> for Halt {
+    fn from(error: InterpretError) -> Self {
+        Halt::Error(error)
+    }
+}
+
+// Stops a program between statements, as `noble debug` does. It is called before each
+// statement that begins a statement of the source, can look at where the program is and
+// what is in scope, and returns false to end the run there.
+pub trait Debugger {
+    fn before(&mut self, at: Span, interpreter: &Interpreter) -> bool;
+}
+
+// Where --trace writes each statement as it runs, with the variables in scope and what
+// the statement's own expressions evaluated to
+struct Trace<'a> {
+    // the source, a line per element, that statements are quoted from
+    lines: Vec<String>,
+    sink: Box<dyn Write + 'a>,
+    // how many evaluations of the running statement are unfinished; only those of its
+    // outermost expressions are written
+    nesting: usize,
+}
+
+struct Function<'a> {
+    params: &'a [(Name, Type)],
+    body: NodeId,
+    linkage: Linkage,
+    // the top-level variables declared before the function, which are all it can see
+    globals: Rc<HashMap<Name, usize>>,
+}
+
+pub struct Interpreter<'a> {
+    nodes: &'a Arena<Node>,
+    root: NodeId,
+    asserts: bool,
+    checked_arithmetic: bool,
+    input: Vec<u8>,
+    position: usize,
+    output: String,
+    // where what print_int prints also goes as it is printed
+    echo: Option<Box<dyn Write + 'a>>,
+    // every live variable, innermost scope last; a scope truncates it when it ends
+    cells: Vec<Value>,
+    // cell indices by name in the running function, or at the top level
+    scopes: Vec<HashMap<Name, usize>>,
+    globals: Rc<HashMap<Name, usize>>,
+    functions: HashMap<Name, Function<'a>>,
+    steps: u64,
+    step_limit: u64,
+    depth: usize,
+    depth_limit: usize,
+    trace: Option<Trace<'a>>,
+    debugger: Option<Box<dyn Debugger + 'a>>,
+    // the span of the statement running, whose parts the debugger does not stop at
+    running: Span,
+}
+
+impl<'a> Interpreter<'a> {
+    pub fn new(ast: &'a Ast) -> Self {
+        Interpreter {
+            nodes: &ast.nodes,
+            root: ast.root,
+            asserts: true,
+            checked_arithmetic: false,
+            input: Vec::new(),
+            position: 0,
+            output: String::new(),
+            echo: None,
+            cells: Vec::new(),
+            scopes: Vec::new(),
+            globals: Rc::new(HashMap::new()),
+            functions: HashMap::new(),
+            steps: 0,
+            step_limit: 10_000_000,
+            depth: 0,
+            depth_limit: 10_000,
+            trace: None,
+            debugger: None,
+            running: Span::default(),
+        }
+    }
+
+    pub fn set_asserts(&mut self, enabled: bool) {
+        self.asserts = enabled;
+    }
+
+    pub fn set_checked_arithmetic(&mut self, enabled: bool) {
+        self.checked_arithmetic = enabled;
+    }
+
+    // what read_int reads from; empty by default, like stdin at its end
+    pub fn set_input(&mut self, input: &[u8]) {
+        self.input = input.to_vec();
+        self.position = 0;
+    }
+
+    pub fn set_step_limit(&mut self, steps: u64) {
+        self.step_limit = steps;
+    }
+
+    // Each call nests a few Rust frames, so a deep limit needs a thread with a big stack
+    pub fn set_depth_limit(&mut self, depth: usize) {
+        self.depth_limit = depth;
+    }
+
+    // Writes a line to `sink` for every statement it runs, quoting its line of `source`,
+    // then the variables in scope before it runs and the value of each expression it
+    // evaluates, other than literals. A loop is written again each time its condition
+    // or bound is checked after the first.
+    pub fn set_trace(&mut self, source: &str, sink: impl Write + 'a) {
+        self.trace = Some(Trace {
+            lines: source.lines().map(|line| line.trim().to_string()).collect(),
+            sink: Box::new(sink),
+            nesting: 0,
+        });
+    }
+
+    // Also writes what print_int prints to `sink` as it is printed, for a program that is
+    // watched while it runs rather than checked after
+    pub fn set_output(&mut self, sink: impl Write + 'a) {
+        self.echo = Some(Box::new(sink));
+    }
+
+    pub fn set_debugger(&mut self, debugger: impl Debugger + 'a) {
+        self.debugger = Some(Box::new(debugger));
+    }
+
+    // How many calls deep the running statement is, 0 at the top level
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    // The variables the running code can see, in the order they were declared, each with
+    // its value written out. A ref parameter is given as *name, by what it points at.
+    pub fn variables(&self) -> Vec<(String, String)> {
+        let mut visible: HashMap<Name, usize> = self
+            .globals
+            .iter()
+            .map(|(&name, &cell)| (name, cell))
+            .collect();
+        for scope in &self.scopes {
+            visible.extend(scope.iter().map(|(&name, &cell)| (name, cell)));
+        }
+        let mut cells: Vec<(usize, Name)> = visible
+            .into_iter()
+            .map(|(name, cell)| (cell, name))
+            .collect();
+        cells.sort();
+        cells
+            .into_iter()
+            .map(|(cell, name)| match self.cells[cell] {
+                Value::Ref(referent) => (format!("*{}", name), self.cells[referent].to_string()),
+                value => (name.to_string(), value.to_string()),
+            })
+            .collect()
+    }
+
+    // The top-level scope is the run's own rather than a body's, so that its variables
+    // outlive the last statement or the exit that stopped the program
+    pub fn run(mut self) -> Result<Outcome, InterpretError> {
+        self.scopes.push(HashMap::new());
+        let status = match self.statement(self.root) {
+            Ok(_) => 0,
+            Err(Halt::Exit(status)) => status,
+            Err(Halt::Error(error)) => return Err(error),
+        };
+        let mut cells: Vec<(usize, Name)> = self.scopes[0]
+            .iter()
+            .map(|(&name, &cell)| (cell, name))
+            .collect();
+        cells.sort();
+        let variables = cells
+            .into_iter()
+            .map(|(cell, name)| (name, self.cells[cell].to_string()))
+            .collect();
+        Ok(Outcome {
+            status,
+            output: self.output,
+            variables,
+        })
+    }
+
+    fn body(&mut self, body: &[NodeId]) -> Result<Flow, Halt> {
+        self.scoped(HashMap::new(), |interpreter| {
+            for &id in body {
+                if let Flow::Return(value) = interpreter.statement(id)? {
+                    return Ok(Flow::Return(value));
+                }
+            }
+            Ok(Flow::Next)
+        })
+    }
+
+    // Runs `f` in a new scope holding `bindings`, the cells pushed last, and frees them
+    // and every cell pushed after them when the scope ends
+    fn scoped<T>(
+        &mut self,
+        bindings: HashMap<Name, usize>,
+        f: impl FnOnce(&mut Self) -> Result<T, Halt>,
+    ) -> Result<T, Halt> {
+        let live = self.cells.len() - bindings.len();
+        self.scopes.push(bindings);
+        let result = f(self);
+        self.scopes.pop();
+        self.cells.truncate(live);
+        result
+    }
+
+    fn statement(&mut self, id: NodeId) -> Result<Flow, Halt> {
+        let enclosing = std::mem::replace(&mut self.running, self.nodes[id].span);
+        let flow = self.pause(id, enclosing).and_then(|_| self.traced(id));
+        self.running = enclosing;
+        flow
+    }
+
+    // Hands control to the debugger before a statement it stops at: not a declaration
+    // of a function or constant, which runs nothing, and not a statement the parser made
+    // as part of the one enclosing it, which has that statement's span
+    fn pause(&mut self, id: NodeId, enclosing: Span) -> Result<(), Halt> {
+        let node = &self.nodes[id];
+        if self.debugger.is_none()
+            || node.span.line == 0
+            || node.span == enclosing
+            || matches!(
+                node.stmt,
+                Stmt::FunctionDeclaration { .. }
+                    | Stmt::ConstantDeclaration { .. }
+                    | Stmt::Define { .. }
+            )
+        {
+            return Ok(());
+        }
+        let mut debugger = self.debugger.take().unwrap();
+        let go_on = debugger.before(node.span, self);
+        self.debugger = Some(debugger);
+        if go_on {
+            Ok(())
+        } else {
+            Err(InterpretError::Stopped.into())
+        }
+    }
+
+    fn traced(&mut self, id: NodeId) -> Result<Flow, Halt> {
+        let Some(trace) = &mut self.trace else {
+            return self.execute(id);
+        };
+        // a call in an expression runs whole statements of its own
+        let nesting = std::mem::take(&mut trace.nesting);
+        self.trace_statement(id);
+        let flow = self.execute(id);
+        if let Some(trace) = &mut self.trace {
+            trace.nesting = nesting;
+        }
+        flow
+    }
+
+    fn trace_statement(&mut self, id: NodeId) {
+        let span = self.nodes[id].span;
+        if span.line == 0 {
+            return;
+        }
+        let bindings: Vec<String> = self
+            .variables()
+            .into_iter()
+            .map(|(name, value)| format!("{} = {}", name, value))
+            .collect();
+        let Some(trace) = &mut self.trace else {
+            return;
+        };
+        let text = trace.lines.get(span.line - 1).map_or("", String::as_str);
+        let _ = writeln!(trace.sink, "{}:{} {}", span.line, span.column, text);
+        if !bindings.is_empty() {
+            let _ = writeln!(trace.sink, "    {}", bindings.join(", "));
+        }
+    }
+
+    fn execute(&mut self, id: NodeId) -> Result<Flow, Halt> {
+        self.step()?;
+        let nodes = self.nodes;
+        let node = &nodes[id];
+        match &node.stmt {
+            Stmt::Entry => {
+                for &id in &node.children {
+                    self.statement(id)?;
+                }
+            }
+            Stmt::Exit(expr) => return Err(Halt::Exit(self.int(expr)?)),
+            Stmt::PrintInt(expr) => {
+                let value = self.int(expr)?;
+                self.output.push_str(&format!("{}\n", value));
+                if let Some(echo) = &mut self.echo {
+                    let _ = writeln!(echo, "{}", value);
+                    let _ = echo.flush();
+                }
+            }
+            // a disabled assert does not evaluate its condition
+            Stmt::Assert(expr) => {
+                if self.asserts && !self.bool(expr)? {
+                    return Err(Halt::Exit(status(runtime::ASSERT_FAIL)));
+                }
+            }
+            // the value is evaluated before the name comes into scope, so it can read
+            // a variable the declaration shadows
+            Stmt::VariableDeclaration { name, type_, value } => {
+                let value = match value {
+                    Some(value) => self.eval(value)?,
+                    None => zero(type_),
+                };
+                self.cells.push(value);
+                let cell = self.cells.len() - 1;
+                self.scopes.last_mut().unwrap().insert(*name, cell);
+            }
+            Stmt::VariableAssignment { name, value } => {
+                let value = self.eval(value)?;
+                let cell = self.resolve(*name);
+                self.cells[cell] = value;
+            }
+            Stmt::DerefAssignment { name, value } => {
+                let value = self.eval(value)?;
+                let cell = self.referent(*name);
+                self.cells[cell] = value;
+            }
+            // the end is evaluated again before every iteration and the step after each
+            // one, both in the enclosing scope
+            Stmt::For {
+                iterator_name,
+                type_,
+                iterator_begin,
+                iterator_end,
+                inclusive,
+                step,
+                body,
+            } => {
+                let within = if *inclusive {
+                    BinOpType::LessThanOrEqual
+                } else {
+                    BinOpType::LessThan
+                };
+                let mut iterator = self.eval(iterator_begin)?;
+                loop {
+                    self.step()?;
+                    let end = self.eval(iterator_end)?;
+                    // a NaN on either side compares false and ends the loop
+                    if let Value::Bool(false) = self.binary(iterator, &within, end)? {
+                        break;
+                    }
+                    self.cells.push(iterator);
+                    let bindings = HashMap::from([(*iterator_name, self.cells.len() - 1)]);
+                    let flow = self.scoped(bindings, |interpreter| interpreter.statement(*body))?;
+                    if let Flow::Return(value) = flow {
+                        return Ok(Flow::Return(value));
+                    }
+                    self.trace_statement(id);
+                    let step = match step {
+                        Some(step) => self.eval(step)?,
+                        None if *type_ == Type::F32S => Value::Float(1.0),
+                        None => Value::Int(1),
+                    };
+                    // the iterator wraps whether or not arithmetic is checked
+                    iterator = match (iterator, step) {
+                        (Value::Int(value), Value::Int(step)) => {
+                            Value::Int(value.wrapping_add(step))
+                        }
+                        (Value::Float(value), Value::Float(step)) => Value::Float(value + step),
+                        (value, step) => panic!("cannot step {:?} by {:?}", value, step),
+                    };
+                }
+            }
+            Stmt::If {
+                condition,
+                body,
+                else_body,
+            } => {
+                if self.bool(condition)? {
+                    return self.statement(*body);
+                }
+                if let Some(else_body) = else_body {
+                    return self.statement(*else_body);
+                }
+            }
+            Stmt::Block { body } => return self.body(body),
+            Stmt::While { condition, body } => {
+                while self.bool(condition)? {
+                    self.step()?;
+                    if let Flow::Return(value) = self.statement(*body)? {
+                        return Ok(Flow::Return(value));
+                    }
+                    self.trace_statement(id);
+                }
+            }
+            Stmt::DoWhile { body, condition } => loop {
+                self.step()?;
+                if let Flow::Return(value) = self.statement(*body)? {
+                    return Ok(Flow::Return(value));
+                }
+                self.trace_statement(id);
+                if !self.bool(condition)? {
+                    break;
+                }
+            },
+            Stmt::Match {
+                scrutinee,
+                arms,
+                else_body,
+            } => {
+                let scrutinee = self.int(scrutinee)?;
+                match arms.iter().find(|(value, _)| *value == scrutinee) {
+                    Some(&(_, body)) => return self.statement(body),
+                    None => {
+                        if let Some(else_body) = else_body {
+                            return self.statement(*else_body);
+                        }
+                    }
+                }
+            }
+            // functions are only declared at the top level, so every scope is a global one
+            Stmt::FunctionDeclaration {
+                name,
+                params,
+                body,
+                linkage,
+                ..
+            } => {
+                let globals = self
+                    .scopes
+                    .iter()
+                    .flatten()
+                    .map(|(&name, &cell)| (name, cell))
+                    .collect();
+                let function = Function {
+                    params,
+                    body: *body,
+                    linkage: *linkage,
+                    globals: Rc::new(globals),
+                };
+                self.functions.insert(*name, function);
+            }
+            Stmt::Return(value) => {
+                let value = match value {
+                    Some(value) => Some(self.eval(value)?),
+                    None => None,
+                };
+                return Ok(Flow::Return(value));
+            }
+            Stmt::Expression(expr) => {
+                self.eval(expr)?;
+            }
+            Stmt::ConstantDeclaration { .. } | Stmt::Define { .. } => {}
+        }
+        Ok(Flow::Next)
+    }
+
+    fn step(&mut self) -> Result<(), InterpretError> {
+        self.steps += 1;
+        if self.steps > self.step_limit {
+            return Err(InterpretError::StepLimit(self.step_limit));
+        }
+        Ok(())
+    }
+
+    fn resolve(&self, name: Name) -> usize {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(&name))
+            .or_else(|| self.globals.get(&name))
+            .copied()
+            .unwrap_or_else(|| panic!("{} is not in scope after type checking", name))
+    }
+
+    // The cell the reference `name` points at
+    fn referent(&self, name: Name) -> usize {
+        match self.cells[self.resolve(name)] {
+            Value::Ref(cell) => cell,
+            value => panic!("{} holds {:?}, not a reference", name, value),
+        }
+    }
+
+    fn int(&mut self, expr: &Expr) -> Result<i32, Halt> {
+        match self.eval(expr)? {
+            Value::Int(value) => Ok(value),
+            value => panic!("expected an i32s, found {:?}", value),
+        }
+    }
+
+    fn bool(&mut self, expr: &Expr) -> Result<bool, Halt> {
+        match self.eval(expr)? {
+            Value::Bool(value) => Ok(value),
+            value => panic!("expected a bool, found {:?}", value),
+        }
+    }
+
+    fn eval(&mut self, expr: &Expr) -> Result<Value, Halt> {
+        let Some(trace) = &mut self.trace else {
+            return self.evaluate(expr);
+        };
+        trace.nesting += 1;
+        let value = self.evaluate(expr);
+        let trace = self.trace.as_mut().unwrap();
+        trace.nesting -= 1;
+        // a literal's value is in the source already, and a void call has none
+        let shown = !matches!(
+            expr.kind,
+            ExprKind::Int(_) | ExprKind::Float(_) | ExprKind::Bool(_) | ExprKind::Char(_)
+        ) && expr.type_ != Some(Type::Void);
+        if trace.nesting == 0
+            && shown
+            && let Ok(value) = &value
+        {
+            let _ = writeln!(trace.sink, "    {} => {}", format::source(expr), value);
+        }
+        value
+    }
+
+    fn evaluate(&mut self, expr: &Expr) -> Result<Value, Halt> {
+        Ok(match &expr.kind {
+            ExprKind::Int(value) => Value::Int(*value),
+            ExprKind::Float(value) => Value::Float(*value),
+            ExprKind::Bool(value) => Value::Bool(*value),
+            ExprKind::Char(value) => Value::Char(*value as u32 as u8),
+            ExprKind::Ident(name) => self.cells[self.resolve(*name)],
+            ExprKind::AddressOf(name) => Value::Ref(self.resolve(*name)),
+            ExprKind::Deref(name) => self.cells[self.referent(*name)],
+            ExprKind::Convert { value, to } => match (self.eval(value)?, to) {
+                (Value::Int(value), Type::F32S) => Value::Float(value as f32),
+                // cvttss2si gives i32 MIN for NaN and anything out of range
+                (Value::Float(value), Type::I32S) => {
+                    let truncated = value.trunc();
+                    if (-2147483648.0..2147483648.0).contains(&truncated) {
+                        Value::Int(truncated as i32)
+                    } else {
+                        Value::Int(i32::MIN)
+                    }
+                }
+                (value, _) => value,
+            },
+            ExprKind::Negate { value } => match self.eval(value)? {
+                Value::Float(value) => Value::Float(-value),
+                Value::Int(value) => match value.checked_neg() {
+                    Some(negated) => Value::Int(negated),
+                    None if self.checked_arithmetic => {
+                        return Err(Halt::Exit(status(runtime::OVERFLOW_FAIL)));
+                    }
+                    None => Value::Int(value),
+                },
+                value => panic!("cannot negate {:?}", value),
+            },
+            ExprKind::Call { name, args } => return self.call(*name, args),
+            ExprKind::BinaryOp { left, op, right } => {
+                let left = self.eval(left)?;
+                let right = self.eval(right)?;
+                self.binary(left, op, right)?
+            }
+        })
+    }
+
+    fn binary(&self, left: Value, op: &BinOpType, right: Value) -> Result<Value, Halt> {
+        Ok(match (left, right) {
+            (Value::Int(left), Value::Int(right)) => {
+                if !op.is_arithmetic() {
+                    return Ok(Value::Bool(compare(op, &left, &right)));
+                }
+                let (value, overflowed) = match op {
+                    BinOpType::Add => left.overflowing_add(right),
+                    BinOpType::Subtract => left.overflowing_sub(right),
+                    BinOpType::Multiply => left.overflowing_mul(right),
+                    _ => return self.divide(left, right).map(Value::Int),
+                };
+                if overflowed && self.checked_arithmetic {
+                    return Err(Halt::Exit(status(runtime::OVERFLOW_FAIL)));
+                }
+                Value::Int(value)
+            }
+            (Value::Float(left), Value::Float(right)) => match op {
+                BinOpType::Add => Value::Float(left + right),
+                BinOpType::Subtract => Value::Float(left - right),
+                BinOpType::Multiply => Value::Float(left * right),
+                BinOpType::Divide => Value::Float(left / right),
+                _ => Value::Bool(compare(op, &left, &right)),
+            },
+            // wraps to a byte whether or not arithmetic is checked
+            (Value::Char(left), Value::Char(right)) => match op {
+                BinOpType::Add => Value::Char(left.wrapping_add(right)),
+                BinOpType::Subtract => Value::Char(left.wrapping_sub(right)),
+                BinOpType::Multiply => Value::Char(left.wrapping_mul(right)),
+                BinOpType::Divide => match left.checked_div(right) {
+                    Some(quotient) => Value::Char(quotient),
+                    None => return Err(Halt::Exit(status(runtime::DIVISION_BY_ZERO_FAIL))),
+                },
+                _ => Value::Bool(compare(op, &left, &right)),
+            },
+            (Value::Bool(left), Value::Bool(right)) => Value::Bool(compare(op, &left, &right)),
+            (left, right) => panic!("cannot apply {} to {:?} and {:?}", op, left, right),
+        })
+    }
+
+    // Division truncates toward zero. A zero divisor exits like the division by zero
+    // check whether or not arithmetic is checked, since the SIGFPE of an unchecked one
+    // gives the same status. MIN / -1 is an overflow when checked and wraps to MIN when
+    // not, like the other operators.
+    fn divide(&self, left: i32, right: i32) -> Result<i32, Halt> {
+        if right == 0 {
+            return Err(Halt::Exit(status(runtime::DIVISION_BY_ZERO_FAIL)));
+        }
+        match left.checked_div(right) {
+            Some(quotient) => Ok(quotient),
+            None if self.checked_arithmetic => Err(Halt::Exit(status(runtime::OVERFLOW_FAIL))),
+            None => Ok(left.wrapping_div(right)),
+        }
+    }
+
+    fn call(&mut self, name: Name, args: &[Expr]) -> Result<Value, Halt> {
+        if name == "read_int" {
+            return Ok(Value::Int(self.read_int()));
+        }
+        let mut values = Vec::with_capacity(args.len());
+        for arg in args {
+            values.push(self.eval(arg)?);
+        }
+        let function = &self.functions[&name];
+        if function.linkage == Linkage::Extern {
+            return Err(InterpretError::Extern(name).into());
+        }
+        if self.depth == self.depth_limit {
+            return Err(InterpretError::CallDepth(self.depth_limit).into());
+        }
+        let (params, body) = (function.params, function.body);
+        let globals = Rc::clone(&function.globals);
+
+        let caller_globals = std::mem::replace(&mut self.globals, globals);
+        let caller_scopes = std::mem::take(&mut self.scopes);
+        let mut bindings = HashMap::new();
+        for ((param, _), value) in params.iter().zip(values) {
+            self.cells.push(value);
+            bindings.insert(*param, self.cells.len() - 1);
+        }
+        self.depth += 1;
+        let result = self.scoped(bindings, |interpreter| interpreter.statement(body));
+        self.depth -= 1;
+        self.scopes = caller_scopes;
+        self.globals = caller_globals;
+
+        // a void function gives nothing back, which an expression statement ignores
+        Ok(match result? {
+            Flow::Return(Some(value)) => value,
+            Flow::Return(None) | Flow::Next => Value::Int(0),
+        })
+    }
+
+    // Like the freestanding runtime: anything but a digit or '-' is skipped before the
+    // number and ends it afterwards, a '-' anywhere makes it negative, and at the end of
+    // the input the number read so far is the result
+    fn read_int(&mut self) -> i32 {
+        let mut value = 0i32;
+        let mut negative = false;
+        let mut digits = false;
+        while let Some(&byte) = self.input.get(self.position) {
+            self.position += 1;
+            match byte {
+                b'-' => negative = true,
+                b'0'..=b'9' => {
+                    value = value.wrapping_mul(10).wrapping_add((byte - b'0') as i32);
+                    digits = true;
+                }
+                _ if digits => break,
+                _ => {}
+            }
+        }
+        if negative {
+            value.wrapping_neg()
+        } else {
+            value
+        }
+    }
+}
+
+fn status(handler: &str) -> i32 {
+    FAILURE_HANDLERS
+        .iter()
+        .find(|(name, _)| *name == handler)
+        .map(|(_, status)| *status)
+        .unwrap()
+}
+
+// What a variable declared without a value holds until it is assigned
+fn zero(type_: &Type) -> Value {
+    match type_ {
+        Type::F32S => Value::Float(0.0),
+        Type::Bool => Value::Bool(false),
+        Type::Char => Value::Char(0),
+        _ => Value::Int(0),
+    }
+}
+
+fn compare<T: PartialOrd>(op: &BinOpType, left: &T, right: &T) -> bool {
+    match op {
+        BinOpType::Equal => left == right,
+        BinOpType::NotEqual => left != right,
+        BinOpType::LessThan => left < right,
+        BinOpType::LessThanOrEqual => left <= right,
+        BinOpType::GreaterThan => left > right,
+        BinOpType::GreaterThanOrEqual => left >= right,
+        _ => unreachable!("{} is not a comparison", op),
+    }
+}
@@ -0,0 +1,36 @@
+pub mod arena;
+pub mod ast;
+pub mod asm;
+pub mod cfg;
+pub mod config;
+pub mod consteval;
+pub mod debug;
+pub mod definite;
+pub mod diagnostic;
+pub mod diverge;
+pub mod encode;
+pub mod error;
+pub mod format;
+#[cfg(feature = "grammar-fuzz")]
+pub mod fuzz;
+pub mod generate;
+pub mod grammar;
+pub mod intern;
+pub mod interpret;
+pub mod json;
+pub mod lint;
+pub mod lsp;
+pub mod optimize;
+pub mod parse;
+pub mod pipeline;
+pub mod report;
+pub mod runtime;
+pub mod symtab;
+pub mod synthetic;
+pub mod target;
+pub mod tokenize;
+pub mod trace;
+pub mod typecheck;
+
+pub use pipeline::Pipeline;
+pub use tokenize::{TokenClass, classify, tokenize_lossless};
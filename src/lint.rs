@@ -0,0 +1,655 @@
+use crate::arena::Arena;
+use crate::ast::{Ast, BinOpType, Expr, ExprKind, Linkage, Node, NodeId, Stmt, Type};
+use crate::consteval::{self, Value};
+use crate::diagnostic::Diagnostic;
+use crate::diverge::Divergence;
+use crate::intern::Name;
+use crate::tokenize::Span;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Level {
+    // not checked at all
+    Allow,
+    Warn,
+    // reported as an error, which stops the build
+    Deny,
+}
+
+impl Level {
+    pub fn from_name(name: &str) -> Option<Level> {
+        match name {
+            "allow" => Some(Level::Allow),
+            "warn" => Some(Level::Warn),
+            "deny" => Some(Level::Deny),
+            _ => None,
+        }
+    }
+}
+
+// What a lint found: the message, the statement it concerns and an optional note
+struct Finding {
+    message: String,
+    span: Span,
+    note: Option<String>,
+}
+
+// A check over the type-checked AST that reports legal but suspicious code
+pub struct Lint {
+    pub name: &'static str,
+    // reported as the diagnostic's code, e.g. in --error-format json
+    pub code: &'static str,
+    pub default: Level,
+    pub summary: &'static str,
+    check: fn(&Ast) -> Vec<Finding>,
+}
+
+// Every lint; adding one is an entry here plus its check function
+pub const LINTS: [Lint; 5] = [
+    Lint {
+        name: "unused-variable",
+        code: "L0001",
+        default: Level::Warn,
+        summary: "a variable that is never read",
+        check: unused_variables,
+    },
+    Lint {
+        name: "constant-condition",
+        code: "L0002",
+        default: Level::Warn,
+        summary: "a condition or comparison that is always true or always false",
+        check: constant_conditions,
+    },
+    Lint {
+        name: "empty-loop",
+        code: "L0003",
+        default: Level::Warn,
+        summary: "a loop with an empty body, or a for loop whose constant bounds never let \
+                  the body run",
+        check: empty_loops,
+    },
+    Lint {
+        name: "shadowing",
+        code: "L0004",
+        default: Level::Warn,
+        summary: "a declaration that hides a variable of an enclosing scope",
+        check: shadowing,
+    },
+    Lint {
+        name: "unreachable-code",
+        code: "L0005",
+        default: Level::Warn,
+        summary: "a statement after one that never finishes, such as exit or return",
+        check: unreachable_code,
+    },
+];
+
+pub fn lint(name: &str) -> Option<&'static Lint> {
+    LINTS.iter().find(|lint| lint.name == name)
+}
+
+fn unknown_lint(name: &str) -> String {
+    let names: Vec<&str> = LINTS.iter().map(|lint| lint.name).collect();
+    format!("unknown lint {}; the lints are {}", name, names.join(", "))
+}
+
+// The level of every lint, its default unless --allow, --warn or --deny changed it
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Levels {
+    overrides: Vec<(&'static str, Level)>,
+}
+
+impl Levels {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // A later setting for the same lint wins
+    pub fn set(&mut self, name: &str, level: Level) -> Result<(), String> {
+        let lint = lint(name).ok_or_else(|| unknown_lint(name))?;
+        self.overrides
+            .retain(|(overridden, _)| *overridden != lint.name);
+        self.overrides.push((lint.name, level));
+        Ok(())
+    }
+
+    pub fn level(&self, lint: &Lint) -> Level {
+        self.overrides
+            .iter()
+            .find(|(name, _)| *name == lint.name)
+            .map_or(lint.default, |(_, level)| *level)
+    }
+}
+
+// A `// noble: allow(name, ...)` comment, or warn(...) or deny(...), which sets the
+// level of the named lints for the statement after it and everything nested in it
+struct Directive {
+    line: usize,
+    // whether code comes before the comment, which then applies to that line's statement
+    trailing: bool,
+    level: Level,
+    names: Vec<&'static str>,
+}
+
+// Runs every lint that is not allowed over a type-checked program. Warnings and errors
+// come back in source order; an error means a denied lint fired.
+pub fn run(ast: &Ast, source: &str, levels: &Levels) -> Vec<Diagnostic> {
+    let (directives, mut diagnostics) = directives(source);
+    let scopes: Vec<(Span, &Directive)> = directives
+        .iter()
+        .filter_map(|directive| Some((target(ast, directive)?, directive)))
+        .collect();
+
+    for lint in &LINTS {
+        // lints that are allowed everywhere are not run at all
+        let reachable = levels.level(lint) != Level::Allow
+            || scopes
+                .iter()
+                .any(|(_, d)| d.level != Level::Allow && d.names.contains(&lint.name));
+        if !reachable {
+            continue;
+        }
+        for finding in (lint.check)(ast) {
+            // the innermost directive covering the finding decides
+            let level = scopes
+                .iter()
+                .filter(|(span, d)| {
+                    d.names.contains(&lint.name)
+                        && span.start <= finding.span.start
+                        && finding.span.start < span.end
+                })
+                .min_by_key(|(span, _)| span.end - span.start)
+                .map_or(levels.level(lint), |(_, d)| d.level);
+            let message = format!("{} [{}]", finding.message, lint.name);
+            let diagnostic = match level {
+                Level::Allow => continue,
+                Level::Warn => Diagnostic::warning(format!("Warning: {}", message), finding.span),
+                Level::Deny => Diagnostic::new(format!("LintError: {}", message), finding.span),
+            };
+            let diagnostic = diagnostic.with_code(lint.code);
+            diagnostics.push(match finding.note {
+                Some(note) => diagnostic.with_note(note),
+                None => diagnostic,
+            });
+        }
+    }
+    diagnostics.sort_by_key(|d| d.span.start);
+    diagnostics
+}
+
+// The directives in the source, and a warning for each lint name they misspell
+fn directives(source: &str) -> (Vec<Directive>, Vec<Diagnostic>) {
+    let mut directives = Vec::new();
+    let mut warnings = Vec::new();
+    let mut offset = 0;
+    for (index, line) in source.split('\n').enumerate() {
+        let line_start = offset;
+        offset += line.chars().count() + 1;
+        let Some(at) = line.find("//") else {
+            continue;
+        };
+        let Some(text) = line[at + 2..].trim().strip_prefix("noble:") else {
+            continue;
+        };
+        let text = text.trim();
+        let Some((level, names)) = text
+            .strip_suffix(')')
+            .and_then(|text| text.split_once('('))
+            .and_then(|(level, names)| Some((Level::from_name(level.trim())?, names)))
+        else {
+            continue;
+        };
+        let column = line[..at].chars().count() + 1;
+        let mut directive = Directive {
+            line: index + 1,
+            trailing: !line[..at].trim().is_empty(),
+            level,
+            names: Vec::new(),
+        };
+        for name in names.split(',').map(str::trim) {
+            match lint(name) {
+                Some(lint) => directive.names.push(lint.name),
+                None => warnings.push(Diagnostic::warning(
+                    format!("Warning: {}", unknown_lint(name)),
+                    Span {
+                        start: line_start + column - 1,
+                        end: line_start + line.chars().count(),
+                        line: index + 1,
+                        column,
+                    },
+                )),
+            }
+        }
+        directives.push(directive);
+    }
+    (directives, warnings)
+}
+
+// The outermost statement a directive applies to: the first one on its line if it
+// trails code, otherwise the first one after it
+fn target(ast: &Ast, directive: &Directive) -> Option<Span> {
+    ast.nodes
+        .iter()
+        .map(|node| node.span)
+        .filter(|span| {
+            span.line > 0
+                && if directive.trailing {
+                    span.line == directive.line
+                } else {
+                    span.line > directive.line
+                }
+        })
+        .min_by_key(|span| (span.start, usize::MAX - span.end))
+}
+
+fn constant_conditions(ast: &Ast) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    for node in ast.nodes.iter() {
+        let condition = match &node.stmt {
+            Stmt::If { condition, .. } => Some((condition, "if")),
+            // `while true` is how a loop that ends by exit or return is written
+            Stmt::While { condition, .. } | Stmt::DoWhile { condition, .. }
+                if matches!(condition.kind, ExprKind::Bool(true)) =>
+            {
+                None
+            }
+            Stmt::While { condition, .. } => Some((condition, "while")),
+            Stmt::DoWhile { condition, .. } => Some((condition, "do-while")),
+            _ => None,
+        };
+        // a condition reported as a whole is not reported again part by part
+        let mut reported = None;
+        if let Some((condition, kind)) = condition
+            && let Ok(Value::Bool(value)) = consteval::evaluate(condition)
+        {
+            findings.push(Finding {
+                message: format!("{} condition is always {}", kind, value),
+                span: node.span,
+                note: None,
+            });
+            reported = Some(condition);
+        }
+
+        // an assert of constants checks a fact at compile time on purpose
+        let asserted = matches!(node.stmt, Stmt::Assert(_));
+        for expr in node.stmt.expressions() {
+            if reported.is_some_and(|condition| std::ptr::eq(condition, expr)) {
+                continue;
+            }
+            let found = expr
+                .walk()
+                .map(|visit| visit.expr)
+                .filter(|expr| is_comparison(expr));
+            for (message, constant) in found.filter_map(constant_comparison) {
+                if !(constant && asserted) {
+                    findings.push(Finding {
+                        message,
+                        span: node.span,
+                        note: None,
+                    });
+                }
+            }
+        }
+    }
+    findings
+}
+
+fn is_comparison(expr: &Expr) -> bool {
+    matches!(&expr.kind, ExprKind::BinaryOp { op, .. } if !op.is_arithmetic())
+}
+
+// What a comparison that does not depend on the values compared always gives, and
+// whether that is because both sides are constants. Comparing a variable with itself
+// counts, except for f32s, where NaN is not equal to itself.
+fn constant_comparison(expr: &Expr) -> Option<(String, bool)> {
+    let ExprKind::BinaryOp { left, op, right } = &expr.kind else {
+        return None;
+    };
+    let always = |left: String, right: String, value: bool| {
+        format!("comparison {} {} {} is always {}", left, op, right, value)
+    };
+    if let (Ok(Value::Bool(value)), Ok(l), Ok(r)) = (
+        consteval::evaluate(expr),
+        consteval::evaluate(left),
+        consteval::evaluate(right),
+    ) {
+        return Some((always(l.to_string(), r.to_string(), value), true));
+    }
+    let operand = match (&left.kind, &right.kind) {
+        (ExprKind::Ident(l), ExprKind::Ident(r)) if l == r => l.to_string(),
+        (ExprKind::Deref(l), ExprKind::Deref(r)) if l == r => format!("*{}", l),
+        _ => return None,
+    };
+    if !matches!(left.type_, Some(Type::I32S | Type::Bool | Type::Char)) {
+        return None;
+    }
+    let value = matches!(
+        op,
+        BinOpType::Equal | BinOpType::LessThanOrEqual | BinOpType::GreaterThanOrEqual
+    );
+    Some((always(operand.clone(), operand, value), false))
+}
+
+fn empty_loops(ast: &Ast) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    for node in ast.nodes.iter() {
+        let finding = |message: String, note: Option<&str>| Finding {
+            message,
+            span: node.span,
+            note: note.map(str::to_string),
+        };
+        match &node.stmt {
+            Stmt::For {
+                iterator_begin,
+                iterator_end,
+                inclusive,
+                step,
+                body,
+                ..
+            } => {
+                if let (Ok(Value::Int(begin)), Ok(Value::Int(end))) = (
+                    consteval::evaluate(iterator_begin),
+                    consteval::evaluate(iterator_end),
+                ) && (begin > end || (begin == end && !inclusive))
+                {
+                    let keyword = if *inclusive { "to" } else { "until" };
+                    findings.push(finding(
+                        format!(
+                            "for loop from {} {} {} never runs its body",
+                            begin, keyword, end
+                        ),
+                        None,
+                    ));
+                } else if ast.nodes[*body].block().is_empty()
+                    && !step.as_ref().is_some_and(Expr::has_call)
+                {
+                    findings.push(finding(
+                        "for loop has an empty body".to_string(),
+                        Some("it only counts; add the statements it should repeat"),
+                    ));
+                }
+            }
+            // a condition that calls a function may be doing the loop's work
+            Stmt::While { condition, body } | Stmt::DoWhile { body, condition }
+                if ast.nodes[*body].block().is_empty() && !condition.has_call() =>
+            {
+                let kind = match node.stmt {
+                    Stmt::While { .. } => "while",
+                    _ => "do-while",
+                };
+                findings.push(finding(
+                    format!("{} loop has an empty body", kind),
+                    Some(
+                        "nothing changes between checks of the condition, so the loop either \
+                         ends at once or never ends",
+                    ),
+                ));
+            }
+            _ => {}
+        }
+    }
+    findings
+}
+
+fn unused_variables(ast: &Ast) -> Vec<Finding> {
+    declarations(ast)
+        .into_iter()
+        .filter(|d| d.reportable && !d.read && !d.name.text().starts_with('_'))
+        .map(|d| Finding {
+            message: format!("{} is never read", d.name),
+            span: d.span,
+            note: Some(format!(
+                "remove it, or name it _{} if it is there on purpose",
+                d.name
+            )),
+        })
+        .collect()
+}
+
+fn shadowing(ast: &Ast) -> Vec<Finding> {
+    declarations(ast)
+        .into_iter()
+        .filter_map(|d| {
+            let outer = d.shadows?;
+            Some(Finding {
+                message: format!(
+                    "{} shadows the variable declared at line {}",
+                    d.name, outer.line
+                ),
+                span: d.span,
+                note: Some(format!(
+                    "the outer {} is hidden until the end of this block; rename one to use both",
+                    d.name
+                )),
+            })
+        })
+        .collect()
+}
+
+// A variable, loop iterator or parameter, with what the program does with it
+struct Declaration {
+    name: Name,
+    // the declaring statement
+    span: Span,
+    read: bool,
+    // only declaration statements; iterators and parameters are often unused by design
+    reportable: bool,
+    // the declaration of the variable of an enclosing scope this one hides
+    shadows: Option<Span>,
+}
+
+// Every declaration in the program, resolving names with the type checker's scopes
+fn declarations(ast: &Ast) -> Vec<Declaration> {
+    let mut resolver = Resolver {
+        declarations: Vec::new(),
+        scopes: vec![Vec::new()],
+    };
+    let root = ast.root;
+    for &child in &ast.nodes[root].children {
+        resolver.statement(&ast.nodes, child);
+    }
+    resolver.declarations
+}
+
+struct Resolver {
+    declarations: Vec<Declaration>,
+    // indices into declarations, innermost scope last
+    scopes: Vec<Vec<usize>>,
+}
+
+impl Resolver {
+    fn resolve(&self, name: Name) -> Option<usize> {
+        self.scopes
+            .iter()
+            .rev()
+            .flat_map(|scope| scope.iter().rev())
+            .copied()
+            .find(|&i| self.declarations[i].name == name)
+    }
+
+    fn declare(&mut self, name: Name, span: Span, reportable: bool) {
+        let shadows = self.resolve(name).map(|i| self.declarations[i].span);
+        self.declarations.push(Declaration {
+            name,
+            span,
+            read: false,
+            reportable,
+            shadows,
+        });
+        let index = self.declarations.len() - 1;
+        self.scopes.last_mut().unwrap().push(index);
+    }
+
+    fn read(&mut self, name: Name) {
+        if let Some(i) = self.resolve(name) {
+            self.declarations[i].read = true;
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        for visit in expr.walk() {
+            if let Some(name) = visit.expr.variable() {
+                self.read(name);
+            }
+        }
+    }
+
+    fn body(&mut self, nodes: &Arena<Node>, body: &[NodeId]) {
+        self.scopes.push(Vec::new());
+        for &stmt in body {
+            self.statement(nodes, stmt);
+        }
+        self.scopes.pop();
+    }
+
+    fn statement(&mut self, nodes: &Arena<Node>, id: NodeId) {
+        let node = &nodes[id];
+        match &node.stmt {
+            Stmt::Exit(expr)
+            | Stmt::PrintInt(expr)
+            | Stmt::Assert(expr)
+            | Stmt::Expression(expr)
+            | Stmt::Return(Some(expr)) => self.expr(expr),
+            Stmt::VariableDeclaration { name, value, .. } => {
+                if let Some(value) = value {
+                    self.expr(value);
+                }
+                self.declare(*name, node.span, true);
+            }
+            // assigning is not reading, even through a reference
+            Stmt::VariableAssignment { value, .. } => self.expr(value),
+            Stmt::DerefAssignment { name, value } => {
+                self.read(*name);
+                self.expr(value);
+            }
+            Stmt::For {
+                iterator_name,
+                iterator_begin,
+                iterator_end,
+                step,
+                body,
+                ..
+            } => {
+                self.expr(iterator_begin);
+                self.expr(iterator_end);
+                self.scopes.push(Vec::new());
+                self.declare(*iterator_name, node.span, false);
+                self.statement(nodes, *body);
+                self.scopes.pop();
+                if let Some(step) = step {
+                    self.expr(step);
+                }
+            }
+            Stmt::If {
+                condition,
+                body,
+                else_body,
+            } => {
+                self.expr(condition);
+                self.statement(nodes, *body);
+                if let Some(else_body) = else_body {
+                    self.statement(nodes, *else_body);
+                }
+            }
+            Stmt::While { condition, body } => {
+                self.expr(condition);
+                self.statement(nodes, *body);
+            }
+            Stmt::DoWhile { body, condition } => {
+                self.statement(nodes, *body);
+                self.expr(condition);
+            }
+            Stmt::Match {
+                scrutinee,
+                arms,
+                else_body,
+            } => {
+                self.expr(scrutinee);
+                for &(_, body) in arms {
+                    self.statement(nodes, body);
+                }
+                if let Some(body) = else_body {
+                    self.statement(nodes, *body);
+                }
+            }
+            Stmt::Block { body } => self.body(nodes, body),
+            // the body of an extern function is in another object file
+            Stmt::FunctionDeclaration { linkage, .. } if *linkage == Linkage::Extern => {}
+            Stmt::FunctionDeclaration { params, body, .. } => {
+                self.scopes.push(Vec::new());
+                for (param, _) in params {
+                    self.declare(*param, node.span, false);
+                }
+                self.statement(nodes, *body);
+                self.scopes.pop();
+            }
+            Stmt::Entry
+            | Stmt::Return(None)
+            | Stmt::ConstantDeclaration { .. }
+            | Stmt::Define { .. } => {}
+        }
+    }
+}
+
+// The first statement of each body that comes after one control never gets past. A
+// function or constant declaration there is left alone: it runs no code.
+fn unreachable_code(ast: &Ast) -> Vec<Finding> {
+    let divergence = Divergence::of(ast);
+    let mut findings = Vec::new();
+    for node in ast.nodes.iter() {
+        for body in node.bodies() {
+            let Some(end) = body
+                .iter()
+                .position(|&id| !divergence.statement_finishes(&ast.nodes, id))
+            else {
+                continue;
+            };
+            let unreachable = body[end + 1..]
+                .iter()
+                .map(|&id| &ast.nodes[id])
+                .find(|node| {
+                    !matches!(
+                        node.stmt,
+                        Stmt::FunctionDeclaration { .. }
+                            | Stmt::ConstantDeclaration { .. }
+                            | Stmt::Define { .. }
+                    )
+                });
+            if let Some(unreachable) = unreachable {
+                let end = &ast.nodes[body[end]];
+                let call = end
+                    .stmt
+                    .expressions()
+                    .into_iter()
+                    .flat_map(|expr| expr.walk())
+                    .find_map(|visit| match visit.expr.kind {
+                        ExprKind::Call { name, .. } if divergence.never_returns(name) => Some(name),
+                        _ => None,
+                    });
+                let line = end.span.line;
+                let note = match call {
+                    Some(name) => format!(
+                        "{} never returns, so control never gets past the call at line {}",
+                        name, line
+                    ),
+                    None => {
+                        let what = match end.stmt {
+                            Stmt::Exit(_) => "exit",
+                            Stmt::Return(_) => "return",
+                            Stmt::If { .. } => "if",
+                            Stmt::Match { .. } => "match",
+                            Stmt::Block { .. } => "block",
+                            _ => "loop",
+                        };
+                        format!("control never gets past the {} at line {}", what, line)
+                    }
+                };
+                findings.push(Finding {
+                    message: "unreachable statement".to_string(),
+                    span: unreachable.span,
+                    note: Some(note),
+                });
+            }
+        }
+    }
+    findings
+}
@@ -0,0 +1,577 @@
+use crate::diagnostic::{self, Diagnostic, Severity};
+use crate::intern;
+use crate::json::Json;
+use crate::lint;
+use crate::parse::Parser;
+use crate::symtab::{Symbol, SymbolKind};
+use crate::target::{self, TargetSpec};
+use crate::tokenize::{Span, TokenClass, TokenType, Tokenizer, classify};
+use crate::typecheck::TypeChecker;
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use std::panic;
+
+// JSON-RPC error code for requests the server does not implement
+const METHOD_NOT_FOUND: i64 = -32601;
+
+// Semantic token legend, indexed by the tokenType field of each encoded token
+const TOKEN_TYPES: [&str; 5] = ["keyword", "number", "variable", "operator", "comment"];
+
+fn legend_index(class: TokenClass) -> usize {
+    match class {
+        TokenClass::Keyword => 0,
+        TokenClass::Literal => 1,
+        TokenClass::Identifier => 2,
+        TokenClass::Operator => 3,
+        TokenClass::Comment => 4,
+    }
+}
+
+// Names interned before the table starts over from the open documents
+const INTERNED_LIMIT: usize = 1 << 16;
+
+// CompletionItemKind values for the items the server offers
+const FUNCTION_ITEM: f64 = 3.0;
+const VARIABLE_ITEM: f64 = 6.0;
+const KEYWORD_ITEM: f64 = 14.0;
+const CONSTANT_ITEM: f64 = 21.0;
+
+// What the server knows about one open document
+struct Analysis {
+    text: String,
+    diagnostics: Vec<Diagnostic>,
+    references: Vec<(Span, Span)>,
+    declarations: Vec<Symbol>,
+    classes: Vec<(Span, TokenClass)>,
+}
+
+// Runs the front end over a document and collects everything the editor asks about
+fn analyze(text: &str, target: &'static TargetSpec) -> Analysis {
+    let classes = classify(text);
+    let source = text.to_string();
+    let text = text.to_string();
+    // a panic is a bug in the compiler, published like any other diagnostic so the
+    // editor keeps its server
+    let result = panic::catch_unwind(move || {
+        let tokens = match Tokenizer::new(text.clone()).try_tokenize() {
+            Ok(tokens) => tokens,
+            Err(e) => {
+                return Analysis {
+                    text: String::new(),
+                    diagnostics: vec![e],
+                    references: Vec::new(),
+                    declarations: Vec::new(),
+                    classes: Vec::new(),
+                };
+            }
+        };
+        let mut parser = Parser::new(tokens);
+        let mut ast = parser.parse_to_ast();
+        let mut diagnostics = parser.errors().to_vec();
+        if diagnostics.is_empty() {
+            let mut checker = TypeChecker::new();
+            checker.set_target(target);
+            diagnostics = checker.check(&mut ast);
+        }
+        if diagnostics.iter().all(|d| d.severity != Severity::Error) {
+            diagnostics.extend(lint::run(&ast, &text, &lint::Levels::new()));
+        }
+        Analysis {
+            text: String::new(),
+            diagnostics,
+            references: parser.references().to_vec(),
+            declarations: parser.declarations().to_vec(),
+            classes: Vec::new(),
+        }
+    });
+    let analysis = result.unwrap_or_else(|payload| Analysis {
+        text: String::new(),
+        diagnostics: vec![diagnostic::internal_error(&*payload)],
+        references: Vec::new(),
+        declarations: Vec::new(),
+        classes: Vec::new(),
+    });
+    // highlighting and completion only need the text, so they survive parse and type
+    // errors
+    Analysis {
+        text: source,
+        classes,
+        ..analysis
+    }
+}
+
+// LSP positions are 0-based; spans are 1-based and single-line
+fn range(span: Span) -> Json {
+    let line = span.line.saturating_sub(1) as f64;
+    let start = span.column.saturating_sub(1);
+    let end = start + span.end.saturating_sub(span.start);
+    Json::object(vec![
+        ("start", position(line, start as f64)),
+        ("end", position(line, end as f64)),
+    ])
+}
+
+// The 0-based line and character of a request's position
+fn cursor(params: &Json) -> Option<(usize, usize)> {
+    let position = params.get("position")?;
+    Some((
+        position.get("line").and_then(Json::as_usize)?,
+        position.get("character").and_then(Json::as_usize)?,
+    ))
+}
+
+// Whether a 0-based position is inside a span, which is 1-based
+fn covers(span: Span, line: usize, character: usize) -> bool {
+    span.line == line + 1
+        && span.column <= character + 1
+        && character + 1 < span.column + (span.end - span.start).max(1)
+}
+
+// The byte offset of a 0-based position in the text, or of the end of its line if the
+// line is shorter
+fn offset(text: &str, line: usize, character: usize) -> usize {
+    let start = match line {
+        0 => 0,
+        _ => match text.match_indices('\n').nth(line - 1) {
+            Some((newline, _)) => newline + 1,
+            None => return text.len(),
+        },
+    };
+    let end = text[start..]
+        .find('\n')
+        .map_or(text.len(), |length| start + length);
+    let mut offset = (start + character).min(end);
+    while !text.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    offset
+}
+
+// How a symbol is declared, as completion details and hovers show it
+fn signature(symbol: &Symbol) -> String {
+    match &symbol.kind {
+        SymbolKind::Variable => format!("{} {}", symbol.type_, symbol.name),
+        SymbolKind::Function { params } => {
+            let params: Vec<String> = params.iter().map(ToString::to_string).collect();
+            format!(
+                "fn {}({}) -> {}",
+                symbol.name,
+                params.join(", "),
+                symbol.type_
+            )
+        }
+        SymbolKind::Constant { value } => format!("const {} = {}", symbol.name, value),
+        SymbolKind::Define => format!("define {}", symbol.name),
+    }
+}
+
+fn completion_item(label: &str, kind: f64, detail: Option<String>) -> Json {
+    let mut fields = vec![
+        ("label", Json::String(label.to_string())),
+        ("kind", Json::Number(kind)),
+    ];
+    if let Some(detail) = detail {
+        fields.push(("detail", Json::String(detail)));
+    }
+    Json::object(fields)
+}
+
+fn position(line: f64, character: f64) -> Json {
+    Json::object(vec![
+        ("line", Json::Number(line)),
+        ("character", Json::Number(character)),
+    ])
+}
+
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<Json>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':')
+            && name.eq_ignore_ascii_case("Content-Length")
+        {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+    let Some(length) = length else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "LSP: message without Content-Length",
+        ));
+    };
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    let body = String::from_utf8_lossy(&body);
+    Json::parse(&body)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn write_message(output: &mut impl Write, message: &Json) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+fn response(id: &Json, result: Json) -> Json {
+    Json::object(vec![
+        ("jsonrpc", Json::String("2.0".to_string())),
+        ("id", id.clone()),
+        ("result", result),
+    ])
+}
+
+fn error_response(id: &Json, code: i64, message: &str) -> Json {
+    Json::object(vec![
+        ("jsonrpc", Json::String("2.0".to_string())),
+        ("id", id.clone()),
+        (
+            "error",
+            Json::object(vec![
+                ("code", Json::Number(code as f64)),
+                ("message", Json::String(message.to_string())),
+            ]),
+        ),
+    ])
+}
+
+fn publish_diagnostics(uri: &str, diagnostics: &[Diagnostic]) -> Json {
+    let diagnostics = diagnostics
+        .iter()
+        .map(|d| {
+            Json::object(vec![
+                ("range", range(d.span)),
+                (
+                    "severity",
+                    Json::Number(match d.severity {
+                        Severity::Error => 1.0,
+                        Severity::Warning => 2.0,
+                        Severity::Note => 3.0,
+                    }),
+                ),
+                ("source", Json::String("noble".to_string())),
+                ("message", Json::String(d.message.clone())),
+            ])
+        })
+        .collect();
+    Json::object(vec![
+        ("jsonrpc", Json::String("2.0".to_string())),
+        (
+            "method",
+            Json::String("textDocument/publishDiagnostics".to_string()),
+        ),
+        (
+            "params",
+            Json::object(vec![
+                ("uri", Json::String(uri.to_string())),
+                ("diagnostics", Json::Array(diagnostics)),
+            ]),
+        ),
+    ])
+}
+
+pub struct Server {
+    documents: HashMap<String, Analysis>,
+    shutdown_requested: bool,
+    // the target documents are checked for, from --target or noble.toml
+    target: &'static TargetSpec,
+}
+
+impl Default for Server {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Server {
+    pub fn new() -> Self {
+        Self {
+            documents: HashMap::new(),
+            shutdown_requested: false,
+            target: &target::X86_64_WINDOWS,
+        }
+    }
+
+    pub fn target(mut self, target: &'static TargetSpec) -> Self {
+        self.target = target;
+        self
+    }
+
+    // Serves requests until the client sends "exit"; returns the process exit code
+    pub fn run(&mut self, input: &mut impl BufRead, output: &mut impl Write) -> io::Result<i32> {
+        while let Some(message) = read_message(input)? {
+            let method = message.get("method").and_then(Json::as_str).unwrap_or("");
+            if method == "exit" {
+                return Ok(if self.shutdown_requested { 0 } else { 1 });
+            }
+            for reply in self.handle(method, &message) {
+                write_message(output, &reply)?;
+            }
+        }
+        Ok(1)
+    }
+
+    fn handle(&mut self, method: &str, message: &Json) -> Vec<Json> {
+        let params = message.get("params").unwrap_or(&Json::Null);
+        let uri = params
+            .get("textDocument")
+            .and_then(|d| d.get("uri"))
+            .and_then(Json::as_str)
+            .unwrap_or("")
+            .to_string();
+
+        match (method, message.get("id")) {
+            ("initialize", Some(id)) => vec![response(
+                id,
+                Json::object(vec![
+                    (
+                        "capabilities",
+                        Json::object(vec![
+                            // 1 = full document sync
+                            ("textDocumentSync", Json::Number(1.0)),
+                            ("definitionProvider", Json::Bool(true)),
+                            ("hoverProvider", Json::Bool(true)),
+                            ("completionProvider", Json::object(Vec::new())),
+                            (
+                                "semanticTokensProvider",
+                                Json::object(vec![
+                                    (
+                                        "legend",
+                                        Json::object(vec![
+                                            (
+                                                "tokenTypes",
+                                                Json::Array(
+                                                    TOKEN_TYPES
+                                                        .iter()
+                                                        .map(|t| Json::String(t.to_string()))
+                                                        .collect(),
+                                                ),
+                                            ),
+                                            ("tokenModifiers", Json::Array(Vec::new())),
+                                        ]),
+                                    ),
+                                    ("full", Json::Bool(true)),
+                                ]),
+                            ),
+                        ]),
+                    ),
+                    (
+                        "serverInfo",
+                        Json::object(vec![("name", Json::String("noble".to_string()))]),
+                    ),
+                ]),
+            )],
+            ("shutdown", Some(id)) => {
+                self.shutdown_requested = true;
+                vec![response(id, Json::Null)]
+            }
+            ("textDocument/definition", Some(id)) => {
+                vec![response(id, self.definition(&uri, params))]
+            }
+            ("textDocument/completion", Some(id)) => {
+                vec![response(id, self.completion(&uri, params))]
+            }
+            ("textDocument/hover", Some(id)) => vec![response(id, self.hover(&uri, params))],
+            ("textDocument/semanticTokens/full", Some(id)) => {
+                vec![response(id, self.semantic_tokens(&uri))]
+            }
+            (_, Some(id)) => vec![error_response(
+                id,
+                METHOD_NOT_FOUND,
+                &format!("unsupported method {}", method),
+            )],
+            ("textDocument/didOpen", None) => {
+                let text = params
+                    .get("textDocument")
+                    .and_then(|d| d.get("text"))
+                    .and_then(Json::as_str)
+                    .unwrap_or("");
+                self.update(&uri, text)
+            }
+            ("textDocument/didChange", None) => {
+                // with full sync the last change holds the whole document
+                let text = match params.get("contentChanges") {
+                    Some(Json::Array(changes)) => changes
+                        .last()
+                        .and_then(|c| c.get("text"))
+                        .and_then(Json::as_str)
+                        .unwrap_or(""),
+                    _ => "",
+                };
+                self.update(&uri, text)
+            }
+            ("textDocument/didClose", None) => {
+                self.documents.remove(&uri);
+                vec![publish_diagnostics(&uri, &[])]
+            }
+            // other notifications need no reply
+            _ => Vec::new(),
+        }
+    }
+
+    fn update(&mut self, uri: &str, text: &str) -> Vec<Json> {
+        // each edit interns whatever names were typed since the last, so a long session
+        // rebuilds the table from what is open now and analyzes every document again
+        if intern::len() > INTERNED_LIMIT {
+            intern::reset();
+            for analysis in self.documents.values_mut() {
+                *analysis = analyze(&analysis.text, self.target);
+            }
+        }
+        let analysis = analyze(text, self.target);
+        let notification = publish_diagnostics(uri, &analysis.diagnostics);
+        self.documents.insert(uri.to_string(), analysis);
+        vec![notification]
+    }
+
+    // Encodes tokens as the LSP's relative (line, start, length, type, modifiers) quintuples
+    fn semantic_tokens(&self, uri: &str) -> Json {
+        let mut data = Vec::new();
+        let (mut previous_line, mut previous_start) = (0, 0);
+        if let Some(analysis) = self.documents.get(uri) {
+            for (span, class) in &analysis.classes {
+                let line = span.line.saturating_sub(1);
+                let start = span.column.saturating_sub(1);
+                let delta_start = if line == previous_line {
+                    start - previous_start
+                } else {
+                    start
+                };
+                for value in [
+                    line - previous_line,
+                    delta_start,
+                    span.end - span.start,
+                    legend_index(*class),
+                    0,
+                ] {
+                    data.push(Json::Number(value as f64));
+                }
+                (previous_line, previous_start) = (line, start);
+            }
+        }
+        Json::object(vec![("data", Json::Array(data))])
+    }
+
+    fn definition(&self, uri: &str, params: &Json) -> Json {
+        let Some(analysis) = self.documents.get(uri) else {
+            return Json::Null;
+        };
+        let Some((line, character)) = cursor(params) else {
+            return Json::Null;
+        };
+
+        // the cursor may sit anywhere inside the identifier
+        let found = analysis
+            .references
+            .iter()
+            .find(|(use_, _)| covers(*use_, line, character));
+        match found {
+            Some((_, declaration)) => Json::object(vec![
+                ("uri", Json::String(uri.to_string())),
+                ("range", range(*declaration)),
+            ]),
+            None => Json::Null,
+        }
+    }
+
+    // The keywords and names that can come at the cursor, found by parsing the document
+    // up to it. A word being typed there is left for the editor to match the items
+    // against, so they are what could stand where the word starts.
+    fn completion(&self, uri: &str, params: &Json) -> Json {
+        let (Some(analysis), Some((line, character))) = (self.documents.get(uri), cursor(params))
+        else {
+            return Json::Null;
+        };
+        let before = &analysis.text[..offset(&analysis.text, line, character)];
+        let before = before.trim_end_matches(|c: char| c.is_alphanumeric() || c == '_');
+        let Ok(fragment) = panic::catch_unwind(|| Parser::parse_statement_fragment(before)) else {
+            return Json::Null;
+        };
+        let mut items = Vec::new();
+        for token_type in &fragment.expected {
+            match token_type {
+                TokenType::Ident => {
+                    for symbol in &fragment.names {
+                        let kind = match symbol.kind {
+                            SymbolKind::Variable => VARIABLE_ITEM,
+                            SymbolKind::Function { .. } => FUNCTION_ITEM,
+                            SymbolKind::Constant { .. } | SymbolKind::Define => CONSTANT_ITEM,
+                        };
+                        items.push(completion_item(
+                            &symbol.name.text(),
+                            kind,
+                            Some(signature(symbol)),
+                        ));
+                    }
+                }
+                TokenType::BoolLit => {
+                    for literal in ["true", "false"] {
+                        items.push(completion_item(literal, KEYWORD_ITEM, None));
+                    }
+                }
+                _ => {
+                    if let Some(spelling) = token_type.spelling()
+                        && spelling.starts_with(|c: char| c.is_alphabetic())
+                    {
+                        items.push(completion_item(spelling, KEYWORD_ITEM, None));
+                    }
+                }
+            }
+        }
+        Json::Array(items)
+    }
+
+    // How the name under the cursor is declared, and where
+    fn hover(&self, uri: &str, params: &Json) -> Json {
+        let (Some(analysis), Some((line, character))) = (self.documents.get(uri), cursor(params))
+        else {
+            return Json::Null;
+        };
+        // a use shows its declaration, and a declaration itself
+        let found = analysis
+            .references
+            .iter()
+            .find(|(use_, _)| covers(*use_, line, character))
+            .copied()
+            .or_else(|| {
+                analysis
+                    .declarations
+                    .iter()
+                    .find(|symbol| covers(symbol.span, line, character))
+                    .map(|symbol| (symbol.span, symbol.span))
+            });
+        let Some((at, declared)) = found else {
+            return Json::Null;
+        };
+        let Some(symbol) = analysis
+            .declarations
+            .iter()
+            .find(|symbol| symbol.span == declared)
+        else {
+            return Json::Null;
+        };
+        let text = format!(
+            "```noble\n{}\n```\ndeclared at {}:{}",
+            signature(symbol),
+            declared.line,
+            declared.column
+        );
+        Json::object(vec![
+            (
+                "contents",
+                Json::object(vec![
+                    ("kind", Json::String("markdown".to_string())),
+                    ("value", Json::String(text)),
+                ]),
+            ),
+            ("range", range(at)),
+        ])
+    }
+}
@@ -1,62 +1,1244 @@
-mod tokenize;
-mod parse;
-mod generate;
+mod cli;
 
 use std::env;
+use std::ffi::OsStr;
 use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::thread;
+use std::time::{Duration, Instant};
+use std::process::{self, Stdio, exit};
 use std::fs::File;
-use std::io::{BufWriter};
+use std::io::{self, BufRead, BufWriter, Read, Write};
 use std::path::{Path, PathBuf};
-use crate::generate::Generator;
-use crate::parse::Parser;
-use crate::parse::ParseTreeNode;
-use crate::tokenize::{Token, Tokenizer};
+use cli::{Action, Command, Emit, ErrorFormat, Options, TokenFormat};
+use noble::asm::{self, Syntax};
+use noble::debug::Session;
+use noble::diagnostic::{self, Diagnostic};
+use noble::encode;
+use noble::error::CompileError;
+use noble::format::format_source;
+use noble::generate::Generator;
+use noble::grammar;
+use noble::interpret::{InterpretError, Interpreter, Outcome};
+use noble::intern;
+use noble::lsp::Server;
+use noble::ast::{Ast, ExprKind};
+use noble::cfg::Cfg;
+use noble::config;
+use noble::parse::{self, Parser};
+use noble::pipeline::Pipeline;
+use noble::report::{self, Reporter};
+use noble::runtime;
+use noble::synthetic;
+use noble::target::Linker;
+use noble::tokenize::{self, Span, Token, Tokenizer};
+use noble::trace::{Stage, Stats, Tracer};
 
 fn main() {
     let args: Vec<String> = env::args().collect();
 
-    if args.len() < 2 {
-        println!("usage: ./d [filename]");
-        return;
+    let mut options = parse_command_line(&args[1..], Options::default());
+    // flags win over noble.toml, so the command line is parsed again over what it sets.
+    // new makes a project rather than building in one.
+    if options.command != Command::New
+        && let Some(path) = options.config.clone().or_else(find_config)
+    {
+        let mut base = Options::default();
+        let loaded = config::load(&path).and_then(|config| {
+            cli::apply_config(&mut base, &config).map_err(|message| CompileError::Config {
+                path: path.clone(),
+                line: None,
+                message,
+            })
+        });
+        if let Err(e) = loaded {
+            report(&e, &options, "");
+            exit(e.exit_code());
+        }
+        options = parse_command_line(&args[1..], base);
     }
+    if let Err(e) = cli::check_input(&options) {
+        usage_error(&e);
+    }
+    let tracer = Tracer::new(options.verbose);
+    let mut stats = Stats::new();
+
+    // build, run, check and fmt work on the input file; its text is kept for rendering
+    // diagnostics. test reads the files of its directory itself.
+    let source = match options.input {
+        _ if let Some(units) = options.bench_synthetic => {
+            let source = synthetic::program(units);
+            stats.count(Stage::Read, source.len(), "bytes");
+            source
+        }
+        Some(_) if matches!(options.command, Command::Test | Command::New) => String::new(),
+        Some(_) => match read_input(&options, &tracer, &mut stats) {
+            Ok(source) => source,
+            Err(e) => {
+                report(&e, &options, "");
+                exit(e.exit_code());
+            }
+        },
+        None => String::new(),
+    };
+
+    let whole_project = options.project.is_some()
+        && options.input.is_none()
+        && options.bench_synthetic.is_none();
+    let result = match options.command {
+        Command::Build | Command::Run if whole_project => {
+            build_project(&options, &tracer, &mut stats)
+        }
+        Command::Build => match options.emit {
+            Emit::Tokens(format) => emit_tokens(&source, format, &options, &mut stats),
+            Emit::ParseTree => emit_parse_tree(&source, &options, &mut stats),
+            Emit::Cfg => emit_cfg(&source, &options, &tracer, &mut stats),
+            Emit::Precedence => write_output(parse::precedence_table(), &options).map(|_| 0),
+            Emit::GrammarEbnf => write_output(grammar::ebnf(), &options).map(|_| 0),
+            _ => build(&source, &options, &tracer, &mut stats).map(|_| 0),
+        },
+        Command::Run => run(&source, &options, &tracer, &mut stats),
+        Command::Check if whole_project => check_project(&options, &tracer, &mut stats),
+        Command::Check => front_end(&source, &options, &tracer, &mut stats).map(|_| 0),
+        Command::Fmt => fmt(&source, &options),
+        Command::Repl => repl(&options),
+        Command::Watch => watch(&options, &tracer),
+        Command::Test => test(&options, &tracer),
+        Command::New => new_project(&options),
+        Command::Debug => debug(&source, &options, &tracer, &mut stats),
+        Command::Lsp => Server::new()
+            .target(options.target)
+            .run(&mut io::stdin().lock(), &mut io::stdout().lock())
+            .map_err(|source| CompileError::Io {
+                path: PathBuf::from("<stdio>"),
+                source,
+            }),
+    };
+
+    if options.timings {
+        stats.report();
+    }
+    match result {
+        Ok(code) => exit(code),
+        Err(e) => {
+            report(&e, &options, &source);
+            exit(e.exit_code());
+        }
+    }
+}
+
+// The options to run with, or exits after --help, --version or a bad command line
+fn parse_command_line(args: &[String], base: Options) -> Options {
+    match cli::parse_args(args, base) {
+        Ok(Action::Run(options)) => *options,
+        Ok(Action::Help) => {
+            print!("{}", cli::help());
+            exit(0);
+        }
+        Ok(Action::Version) => {
+            println!("{}", cli::version());
+            exit(0);
+        }
+        Err(e) => usage_error(&e),
+    }
+}
 
-    let input_file_path: PathBuf = Path::new(env!("CARGO_MANIFEST_DIR"))
-        .join("src")
-        .join(&args[1]);
+fn usage_error(message: &str) -> ! {
+    eprintln!("{}", message);
+    eprintln!("{}", cli::usage());
+    eprintln!("run with --help for the list of commands and options");
+    exit(2);
+}
+
+// The nearest noble.toml in the working directory or above it
+fn find_config() -> Option<PathBuf> {
+    env::current_dir().ok().and_then(|dir| config::find(&dir))
+}
+
+// Writes one diagnostic to stderr in the format --error-format asked for
+fn emit_diagnostic(diagnostic: &Diagnostic, options: &Options, source: &str) {
+    let file = options.input.as_deref().unwrap_or("<repl>");
+    match options.error_format {
+        ErrorFormat::Human => {
+            let reporter = Reporter::new(file, source, options.color.enabled());
+            eprintln!("{}", reporter.render(diagnostic));
+        }
+        ErrorFormat::Json => eprintln!("{}", report::to_json(diagnostic, file)),
+        ErrorFormat::Short => eprintln!("{}", report::to_line(diagnostic, file)),
+    }
+}
+
+fn report(error: &CompileError, options: &Options, source: &str) {
+    if error.diagnostics().is_empty() {
+        let diagnostic = Diagnostic::new(error.to_string(), Span::default());
+        emit_diagnostic(&diagnostic, options, source);
+    }
+    for diagnostic in error.diagnostics() {
+        emit_diagnostic(diagnostic, options, source);
+    }
+}
 
-    let file_contents: String = read_file(input_file_path);
+fn read_input(options: &Options, tracer: &Tracer, stats: &mut Stats) -> Result<String, CompileError> {
+    let input_file_path = input_path(options);
 
-    println!("{:?}", file_contents);
+    tracer.trace(Stage::Read, &format!("reading {}", input_file_path.display()));
+    let file_contents: String = stats.time(Stage::Read, || read_file(&input_file_path))?;
+    stats.count(Stage::Read, file_contents.len(), "bytes");
 
-    let mut tokenizer = Tokenizer::new(file_contents);
-    let tokens: Vec<Token> = tokenizer.tokenize();
-    
-    for token in &tokens {
-        println!("{:?}", token);
+    if tracer.is_verbose() {
+        println!("{:?}", file_contents);
     }
-    
+    Ok(file_contents)
+}
+
+// Compiles the input file and returns the path of what --emit asked for
+fn build(
+    file_contents: &str,
+    options: &Options,
+    tracer: &Tracer,
+    stats: &mut Stats,
+) -> Result<PathBuf, CompileError> {
+    let ast = front_end(file_contents, options, tracer, stats)?;
+    build_checked(file_contents, &ast, options, tracer, stats)
+}
+
+// The back half of build, from a program that has been through the front end
+fn build_checked(
+    file_contents: &str,
+    ast: &Ast,
+    options: &Options,
+    tracer: &Tracer,
+    stats: &mut Stats,
+) -> Result<PathBuf, CompileError> {
+    match options.emit {
+        Emit::Asm => {
+            let output_file_path = match &options.output {
+                Some(path) => path.clone(),
+                None => output_dir(options)?.join("out.asm"),
+            };
+            generate(file_contents, ast, &output_file_path, options, tracer, stats)?;
+            Ok(output_file_path)
+        }
+        Emit::Exe => {
+            let executable = match &options.output {
+                Some(path) => path.clone(),
+                None => {
+                    output_dir(options)?
+                        .join("out")
+                        .with_extension(options.target.executable_extension)
+                }
+            };
+            let asm_path = executable.with_extension("asm");
+            generate(file_contents, ast, &asm_path, options, tracer, stats)?;
+            assemble_and_link(&asm_path, &executable, options, tracer)?;
+            Ok(executable)
+        }
+        Emit::Bin => {
+            let binary = match &options.output {
+                Some(path) => path.clone(),
+                None => output_dir(options)?.join("out.bin"),
+            };
+            write_binary(file_contents, ast, &binary, options, tracer, stats)?;
+            Ok(binary)
+        }
+        #[cfg(feature = "obj")]
+        Emit::Obj => {
+            let object = match &options.output {
+                Some(path) => path.clone(),
+                None => output_dir(options)?
+                    .join("out")
+                    .with_extension(options.target.object_extension),
+            };
+            write_objects(file_contents, ast, &object, options, tracer, stats)?;
+            Ok(object)
+        }
+        #[cfg(not(feature = "obj"))]
+        Emit::Obj => unreachable!("--emit obj is refused without the obj feature"),
+        Emit::Tokens(_)
+        | Emit::ParseTree
+        | Emit::Cfg
+        | Emit::Precedence
+        | Emit::GrammarEbnf => {
+            unreachable!("tokens, parse trees, graphs and tables are emitted without building")
+        }
+    }
+}
+
+// Writes the token stream of the input for --emit tokens, stopping before the parser
+fn emit_tokens(
+    source: &str,
+    format: TokenFormat,
+    options: &Options,
+    stats: &mut Stats,
+) -> Result<i32, CompileError> {
+    let mut tokenizer = Tokenizer::new(source.to_string());
+    let tokens: Vec<Token> = stats
+        .time(Stage::Tokenize, || tokenizer.try_tokenize())
+        .map_err(CompileError::Tokenize)?;
+    stats.count(Stage::Tokenize, tokens.len(), "tokens");
+
+    write_output(token_lines(&tokens, format), options)?;
+    Ok(0)
+}
+
+// One line per token, for --emit tokens and --dump-tokens
+fn token_lines(tokens: &[Token], format: TokenFormat) -> String {
+    let mut out = String::new();
+    for token in tokens {
+        let line = match format {
+            TokenFormat::Json => token.to_json().to_string(),
+            TokenFormat::Text => token.to_string(),
+        };
+        out.push_str(&line);
+        out.push('\n');
+    }
+    out
+}
+
+// Writes the concrete parse tree for --emit parse-tree. The tree is written even when
+// parsing stopped early, since that is when it is most useful; the errors follow.
+fn emit_parse_tree(
+    source: &str,
+    options: &Options,
+    stats: &mut Stats,
+) -> Result<i32, CompileError> {
+    let mut tokenizer = Tokenizer::new(source.to_string());
+    let tokens: Vec<Token> = stats
+        .time(Stage::Tokenize, || tokenizer.try_tokenize())
+        .map_err(CompileError::Tokenize)?;
+    stats.count(Stage::Tokenize, tokens.len(), "tokens");
+
     let mut parser = Parser::new(tokens);
-    let tree: ParseTreeNode = parser.parse();
+    parser.set_max_depth(options.max_nesting);
+    let tree = stats.time(Stage::Parse, || parser.parse());
+    stats.count(Stage::Parse, tree.node_count(), "parse nodes");
+    write_output(tree.render(), options)?;
+    if !parser.errors().is_empty() {
+        return Err(CompileError::Parse(parser.errors().to_vec()));
+    }
+    Ok(0)
+}
+
+// Writes the control-flow graph of the generated code for --emit cfg, as DOT
+fn emit_cfg(
+    source: &str,
+    options: &Options,
+    tracer: &Tracer,
+    stats: &mut Stats,
+) -> Result<i32, CompileError> {
+    let ast = front_end(source, options, tracer, stats)?;
+    let generator = compile(source, &ast, options, tracer, stats)?;
+    let cfg = Cfg::build(generator.lines());
+    tracer.trace(Stage::Generate, &format!("{} basic blocks", cfg.blocks.len()));
+    write_output(cfg.to_dot(generator.lines(), options.syntax), options)?;
+    Ok(0)
+}
 
-    parser.print_tree(&tree, 0);
-    println!();
+// Writes to --output if given, otherwise to stdout
+fn write_output(text: String, options: &Options) -> Result<(), CompileError> {
+    match &options.output {
+        Some(path) => fs::write(path, text).map_err(|source| CompileError::Io {
+            path: path.clone(),
+            source,
+        }),
+        None => {
+            print!("{}", text);
+            Ok(())
+        }
+    }
+}
 
-    let ast = parser.build_ast(&tree);
-    parser.print_ast(&ast, 0);
+// The compilation the options ask for, adding its timings to stats and reporting its
+// warnings as they are found
+fn pipeline<'a>(
+    source: &'a str,
+    options: &'a Options,
+    tracer: &'a Tracer,
+    stats: &'a mut Stats,
+) -> Pipeline<'a> {
+    let mut pipeline = Pipeline::new()
+        .file(options.input.as_deref().unwrap_or("<repl>"))
+        .target(options.target)
+        .syntax(options.syntax)
+        .opt_level(options.opt_level)
+        .asserts(!options.release)
+        .checked_arithmetic(options.checked_arithmetic)
+        .strict_numerics(options.strict_numerics)
+        .max_nesting(options.max_nesting)
+        .lints(options.lints.clone())
+        .print_passes(options.print_passes)
+        .stats(stats)
+        .tracer(tracer)
+        .on_warnings(|warnings| {
+            for warning in warnings.iter() {
+                emit_diagnostic(warning, options, source);
+            }
+        });
+    if let Some(entry) = &options.entry {
+        pipeline = pipeline.entry(entry);
+    }
+    if options.dump_tokens {
+        pipeline = pipeline.on_tokens(|tokens| {
+            print!("{}", token_lines(tokens, TokenFormat::Text))
+        });
+    }
+    if options.dump_parse_tree {
+        pipeline = pipeline.on_parse_tree(|tree| print!("{}", tree.render()));
+    }
+    if options.dump_ast {
+        pipeline = pipeline.on_parsed_ast(|ast| Parser::print_ast(ast, ast.root, 0));
+    }
+    pipeline
+}
 
-    let output_file_path: PathBuf = Path::new(env!("CARGO_MANIFEST_DIR"))
-        .join("src/out.asm");
+// Tokenize, parse and type-check, printing whatever --dump-* asked for
+fn front_end(
+    source: &str,
+    options: &Options,
+    tracer: &Tracer,
+    stats: &mut Stats,
+) -> Result<Ast, CompileError> {
+    pipeline(source, options, tracer, stats).check(source)
+}
 
-    let output_file = File::create(output_file_path).expect("Unable to create file.");
+fn generate(
+    source: &str,
+    ast: &Ast,
+    output_file_path: &Path,
+    options: &Options,
+    tracer: &Tracer,
+    stats: &mut Stats,
+) -> Result<(), CompileError> {
+    let generator = compile(source, ast, options, tracer, stats)?;
+    let output_file = File::create(output_file_path).map_err(|source| CompileError::Io {
+        path: output_file_path.to_path_buf(),
+        source,
+    })?;
     let mut writer = BufWriter::new(&output_file);
+    let bytes_written = stats
+        .time(Stage::Write, || generator.write(&mut writer, options.syntax))
+        .map_err(CompileError::Codegen)?;
+    stats.count(Stage::Write, bytes_written, "bytes");
+    tracer.trace(Stage::Generate, &format!("wrote {}", output_file_path.display()));
+
+    if options.source_map {
+        let file = options.input.as_deref().unwrap_or("<repl>");
+        write_source_map(&generator, file, output_file_path, options, tracer)?;
+    }
+    write_runtime(output_file_path, options, tracer)
+}
 
-    let mut generator = Generator::new();
-    generator.generate_boilerplate(&mut writer);
-    generator.generate_x64(&ast, &mut writer);
+// Encodes the program and the runtime module into one flat image for --emit bin,
+// without an assembler or linker
+fn write_binary(
+    source: &str,
+    ast: &Ast,
+    path: &Path,
+    options: &Options,
+    tracer: &Tracer,
+    stats: &mut Stats,
+) -> Result<(), CompileError> {
+    if !options.target.freestanding {
+        return Err(CompileError::Target(format!(
+            "--emit bin needs a freestanding target such as x86_64-linux; {} programs call \
+             the C runtime",
+            options.target.name
+        )));
+    }
+    let generator = compile(source, ast, options, tracer, stats)?;
+    let mut lines = generator.lines().to_vec();
+    lines.extend(runtime::module(options.target));
+    let image = stats
+        .time(Stage::Write, || encode::flat_binary(&lines, options.origin))
+        .map_err(CompileError::Encode)?;
+    fs::write(path, &image).map_err(|source| CompileError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    stats.count(Stage::Write, image.len(), "bytes");
+    tracer.trace(Stage::Generate, &format!("wrote {}", path.display()));
+    Ok(())
+}
+
+// Encodes the program into an object file for --emit obj, and the runtime module into
+// another beside it, ready for the target's linker without an assembler
+#[cfg(feature = "obj")]
+fn write_objects(
+    source: &str,
+    ast: &Ast,
+    path: &Path,
+    options: &Options,
+    tracer: &Tracer,
+    stats: &mut Stats,
+) -> Result<(), CompileError> {
+    let generator = compile(source, ast, options, tracer, stats)?;
+    let runtime = runtime::module(options.target);
+    let objects = [
+        (generator.lines(), path.to_path_buf()),
+        (runtime.as_slice(), runtime_path(path)),
+    ];
+    let mut bytes = 0;
+    for (lines, path) in &objects {
+        let object = stats
+            .time(Stage::Write, || encode::object_file(lines, options.target))
+            .map_err(CompileError::Encode)?;
+        fs::write(path, &object).map_err(|source| CompileError::Io {
+            path: path.clone(),
+            source,
+        })?;
+        bytes += object.len();
+        tracer.trace(Stage::Generate, &format!("wrote {}", path.display()));
+    }
+    stats.count(Stage::Write, bytes, "bytes");
+    Ok(())
+}
+
+// Generates and optimizes the program at the level the options ask for
+fn compile(
+    source: &str,
+    ast: &Ast,
+    options: &Options,
+    tracer: &Tracer,
+    stats: &mut Stats,
+) -> Result<Generator, CompileError> {
+    pipeline(source, options, tracer, stats).generate(source, ast)
+}
+
+// One "asm-line file:line:column" entry per instruction, so an address resolved to an
+// assembly line can be traced back to the Noble statement it came from
+fn write_source_map(
+    generator: &Generator,
+    file: &str,
+    program: &Path,
+    options: &Options,
+    tracer: &Tracer,
+) -> Result<(), CompileError> {
+    let path = program.with_extension("map");
+    let mut map = String::new();
+    for (asm_line, line, column) in generator.source_map(options.syntax) {
+        map.push_str(&format!("{} {}:{}:{}\n", asm_line, file, line, column));
+    }
+    // the symbols globals were mangled to, so a debugger's addresses can be named
+    for (symbol, name, span) in generator.global_symbols() {
+        map.push_str(&format!("{} {}:{}:{} {}\n", symbol, file, span.line, span.column, name));
+    }
+    fs::write(&path, map).map_err(|source| CompileError::Io {
+        path: path.clone(),
+        source,
+    })?;
+    tracer.trace(Stage::Generate, &format!("wrote {}", path.display()));
+    Ok(())
 }
 
-fn read_file(file_path: PathBuf) -> String {
-    let contents: String =
-        fs::read_to_string(file_path).expect("Unable to read file.");
-    contents
-}
\ No newline at end of file
+// The runtime module is written next to the program as runtime.asm, or with the
+// program's own extension, e.g. runtime.s next to out.s
+fn runtime_path(program: &Path) -> PathBuf {
+    let extension = program.extension().unwrap_or(OsStr::new("asm"));
+    program.with_file_name("runtime").with_extension(extension)
+}
+
+fn write_runtime(program: &Path, options: &Options, tracer: &Tracer) -> Result<(), CompileError> {
+    let path = runtime_path(program);
+    let file = File::create(&path).map_err(|source| CompileError::Io {
+        path: path.clone(),
+        source,
+    })?;
+    let lines = runtime::module(options.target);
+    asm::write_program(&lines, options.syntax, &mut BufWriter::new(file))
+        .map_err(CompileError::Codegen)?;
+    tracer.trace(Stage::Generate, &format!("wrote {}", path.display()));
+    Ok(())
+}
+
+// Hands the program and the runtime module to the platform's assembler, then links
+// the two objects
+fn assemble_and_link(
+    asm_path: &Path,
+    executable: &Path,
+    options: &Options,
+    tracer: &Tracer,
+) -> Result<(), CompileError> {
+    let extension = options.target.object_extension;
+    let runtime_asm = runtime_path(asm_path);
+    let sources = [
+        (asm_path, executable.with_extension(extension)),
+        (runtime_asm.as_path(), runtime_asm.with_extension(extension)),
+    ];
+    for (source, object) in &sources {
+        let asm = source.display().to_string();
+        let obj = object.display().to_string();
+        match (options.syntax, options.target.nasm_format) {
+            (Syntax::Intel, Some(format)) => {
+                run_tool(tracer, "nasm", &["-f", format, &asm, "-o", &obj])?
+            }
+            (Syntax::Intel, None) => {
+                return Err(CompileError::Toolchain {
+                    program: "nasm".to_string(),
+                    message: format!("cannot assemble for {}", options.target.name),
+                });
+            }
+            (Syntax::Att, _) => run_tool(tracer, "as", &[&asm, "-o", &obj])?,
+        }
+    }
+    let obj = sources[0].1.display().to_string();
+    let runtime_obj = sources[1].1.display().to_string();
+
+    let exe = executable.display().to_string();
+    let entry = options.entry.as_deref().unwrap_or(options.target.entry);
+    match options.target.linker {
+        Linker::Msvc => {
+            let entry = format!("/entry:{}", entry);
+            let out = format!("/out:{}", exe);
+            let args = [&obj, &runtime_obj, "/subsystem:console", &entry, &out];
+            run_tool(tracer, "link", &args)
+        }
+        Linker::Ld => {
+            run_tool(tracer, "ld", &["-e", entry, &obj, &runtime_obj, "-o", &exe])
+        }
+    }
+}
+
+fn run_tool(tracer: &Tracer, program: &str, args: &[&str]) -> Result<(), CompileError> {
+    tracer.trace(Stage::Write, &format!("{} {}", program, args.join(" ")));
+    let status = process::Command::new(program)
+        .args(args)
+        .status()
+        .map_err(|e| CompileError::Toolchain {
+            program: program.to_string(),
+            message: e.to_string(),
+        })?;
+    if !status.success() {
+        return Err(CompileError::Toolchain {
+            program: program.to_string(),
+            message: format!("failed with {}", status),
+        });
+    }
+    Ok(())
+}
+
+// Builds an executable and runs it, passing its exit code through, or with --interpret
+// runs the checked program in the interpreter instead
+fn run(
+    source: &str,
+    options: &Options,
+    tracer: &Tracer,
+    stats: &mut Stats,
+) -> Result<i32, CompileError> {
+    let code = if options.interpret {
+        let ast = front_end(source, options, tracer, stats)?;
+        run_interpreter(source, &ast, options)?
+    } else {
+        let options = Options {
+            emit: Emit::Exe,
+            ..options.clone()
+        };
+        let executable = build(source, &options, tracer, stats)?;
+        execute(&executable)?
+    };
+    Ok(print_exit(code, options))
+}
+
+// Interprets the program the way its executable would run: with stdin as the input of
+// read_int and no limit on the statements it runs. What it printed is written once it
+// has stopped, followed with --dump-env by its top-level variables; --trace logs each
+// statement to stderr as it runs.
+fn run_interpreter(source: &str, ast: &Ast, options: &Options) -> Result<i32, CompileError> {
+    let mut input = Vec::new();
+    if calls_read_int(ast) {
+        io::stdin()
+            .read_to_end(&mut input)
+            .map_err(|source| CompileError::Io {
+                path: PathBuf::from("<stdin>"),
+                source,
+            })?;
+    }
+    let outcome = interpret(ast, options, |interpreter| {
+        interpreter.set_input(&input);
+        interpreter.set_step_limit(u64::MAX);
+        if options.trace {
+            interpreter.set_trace(source, io::stderr());
+        }
+    })
+    .map_err(|e| CompileError::Toolchain {
+        program: "interpreter".to_string(),
+        message: e.to_string(),
+    })?;
+    print!("{}", outcome.output);
+    if options.dump_env {
+        for (name, value) in &outcome.variables {
+            println!("{} = {}", name, value);
+        }
+    }
+    Ok(outcome.process_status(options.target))
+}
+
+// Runs the program in the interpreter under a debugger that reads its commands from
+// stdin, so read_int sees no input. What the program prints is written as it is printed,
+// between the debugger's own lines.
+fn debug(
+    source: &str,
+    options: &Options,
+    tracer: &Tracer,
+    stats: &mut Stats,
+) -> Result<i32, CompileError> {
+    let ast = front_end(source, options, tracer, stats)?;
+    let outcome = interpret(&ast, options, |interpreter| {
+        interpreter.set_step_limit(u64::MAX);
+        interpreter.set_output(io::stdout());
+        interpreter.set_debugger(Session::new(source, &ast, io::stdin().lock(), io::stdout()));
+    });
+    match outcome {
+        Ok(outcome) => {
+            let code = outcome.process_status(options.target);
+            println!("exit code: {}", code);
+            Ok(code)
+        }
+        Err(InterpretError::Stopped) => Ok(0),
+        Err(e) => Err(CompileError::Toolchain {
+            program: "interpreter".to_string(),
+            message: e.to_string(),
+        }),
+    }
+}
+
+// Whether the program reads stdin, which is only read to its end when it does
+fn calls_read_int(ast: &Ast) -> bool {
+    ast.iter().any(|visit| {
+        visit.node.stmt.expressions().iter().any(|expr| {
+            expr.walk().any(|visit| {
+                matches!(&visit.expr.kind, ExprKind::Call { name, .. } if *name == "read_int")
+            })
+        })
+    })
+}
+
+// With --print-exit, a last line with the exit code, after anything the program printed
+fn print_exit(code: i32, options: &Options) -> i32 {
+    if options.print_exit {
+        let _ = io::stdout().flush();
+        println!("exit code: {}", code);
+    }
+    code
+}
+
+fn execute(executable: &Path) -> Result<i32, CompileError> {
+    let status = process::Command::new(executable)
+        .status()
+        .map_err(|e| CompileError::Toolchain {
+            program: executable.display().to_string(),
+            message: e.to_string(),
+        })?;
+    Ok(exit_code(status))
+}
+
+// A program killed by a signal has no exit code, so it gets the one a shell would show:
+// 128 plus the signal, which makes the SIGFPE of an unchecked division by zero 136 like
+// the checked one
+fn exit_code(status: process::ExitStatus) -> i32 {
+    #[cfg(unix)]
+    if let Some(signal) = std::os::unix::process::ExitStatusExt::signal(&status) {
+        return 128 + signal;
+    }
+    status.code().unwrap_or(1)
+}
+
+// Builds every .nbl file in the project's source roots into its out-dir, each as a
+// program of its own named after the file, except that main.nbl is named after the
+// package. run then runs the one built from main.nbl.
+fn build_project(
+    options: &Options,
+    tracer: &Tracer,
+    stats: &mut Stats,
+) -> Result<i32, CompileError> {
+    let root = options.project.as_deref().unwrap_or(Path::new(""));
+    let config_error = |message: String| CompileError::Config {
+        path: root.join(config::FILE_NAME),
+        line: None,
+        message,
+    };
+    let sources = project_sources(options)?;
+
+    let out_dir = output_dir(options)?;
+    let emit = match options.command {
+        Command::Run => Emit::Exe,
+        _ => options.emit,
+    };
+    let mut built: Vec<(&Path, String)> = Vec::new();
+    let mut main = None;
+    for path in &sources {
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let name = match (stem.as_ref(), &options.package) {
+            ("main", Some(package)) => package.clone(),
+            _ => stem.to_string(),
+        };
+        if let Some((other, _)) = built.iter().find(|(_, built)| *built == name) {
+            return Err(config_error(format!(
+                "{} and {} would both be built as {}",
+                other.display(),
+                path.display(),
+                name
+            )));
+        }
+        let output = match emit {
+            Emit::Exe => match options.target.executable_extension {
+                "" => out_dir.join(&name),
+                extension => out_dir.join(format!("{}.{}", name, extension)),
+            },
+            Emit::Bin => out_dir.join(format!("{}.bin", name)),
+            Emit::Obj => out_dir.join(format!("{}.{}", name, options.target.object_extension)),
+            _ => out_dir.join(format!("{}.asm", name)),
+        };
+        let case = Options {
+            input: Some(path.strip_prefix(root).unwrap_or(path).display().to_string()),
+            output: Some(output),
+            emit,
+            ..options.clone()
+        };
+        tracer.trace(Stage::Read, &format!("reading {}", path.display()));
+        let source = read_file(path)?;
+        match build(&source, &case, tracer, stats) {
+            Ok(artifact) if stem == "main" => main = Some(artifact),
+            Ok(_) => {}
+            Err(e) => {
+                report(&e, &case, &source);
+                return Ok(e.exit_code());
+            }
+        }
+        built.push((path, name));
+    }
+
+    if options.command != Command::Run {
+        return Ok(0);
+    }
+    match main {
+        Some(executable) => execute(&executable).map(|code| print_exit(code, options)),
+        None => Err(config_error("no main.nbl in the source roots to run".to_string())),
+    }
+}
+
+// Checks every .nbl file in the project's source roots, reporting the problems of all of
+// them rather than stopping at the first file that has any
+fn check_project(
+    options: &Options,
+    tracer: &Tracer,
+    stats: &mut Stats,
+) -> Result<i32, CompileError> {
+    let root = options.project.as_deref().unwrap_or(Path::new(""));
+    let mut status = 0;
+    for path in project_sources(options)? {
+        let case = Options {
+            input: Some(path.strip_prefix(root).unwrap_or(&path).display().to_string()),
+            ..options.clone()
+        };
+        tracer.trace(Stage::Read, &format!("reading {}", path.display()));
+        let source = read_file(&path)?;
+        if let Err(e) = front_end(&source, &case, tracer, stats) {
+            report(&e, &case, &source);
+            if status == 0 {
+                status = e.exit_code();
+            }
+        }
+    }
+    Ok(status)
+}
+
+// Every .nbl file in the project's source roots
+fn project_sources(options: &Options) -> Result<Vec<PathBuf>, CompileError> {
+    let mut sources = Vec::new();
+    for dir in &options.source_roots {
+        sources.extend(nbl_files(dir)?);
+    }
+    if sources.is_empty() {
+        let root = options.project.as_deref().unwrap_or(Path::new(""));
+        return Err(CompileError::Config {
+            path: root.join(config::FILE_NAME),
+            line: None,
+            message: "no .nbl files in the source roots".to_string(),
+        });
+    }
+    Ok(sources)
+}
+
+// Creates <name>/noble.toml and <name>/src/main.nbl; an existing directory is left alone
+fn new_project(options: &Options) -> Result<i32, CompileError> {
+    let dir = PathBuf::from(options.input.as_deref().unwrap_or_default());
+    let io_error = |path: &Path, source| CompileError::Io {
+        path: path.to_path_buf(),
+        source,
+    };
+    if dir.exists() {
+        let source = io::Error::new(io::ErrorKind::AlreadyExists, "already exists");
+        return Err(io_error(&dir, source));
+    }
+    let name = dir.file_name().unwrap_or_default().to_string_lossy();
+    let manifest = format!(
+        "[package]\nname = \"{}\"\n\n[build]\ntarget = \"{}\"\n",
+        name.replace('\\', "\\\\").replace('"', "\\\""),
+        options.target.name
+    );
+    let files = [
+        (dir.join(config::FILE_NAME), manifest),
+        (dir.join("src").join("main.nbl"), "print_int(6 * 7);\n".to_string()),
+    ];
+    fs::create_dir_all(dir.join("src")).map_err(|source| io_error(&dir, source))?;
+    for (path, text) in &files {
+        fs::write(path, text).map_err(|source| io_error(path, source))?;
+    }
+    println!("created {}", dir.display());
+    Ok(0)
+}
+
+fn fmt(source: &str, options: &Options) -> Result<i32, CompileError> {
+    let formatted = format_source(source).map_err(CompileError::Tokenize)?;
+    write_output(formatted, options)?;
+    Ok(0)
+}
+
+// Statements accumulate into one program; each entry is checked against what
+// came before and dropped if it doesn't compile
+fn repl(options: &Options) -> Result<i32, CompileError> {
+    println!("{} repl; :run builds and runs the program, :show prints it, :reset clears it, :quit exits", cli::version());
+    let tracer = Tracer::new(false);
+    let mut program = String::new();
+    let mut entry = String::new();
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+
+    loop {
+        print!("{}", if entry.is_empty() { "noble> " } else { "...> " });
+        let _ = io::stdout().flush();
+        let Some(line) = lines.next() else {
+            return Ok(0);
+        };
+        let line = line.map_err(|source| CompileError::Io {
+            path: PathBuf::from("<stdin>"),
+            source,
+        })?;
+
+        if entry.is_empty() {
+            match line.trim() {
+                "" => continue,
+                ":quit" | ":q" => return Ok(0),
+                ":show" => {
+                    print!("{}", program);
+                    continue;
+                }
+                ":reset" => {
+                    program.clear();
+                    continue;
+                }
+                ":run" => {
+                    match repl_run(&program, options, &tracer) {
+                        Ok(code) => println!("exit code {}", code),
+                        Err(e) => report(&e, options, &program),
+                    }
+                    continue;
+                }
+                _ => {}
+            }
+        }
+
+        entry.push_str(&line);
+        entry.push('\n');
+        // keep reading until every brace is closed
+        if entry.matches('{').count() > entry.matches('}').count() {
+            continue;
+        }
+
+        let candidate = format!("{}{}", program, entry);
+        entry.clear();
+        // each entry checks the whole program again, so nothing interned before is kept
+        intern::reset();
+        let quiet = Options {
+            dump_tokens: false,
+            dump_parse_tree: false,
+            dump_ast: false,
+            ..options.clone()
+        };
+        match front_end(&candidate, &quiet, &tracer, &mut Stats::new()) {
+            Ok(_) => program = candidate,
+            Err(e) => report(&e, options, &candidate),
+        }
+    }
+}
+
+fn repl_run(program: &str, options: &Options, tracer: &Tracer) -> Result<i32, CompileError> {
+    let ast = front_end(program, options, tracer, &mut Stats::new())?;
+    let executable = env::temp_dir().join(format!("noble_repl_{}", process::id()));
+    let asm_path = executable.with_extension("asm");
+    generate(program, &ast, &asm_path, options, tracer, &mut Stats::new())?;
+    assemble_and_link(&asm_path, &executable, options, tracer)?;
+    execute(&executable)
+}
+
+// Builds the input now and again each time it is saved, until interrupted. A failed
+// build is reported and waited out like any other, so one bad save doesn't end the
+// session. Noble has no includes, so the input is the only file to watch.
+fn watch(options: &Options, tracer: &Tracer) -> Result<i32, CompileError> {
+    let path = input_path(options);
+    let mut seen = None;
+    loop {
+        let modified = fs::metadata(&path).and_then(|m| m.modified()).ok();
+        if modified.is_some() && modified != seen {
+            seen = modified;
+            watch_build(options, tracer);
+        }
+        thread::sleep(WATCH_INTERVAL);
+    }
+}
+
+const WATCH_INTERVAL: Duration = Duration::from_millis(200);
+
+fn watch_build(options: &Options, tracer: &Tracer) {
+    let file = options.input.as_deref().unwrap_or_default();
+    let start = Instant::now();
+    let source = match read_input(options, tracer, &mut Stats::new()) {
+        Ok(source) => source,
+        Err(e) => return report(&e, options, ""),
+    };
+    // every build starts over from the source, so nothing interned before is kept
+    intern::reset();
+    // a compiler bug that panics ends this build, not the session
+    let built = panic::catch_unwind(AssertUnwindSafe(|| {
+        build(&source, options, tracer, &mut Stats::new())
+    }));
+    match built {
+        Ok(Ok(path)) => println!(
+            "{}: built {} in {} ms",
+            file,
+            path.display(),
+            start.elapsed().as_millis()
+        ),
+        Ok(Err(e)) => {
+            report(&e, options, &source);
+            println!("{}: build failed", file);
+        }
+        Err(payload) => {
+            emit_diagnostic(&diagnostic::internal_error(&*payload), options, &source);
+            println!("{}: build failed", file);
+        }
+    }
+}
+
+// Builds and runs every .nbl file in the input directory that starts with an
+// "// expect: N" comment, and checks that it exits with N. With --differential every
+// file is also run in the interpreter, and the executable must exit with the same status
+// and print the same numbers. Exits with 1 if any fail.
+fn test(options: &Options, tracer: &Tracer) -> Result<i32, CompileError> {
+    let dir = input_path(options);
+    let files = nbl_files(&dir)?;
+
+    let out_dir = env::temp_dir().join(format!("noble_test_{}", process::id()));
+    fs::create_dir_all(&out_dir).map_err(|source| CompileError::Io {
+        path: out_dir.clone(),
+        source,
+    })?;
+    let (mut passed, mut failed, mut skipped) = (0, 0, 0);
+    for path in &files {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let source = read_file(path)?;
+        let expected = expected_exit_code(&source);
+        if expected.is_none() && !options.differential {
+            skipped += 1;
+            continue;
+        }
+        let case = Options {
+            input: Some(name.to_string()),
+            output: Some(out_dir.join(path.file_stem().unwrap_or_default())),
+            emit: Emit::Exe,
+            ..options.clone()
+        };
+        match test_case(&source, expected, &case, tracer) {
+            Verdict::Passed => {
+                passed += 1;
+                println!("test {} ... ok", name);
+            }
+            Verdict::Failed(reason) => {
+                failed += 1;
+                println!("test {} ... FAILED: {}", name, reason);
+            }
+            Verdict::Skipped(reason) => {
+                skipped += 1;
+                println!("test {} ... skipped: {}", name, reason);
+            }
+        }
+    }
+    let _ = fs::remove_dir_all(&out_dir);
+
+    println!("\n{} passed, {} failed, {} skipped", passed, failed, skipped);
+    Ok(if failed > 0 { 1 } else { 0 })
+}
+
+enum Verdict {
+    Passed,
+    Failed(String),
+    Skipped(String),
+}
+
+fn test_case(source: &str, expected: Option<i32>, case: &Options, tracer: &Tracer) -> Verdict {
+    let mut stats = Stats::new();
+    let ast = match front_end(source, case, tracer, &mut stats) {
+        Ok(ast) => ast,
+        Err(e) => {
+            report(&e, case, source);
+            return Verdict::Failed("did not build".to_string());
+        }
+    };
+    // a program the interpreter cannot finish is still checked against its expect line,
+    // and otherwise not run at all, since it may not finish either
+    let reference = match case.differential.then(|| interpret(&ast, case, |_| {})) {
+        Some(Err(e)) if expected.is_none() => {
+            return Verdict::Skipped(format!("not interpreted: {}", e));
+        }
+        reference => reference.and_then(Result::ok),
+    };
+    let ran = build_checked(source, &ast, case, tracer, &mut stats).and_then(|executable| {
+        // the program reads end of input, and what it prints is only compared
+        process::Command::new(&executable)
+            .stdin(Stdio::null())
+            .output()
+            .map_err(|e| CompileError::Toolchain {
+                program: executable.display().to_string(),
+                message: e.to_string(),
+            })
+    });
+    let output = match ran {
+        Ok(output) => output,
+        Err(e) => {
+            report(&e, case, source);
+            return Verdict::Failed("did not build".to_string());
+        }
+    };
+    let code = exit_code(output.status);
+
+    if let Some(outcome) = reference {
+        let status = outcome.process_status(case.target);
+        if code != status {
+            return Verdict::Failed(format!(
+                "exited with {}, the interpreter with {}",
+                code, status
+            ));
+        }
+        // C's stdout on Windows writes "\r\n" for each "\n"
+        let printed = String::from_utf8_lossy(&output.stdout).replace("\r\n", "\n");
+        if let Some(difference) = output_difference(&printed, &outcome.output) {
+            return Verdict::Failed(difference);
+        }
+    }
+    match expected {
+        Some(expected) if code != expected => {
+            Verdict::Failed(format!("exited with {}, expected {}", code, expected))
+        }
+        _ => Verdict::Passed,
+    }
+}
+
+// enough for the interpreter's deepest call nesting, with a margin
+const INTERPRETER_STACK: usize = 256 << 20;
+
+// Runs a checked program in the interpreter, with the asserts and checks the case builds
+// with and whatever else `configure` sets, on a thread with room for deeply recursive
+// programs
+fn interpret(
+    ast: &Ast,
+    options: &Options,
+    configure: impl FnOnce(&mut Interpreter) + Send,
+) -> Result<Outcome, InterpretError> {
+    // the tree's names were interned on this thread
+    let interner = intern::current();
+    thread::scope(|scope| {
+        thread::Builder::new()
+            .stack_size(INTERPRETER_STACK)
+            .spawn_scoped(scope, || {
+                intern::install(interner);
+                let mut interpreter = Interpreter::new(ast);
+                interpreter.set_asserts(!options.release);
+                interpreter.set_checked_arithmetic(options.checked_arithmetic);
+                configure(&mut interpreter);
+                interpreter.run()
+            })
+            .expect("could not start the interpreter thread")
+            .join()
+            .unwrap_or_else(|payload| panic::resume_unwind(payload))
+    })
+}
+
+// The first line where the executable printed something other than the interpreter
+fn output_difference(printed: &str, expected: &str) -> Option<String> {
+    let (mut printed, mut expected) = (printed.lines(), expected.lines());
+    for line in 1.. {
+        match (printed.next(), expected.next()) {
+            (None, None) => return None,
+            (Some(a), Some(b)) if a == b => {}
+            (Some(a), Some(b)) => {
+                return Some(format!("printed {} on line {}, the interpreter {}", a, line, b));
+            }
+            (Some(a), None) => {
+                return Some(format!("printed {} on line {}, the interpreter nothing", a, line));
+            }
+            (None, Some(b)) => {
+                return Some(format!("printed nothing on line {}, the interpreter {}", line, b));
+            }
+        }
+    }
+    unreachable!()
+}
+
+// The .nbl files in a directory, sorted by name
+fn nbl_files(dir: &Path) -> Result<Vec<PathBuf>, CompileError> {
+    let mut files: Vec<PathBuf> = fs::read_dir(dir)
+        .map_err(|source| CompileError::Io {
+            path: dir.to_path_buf(),
+            source,
+        })?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension() == Some(OsStr::new("nbl")))
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+// N from a "// expect: N" line among the comments a program starts with
+fn expected_exit_code(source: &str) -> Option<i32> {
+    source
+        .lines()
+        .map(str::trim)
+        .take_while(|line| line.starts_with("//"))
+        .find_map(|line| line[2..].trim().strip_prefix("expect:"))
+        .and_then(|code| code.trim().parse().ok())
+}
+
+// The input in the first source root that has it, the first root if none do, or src/
+// of the compiler's own tree when noble.toml names no roots
+fn input_path(options: &Options) -> PathBuf {
+    let input = options.input.as_deref().unwrap_or_default();
+    match options.source_roots.as_slice() {
+        [] => Path::new(env!("CARGO_MANIFEST_DIR")).join("src").join(input),
+        roots => roots
+            .iter()
+            .map(|root| root.join(input))
+            .find(|path| path.exists())
+            .unwrap_or_else(|| roots[0].join(input)),
+    }
+}
+
+// Where out.asm and out are written without --output, created if it is missing
+fn output_dir(options: &Options) -> Result<PathBuf, CompileError> {
+    match &options.out_dir {
+        Some(dir) => fs::create_dir_all(dir).map(|_| dir.clone()).map_err(|source| {
+            CompileError::Io {
+                path: dir.clone(),
+                source,
+            }
+        }),
+        None => Ok(Path::new(env!("CARGO_MANIFEST_DIR")).join("src")),
+    }
+}
+
+fn read_file(file_path: &Path) -> Result<String, CompileError> {
+    let bytes = fs::read(file_path).map_err(|source| CompileError::Io {
+        path: file_path.to_path_buf(),
+        source,
+    })?;
+    tokenize::decode(bytes).map_err(CompileError::Tokenize)
+}
@@ -0,0 +1,1212 @@
+use crate::arena::Arena;
+use crate::asm::{self, Line, Operand, Size};
+use crate::ast::{Ast, Expr, ExprKind, Linkage, Node, NodeId, Stmt, Type};
+use crate::cfg::{self, Cfg, Place};
+use crate::consteval::fold_constants;
+use crate::intern::Name;
+use crate::tokenize::Span;
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pass {
+    // on the AST, before code generation
+    ConstantFolding,
+    CopyPropagation,
+    LoopInvariantCodeMotion,
+    // on the generated assembly
+    DeadStoreElimination,
+    RedundantLoads,
+    RegisterAllocation,
+    Peephole,
+    StrengthReduction,
+}
+
+impl Pass {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Pass::ConstantFolding => "constant-folding",
+            Pass::CopyPropagation => "copy-propagation",
+            Pass::LoopInvariantCodeMotion => "loop-invariant-code-motion",
+            Pass::DeadStoreElimination => "dead-store-elimination",
+            Pass::RedundantLoads => "redundant-loads",
+            Pass::RegisterAllocation => "register-allocation",
+            Pass::Peephole => "peephole",
+            Pass::StrengthReduction => "strength-reduction",
+        }
+    }
+
+    fn on_ast(&self) -> bool {
+        matches!(
+            self,
+            Pass::ConstantFolding | Pass::CopyPropagation | Pass::LoopInvariantCodeMotion
+        )
+    }
+}
+
+// The passes each -O level runs, in order; AST passes always come before assembly ones
+pub fn passes(level: u8) -> Vec<Pass> {
+    match level {
+        0 => vec![],
+        1 => vec![
+            Pass::ConstantFolding,
+            Pass::Peephole,
+            Pass::StrengthReduction,
+        ],
+        _ => vec![
+            Pass::ConstantFolding,
+            Pass::CopyPropagation,
+            Pass::LoopInvariantCodeMotion,
+            Pass::DeadStoreElimination,
+            Pass::RedundantLoads,
+            Pass::RegisterAllocation,
+            Pass::Peephole,
+            Pass::StrengthReduction,
+        ],
+    }
+}
+
+struct PassRun {
+    pass: Pass,
+    elapsed: Duration,
+    // instruction counts before and after, for passes over the assembly
+    instructions: Option<(usize, usize)>,
+}
+
+// Runs the passes of one optimization level and remembers how long each took,
+// for --print-passes
+pub struct PassManager {
+    passes: Vec<Pass>,
+    runs: Vec<PassRun>,
+}
+
+impl PassManager {
+    pub fn new(level: u8) -> Self {
+        Self {
+            passes: passes(level),
+            runs: Vec::new(),
+        }
+    }
+
+    pub fn has_ast_passes(&self) -> bool {
+        self.passes.iter().any(Pass::on_ast)
+    }
+
+    pub fn run_ast(&mut self, ast: &mut Ast) {
+        for pass in self.passes.clone().into_iter().filter(Pass::on_ast) {
+            let start = Instant::now();
+            match pass {
+                Pass::ConstantFolding => fold_constants(ast),
+                Pass::CopyPropagation => propagate_copies(ast),
+                Pass::LoopInvariantCodeMotion => hoist_loop_bounds(ast),
+                _ => unreachable!("{} is not an AST pass", pass.name()),
+            }
+            self.runs.push(PassRun {
+                pass,
+                elapsed: start.elapsed(),
+                instructions: None,
+            });
+        }
+    }
+
+    pub fn run_asm(&mut self, lines: &mut Vec<Line>) {
+        for pass in self.passes.clone().into_iter().filter(|p| !p.on_ast()) {
+            let before = instruction_count(lines);
+            let start = Instant::now();
+            match pass {
+                Pass::DeadStoreElimination => eliminate_dead_stores(lines),
+                Pass::RedundantLoads => eliminate_redundant_loads(lines),
+                Pass::RegisterAllocation => allocate_registers(lines),
+                Pass::Peephole => peephole(lines),
+                Pass::StrengthReduction => reduce_strength(lines),
+                _ => unreachable!("{} is not an assembly pass", pass.name()),
+            }
+            self.runs.push(PassRun {
+                pass,
+                elapsed: start.elapsed(),
+                instructions: Some((before, instruction_count(lines))),
+            });
+        }
+    }
+
+    pub fn report(&self) {
+        if self.runs.is_empty() {
+            eprintln!("no passes (-O0)");
+        }
+        for run in &self.runs {
+            let counts = match run.instructions {
+                Some((before, after)) => format!("{} -> {} instructions", before, after),
+                None => String::new(),
+            };
+            let line = format!(
+                "{:<28} {:>10.3}ms  {}",
+                run.pass.name(),
+                run.elapsed.as_secs_f64() * 1000.0,
+                counts
+            );
+            eprintln!("{}", line.trim_end());
+        }
+    }
+}
+
+fn instruction_count(lines: &[Line]) -> usize {
+    lines
+        .iter()
+        .filter(|line| matches!(line, Line::Instruction { .. }))
+        .count()
+}
+
+// Replaces reads of a variable that holds a plain copy of another, `y` in
+// `i32s y = x; exit y;`, with the original. The program and each block are a
+// straight-line sequence; anything that could change a variable behind the pass's back
+// (a call, a loop, a nested body, a write through a reference) forgets every copy.
+fn propagate_copies(ast: &mut Ast) {
+    let mut bodies = vec![ast.root().children.clone()];
+    for node in ast.nodes.iter() {
+        if let Stmt::Block { body } = &node.stmt {
+            bodies.push(body.clone());
+        }
+    }
+    for body in bodies {
+        propagate_in(&mut ast.nodes, &body);
+    }
+}
+
+fn propagate_in(nodes: &mut Arena<Node>, body: &[NodeId]) {
+    // (copy, original) pairs known to hold the same value
+    let mut copies: Vec<(Name, Name)> = Vec::new();
+    for &id in body {
+        match &mut nodes[id].stmt {
+            Stmt::VariableDeclaration {
+                name,
+                value: Some(value),
+                type_,
+            } if !value.has_call() && !matches!(type_, Type::Ref(_)) => {
+                substitute(value, &copies);
+                copies.retain(|(copy, original)| copy != name && original != name);
+                if let ExprKind::Ident(original) = &value.kind
+                    && original != name
+                {
+                    copies.push((*name, *original));
+                }
+            }
+            Stmt::VariableAssignment { name, value } if !value.has_call() => {
+                substitute(value, &copies);
+                copies.retain(|(copy, original)| copy != name && original != name);
+                if let ExprKind::Ident(original) = &value.kind
+                    && original != name
+                {
+                    copies.push((*name, *original));
+                }
+            }
+            Stmt::Exit(expr)
+            | Stmt::PrintInt(expr)
+            | Stmt::Assert(expr)
+            | Stmt::Expression(expr)
+            | Stmt::Return(Some(expr))
+                if !expr.has_call() =>
+            {
+                substitute(expr, &copies)
+            }
+            // evaluated once before the body runs
+            Stmt::If { condition, .. } if !condition.has_call() => {
+                substitute(condition, &copies);
+                copies.clear();
+            }
+            Stmt::Match { scrutinee, .. } if !scrutinee.has_call() => {
+                substitute(scrutinee, &copies);
+                copies.clear();
+            }
+            Stmt::ConstantDeclaration { .. } | Stmt::Define { .. } | Stmt::Return(None) => {}
+            _ => copies.clear(),
+        }
+    }
+}
+
+fn substitute(expr: &mut Expr, copies: &[(Name, Name)]) {
+    match &mut expr.kind {
+        ExprKind::Ident(name) => {
+            if let Some((_, original)) = copies.iter().find(|(copy, _)| copy == name) {
+                *name = *original;
+            }
+        }
+        ExprKind::BinaryOp { left, right, .. } => {
+            substitute(left, copies);
+            substitute(right, copies);
+        }
+        ExprKind::Convert { value, .. } | ExprKind::Negate { value, .. } => {
+            substitute(value, copies)
+        }
+        _ => {}
+    }
+}
+
+// Evaluates the upper bound of a for loop once, before the loop, when nothing the body
+// does can change it: `for i in 0 to n * 2 { ... }` becomes
+// `{ i32s i.end = n * 2; for i in 0 to i.end { ... } }`, where the '.' keeps the new
+// variable from colliding with any the program declares. Only bounds that compute
+// something are hoisted, since reloading a variable or constant costs no more.
+fn hoist_loop_bounds(ast: &mut Ast) {
+    let pure = pure_functions(ast);
+    let loops: Vec<NodeId> = ast
+        .nodes
+        .ids()
+        .filter(|&id| matches!(ast.nodes[id].stmt, Stmt::For { .. }))
+        .collect();
+    for id in loops {
+        let Stmt::For {
+            iterator_name,
+            type_,
+            iterator_begin,
+            iterator_end,
+            body,
+            ..
+        } = &ast.nodes[id].stmt
+        else {
+            continue;
+        };
+        if matches!(
+            iterator_end.kind,
+            ExprKind::Int(_) | ExprKind::Float(_) | ExprKind::Ident(_)
+        ) || !calls_only(iterator_begin, &pure)
+            || !is_invariant(&ast.nodes, iterator_end, *body, &pure)
+        {
+            continue;
+        }
+
+        let span = ast.nodes[id].span;
+        let type_ = type_.clone();
+        let bound = Name::intern(&format!("{}.end{}", iterator_name, id.index()));
+        let mut stmt = ast.nodes[id].stmt.clone();
+        let Stmt::For { iterator_end, .. } = &mut stmt else {
+            unreachable!()
+        };
+        let value = std::mem::replace(
+            iterator_end,
+            Expr::typed(ExprKind::Ident(bound), type_.clone()),
+        );
+        let declaration = ast.nodes.alloc(Node {
+            stmt: Stmt::VariableDeclaration {
+                name: bound,
+                type_,
+                value: Some(value),
+            },
+            children: vec![],
+            span,
+        });
+        let for_ = ast.nodes.alloc(Node {
+            stmt,
+            children: vec![],
+            span,
+        });
+        // the block replaces the loop in place, so whatever held its id now holds both
+        ast.nodes[id].stmt = Stmt::Block {
+            body: vec![declaration, for_],
+        };
+        ast.nodes[id].span = Span::default();
+    }
+}
+
+// Whether a loop bound has the same value on every iteration: it reads no variable the
+// body assigns and calls only pure functions. When the body writes through a reference
+// or calls a function that may have side effects, any variable could change, so the
+// bound may read none.
+fn is_invariant(nodes: &Arena<Node>, bound: &Expr, body: NodeId, pure: &HashSet<Name>) -> bool {
+    let mut assigned = HashSet::new();
+    let mut writes_anything = false;
+    let mut pending = vec![body];
+    while let Some(id) = pending.pop() {
+        let node = &nodes[id];
+        match &node.stmt {
+            Stmt::FunctionDeclaration { .. } => continue,
+            Stmt::VariableAssignment { name, .. } => {
+                assigned.insert(*name);
+            }
+            Stmt::DerefAssignment { .. } => writes_anything = true,
+            _ => {}
+        }
+        writes_anything |= statement_exprs(&node.stmt)
+            .iter()
+            .any(|expr| !calls_only(expr, pure));
+        pending.extend(node.child_ids());
+    }
+    let mut reads = Vec::new();
+    variables_read(bound, &mut reads);
+    calls_only(bound, pure)
+        && !has_deref(bound)
+        && (reads.is_empty() || !writes_anything)
+        && reads.iter().all(|name| !assigned.contains(name))
+}
+
+// Functions whose result depends only on their arguments and which change nothing the
+// caller can see: no ref parameters, no reads or writes of globals, no I/O, exits or
+// asserts, and calls only to other such functions. Starts from every candidate and
+// drops the ones that call a dropped one until none changes, so recursion stays pure.
+fn pure_functions(ast: &Ast) -> HashSet<Name> {
+    let functions: Vec<(&Name, Vec<Name>, NodeId)> = ast
+        .nodes
+        .iter()
+        .filter_map(|node| match &node.stmt {
+            Stmt::FunctionDeclaration {
+                name,
+                params,
+                body,
+                linkage,
+                ..
+            } if *linkage != Linkage::Extern
+                && params
+                    .iter()
+                    .all(|(_, type_)| !matches!(type_, Type::Ref(_))) =>
+            {
+                Some((name, params.iter().map(|(p, _)| *p).collect(), *body))
+            }
+            _ => None,
+        })
+        .collect();
+    let mut pure: HashSet<Name> = functions.iter().map(|(name, ..)| **name).collect();
+    loop {
+        let impure: Vec<&Name> = functions
+            .iter()
+            .filter(|(name, params, body)| {
+                pure.contains(*name) && {
+                    let mut scopes = vec![params.clone()];
+                    !statement_is_pure(&ast.nodes, *body, &mut scopes, &pure)
+                }
+            })
+            .map(|(name, ..)| *name)
+            .collect();
+        if impure.is_empty() {
+            return pure;
+        }
+        for name in impure {
+            pure.remove(name);
+        }
+    }
+}
+
+// Walks statements in order, tracking the names declared so far, so a name is known to
+// be local at the point it is used
+fn statement_is_pure(
+    nodes: &Arena<Node>,
+    id: NodeId,
+    scopes: &mut Vec<Vec<Name>>,
+    pure: &HashSet<Name>,
+) -> bool {
+    let local = |scopes: &Vec<Vec<Name>>, name: &Name| scopes.iter().flatten().any(|n| n == name);
+    let expr_pure = |scopes: &Vec<Vec<Name>>, expr: &Expr| {
+        let mut reads = Vec::new();
+        variables_read(expr, &mut reads);
+        calls_only(expr, pure) && !has_deref(expr) && reads.iter().all(|name| local(scopes, name))
+    };
+    match &nodes[id].stmt {
+        Stmt::VariableDeclaration { name, value, .. } => {
+            let result = value.as_ref().is_none_or(|value| expr_pure(scopes, value));
+            scopes.last_mut().unwrap().push(*name);
+            result
+        }
+        Stmt::VariableAssignment { name, value } => local(scopes, name) && expr_pure(scopes, value),
+        Stmt::Expression(expr) | Stmt::Return(Some(expr)) => expr_pure(scopes, expr),
+        Stmt::For {
+            iterator_name,
+            iterator_begin,
+            iterator_end,
+            step,
+            body,
+            ..
+        } => {
+            if !expr_pure(scopes, iterator_begin)
+                || !expr_pure(scopes, iterator_end)
+                || !step.as_ref().is_none_or(|step| expr_pure(scopes, step))
+            {
+                return false;
+            }
+            scopes.push(vec![*iterator_name]);
+            let result = statement_is_pure(nodes, *body, scopes, pure);
+            scopes.pop();
+            result
+        }
+        Stmt::If {
+            condition,
+            body,
+            else_body,
+        } => {
+            expr_pure(scopes, condition)
+                && statement_is_pure(nodes, *body, scopes, pure)
+                && else_body.is_none_or(|id| statement_is_pure(nodes, id, scopes, pure))
+        }
+        Stmt::While { condition, body } | Stmt::DoWhile { body, condition } => {
+            expr_pure(scopes, condition) && statement_is_pure(nodes, *body, scopes, pure)
+        }
+        Stmt::Match {
+            scrutinee,
+            arms,
+            else_body,
+        } => {
+            expr_pure(scopes, scrutinee)
+                && arms
+                    .iter()
+                    .all(|&(_, body)| statement_is_pure(nodes, body, scopes, pure))
+                && else_body.is_none_or(|body| statement_is_pure(nodes, body, scopes, pure))
+        }
+        Stmt::Block { body } => {
+            scopes.push(Vec::new());
+            let result = body
+                .iter()
+                .all(|&id| statement_is_pure(nodes, id, scopes, pure));
+            scopes.pop();
+            result
+        }
+        Stmt::Return(None)
+        | Stmt::ConstantDeclaration { .. }
+        | Stmt::Define { .. }
+        | Stmt::FunctionDeclaration { .. } => true,
+        Stmt::Entry
+        | Stmt::Exit(_)
+        | Stmt::PrintInt(_)
+        | Stmt::Assert(_)
+        | Stmt::DerefAssignment { .. } => false,
+    }
+}
+
+// The expressions a statement evaluates itself, not counting its nested statements
+fn statement_exprs(stmt: &Stmt) -> Vec<&Expr> {
+    match stmt {
+        Stmt::Exit(expr)
+        | Stmt::PrintInt(expr)
+        | Stmt::Assert(expr)
+        | Stmt::Expression(expr)
+        | Stmt::Return(Some(expr))
+        | Stmt::VariableDeclaration {
+            value: Some(expr), ..
+        }
+        | Stmt::VariableAssignment { value: expr, .. }
+        | Stmt::DerefAssignment { value: expr, .. }
+        | Stmt::If {
+            condition: expr, ..
+        }
+        | Stmt::While {
+            condition: expr, ..
+        }
+        | Stmt::DoWhile {
+            condition: expr, ..
+        }
+        | Stmt::Match {
+            scrutinee: expr, ..
+        } => vec![expr],
+        Stmt::For {
+            iterator_begin,
+            iterator_end,
+            step,
+            ..
+        } => [iterator_begin, iterator_end]
+            .into_iter()
+            .chain(step)
+            .collect(),
+        Stmt::Entry
+        | Stmt::Return(None)
+        | Stmt::VariableDeclaration { value: None, .. }
+        | Stmt::Block { .. }
+        | Stmt::FunctionDeclaration { .. }
+        | Stmt::ConstantDeclaration { .. }
+        | Stmt::Define { .. } => vec![],
+    }
+}
+
+// Whether every call in an expression is to a pure function; read_int and extern
+// functions never are
+fn calls_only(expr: &Expr, pure: &HashSet<Name>) -> bool {
+    expr.walk().all(|visit| match &visit.expr.kind {
+        ExprKind::Call { name, .. } => pure.contains(name),
+        _ => true,
+    })
+}
+
+fn has_deref(expr: &Expr) -> bool {
+    expr.walk()
+        .any(|visit| matches!(visit.expr.kind, ExprKind::Deref(_) | ExprKind::AddressOf(_)))
+}
+
+fn variables_read(expr: &Expr, reads: &mut Vec<Name>) {
+    reads.extend(expr.walk().filter_map(|visit| match visit.expr.kind {
+        ExprKind::Ident(name) => Some(name),
+        _ => None,
+    }));
+}
+
+// Drops a store to a variable when a later store in the same basic block overwrites it
+// before anything could read it
+fn eliminate_dead_stores(lines: &mut Vec<Line>) {
+    let mut dead = vec![false; lines.len()];
+    for (i, line) in lines.iter().enumerate() {
+        let Some((base, offset, size)) = variable_store(line) else {
+            continue;
+        };
+        for later in &lines[i + 1..] {
+            match later {
+                Line::Location { .. } | Line::Comment(_) | Line::Blank => continue,
+                Line::Instruction { .. } => {}
+                _ => break,
+            }
+            if let Some((b, o, s)) = variable_store(later)
+                && b == base
+                && o == offset
+            {
+                dead[i] = s.bytes() >= size.bytes() && !reads_operand(later, base, offset);
+                break;
+            }
+            if ends_block(later) || may_read(later, base, offset) {
+                break;
+            }
+        }
+    }
+    let mut index = 0;
+    lines.retain(|_| {
+        index += 1;
+        !dead[index - 1]
+    });
+}
+
+// `mov size [variable], source` where the variable is a global or an rbp-relative slot
+fn variable_store(line: &Line) -> Option<(&str, i64, Size)> {
+    let Line::Instruction { mnemonic, operands } = line else {
+        return None;
+    };
+    match (mnemonic.as_str(), operands.as_slice()) {
+        (
+            "mov",
+            [
+                Operand::Memory {
+                    size: Some(size),
+                    base,
+                    offset,
+                },
+                Operand::Register(_) | Operand::Immediate(_),
+            ],
+        ) if base == "rbp" || !is_register(base) => Some((base, *offset, *size)),
+        _ => None,
+    }
+}
+
+// A register known to hold the current value of a variable, because it was loaded from
+// or stored to it and neither has been written since
+struct Held {
+    register: String,
+    base: String,
+    offset: i64,
+    size: Size,
+}
+
+// Within a basic block, drops a load of a variable into a register that already holds it
+// and turns a load of one held in another register into a register move. Only a label
+// joins paths, so knowledge survives a conditional jump into the fall-through path.
+fn eliminate_redundant_loads(lines: &mut Vec<Line>) {
+    let mut held: Vec<Held> = Vec::new();
+    let mut dead = vec![false; lines.len()];
+    for i in 0..lines.len() {
+        match &lines[i] {
+            Line::Instruction { .. } => {}
+            Line::Location { .. } | Line::Comment(_) | Line::Blank => continue,
+            _ => {
+                held.clear();
+                continue;
+            }
+        }
+        if let Some((register, base, offset, size)) = variable_load(&lines[i]) {
+            let holds = |h: &&Held| h.base == base && h.offset == offset && h.size == size;
+            if held.iter().filter(holds).any(|h| h.register == register) {
+                dead[i] = true;
+                continue;
+            }
+            let copy = held.iter().find(holds).map(|h| h.register.clone());
+            let (register, base) = (register.to_string(), base.to_string());
+            forget_register(&mut held, &register);
+            if let Some(source) = copy {
+                lines[i] = Line::Instruction {
+                    mnemonic: "mov".to_string(),
+                    operands: vec![
+                        Operand::Register(register.clone()),
+                        Operand::Register(source),
+                    ],
+                };
+            }
+            held.push(Held {
+                register,
+                base,
+                offset,
+                size,
+            });
+            continue;
+        }
+        if let Some((base, offset, size)) = variable_store(&lines[i]) {
+            let (base, stored) = (base.to_string(), lines[i].clone());
+            forget_memory(&mut held, &base, offset, Some(size));
+            if let Line::Instruction { operands, .. } = stored
+                && let Operand::Register(register) = &operands[1]
+                && asm::register_size(register) == Some(size)
+            {
+                held.push(Held {
+                    register: register.clone(),
+                    base,
+                    offset,
+                    size,
+                });
+            }
+            continue;
+        }
+        forget_written(&lines[i], &mut held);
+    }
+    let mut index = 0;
+    lines.retain(|_| {
+        index += 1;
+        !dead[index - 1]
+    });
+}
+
+// `mov register, size [variable]`, the inverse of variable_store
+fn variable_load(line: &Line) -> Option<(&str, &str, i64, Size)> {
+    let Line::Instruction { mnemonic, operands } = line else {
+        return None;
+    };
+    match (mnemonic.as_str(), operands.as_slice()) {
+        (
+            "mov",
+            [
+                Operand::Register(register),
+                Operand::Memory {
+                    size: Some(size),
+                    base,
+                    offset,
+                },
+            ],
+        ) if asm::register_size(register) == Some(*size)
+            && (base == "rbp" || !is_register(base)) =>
+        {
+            Some((register, base, *offset, *size))
+        }
+        _ => None,
+    }
+}
+
+// Forgets whatever an instruction other than a variable load or store overwrites, and
+// everything when it is not known to leave the rest alone
+fn forget_written(line: &Line, held: &mut Vec<Held>) {
+    let Line::Instruction { mnemonic, operands } = line else {
+        held.clear();
+        return;
+    };
+    let writes_first = match mnemonic.as_str() {
+        "cmp" | "test" | "comiss" | "ucomiss" | "push" => false,
+        "cdq" => {
+            forget_register(held, "edx");
+            false
+        }
+        "idiv" | "div" => {
+            forget_register(held, "eax");
+            forget_register(held, "edx");
+            false
+        }
+        "mov" | "movzx" | "movsx" | "lea" | "add" | "sub" | "imul" | "and" | "or" | "xor"
+        | "shl" | "shr" | "sar" | "inc" | "dec" | "neg" | "not" | "pop" | "movd" | "movss"
+        | "addss" | "subss" | "mulss" | "divss" | "cvtsi2ss" | "cvttss2si" => true,
+        m if m.starts_with("set") => true,
+        m if m.starts_with('j') && m != "jmp" => false,
+        _ => {
+            held.clear();
+            return;
+        }
+    };
+    match operands.first() {
+        Some(Operand::Register(register)) if writes_first => {
+            if register == "rbp" {
+                held.clear();
+            } else {
+                forget_register(held, register);
+            }
+        }
+        // anything but the frame and the stack could be a variable behind a reference
+        Some(Operand::Memory { base, offset, size }) if writes_first => {
+            if base == "rbp" || !is_register(base) {
+                forget_memory(held, base, *offset, *size);
+            } else if base != "rsp" {
+                held.clear();
+            }
+        }
+        _ => {}
+    }
+}
+
+fn forget_register(held: &mut Vec<Held>, register: &str) {
+    let family = asm::register_family(register);
+    held.retain(|h| asm::register_family(&h.register) != family);
+}
+
+// Forgets every register holding a variable that overlaps the bytes written. A write of
+// unknown size could reach any variable at the same base.
+fn forget_memory(held: &mut Vec<Held>, base: &str, offset: i64, size: Option<Size>) {
+    held.retain(|h| {
+        h.base != base
+            || size.is_some_and(|size| {
+                offset + size.bytes() as i64 <= h.offset
+                    || h.offset + h.size.bytes() as i64 <= offset
+            })
+    });
+}
+
+fn reads_operand(line: &Line, base: &str, offset: i64) -> bool {
+    let Line::Instruction { operands, .. } = line else {
+        return false;
+    };
+    operands[1..].iter().any(|op| addresses(op, base, offset))
+}
+
+// Whether an instruction other than a plain store to the variable may read it. Memory
+// reached through any register but rbp could be the variable behind a reference.
+fn may_read(line: &Line, base: &str, offset: i64) -> bool {
+    let Line::Instruction { mnemonic, operands } = line else {
+        return true;
+    };
+    operands.iter().enumerate().any(|(i, op)| match op {
+        Operand::Memory { base: b, .. } if is_register(b) && b != "rbp" => true,
+        // lea takes the address, which can be read through later
+        _ if mnemonic == "lea" => {
+            matches!(op, Operand::Memory { .. }) && addresses(op, base, offset)
+        }
+        _ => addresses(op, base, offset) && !(i == 0 && mnemonic == "mov"),
+    })
+}
+
+fn addresses(operand: &Operand, base: &str, offset: i64) -> bool {
+    matches!(operand, Operand::Memory { base: b, offset: o, .. } if b == base && *o == offset)
+}
+
+fn ends_block(line: &Line) -> bool {
+    match line {
+        Line::Instruction { mnemonic, .. } => {
+            mnemonic.starts_with('j') || matches!(mnemonic.as_str(), "call" | "ret" | "syscall")
+        }
+        _ => true,
+    }
+}
+
+fn is_register(name: &str) -> bool {
+    asm::register_size(name).is_some()
+}
+
+// Local rewrites repeated until nothing changes: moves of a register to itself, a push
+// and pop of rax around one instruction that leaves rax alone, a jump to the very
+// next label, and instructions after an unconditional jump that no label leads to
+fn peephole(lines: &mut Vec<Line>) {
+    loop {
+        let before = lines.len();
+        let mut dead = vec![false; lines.len()];
+        let mut unreachable = false;
+        for i in 0..lines.len() {
+            match &lines[i] {
+                Line::Label(_) => unreachable = false,
+                Line::Instruction { .. } if unreachable => dead[i] = true,
+                Line::Instruction { mnemonic, operands } => {
+                    if mnemonic == "mov"
+                        && let [Operand::Register(a), Operand::Register(b)] = operands.as_slice()
+                        && a == b
+                    {
+                        dead[i] = true;
+                    }
+                    if mnemonic == "push"
+                        && operands == &[Operand::Register("rax".to_string())]
+                        && let Some(middle) = next_instruction(lines, i)
+                        && let Some(pop) = next_instruction(lines, middle)
+                        && lines[pop] == asm::parse_instruction("pop rax")
+                        && !dead[middle]
+                        && leaves_rax(&lines[middle])
+                    {
+                        dead[i] = true;
+                        dead[pop] = true;
+                    }
+                    if mnemonic == "jmp"
+                        && let [Operand::Label(target)] = operands.as_slice()
+                        && lines[i + 1..]
+                            .iter()
+                            .find(|line| !is_marker(line))
+                            .is_some_and(|line| *line == Line::Label(target.clone()))
+                    {
+                        dead[i] = true;
+                    }
+                    if mnemonic == "jmp" || mnemonic == "ret" {
+                        unreachable = true;
+                    }
+                }
+                Line::Section(_) => unreachable = false,
+                _ => {}
+            }
+        }
+        let mut index = 0;
+        lines.retain(|_| {
+            index += 1;
+            !dead[index - 1]
+        });
+        if lines.len() == before {
+            break;
+        }
+    }
+}
+
+fn is_marker(line: &Line) -> bool {
+    matches!(line, Line::Location { .. } | Line::Comment(_) | Line::Blank)
+}
+
+fn next_instruction(lines: &[Line], after: usize) -> Option<usize> {
+    let next = (after + 1..lines.len()).find(|&i| !matches!(lines[i], Line::Location { .. }))?;
+    matches!(lines[next], Line::Instruction { .. }).then_some(next)
+}
+
+// A data move that neither reads nor writes rax, explicitly or through the stack
+fn leaves_rax(line: &Line) -> bool {
+    let Line::Instruction { mnemonic, operands } = line else {
+        return false;
+    };
+    matches!(mnemonic.as_str(), "mov" | "movzx" | "lea" | "movd")
+        && operands.iter().all(|op| match op {
+            Operand::Register(r) => {
+                !matches!(r.as_str(), "rax" | "eax" | "ax" | "al" | "ah" | "rsp")
+            }
+            Operand::Memory { base, .. } => !matches!(base.as_str(), "rax" | "rsp"),
+            _ => true,
+        })
+}
+
+// Caller-saved under both calling conventions and otherwise only used to pass arguments,
+// so holding a value in one across instructions that leave it alone is invisible to any
+// caller or callee
+const SPILL_REGISTERS: [&str; 4] = ["r8", "r9", "r10", "r11"];
+
+// The generator keeps the left operand of a binary operation on the stack while it
+// evaluates the right one. This pass allocates registers to those stack slots instead,
+// by linear scan over the push/pop pairs of each basic block in order: a pair gets the
+// first spill register that no instruction between them touches, that no enclosing
+// pair holds and that liveness shows is dead afterwards. A pair whose register still
+// holds the pushed value at the pop needs none and is dropped. When the pop is
+// `mov ebx, eax` / `pop rax` / `op eax, ebx`, the operation is done in the spill
+// register instead, which saves a move:
+//
+//     mov r8, rax
+//     ...
+//     sub r8d, eax
+//     mov eax, r8d
+fn allocate_registers(lines: &mut Vec<Line>) {
+    let graph = Cfg::build(lines);
+    let live_out = graph.live_registers(lines);
+    let mut edits: Vec<(usize, Vec<Line>)> = Vec::new();
+    for (b, block) in graph.blocks.iter().enumerate() {
+        let live_after = graph.live_after(lines, b, &live_out[b]);
+        let live = |i: usize, register: &'static str| {
+            live_after[i - block.lines.start].contains(&Place::Register(register))
+        };
+        // (push, pop) pairs in the order of their pushes, which is linear-scan order
+        let mut pairs: Vec<(usize, usize)> = Vec::new();
+        let mut open: Vec<usize> = Vec::new();
+        for i in block.lines.clone() {
+            let Line::Instruction { mnemonic, operands } = &lines[i] else {
+                continue;
+            };
+            match (mnemonic.as_str(), operands.as_slice()) {
+                ("push", [Operand::Register(r)]) if asm::register_size(r) == Some(Size::Qword) => {
+                    open.push(i)
+                }
+                ("push", [Operand::Memory { base, .. }]) if base != "rsp" => open.push(i),
+                ("pop", [Operand::Register(_)]) => {
+                    if let Some(push) = open.pop() {
+                        pairs.push((push, i));
+                    }
+                }
+                ("push" | "pop", _) => open.clear(),
+                // anything else that moves or addresses the stack may depend on where the
+                // pushed values are, and a call's stack arguments are read by the callee
+                _ if mentions(&lines[i], "rsp") => open.clear(),
+                _ => {}
+            }
+        }
+        pairs.sort();
+
+        // (pop, register) of the pairs given a register so far
+        let mut active: Vec<(usize, &'static str)> = Vec::new();
+        for (push, pop) in pairs {
+            active.retain(|&(end, _)| end > push);
+            let (
+                Line::Instruction {
+                    operands: pushed, ..
+                },
+                Line::Instruction {
+                    operands: popped, ..
+                },
+            ) = (&lines[push], &lines[pop])
+            else {
+                continue;
+            };
+            let (source, Operand::Register(destination)) = (&pushed[0], &popped[0]) else {
+                continue;
+            };
+            let between = push + 1..pop;
+            if *source == popped[0]
+                && let Some(family) = asm::register_family(destination)
+                && !lines[between.clone()]
+                    .iter()
+                    .any(|line| cfg::effects(line).writes.contains(&Place::Register(family)))
+            {
+                edits.push((push, vec![]));
+                edits.push((pop, vec![]));
+                continue;
+            }
+            let touches = |operand: &Operand, register: &str| match operand {
+                Operand::Register(r) | Operand::Memory { base: r, .. } => {
+                    asm::register_family(r) == Some(register)
+                }
+                _ => false,
+            };
+            let Some(register) = SPILL_REGISTERS.into_iter().find(|&register| {
+                !active.iter().any(|&(_, r)| r == register)
+                    && !touches(source, register)
+                    && !touches(&popped[0], register)
+                    && !lines[between.clone()]
+                        .iter()
+                        .any(|line| mentions(line, register))
+                    && !live(pop, register)
+            }) else {
+                continue;
+            };
+            active.push((pop, register));
+
+            let spill = Operand::Register(register.to_string());
+            edits.push((
+                push,
+                vec![instruction("mov", vec![spill.clone(), source.clone()])],
+            ));
+            match fold_into_spill(lines, pop, destination, &live) {
+                Some((copy, operation, mnemonic)) => {
+                    let spill = format!("{}d", register);
+                    edits.push((copy, vec![]));
+                    edits.push((pop, vec![]));
+                    edits.push((
+                        operation,
+                        vec![
+                            asm::parse_instruction(&format!("{} {}, eax", mnemonic, spill)),
+                            asm::parse_instruction(&format!("mov eax, {}", spill)),
+                        ],
+                    ));
+                }
+                None => {
+                    let restore = vec![popped[0].clone(), spill];
+                    edits.push((pop, vec![instruction("mov", restore)]));
+                }
+            }
+        }
+    }
+    edits.sort_by_key(|(i, _)| *i);
+    for (i, replacement) in edits.into_iter().rev() {
+        lines.splice(i..=i, replacement);
+    }
+}
+
+fn instruction(mnemonic: &str, operands: Vec<Operand>) -> Line {
+    Line::Instruction {
+        mnemonic: mnemonic.to_string(),
+        operands,
+    }
+}
+
+// For a pop of rax between `mov ebx, eax` and `op eax, ebx`, the lines of the copy and
+// the operation and the operation's mnemonic, provided nothing reads ebx afterwards
+fn fold_into_spill(
+    lines: &[Line],
+    pop: usize,
+    destination: &str,
+    live: &impl Fn(usize, &'static str) -> bool,
+) -> Option<(usize, usize, String)> {
+    if destination != "rax" {
+        return None;
+    }
+    let copy = previous_instruction(lines, pop)?;
+    let operation = (pop + 1..lines.len()).find(|&i| !is_marker(&lines[i]))?;
+    if lines[copy] != asm::parse_instruction("mov ebx, eax") {
+        return None;
+    }
+    let Line::Instruction { mnemonic, operands } = &lines[operation] else {
+        return None;
+    };
+    let operands_match = *operands
+        == [
+            Operand::Register("eax".to_string()),
+            Operand::Register("ebx".to_string()),
+        ];
+    (operands_match
+        && matches!(
+            mnemonic.as_str(),
+            "add" | "sub" | "imul" | "and" | "or" | "xor" | "cmp"
+        )
+        && !live(operation, "rbx"))
+    .then(|| (copy, operation, mnemonic.clone()))
+}
+
+// Replaces multiplication and division by a power of two loaded just before with shifts:
+// `mov ebx, 8` / `imul eax, ebx` becomes `shl eax, 3`, and `mov ebx, 4` / `cdq` /
+// `idiv ebx` becomes `cdq` / `and edx, 3` / `add eax, edx` / `sar eax, 2`, where adding
+// the low bits of a negative dividend makes the shift round toward zero like idiv. Runs
+// after the peephole pass, which leaves the constant next to the instruction using it.
+fn reduce_strength(lines: &mut Vec<Line>) {
+    let mut i = 0;
+    while i < lines.len() {
+        if let Some(replaced) = reduce_at(lines, i) {
+            i = replaced;
+        }
+        i += 1;
+    }
+}
+
+// Rewrites the instruction at i if it can be, returning the index of the last line of
+// its replacement
+fn reduce_at(lines: &mut Vec<Line>, i: usize) -> Option<usize> {
+    let Line::Instruction { mnemonic, operands } = &lines[i] else {
+        return None;
+    };
+    match (mnemonic.as_str(), operands.as_slice()) {
+        // a shift sets the overflow flag differently, so checked products are left alone
+        ("imul", [Operand::Register(product), Operand::Register(factor)])
+            if product != factor && !next_reads_flags(lines, i) =>
+        {
+            let (product, factor) = (product.clone(), factor.clone());
+            if let Some((load, shift)) = power_of_two_load(lines, i, &factor) {
+                lines[i] = asm::parse_instruction(&format!("shl {}, {}", product, shift));
+                if overwritten_before_read(lines, i, &factor) {
+                    lines.remove(load);
+                    return Some(i - 1);
+                }
+                return Some(i);
+            }
+            // the constant came first: shift the other factor into its place instead
+            let (load, shift) = power_of_two_load(lines, i, &product)?;
+            lines[i] = asm::parse_instruction(&format!("shl {}, {}", product, shift));
+            lines.insert(
+                i,
+                asm::parse_instruction(&format!("mov {}, {}", product, factor)),
+            );
+            lines.remove(load);
+            Some(i)
+        }
+        ("idiv", [Operand::Register(divisor)]) if divisor == "ebx" => {
+            let divisor = divisor.clone();
+            let cdq = previous_instruction(lines, i)?;
+            if lines[cdq] != asm::parse_instruction("cdq") {
+                return None;
+            }
+            let (load, shift) = power_of_two_load(lines, cdq, &divisor)?;
+            // the remainder idiv leaves in edx is never used, so edx is free
+            let replacement = [
+                format!("and edx, {}", (1i64 << shift) - 1),
+                "add eax, edx".to_string(),
+                format!("sar eax, {}", shift),
+            ];
+            lines.splice(i..=i, replacement.iter().map(|s| asm::parse_instruction(s)));
+            let last = i + replacement.len() - 1;
+            if overwritten_before_read(lines, last, &divisor) {
+                lines.remove(load);
+                return Some(last - 1);
+            }
+            Some(last)
+        }
+        _ => None,
+    }
+}
+
+// The `mov register, 2^k` (k >= 1) that last set a register before line `before`, and k,
+// provided nothing in between mentions the register or could change it implicitly
+fn power_of_two_load(lines: &[Line], before: usize, register: &str) -> Option<(usize, u32)> {
+    let family = asm::register_family(register)?;
+    let mut at = before;
+    loop {
+        at = previous_instruction(lines, at)?;
+        let Line::Instruction { mnemonic, operands } = &lines[at] else {
+            return None;
+        };
+        if mnemonic == "mov"
+            && let [Operand::Register(r), Operand::Immediate(value)] = operands.as_slice()
+            && r == register
+        {
+            let power = (*value > 1 && value & (value - 1) == 0 && *value <= 1 << 30)
+                .then(|| value.trailing_zeros());
+            return power.map(|shift| (at, shift));
+        }
+        if mentions(&lines[at], family) {
+            return None;
+        }
+    }
+}
+
+fn previous_instruction(lines: &[Line], before: usize) -> Option<usize> {
+    let at = (0..before).rev().find(|&i| !is_marker(&lines[i]))?;
+    matches!(lines[at], Line::Instruction { .. }).then_some(at)
+}
+
+// Whether an instruction uses or changes a register family, explicitly or, for the
+// ones the generator emits, implicitly
+fn mentions(line: &Line, family: &str) -> bool {
+    let Line::Instruction { mnemonic, operands } = line else {
+        return true;
+    };
+    let implicit = match mnemonic.as_str() {
+        "call" | "syscall" | "ret" => true,
+        "cdq" | "cqo" | "idiv" | "div" | "mul" => matches!(family, "rax" | "rdx"),
+        _ => false,
+    };
+    implicit
+        || operands.iter().any(|op| match op {
+            Operand::Register(r) => asm::register_family(r) == Some(family),
+            Operand::Memory { base, .. } => asm::register_family(base) == Some(family),
+            _ => false,
+        })
+}
+
+// Whether a register is written before anything reads it in the rest of the block. The
+// end of the block counts as a read, so a value the generator carries past a label or
+// a jump is kept.
+fn overwritten_before_read(lines: &[Line], after: usize, register: &str) -> bool {
+    let Some(family) = asm::register_family(register) else {
+        return false;
+    };
+    for line in lines[after + 1..].iter().filter(|line| !is_marker(line)) {
+        if ends_block(line) {
+            return false;
+        }
+        if !mentions(line, family) {
+            continue;
+        }
+        // a write of at least 32 bits replaces the whole register; `mov ebx, [rbx]`
+        // reads it first
+        let Line::Instruction { mnemonic, operands } = line else {
+            return false;
+        };
+        return matches!(mnemonic.as_str(), "mov" | "movzx" | "lea" | "pop")
+            && matches!(operands.first(), Some(Operand::Register(r))
+                if asm::register_family(r) == Some(family)
+                    && asm::register_size(r).is_some_and(|size| size.bytes() >= 4))
+            && !operands[1..].iter().any(|op| match op {
+                Operand::Register(r) | Operand::Memory { base: r, .. } => {
+                    asm::register_family(r) == Some(family)
+                }
+                _ => false,
+            });
+    }
+    false
+}
+
+fn next_reads_flags(lines: &[Line], after: usize) -> bool {
+    lines[after + 1..]
+        .iter()
+        .find(|line| !is_marker(line))
+        .is_some_and(|line| match line {
+            Line::Instruction { mnemonic, .. } => {
+                (mnemonic.starts_with('j') && mnemonic != "jmp")
+                    || mnemonic.starts_with("set")
+                    || mnemonic.starts_with("cmov")
+            }
+            _ => false,
+        })
+}
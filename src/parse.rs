@@ -1,50 +1,43 @@
-use crate::tokenize::{Token, TokenType};
-use std::collections::HashMap;
+use crate::arena::Arena;
+use crate::ast::{Ast, ExprKind, Linkage, Node, Stmt};
+use crate::consteval::{self, Value};
+use crate::diagnostic::{Diagnostic, closest};
+use crate::intern::Name;
+use crate::symtab::{BUILTIN_FUNCTIONS, Symbol, SymbolKind, SymbolTable};
+use crate::tokenize::{
+    KEYWORDS, Span, TOKEN_TYPES, Token, TokenType, Tokenizer, keyword, strip_literal_suffix,
+};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::vec;
 
-#[derive(Debug)]
-pub enum AbstractSyntaxTreeSymbol {
-    AbstractSyntaxTreeSymbolEntry,
-    AbstractSyntaxTreeSymbolExit(Expr),
-    AbstractSyntaxTreeSymbolVariableDeclaration {
-        name: String,
-        type_: Type,
-        value: Expr,
-    },
-    AbstractSyntaxTreeSymbolVariableAssignment {
-        name: String,
-        value: Expr,
-    },
-    AbstractSyntaxTreeSymbolFor {
-        iterator_name: String,
-        iterator_begin: Expr,
-        iterator_end: Expr,
-        body: Vec<AbstractSyntaxTreeNode>,
-    },
-    AbstractSyntaxTreeSymbolIf {
-        condition: Expr,
-        body: Vec<AbstractSyntaxTreeNode>,
-        else_body: Option<Box<AbstractSyntaxTreeNode>>,
-    },
-    AbstractSyntaxTreeSymbolBlock {
-        body: Vec<AbstractSyntaxTreeNode>,
-    },
-}
-
-#[derive(Debug)]
-pub struct AbstractSyntaxTreeNode {
-    pub symbol: AbstractSyntaxTreeSymbol,
-    pub children: Vec<AbstractSyntaxTreeNode>,
-}
-
-#[derive(Debug, PartialEq)]
+// The tree types moved to crate::ast; these names keep old code compiling for one
+// more release. The AbstractSyntaxTreeSymbolX variants themselves are now Stmt::X.
+pub use crate::ast::{BinOpType, Expr, NodeId, Type};
+#[deprecated(note = "use noble::ast::Stmt")]
+pub type AbstractSyntaxTreeSymbol = Stmt;
+#[deprecated(note = "use noble::ast::Node")]
+pub type AbstractSyntaxTreeNode = Node;
+#[deprecated(note = "use noble::ast::Ast")]
+pub type AbstractSyntaxTree = Ast;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[allow(clippy::enum_variant_names)]
 pub enum ParseTreeSymbol {
     ParseTreeSymbolNodeEntryPoint,
     ParseTreeSymbolNodeStatement,
     ParseTreeSymbolNodeExpression,
     ParseTreeSymbolNodeExit,
+    ParseTreeSymbolNodePrintInt,
+    ParseTreeSymbolNodeAssert,
+    ParseTreeSymbolNodeConstantDeclaration,
+    // a use of a constant, holding the literal of its value
+    ParseTreeSymbolNodeConstant,
+    ParseTreeSymbolNodeDefine,
+    // a use of a define, holding the expression it stands for
+    ParseTreeSymbolNodeDefined,
     ParseTreeSymbolNodeVariableDeclaration,
     ParseTreeSymbolNodeVariableAssignment,
+    ParseTreeSymbolNodeParallelAssignment,
     ParseTreeSymbolNodeType,
     ParseTreeSymbolNodeFor,
     ParseTreeSymbolNodeIf,
@@ -55,6 +48,26 @@ pub enum ParseTreeSymbol {
     ParseTreeSymbolNodeAdd,
     ParseTreeSymbolNodeMul,
     ParseTreeSymbolNodePrimary,
+    ParseTreeSymbolNodeFunction,
+    ParseTreeSymbolNodeReturn,
+    ParseTreeSymbolTerminalReturn,
+    ParseTreeSymbolNodeExpressionStatement,
+    ParseTreeSymbolNodeAddressOf,
+    ParseTreeSymbolNodeDereference,
+    // -x
+    ParseTreeSymbolNodeNegate,
+    ParseTreeSymbolNodeDerefAssignment,
+    // f32s(x) or i32s(x)
+    ParseTreeSymbolNodeConversion,
+    ParseTreeSymbolTerminalVoid,
+    ParseTreeSymbolNodeParameters,
+    ParseTreeSymbolNodeParameter,
+    ParseTreeSymbolNodeCall,
+    ParseTreeSymbolNodeArguments,
+    ParseTreeSymbolNodeWhile,
+    ParseTreeSymbolNodeDoWhile,
+    ParseTreeSymbolNodeMatch,
+    ParseTreeSymbolNodeMatchArm,
     ParseTreeSymbolTerminalExit,
     ParseTreeSymbolTerminalSemicolon,
     ParseTreeSymbolTerminalIntegerLiteral,
@@ -70,6 +83,8 @@ pub enum ParseTreeSymbol {
     ParseTreeSymbolTerminalFor,
     ParseTreeSymbolTerminalForIn,
     ParseTreeSymbolTerminalForTo,
+    ParseTreeSymbolTerminalForUntil,
+    ParseTreeSymbolTerminalForStep,
     ParseTreeSymbolTerminalIf,
     ParseTreeSymbolTerminalElse,
     ParseTreeSymbolTerminalLeftCurlyBrace,
@@ -86,60 +101,319 @@ pub enum ParseTreeSymbol {
     ParseTreeSymbolTerminalNotEquals,
     ParseTreeSymbolTerminalLeftParen,
     ParseTreeSymbolTerminalRightParen,
+    ParseTreeSymbolTerminalFn,
+    ParseTreeSymbolTerminalComma,
+    ParseTreeSymbolTerminalArrow,
+    ParseTreeSymbolTerminalWhile,
+    ParseTreeSymbolTerminalDo,
+    ParseTreeSymbolTerminalMatch,
+    ParseTreeSymbolTerminalFatArrow,
+    ParseTreeSymbolTerminalPrintInt,
+    ParseTreeSymbolTerminalAssert,
+    ParseTreeSymbolTerminalConst,
+    ParseTreeSymbolTerminalDefine,
+    ParseTreeSymbolTerminalRef,
+    ParseTreeSymbolTerminalAmpersand,
+    ParseTreeSymbolTerminalPlusPlus,
+    ParseTreeSymbolTerminalMinusMinus,
+    ParseTreeSymbolTerminalExtern,
+    ParseTreeSymbolTerminalExport,
 }
 
-#[derive(Debug)]
-pub struct ParseTreeNode {
-    symbol: ParseTreeSymbol,
-    children: Vec<ParseTreeNode>,
-    value: Option<String>,
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Associativity {
+    // a - b - c is (a - b) - c
+    Left,
+    // a op b op c is a op (b op c)
+    Right,
 }
 
-#[derive(Debug, Clone)]
-pub enum Type {
-    I32S,
-    F32S,
-    Bool,
-    Char,
+// A precedence level of binary operators: its rule in the grammar, the parse tree node
+// its operators build and which way a chain of them groups
+pub struct Level {
+    pub name: &'static str,
+    // the rule the grammar gives the level
+    pub rule: &'static str,
+    pub node: ParseTreeSymbol,
+    pub associativity: Associativity,
 }
 
-#[derive(Debug, Clone)]
-pub enum Expr {
-    Int(i32),
-    Float(f32),
-    Bool(bool),
-    Char(char),
-    Ident(String),
-    BinaryOp {
-        left: Box<Expr>,
-        op: BinOpType,
-        right: Box<Expr>,
+// Precedence levels, loosest binding first; an operator's precedence is its level's
+// position here, counting from 1
+pub const LEVELS: [Level; 4] = [
+    Level {
+        name: "equality",
+        rule: "Equality",
+        node: ParseTreeSymbol::ParseTreeSymbolNodeEquality,
+        associativity: Associativity::Left,
+    },
+    Level {
+        name: "comparison",
+        rule: "Comparison",
+        node: ParseTreeSymbol::ParseTreeSymbolNodeComparison,
+        associativity: Associativity::Left,
+    },
+    Level {
+        name: "additive",
+        rule: "Add",
+        node: ParseTreeSymbol::ParseTreeSymbolNodeAdd,
+        associativity: Associativity::Left,
+    },
+    Level {
+        name: "multiplicative",
+        rule: "Mul",
+        node: ParseTreeSymbol::ParseTreeSymbolNodeMul,
+        associativity: Associativity::Left,
     },
+];
+
+// One binary operator: its token, the level it binds at, its parse tree terminal and
+// the AST operator it lowers to
+pub struct InfixOperator {
+    pub token: TokenType,
+    pub precedence: u8,
+    pub terminal: ParseTreeSymbol,
+    pub op: BinOpType,
+}
+
+impl InfixOperator {
+    pub fn level(&self) -> &'static Level {
+        &LEVELS[self.precedence as usize - 1]
+    }
+}
+
+// Every binary operator. Adding one is an entry here, plus a level above if it binds
+// unlike any existing operator; parse_binary and build_binary need no changes.
+pub const INFIX_OPERATORS: [InfixOperator; 10] = [
+    InfixOperator {
+        token: TokenType::EqEq,
+        precedence: 1,
+        terminal: ParseTreeSymbol::ParseTreeSymbolTerminalEqualsEquals,
+        op: BinOpType::Equal,
+    },
+    InfixOperator {
+        token: TokenType::NotEq,
+        precedence: 1,
+        terminal: ParseTreeSymbol::ParseTreeSymbolTerminalNotEquals,
+        op: BinOpType::NotEqual,
+    },
+    InfixOperator {
+        token: TokenType::Lt,
+        precedence: 2,
+        terminal: ParseTreeSymbol::ParseTreeSymbolTerminalLessThan,
+        op: BinOpType::LessThan,
+    },
+    InfixOperator {
+        token: TokenType::Le,
+        precedence: 2,
+        terminal: ParseTreeSymbol::ParseTreeSymbolTerminalLessThanOrEqual,
+        op: BinOpType::LessThanOrEqual,
+    },
+    InfixOperator {
+        token: TokenType::Gt,
+        precedence: 2,
+        terminal: ParseTreeSymbol::ParseTreeSymbolTerminalGreaterThan,
+        op: BinOpType::GreaterThan,
+    },
+    InfixOperator {
+        token: TokenType::Ge,
+        precedence: 2,
+        terminal: ParseTreeSymbol::ParseTreeSymbolTerminalGreaterThanOrEqual,
+        op: BinOpType::GreaterThanOrEqual,
+    },
+    InfixOperator {
+        token: TokenType::Plus,
+        precedence: 3,
+        terminal: ParseTreeSymbol::ParseTreeSymbolTerminalPlus,
+        op: BinOpType::Add,
+    },
+    InfixOperator {
+        token: TokenType::Minus,
+        precedence: 3,
+        terminal: ParseTreeSymbol::ParseTreeSymbolTerminalMinus,
+        op: BinOpType::Subtract,
+    },
+    InfixOperator {
+        token: TokenType::Star,
+        precedence: 4,
+        terminal: ParseTreeSymbol::ParseTreeSymbolTerminalStar,
+        op: BinOpType::Multiply,
+    },
+    InfixOperator {
+        token: TokenType::Slash,
+        precedence: 4,
+        terminal: ParseTreeSymbol::ParseTreeSymbolTerminalSlash,
+        op: BinOpType::Divide,
+    },
+];
+
+// The binary operators by precedence, loosest first, one level per line:
+// `1  equality        left   == !=`
+pub fn precedence_table() -> String {
+    let mut out = String::new();
+    for (index, level) in LEVELS.iter().enumerate() {
+        let precedence = index as u8 + 1;
+        let operators: Vec<&str> = INFIX_OPERATORS
+            .iter()
+            .filter(|o| o.precedence == precedence)
+            .filter_map(|o| o.token.spelling())
+            .collect();
+        let associativity = match level.associativity {
+            Associativity::Left => "left",
+            Associativity::Right => "right",
+        };
+        out.push_str(&format!(
+            "{:<3}{:<16}{:<7}{}\n",
+            precedence,
+            level.name,
+            associativity,
+            operators.join(" ")
+        ));
+    }
+    out
+}
+
+// Keywords parse_bare_statement starts a statement at
+fn starts_statement(token: TokenType) -> bool {
+    is_type(token)
+        || matches!(
+            token,
+            TokenType::Exit
+                | TokenType::PrintInt
+                | TokenType::Assert
+                | TokenType::Const
+                | TokenType::Define
+                | TokenType::For
+                | TokenType::If
+                | TokenType::While
+                | TokenType::Do
+                | TokenType::Match
+                | TokenType::Fn
+                | TokenType::Extern
+                | TokenType::Export
+                | TokenType::Return
+        )
+}
+
+// Keywords parse_type accepts
+fn is_type(token: TokenType) -> bool {
+    matches!(
+        token,
+        TokenType::I32S | TokenType::F32S | TokenType::Bool | TokenType::Char
+    )
+}
+
+fn infix_operator(token: TokenType) -> Option<&'static InfixOperator> {
+    INFIX_OPERATORS.iter().find(|o| o.token == token)
 }
 
 #[derive(Debug, Clone)]
-pub enum BinOpType {
-    Multiply,
-    Divide,
-    Add,
-    Subtract,
-    Equal,
-    NotEqual,
-    LessThan,
-    LessThanOrEqual,
-    GreaterThan,
-    GreaterThanOrEqual,
+pub struct ParseTreeNode {
+    symbol: ParseTreeSymbol,
+    children: Vec<ParseTreeNode>,
+    value: Option<Name>,
+    span: Span,
+}
+
+impl ParseTreeNode {
+    pub fn node_count(&self) -> usize {
+        1 + self.children.iter().map(|n| n.node_count()).sum::<usize>()
+    }
+
+    // Each node's symbol with its value on the next line, indented by depth
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        self.render_into(&mut out, 0);
+        out
+    }
+
+    fn render_into(&self, out: &mut String, indent: usize) {
+        let padding = "    ".repeat(indent);
+        out.push_str(&format!("{}{:?}\n", padding, self.symbol));
+        out.push_str(&format!("{}{:?}\n", padding, self.value));
+        for child in &self.children {
+            child.render_into(out, indent + 1);
+        }
+    }
 }
 
-struct VarEntry {
-    var_type: Type,
-    var_value: Expr,
+// How deeply statements and expressions may nest by default: parentheses, operands
+// of unary and binary operators and bodies of blocks, loops, conditionals and
+// functions each add a level, so `1 + 1 + 1` is three deep. The parser and later
+// stages recurse once per level, so an unbounded depth would overflow the stack instead
+// of failing with a diagnostic.
+pub const DEFAULT_MAX_DEPTH: usize = 256;
+
+// A '{' or '(' opened at `at` that the input ends inside of
+fn unclosed(open: TokenType, at: Span, span: Span) -> Diagnostic {
+    let close = if open == TokenType::LBrace { '}' } else { ')' };
+    Diagnostic::new(
+        format!(
+            "ParseError: unclosed '{}' (opened at {}:{})",
+            open.spelling().unwrap(),
+            at.line,
+            at.column
+        ),
+        span,
+    )
+    .with_note(format!("the input ends before its closing '{}'", close))
 }
 
 pub struct Parser {
     tokens: Vec<Token>,
     token_index: usize,
-    scopes: Vec<HashMap<String, VarEntry>>,
+    symbols: SymbolTable,
+    // syntax errors, plus problems found while lowering the parse tree,
+    // e.g. literals that do not fit their type
+    errors: Vec<Diagnostic>,
+    // statement being lowered, used as the location of lowering errors
+    statement_span: Span,
+    // (use, declaration) span pairs for every resolved identifier, for go-to-definition
+    references: Vec<(Span, Span)>,
+    // every symbol the source declares, in order, for hover
+    declarations: Vec<Symbol>,
+    // set while parsing a function body, where return is allowed
+    in_function: bool,
+    // declaration whose initializer is being lowered, for diagnostics
+    declaration: Option<(Name, Type)>,
+    // statements lowered so far, handed over with the finished tree
+    nodes: Arena<Node>,
+    // current and greatest nesting of statements and expressions
+    depth: usize,
+    max_depth: usize,
+    // a note for the syntax error being returned, such as the name it was probably
+    // meant to be
+    note: Option<String>,
+    // where the syntax error being returned is, when that is not the token the parser
+    // stopped on, such as the name a duplicate declaration repeats
+    error_span: Option<Span>,
+    // the '(' and '{' tokens consumed and not yet closed, innermost last, so that input
+    // ending inside one can say where it was opened
+    open_delimiters: Vec<(TokenType, Span)>,
+    // set while parsing a fragment, where a name not declared is assumed declared
+    // outside it
+    fragment: bool,
+    // in a fragment, the names in scope when its last token was consumed
+    at_end: Vec<Symbol>,
+    // for each define, by where its name starts: the expression it stands for and the
+    // names that expression uses, with where the ones it means are declared
+    defined: HashMap<usize, (ParseTreeNode, Vec<(Name, Span)>)>,
+}
+
+// What the parser makes of input that stops at a cursor, as an editor has it while
+// something is being typed: as much of the input as parses once the parentheses and
+// braces it left open are closed, and the tokens that could come next. Names the input
+// uses without declaring them are taken to be declared elsewhere.
+#[derive(Debug)]
+pub struct Fragment<T> {
+    // None when no part of the input parses
+    pub parsed: Option<T>,
+    // the input `parsed` was made from, from its first token through the last one used
+    pub span: Span,
+    // the token types that could follow the input, in the order they are declared
+    pub expected: Vec<TokenType>,
+    // the names the input can refer to at its end, in order of name
+    pub names: Vec<Symbol>,
 }
 
 impl Parser {
@@ -147,28 +421,130 @@ impl Parser {
         Self {
             tokens,
             token_index: 0,
-            scopes: vec![HashMap::new()],
+            symbols: SymbolTable::new(),
+            errors: Vec::new(),
+            statement_span: Span::default(),
+            references: Vec::new(),
+            declarations: Vec::new(),
+            in_function: false,
+            declaration: None,
+            nodes: Arena::new(),
+            depth: 0,
+            max_depth: DEFAULT_MAX_DEPTH,
+            note: None,
+            error_span: None,
+            open_delimiters: Vec::new(),
+            fragment: false,
+            at_end: Vec::new(),
+            defined: HashMap::new(),
         }
     }
 
-    // Assume the tokens are given to us starting from the entry point
-    pub fn parse(&mut self) -> ParseTreeNode {
-        self.parse_entry()
+    // An expression that stops at the cursor, such as `a + f(b, ` while an argument is
+    // being typed
+    pub fn parse_expression_fragment(text: &str) -> Fragment<Expr> {
+        fragment(text, false, |parser| {
+            let node = parser.parse_expression()?;
+            match parser.current() {
+                None => Ok(parser.build_expr(&node)),
+                Some(token) => Err(format!(
+                    "ParseError: expected the end of the expression, found {}",
+                    token.token_type.describe()
+                )),
+            }
+        })
+    }
+
+    // A program that stops at the cursor: the statements before it, the last one closed
+    // off where it was cut short
+    pub fn parse_statement_fragment(text: &str) -> Fragment<Ast> {
+        fragment(text, true, |parser| {
+            let mut children = Vec::new();
+            while !parser.is_at_end() {
+                let statement = parser.parse_statement()?;
+                children.extend(parser.build_statement(&statement));
+            }
+            let root = parser.nodes.alloc(Node {
+                stmt: Stmt::Entry,
+                children,
+                span: Span::default(),
+            });
+            Ok(Ast {
+                nodes: std::mem::take(&mut parser.nodes),
+                root,
+            })
+        })
+    }
+
+    pub fn set_max_depth(&mut self, max_depth: usize) {
+        self.max_depth = max_depth;
+    }
+
+    pub fn errors(&self) -> &[Diagnostic] {
+        &self.errors
+    }
+
+    pub fn references(&self) -> &[(Span, Span)] {
+        &self.references
     }
 
-    pub fn print_tree(&mut self, node: &ParseTreeNode, indent: usize) {
-        for _i in 0..indent {
-            print!("    ");
+    pub fn declarations(&self) -> &[Symbol] {
+        &self.declarations
+    }
+
+    fn declare(&mut self, symbol: Symbol) -> Result<(), String> {
+        if let Err(e) = self.symbols.declare(symbol.clone()) {
+            self.error_span = Some(symbol.span);
+            return Err(e);
         }
-        println!("{:?}", node.symbol);
+        self.declarations.push(symbol);
+        Ok(())
+    }
 
-        for _i in 0..indent {
-            print!("    ");
+    // The symbols in scope where parsing stopped, in order of name, an inner one in place
+    // of an outer one with its name. Names a fragment only assumed are left out.
+    fn in_scope(&self) -> Vec<Symbol> {
+        let mut in_scope = BTreeMap::new();
+        for symbol in self.symbols.visible() {
+            let builtin = BUILTIN_FUNCTIONS.iter().any(|(name, _, _)| symbol.name == *name);
+            if symbol.span.line > 0 || builtin {
+                in_scope.insert(symbol.name.text(), symbol.clone());
+            }
         }
-        println!("{:?}", node.value);
+        in_scope.into_values().collect()
+    }
 
-        for child in &node.children {
-            self.print_tree(child, indent + 1);
+    fn error(&mut self, message: String) {
+        self.errors
+            .push(Diagnostic::new(message, self.statement_span));
+    }
+
+    // Assume the tokens are given to us starting from the entry point. Keeps the
+    // whole concrete parse tree, which only --emit parse-tree needs.
+    pub fn parse(&mut self) -> ParseTreeNode {
+        let mut entry_node = ParseTreeNode {
+            symbol: ParseTreeSymbol::ParseTreeSymbolNodeEntryPoint,
+            children: Vec::new(),
+            value: None,
+            span: Span::default(),
+        };
+        self.parse_statements(|_, stmt| entry_node.children.push(stmt));
+        entry_node
+    }
+
+    // Lowers each top-level statement as soon as it is parsed and drops its parse
+    // tree, so at most one statement's tree is alive at a time
+    pub fn parse_to_ast(&mut self) -> Ast {
+        let mut children = Vec::new();
+        self.parse_statements(|parser, stmt| children.extend(parser.build_statement(&stmt)));
+        let root = self.nodes.alloc(Node {
+            stmt: Stmt::Entry,
+            children,
+            span: Span::default(),
+        });
+        Ast {
+            nodes: std::mem::take(&mut self.nodes),
+            root,
         }
     }
 
@@ -183,93 +559,204 @@ impl Parser {
     fn consume(&mut self) -> &Token {
         let token = &self.tokens[self.token_index];
         self.token_index += 1;
-        token
+        match token.token_type {
+            TokenType::LParen | TokenType::LBrace => {
+                self.open_delimiters.push((token.token_type, token.span))
+            }
+            TokenType::RParen | TokenType::RBrace => {
+                self.open_delimiters.pop();
+            }
+            _ => {}
+        }
+        if self.fragment && self.is_at_end() {
+            self.at_end = self.in_scope();
+        }
+        &self.tokens[self.token_index - 1]
     }
 
-    fn parse_entry(&mut self) -> ParseTreeNode {
-        self.consume();
+    // The next token, for a rule that needs one more
+    fn next_token(&self) -> Result<&Token, String> {
+        self.current()
+            .ok_or_else(|| "ParseError: unexpected end of input".to_string())
+    }
 
-        let mut entry_node = ParseTreeNode {
-            symbol: ParseTreeSymbol::ParseTreeSymbolNodeEntryPoint,
-            children: Vec::new(),
-            value: None,
-        };
+    // Hands each top-level statement to `each`. After a syntax error it skips to the
+    // next top-level statement and carries on, so one run reports the errors of every
+    // statement; an undefined name after the first error is left out, since the
+    // statement skipped may have been the one to declare it.
+    fn parse_statements(&mut self, mut each: impl FnMut(&mut Self, ParseTreeNode)) {
+        self.consume();
 
         while !self.is_at_end() {
+            self.note = None;
+            self.error_span = None;
             match self.parse_statement() {
-                Ok(stmt) => entry_node.children.push(stmt),
+                Ok(stmt) => each(self, stmt),
                 Err(e) => {
-                    eprintln!("Fatal -- {}", e);
+                    let failed = !self.errors.is_empty();
+                    // point at the token the parser stopped on, or the last one at end of input
+                    let stopped = self.current().or(self.tokens.last()).map(|t| t.span);
+                    let span = self.error_span.take().or(stopped).unwrap_or_default();
+                    let diagnostic = match self.open_delimiters.last() {
+                        Some(&(open, at)) if self.is_at_end() => unclosed(open, at, span),
+                        _ => {
+                            let diagnostic = Diagnostic::new(e, span);
+                            match self.note.take() {
+                                Some(note) => diagnostic.with_note(note),
+                                None => diagnostic,
+                            }
+                        }
+                    };
+                    if !(failed && diagnostic.message.starts_with("ParseError: Undefined")) {
+                        self.errors.push(diagnostic);
+                    }
+                    if !self.recover() {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    // Skips what is left of a statement that failed to parse: up to the '}' that closes
+    // the outermost block it opened, or past its ';' when the error came before any.
+    // Running out of input on the way means a '{' was never closed, which is reported
+    // too. Returns whether there is input left to carry on with.
+    fn recover(&mut self) -> bool {
+        // where each '{' still open was opened
+        let mut open: Vec<Span> = self
+            .open_delimiters
+            .iter()
+            .filter(|(open, _)| *open == TokenType::LBrace)
+            .map(|&(_, at)| at)
+            .collect();
+        let mut skipped = false;
+        while let Some(token) = self.current() {
+            let (token_type, span) = (token.token_type, token.span);
+            self.consume();
+            skipped = true;
+            match token_type {
+                TokenType::LBrace => open.push(span),
+                TokenType::RBrace if open.len() <= 1 => {
+                    open.clear();
                     break;
                 }
+                TokenType::RBrace => {
+                    open.pop();
+                }
+                TokenType::Semi if open.is_empty() => break,
+                _ => {}
             }
         }
-        entry_node
+        // an error at the end of the input has already been reported as the unclosed '{'
+        if skipped
+            && self.is_at_end()
+            && let Some(&at) = open.last()
+        {
+            let span = self.tokens.last().map(|t| t.span).unwrap_or_default();
+            self.errors.push(unclosed(TokenType::LBrace, at, span));
+        }
+        // scopes the statement entered stay entered when it fails part way through
+        self.symbols.exit_to_global_scope();
+        self.open_delimiters.clear();
+        self.in_function = false;
+        !self.is_at_end()
     }
 
     fn parse_statement(&mut self) -> Result<ParseTreeNode, String> {
+        self.nested("statement", Self::parse_bare_statement)
+    }
+
+    // Whether the next token is the identifier `word`, for a contextual keyword
+    fn at_word(&self, word: &str) -> bool {
+        self.current().is_some_and(|token| {
+            token.token_type == TokenType::Ident && token.value.is_some_and(|name| name == word)
+        })
+    }
+
+    // Parses one more level of nesting, or fails once the limit is reached
+    fn nested(
+        &mut self,
+        what: &str,
+        parse: impl FnOnce(&mut Self) -> Result<ParseTreeNode, String>,
+    ) -> Result<ParseTreeNode, String> {
+        if self.depth >= self.max_depth {
+            return Err(format!(
+                "ParseError: {} too deeply nested, more than {} levels (see --max-nesting)",
+                what, self.max_depth
+            ));
+        }
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
+    fn parse_bare_statement(&mut self) -> Result<ParseTreeNode, String> {
         let token = &self.current().unwrap();
+        let start = token.span;
 
         let mut statement_node = ParseTreeNode {
             symbol: ParseTreeSymbol::ParseTreeSymbolNodeStatement,
             children: Vec::new(),
             value: None,
+            span: Span::default(),
         };
 
-        match token.token_type {
-            TokenType::TokenTypeExit => {
-                statement_node.children.push(self.parse_exit()?);
-                Ok(statement_node)
+        let child = match token.token_type {
+            TokenType::Exit => self.parse_exit()?,
+            TokenType::PrintInt => self.parse_print_int()?,
+            TokenType::Assert => self.parse_assert()?,
+            TokenType::Const => self.parse_constant_declaration()?,
+            TokenType::Define => self.parse_define()?,
+            TokenType::Star => self.parse_deref_assignment()?,
+            TokenType::I32S
+            | TokenType::F32S
+            | TokenType::Bool
+            | TokenType::Char => self.parse_variable_declaration()?,
+            TokenType::Void => {
+                return Err("ParseError: void is only allowed as a function return type".to_string());
             }
-            TokenType::TokenTypeTypeI32S => {
-                statement_node
-                    .children
-                    .push(self.parse_variable_declaration()?);
-                Ok(statement_node)
+            TokenType::Ident
+                if self
+                    .tokens
+                    .get(self.token_index + 1)
+                    .is_some_and(|t| t.token_type == TokenType::LParen) =>
+            {
+                self.parse_expression_statement()?
             }
-            TokenType::TokenTypeTypeF32S => {
-                statement_node
-                    .children
-                    .push(self.parse_variable_declaration()?);
-                Ok(statement_node)
-            }
-            TokenType::TokenTypeTypeBool => {
-                statement_node
-                    .children
-                    .push(self.parse_variable_declaration()?);
-                Ok(statement_node)
+            TokenType::Ident => self.parse_variable_assignment()?,
+            TokenType::For => self.parse_for()?,
+            TokenType::If => self.parse_if()?,
+            TokenType::While => self.parse_while()?,
+            TokenType::Do => self.parse_do_while()?,
+            TokenType::Match => self.parse_match()?,
+            TokenType::Fn | TokenType::Extern | TokenType::Export => {
+                self.parse_function()?
             }
-            TokenType::TokenTypeTypeChar => {
-                statement_node
-                    .children
-                    .push(self.parse_variable_declaration()?);
-                Ok(statement_node)
-            }
-            TokenType::TokenTypeIdentifier => {
-                statement_node
-                    .children
-                    .push(self.parse_variable_assignment()?);
-                Ok(statement_node)
-            }
-            TokenType::TokenTypeFor => {
-                statement_node.children.push(self.parse_for()?);
-                Ok(statement_node)
-            }
-            TokenType::TokenTypeIf => {
-                statement_node.children.push(self.parse_if()?);
-                Ok(statement_node)
+            TokenType::Return => self.parse_return()?,
+            TokenType::LBrace => {
+                self.symbols.enter_scope();
+                let block = self.parse_block()?;
+                self.symbols.exit_scope();
+                block
             }
-            TokenType::TokenTypeLeftCurlyBrace => {
-                self.push_scope();
-                statement_node.children.push(self.parse_block()?);
-                self.pop_scope();
-                Ok(statement_node)
+            _ => {
+                return Err(format!(
+                    "ParseError: expected a statement, found {}",
+                    token.token_type.describe()
+                ));
             }
-            _ => Err(format!(
-                "ParseError: unrecognized token type: {:?}",
-                token.token_type
-            )),
-        }
+        };
+        statement_node.children.push(child);
+
+        // from the statement's first token through the last one it consumed
+        let end = self.tokens[self.token_index - 1].span;
+        statement_node.span = Span {
+            end: end.end,
+            ..start
+        };
+        Ok(statement_node)
     }
 
     fn parse_exit(&mut self) -> Result<ParseTreeNode, String> {
@@ -277,6 +764,7 @@ impl Parser {
             symbol: ParseTreeSymbol::ParseTreeSymbolTerminalExit,
             children: Vec::new(),
             value: None,
+            span: Span::default(),
         };
         self.consume();
 
@@ -284,19 +772,21 @@ impl Parser {
 
         let semi_terminal = if self
             .current()
-            .map_or(false, |t| t.token_type == TokenType::TokenTypeSemicolon)
+            .is_some_and(|t| t.token_type == TokenType::Semi)
         {
             let node = ParseTreeNode {
                 symbol: ParseTreeSymbol::ParseTreeSymbolTerminalSemicolon,
                 children: Vec::new(),
                 value: None,
+                span: Span::default(),
             };
             self.consume();
             node
         } else {
             return Err(format!(
-                "MissingTokenError: expected Semicolon, found: {:?}",
-                self.current().map(|t| &t.token_type)
+                "MissingTokenError: expected ';', found {}",
+                self.current()
+                    .map_or("end of input".to_string(), |t| t.token_type.describe())
             ));
         };
 
@@ -304,286 +794,569 @@ impl Parser {
             symbol: ParseTreeSymbol::ParseTreeSymbolNodeExit,
             children: vec![exit_terminal, expr_node, semi_terminal],
             value: None,
+            span: Span::default(),
         })
     }
 
-    fn parse_expression(&mut self) -> Result<ParseTreeNode, String> {
-        let expr_content = self.parse_equality()?;
+    fn parse_print_int(&mut self) -> Result<ParseTreeNode, String> {
+        let print_terminal = self.expect_terminal(
+            TokenType::PrintInt,
+            ParseTreeSymbol::ParseTreeSymbolTerminalPrintInt,
+            "'print_int'",
+        )?;
+        let left_paren = self.expect_terminal(
+            TokenType::LParen,
+            ParseTreeSymbol::ParseTreeSymbolTerminalLeftParen,
+            "'(' after print_int",
+        )?;
+        let expr_node = self.parse_expression()?;
+        let right_paren = self.expect_terminal(
+            TokenType::RParen,
+            ParseTreeSymbol::ParseTreeSymbolTerminalRightParen,
+            "')' to close print_int",
+        )?;
+        let semi_terminal = self.expect_terminal(
+            TokenType::Semi,
+            ParseTreeSymbol::ParseTreeSymbolTerminalSemicolon,
+            "';' after print_int",
+        )?;
+
         Ok(ParseTreeNode {
-            symbol: ParseTreeSymbol::ParseTreeSymbolNodeExpression,
-            children: vec![expr_content],
+            symbol: ParseTreeSymbol::ParseTreeSymbolNodePrintInt,
+            children: vec![print_terminal, left_paren, expr_node, right_paren, semi_terminal],
             value: None,
+            span: Span::default(),
         })
     }
 
-    // Equality → Comparison (("==" | "!=") Comparison)*
-    fn parse_equality(&mut self) -> Result<ParseTreeNode, String> {
-        let mut left = self.parse_comparison()?;
+    // Assert → "assert" Expr ";"
+    fn parse_assert(&mut self) -> Result<ParseTreeNode, String> {
+        let assert_terminal = self.expect_terminal(
+            TokenType::Assert,
+            ParseTreeSymbol::ParseTreeSymbolTerminalAssert,
+            "'assert'",
+        )?;
+        let expr_node = self.parse_expression()?;
+        let semi_terminal = self.expect_terminal(
+            TokenType::Semi,
+            ParseTreeSymbol::ParseTreeSymbolTerminalSemicolon,
+            "';' after assert",
+        )?;
 
-        while let Some(token) = self.current() {
-            match token.token_type {
-                TokenType::TokenTypeEqualsEquals | TokenType::TokenTypeNotEquals => {
-                    let op_type = token.token_type;
-                    let op_terminal = ParseTreeNode {
-                        symbol: match op_type {
-                            TokenType::TokenTypeEqualsEquals => {
-                                ParseTreeSymbol::ParseTreeSymbolTerminalEqualsEquals
-                            }
-                            TokenType::TokenTypeNotEquals => {
-                                ParseTreeSymbol::ParseTreeSymbolTerminalNotEquals
-                            }
-                            _ => unreachable!(),
-                        },
-                        children: Vec::new(),
-                        value: None,
-                    };
-                    self.consume();
+        Ok(ParseTreeNode {
+            symbol: ParseTreeSymbol::ParseTreeSymbolNodeAssert,
+            children: vec![assert_terminal, expr_node, semi_terminal],
+            value: None,
+            span: Span::default(),
+        })
+    }
 
-                    let right = self.parse_comparison()?;
+    // ConstantDeclaration → "const" Ident "=" Expr ";", evaluated here so that
+    // later uses can be replaced by the value
+    fn parse_constant_declaration(&mut self) -> Result<ParseTreeNode, String> {
+        let const_terminal = self.expect_terminal(
+            TokenType::Const,
+            ParseTreeSymbol::ParseTreeSymbolTerminalConst,
+            "'const'",
+        )?;
+        let ident_span = self.current().map(|t| t.span).unwrap_or_default();
+        let ident_terminal = self.parse_identifier()?;
+        let equals_terminal = self.expect_terminal(
+            TokenType::Eq,
+            ParseTreeSymbol::ParseTreeSymbolTerminalEquals,
+            "'='",
+        )?;
+        let expr_start = self.current().map(|t| t.span).unwrap_or_default();
+        let expr_node = self.parse_expression()?;
+        let expr_span = Span {
+            end: self.tokens[self.token_index - 1].span.end,
+            ..expr_start
+        };
+        let semi_terminal = self.expect_terminal(
+            TokenType::Semi,
+            ParseTreeSymbol::ParseTreeSymbolTerminalSemicolon,
+            "';'",
+        )?;
 
-                    left = ParseTreeNode {
-                        symbol: ParseTreeSymbol::ParseTreeSymbolNodeEquality,
-                        children: vec![left, op_terminal, right],
-                        value: None,
-                    };
-                }
-                _ => break,
-            }
-        }
+        let name = ident_terminal
+            .value
+            .expect("Identifier should have a value");
+        // out-of-range literals are reported when the tree is lowered, with the statement's span
+        let reported = self.errors.len();
+        let expr = self.build_expr(&expr_node);
+        self.errors.truncate(reported);
+        // parsing carries on with a placeholder so uses don't turn into undefined names
+        let value = consteval::evaluate(&expr).unwrap_or_else(|e| {
+            self.errors.push(Diagnostic::new(
+                format!("ConstError: initializer of {}: {}", name, e),
+                expr_span,
+            ));
+            Value::Int(0)
+        });
+        self.declare(Symbol {
+            name,
+            kind: SymbolKind::Constant { value },
+            type_: value.type_(),
+            span: ident_span,
+            mutable: false,
+        })?;
 
-        Ok(left)
+        Ok(ParseTreeNode {
+            symbol: ParseTreeSymbol::ParseTreeSymbolNodeConstantDeclaration,
+            children: vec![
+                const_terminal,
+                ident_terminal,
+                equals_terminal,
+                expr_node,
+                semi_terminal,
+            ],
+            value: None,
+            span: Span::default(),
+        })
     }
 
-    // Comparison → Add (("<" | "<=" | ">" | ">=") Add)*
-    fn parse_comparison(&mut self) -> Result<ParseTreeNode, String> {
-        let mut left = self.parse_add()?;
+    // Define → "define" Ident Expr ";". Later uses of the name are replaced by a copy of
+    // the expression's tree, so it is checked and evaluated anew wherever it is used. The
+    // names it uses keep the meaning they had here: a use where one of them means
+    // something else is an error rather than a quiet capture.
+    fn parse_define(&mut self) -> Result<ParseTreeNode, String> {
+        let define_terminal = self.expect_terminal(
+            TokenType::Define,
+            ParseTreeSymbol::ParseTreeSymbolTerminalDefine,
+            "'define'",
+        )?;
+        let ident_span = self.current().map(|t| t.span).unwrap_or_default();
+        let ident_terminal = self.parse_identifier()?;
+        let expr_node = self.parse_expression()?;
+        let semi_terminal = self.expect_terminal(
+            TokenType::Semi,
+            ParseTreeSymbol::ParseTreeSymbolTerminalSemicolon,
+            "';'",
+        )?;
 
-        while let Some(token) = self.current() {
-            match token.token_type {
-                TokenType::TokenTypeLessThan
-                | TokenType::TokenTypeLessThanOrEqual
-                | TokenType::TokenTypeGreaterThan
-                | TokenType::TokenTypeGreaterThanOrEqual => {
-                    let op_type = token.token_type;
-                    let op_terminal = ParseTreeNode {
-                        symbol: match op_type {
-                            TokenType::TokenTypeLessThan => {
-                                ParseTreeSymbol::ParseTreeSymbolTerminalLessThan
-                            }
-                            TokenType::TokenTypeLessThanOrEqual => {
-                                ParseTreeSymbol::ParseTreeSymbolTerminalLessThanOrEqual
-                            }
-                            TokenType::TokenTypeGreaterThan => {
-                                ParseTreeSymbol::ParseTreeSymbolTerminalGreaterThan
-                            }
-                            TokenType::TokenTypeGreaterThanOrEqual => {
-                                ParseTreeSymbol::ParseTreeSymbolTerminalGreaterThanOrEqual
-                            }
-                            _ => unreachable!(),
-                        },
-                        children: Vec::new(),
-                        value: None,
-                    };
-                    self.consume();
+        let name = ident_terminal
+            .value
+            .expect("Identifier should have a value");
+        let mut uses = Vec::new();
+        identifiers(&expr_node, &mut uses);
+        let captures = uses
+            .into_iter()
+            .filter_map(|used| self.symbols.resolve(used).map(|symbol| (used, symbol.span)))
+            .collect();
+        self.declare(Symbol {
+            name,
+            kind: SymbolKind::Define,
+            // the expression's type is only known where it is used
+            type_: Type::Void,
+            span: ident_span,
+            mutable: false,
+        })?;
+        self.defined.insert(ident_span.start, (expr_node.clone(), captures));
 
-                    let right = self.parse_add()?;
+        Ok(ParseTreeNode {
+            symbol: ParseTreeSymbol::ParseTreeSymbolNodeDefine,
+            children: vec![define_terminal, ident_terminal, expr_node, semi_terminal],
+            value: None,
+            span: Span::default(),
+        })
+    }
 
-                    left = ParseTreeNode {
-                        symbol: ParseTreeSymbol::ParseTreeSymbolNodeComparison,
-                        children: vec![left, op_terminal, right],
-                        value: None,
-                    };
+    // The tree a use of the define declared at `declared_at` stands for, provided every
+    // name in it still means what it did where the define was declared
+    fn expand_define(&mut self, name: Name, declared_at: Span) -> Result<ParseTreeNode, String> {
+        let (expr, captures) = &self.defined[&declared_at.start];
+        for (used, meant) in captures {
+            match self.symbols.resolve(*used) {
+                Some(symbol) if symbol.span == *meant => {}
+                Some(symbol) => {
+                    return Err(format!(
+                        "ParseError: define {} uses {} declared at {}:{}, but here {} is the \
+                         one declared at {}:{}",
+                        name,
+                        used,
+                        meant.line,
+                        meant.column,
+                        used,
+                        symbol.span.line,
+                        symbol.span.column
+                    ));
+                }
+                None => {
+                    return Err(format!(
+                        "ParseError: define {} uses {} declared at {}:{}, which is not in \
+                         scope here",
+                        name, used, meant.line, meant.column
+                    ));
                 }
-                _ => break,
             }
         }
-
-        Ok(left)
+        Ok(ParseTreeNode {
+            symbol: ParseTreeSymbol::ParseTreeSymbolNodeDefined,
+            children: vec![expr.clone()],
+            value: Some(name),
+            span: Span::default(),
+        })
     }
 
-    // Add → → Mul (("+" | "-") Mul)*
-    fn parse_add(&mut self) -> Result<ParseTreeNode, String> {
-        let mut left = self.parse_mul()?;
-
-        while let Some(token) = self.current() {
-            match token.token_type {
-                TokenType::TokenTypePlus | TokenType::TokenTypeMinus => {
-                    let op_type = token.token_type;
-                    let op_terminal = ParseTreeNode {
-                        symbol: match op_type {
-                            TokenType::TokenTypePlus => {
-                                ParseTreeSymbol::ParseTreeSymbolTerminalPlus
-                            }
-                            TokenType::TokenTypeMinus => {
-                                ParseTreeSymbol::ParseTreeSymbolTerminalMinus
-                            }
-                            _ => unreachable!(),
-                        },
-                        children: Vec::new(),
-                        value: None,
-                    };
-                    self.consume();
-
-                    let right = self.parse_mul()?;
-
-                    left = ParseTreeNode {
-                        symbol: ParseTreeSymbol::ParseTreeSymbolNodeAdd,
-                        children: vec![left, op_terminal, right],
-                        value: None,
-                    };
-                }
-                _ => break,
-            }
-        }
+    fn parse_expression(&mut self) -> Result<ParseTreeNode, String> {
+        let expr_content = self.parse_binary(0)?;
+        Ok(ParseTreeNode {
+            symbol: ParseTreeSymbol::ParseTreeSymbolNodeExpression,
+            children: vec![expr_content],
+            value: None,
+            span: Span::default(),
+        })
+    }
 
-        Ok(left)
+    // Precedence climbing over INFIX_OPERATORS: an operand, then every operator that
+    // binds at least as tightly as `min_precedence`, each with its right operand.
+    // Every operator becomes a [left, operator, right] node, one level deeper than the
+    // operands it holds, so a long chain is as deep as it is long.
+    fn parse_binary(&mut self, min_precedence: u8) -> Result<ParseTreeNode, String> {
+        let depth = self.depth;
+        let result = self.parse_operators(min_precedence);
+        self.depth = depth;
+        result
     }
 
-    // Mul → Primary (("*" | "/") Primary)*
-    fn parse_mul(&mut self) -> Result<ParseTreeNode, String> {
+    fn parse_operators(&mut self, min_precedence: u8) -> Result<ParseTreeNode, String> {
         let mut left = self.parse_primary()?;
 
-        while let Some(token) = self.current() {
-            match token.token_type {
-                TokenType::TokenTypeMultiply | TokenType::TokenTypeDivide => {
-                    let op_type = token.token_type;
-                    let op_terminal = ParseTreeNode {
-                        symbol: match op_type {
-                            TokenType::TokenTypeMultiply => {
-                                ParseTreeSymbol::ParseTreeSymbolTerminalStar
-                            }
-                            TokenType::TokenTypeDivide => {
-                                ParseTreeSymbol::ParseTreeSymbolTerminalSlash
-                            }
-                            _ => unreachable!(),
-                        },
-                        children: Vec::new(),
-                        value: None,
-                    };
-                    self.consume();
-
-                    let right = self.parse_primary()?;
-
-                    left = ParseTreeNode {
-                        symbol: ParseTreeSymbol::ParseTreeSymbolNodeMul,
-                        children: vec![left, op_terminal, right],
-                        value: None,
-                    };
-                }
-                _ => break,
+        while let Some(operator) = self.current().and_then(|t| infix_operator(t.token_type)) {
+            if operator.precedence < min_precedence {
+                break;
+            }
+            if self.depth >= self.max_depth {
+                return Err(format!(
+                    "ParseError: expression too deeply nested, more than {} levels \
+                     (see --max-nesting)",
+                    self.max_depth
+                ));
             }
+            self.depth += 1;
+            self.consume();
+            let level = operator.level();
+            let next = match level.associativity {
+                Associativity::Left => operator.precedence + 1,
+                Associativity::Right => operator.precedence,
+            };
+            let right = self.parse_binary(next)?;
+            let op_terminal = ParseTreeNode {
+                symbol: operator.terminal,
+                children: Vec::new(),
+                value: None,
+                span: Span::default(),
+            };
+            left = ParseTreeNode {
+                symbol: level.node,
+                children: vec![left, op_terminal, right],
+                value: None,
+                span: Span::default(),
+            };
         }
 
         Ok(left)
     }
 
-    // Primary → Int_Lit | Float_Lit | Bool_Lit | Ident | "(" Expr ")"
+    // Primary → Int_Lit | Float_Lit | Bool_Lit | Ident | Call | "(" Expr ")"
     fn parse_primary(&mut self) -> Result<ParseTreeNode, String> {
+        self.nested("expression", Self::parse_bare_primary)
+    }
+
+    fn parse_bare_primary(&mut self) -> Result<ParseTreeNode, String> {
         let token = self
             .current()
             .ok_or("ParseError: Unexpected end of input in primary expression")?;
 
         match token.token_type {
-            TokenType::TokenTypeIntegerLiteral => {
+            // &x and *x only apply to variables, so the operand is a bare identifier
+            TokenType::Amp | TokenType::Star => {
+                let (operator, symbol) = if token.token_type == TokenType::Amp {
+                    (
+                        ParseTreeSymbol::ParseTreeSymbolTerminalAmpersand,
+                        ParseTreeSymbol::ParseTreeSymbolNodeAddressOf,
+                    )
+                } else {
+                    (
+                        ParseTreeSymbol::ParseTreeSymbolTerminalStar,
+                        ParseTreeSymbol::ParseTreeSymbolNodeDereference,
+                    )
+                };
+                let address_of = symbol == ParseTreeSymbol::ParseTreeSymbolNodeAddressOf;
+                self.consume();
+                let operand = self.parse_variable_operand(address_of)?;
+                Ok(ParseTreeNode {
+                    symbol: ParseTreeSymbol::ParseTreeSymbolNodePrimary,
+                    children: vec![ParseTreeNode {
+                        symbol,
+                        children: vec![
+                            ParseTreeNode {
+                                symbol: operator,
+                                children: Vec::new(),
+                                value: None,
+                                span: Span::default(),
+                            },
+                            operand,
+                        ],
+                        value: None,
+                        span: Span::default(),
+                    }],
+                    value: None,
+                    span: Span::default(),
+                })
+            }
+
+            TokenType::Minus => {
+                // children: "-", Primary
+                self.consume();
+                let operand = self.parse_primary()?;
+                Ok(ParseTreeNode {
+                    symbol: ParseTreeSymbol::ParseTreeSymbolNodePrimary,
+                    children: vec![ParseTreeNode {
+                        symbol: ParseTreeSymbol::ParseTreeSymbolNodeNegate,
+                        children: vec![
+                            ParseTreeNode {
+                                symbol: ParseTreeSymbol::ParseTreeSymbolTerminalMinus,
+                                children: Vec::new(),
+                                value: None,
+                                span: Span::default(),
+                            },
+                            operand,
+                        ],
+                        value: None,
+                        span: Span::default(),
+                    }],
+                    value: None,
+                    span: Span::default(),
+                })
+            }
+
+            TokenType::I32S | TokenType::F32S => {
+                // children: Type, "(", Expression, ")"
+                let type_node = self.parse_type()?;
+                let left_paren = self.expect_terminal(
+                    TokenType::LParen,
+                    ParseTreeSymbol::ParseTreeSymbolTerminalLeftParen,
+                    "'(' after a conversion type",
+                )?;
+                let value = self.parse_expression()?;
+                let right_paren = self.expect_terminal(
+                    TokenType::RParen,
+                    ParseTreeSymbol::ParseTreeSymbolTerminalRightParen,
+                    "')'",
+                )?;
+                Ok(ParseTreeNode {
+                    symbol: ParseTreeSymbol::ParseTreeSymbolNodePrimary,
+                    children: vec![ParseTreeNode {
+                        symbol: ParseTreeSymbol::ParseTreeSymbolNodeConversion,
+                        children: vec![type_node, left_paren, value, right_paren],
+                        value: None,
+                        span: Span::default(),
+                    }],
+                    value: None,
+                    span: Span::default(),
+                })
+            }
+
+            TokenType::IntLit => {
                 let child = ParseTreeNode {
                     symbol: ParseTreeSymbol::ParseTreeSymbolTerminalIntegerLiteral,
                     children: Vec::new(),
-                    value: token.value.clone(),
+                    value: token.value,
+                    span: Span::default(),
                 };
                 self.consume();
                 Ok(ParseTreeNode {
                     symbol: ParseTreeSymbol::ParseTreeSymbolNodePrimary,
                     children: vec![child],
                     value: None,
+                    span: Span::default(),
                 })
             }
 
-            TokenType::TokenTypeFloatLiteral => {
+            TokenType::FloatLit => {
                 let child = ParseTreeNode {
                     symbol: ParseTreeSymbol::ParseTreeSymbolTerminalFloatLiteral,
                     children: Vec::new(),
-                    value: token.value.clone(),
+                    value: token.value,
+                    span: Span::default(),
                 };
                 self.consume();
                 Ok(ParseTreeNode {
                     symbol: ParseTreeSymbol::ParseTreeSymbolNodePrimary,
                     children: vec![child],
                     value: None,
+                    span: Span::default(),
                 })
             }
 
-            TokenType::TokenTypeBooleanLiteral => {
+            TokenType::BoolLit => {
                 let child = ParseTreeNode {
                     symbol: ParseTreeSymbol::ParseTreeSymbolTerminalBooleanLiteral,
                     children: Vec::new(),
-                    value: token.value.clone(),
+                    value: token.value,
+                    span: Span::default(),
                 };
                 self.consume();
                 Ok(ParseTreeNode {
                     symbol: ParseTreeSymbol::ParseTreeSymbolNodePrimary,
                     children: vec![child],
                     value: None,
+                    span: Span::default(),
                 })
             }
 
-            TokenType::TokenTypeCharLiteral => {
+            TokenType::CharLit => {
                 let child = ParseTreeNode {
                     symbol: ParseTreeSymbol::ParseTreeSymbolTerminalCharLiteral,
                     children: Vec::new(),
-                    value: token.value.clone(),
+                    value: token.value,
+                    span: Span::default(),
                 };
                 self.consume();
                 Ok(ParseTreeNode {
                     symbol: ParseTreeSymbol::ParseTreeSymbolNodePrimary,
                     children: vec![child],
                     value: None,
+                    span: Span::default(),
                 })
             }
 
-            TokenType::TokenTypeIdentifier => {
+            TokenType::Ident => {
+                let name = token.value.expect("Identifier should have a value");
+                let use_span = token.span;
+                let is_call = self
+                    .tokens
+                    .get(self.token_index + 1)
+                    .is_some_and(|t| t.token_type == TokenType::LParen);
+                // builtins are declared with a default span and have no source location
+                if let Some(symbol) = self.symbols.resolve(name)
+                    && symbol.span.line > 0
+                {
+                    let declared_at = symbol.span;
+                    self.references.push((use_span, declared_at));
+                }
+                self.assume_declared(name, is_call);
+                match self.symbols.resolve(name) {
+                    None => return Err(self.undefined(name, is_call)),
+                    Some(symbol) => match (&symbol.kind, is_call) {
+                        (SymbolKind::Function { .. }, true) => {
+                            let call = self.parse_call()?;
+                            return Ok(ParseTreeNode {
+                                symbol: ParseTreeSymbol::ParseTreeSymbolNodePrimary,
+                                children: vec![call],
+                                value: None,
+                                span: Span::default(),
+                            });
+                        }
+                        (SymbolKind::Function { .. }, false) => {
+                            return Err(format!(
+                                "ParseError: Function {} must be called with an argument list",
+                                name
+                            ));
+                        }
+                        (
+                            SymbolKind::Variable | SymbolKind::Constant { .. } | SymbolKind::Define,
+                            true,
+                        ) => {
+                            return Err(format!("ParseError: {} is not a function", name));
+                        }
+                        (SymbolKind::Define, false) => {
+                            let declared_at = symbol.span;
+                            let defined = self.expand_define(name, declared_at)?;
+                            self.consume();
+                            return Ok(ParseTreeNode {
+                                symbol: ParseTreeSymbol::ParseTreeSymbolNodePrimary,
+                                children: vec![defined],
+                                value: None,
+                                span: Span::default(),
+                            });
+                        }
+                        (SymbolKind::Constant { value }, false) => {
+                            let (symbol, text) = match value {
+                                Value::Int(i) => (
+                                    ParseTreeSymbol::ParseTreeSymbolTerminalIntegerLiteral,
+                                    i.to_string(),
+                                ),
+                                Value::Bool(b) => (
+                                    ParseTreeSymbol::ParseTreeSymbolTerminalBooleanLiteral,
+                                    b.to_string(),
+                                ),
+                                Value::Char(c) => (
+                                    ParseTreeSymbol::ParseTreeSymbolTerminalCharLiteral,
+                                    c.to_string(),
+                                ),
+                            };
+                            let literal = ParseTreeNode {
+                                symbol,
+                                children: Vec::new(),
+                                value: Some(Name::intern(&text)),
+                                span: Span::default(),
+                            };
+                            self.consume();
+                            return Ok(ParseTreeNode {
+                                symbol: ParseTreeSymbol::ParseTreeSymbolNodePrimary,
+                                children: vec![ParseTreeNode {
+                                    symbol: ParseTreeSymbol::ParseTreeSymbolNodeConstant,
+                                    children: vec![literal],
+                                    value: Some(name),
+                                    span: Span::default(),
+                                }],
+                                value: None,
+                                span: Span::default(),
+                            });
+                        }
+                        (SymbolKind::Variable, false) => {}
+                    },
+                }
                 let child = ParseTreeNode {
                     symbol: ParseTreeSymbol::ParseTreeSymbolTerminalIdentifier,
                     children: Vec::new(),
-                    value: token.value.clone(),
+                    value: Some(name),
+                    span: Span::default(),
                 };
                 self.consume();
                 Ok(ParseTreeNode {
                     symbol: ParseTreeSymbol::ParseTreeSymbolNodePrimary,
                     children: vec![child],
                     value: None,
+                    span: Span::default(),
                 })
             }
 
-            TokenType::TokenTypeLeftParen => {
+            TokenType::LParen => {
                 let left_paren = ParseTreeNode {
                     symbol: ParseTreeSymbol::ParseTreeSymbolTerminalLeftParen,
                     children: Vec::new(),
                     value: None,
+                    span: Span::default(),
                 };
                 self.consume();
 
                 // Recursively parse the expression inside parentheses
-                let expr_content = self.parse_equality()?;
+                let expr_content = self.parse_binary(0)?;
                 // Wrap it in an Expression node
                 let expr = ParseTreeNode {
                     symbol: ParseTreeSymbol::ParseTreeSymbolNodeExpression,
                     children: vec![expr_content],
                     value: None,
+                    span: Span::default(),
                 };
 
                 let right_paren_token = self
                     .current()
                     .ok_or("ParseError: Expected ')', found end of input")?;
-                if right_paren_token.token_type != TokenType::TokenTypeRightParen {
+                if right_paren_token.token_type != TokenType::RParen {
                     return Err(format!(
-                        "ParseError: Expected ')', found {:?}",
-                        right_paren_token.token_type
+                        "ParseError: Expected ')', found {}",
+                        right_paren_token.token_type.describe()
                     ));
                 }
                 let right_paren = ParseTreeNode {
                     symbol: ParseTreeSymbol::ParseTreeSymbolTerminalRightParen,
                     children: Vec::new(),
                     value: None,
+                    span: Span::default(),
                 };
                 self.consume();
 
@@ -591,163 +1364,471 @@ impl Parser {
                     symbol: ParseTreeSymbol::ParseTreeSymbolNodePrimary,
                     children: vec![left_paren, expr, right_paren],
                     value: None,
+                    span: Span::default(),
                 })
             }
 
-            _ => Err(format!(
-                "Unexpected token in primary expression: {:?}",
-                token.token_type
-            )),
+            _ => Err(format!(
+                "ParseError: expected an expression, found {}",
+                token.token_type.describe()
+            )),
+        }
+    }
+
+    // VariableDeclaration → Type Declarator ("," Declarator)* ";"
+    // Declarator → Ident ("=" Expr)?
+    fn parse_variable_declaration(&mut self) -> Result<ParseTreeNode, String> {
+        let type_node = self.parse_type()?;
+        let var_type = self.match_type_in_scope(&type_node);
+
+        let mut children = vec![type_node];
+        children.extend(self.parse_declarator(&var_type)?);
+        while self.current().is_some_and(|t| t.token_type == TokenType::Comma) {
+            self.consume();
+            children.push(ParseTreeNode {
+                symbol: ParseTreeSymbol::ParseTreeSymbolTerminalComma,
+                children: vec![],
+                value: None,
+                span: Span::default(),
+            });
+            children.extend(self.parse_declarator(&var_type)?);
+        }
+
+        children.push(self.expect_terminal(
+            TokenType::Semi,
+            ParseTreeSymbol::ParseTreeSymbolTerminalSemicolon,
+            "';'",
+        )?);
+
+        Ok(ParseTreeNode {
+            symbol: ParseTreeSymbol::ParseTreeSymbolNodeVariableDeclaration,
+            children,
+            value: None,
+            span: Span::default(),
+        })
+    }
+
+    // One variable of a declaration. It is declared once its value has been read, so
+    // `i32s a = 1, b = a;` sees a but `i32s a = a;` does not.
+    fn parse_declarator(&mut self, var_type: &Type) -> Result<Vec<ParseTreeNode>, String> {
+        let ident_span = self.current().map(|t| t.span).unwrap_or_default();
+        let ident_terminal = self.parse_identifier()?;
+
+        // `i32s x;` declares x without a value; the type checker makes sure it is
+        // assigned before it is read
+        let mut nodes = Vec::new();
+        let next = self
+            .current()
+            .ok_or("ParseError: Expected '=', ',' or ';', found end of input")?;
+        if next.token_type == TokenType::Eq {
+            nodes.push(ParseTreeNode {
+                symbol: ParseTreeSymbol::ParseTreeSymbolTerminalEquals,
+                children: vec![],
+                value: None,
+                span: Span::default(),
+            });
+            self.consume();
+            nodes.push(self.parse_expression()?);
+        } else if next.token_type != TokenType::Semi && next.token_type != TokenType::Comma {
+            return Err(format!(
+                "ParseError: Expected '=', ',' or ';', found {}",
+                next.token_type.describe()
+            ));
+        }
+
+        let var_name = ident_terminal
+            .value
+            .expect("Identifier should have a value");
+        self.declare(Symbol {
+            name: var_name,
+            kind: SymbolKind::Variable,
+            type_: var_type.clone(),
+            span: ident_span,
+            mutable: true,
+        })?;
+
+        nodes.insert(0, ident_terminal);
+        Ok(nodes)
+    }
+
+    fn parse_variable_assignment(&mut self) -> Result<ParseTreeNode, String> {
+        let target_span = self.current().map(|t| t.span).unwrap_or_default();
+        let ident_terminal = self.parse_identifier()?;
+        let var_name = ident_terminal
+            .value
+            .expect("Identifier should have a value");
+        if self.current().is_some_and(|t| t.token_type == TokenType::Comma) {
+            return self.parse_parallel_assignment(ident_terminal, target_span);
+        }
+
+        let equals_token = self
+            .current()
+            .ok_or("ParseError: Expected '=', found end of input")?;
+        // x++ and x-- take the place of `= value`, and lower to x = x + 1 and x = x - 1
+        let operator = match equals_token.token_type {
+            TokenType::Eq => ParseTreeSymbol::ParseTreeSymbolTerminalEquals,
+            TokenType::PlusPlus => ParseTreeSymbol::ParseTreeSymbolTerminalPlusPlus,
+            TokenType::MinusMinus => ParseTreeSymbol::ParseTreeSymbolTerminalMinusMinus,
+            other => {
+                // two words in a row: the first is more likely a misspelled keyword, as
+                // in `whiel x < 3 { ... }`, than a variable
+                if self.symbols.resolve(var_name).is_none() {
+                    self.suggest(var_name, KEYWORDS.iter().filter_map(|(word, token)| {
+                        starts_statement(*token).then_some(*word)
+                    }));
+                }
+                return Err(format!(
+                    "ParseError: Expected '=', '++' or '--', found {}",
+                    other.describe()
+                ));
+            }
+        };
+        let equals_terminal = ParseTreeNode {
+            symbol: operator,
+            children: vec![],
+            value: None,
+            span: Span::default(),
+        };
+        self.consume();
+
+        let expr_node = match operator {
+            ParseTreeSymbol::ParseTreeSymbolTerminalEquals => self.parse_expression()?,
+            _ => self.one_of_type(var_name)?,
+        };
+
+        let semi_token = self
+            .current()
+            .ok_or("ParseError: Expected ';', found end of input")?;
+        if semi_token.token_type != TokenType::Semi {
+            return Err(format!(
+                "ParseError: Expected ';', found {}",
+                semi_token.token_type.describe()
+            ));
+        }
+        let semi_terminal = ParseTreeNode {
+            symbol: ParseTreeSymbol::ParseTreeSymbolTerminalSemicolon,
+            children: vec![],
+            value: None,
+            span: Span::default(),
+        };
+        self.consume();
+
+        self.check_assignment_target(var_name, target_span)?;
+
+        Ok(ParseTreeNode {
+            symbol: ParseTreeSymbol::ParseTreeSymbolNodeVariableAssignment,
+            children: vec![ident_terminal, equals_terminal, expr_node, semi_terminal],
+            value: None,
+            span: Span::default(),
+        })
+    }
+
+    // ParallelAssignment → Ident ("," Ident)+ "=" Expr ("," Expr)+ ";"
+    // Every value is read before any variable is assigned, so `a, b = b, a;` swaps.
+    // Each target carries its type as a child, for the temporary that holds its value.
+    fn parse_parallel_assignment(
+        &mut self,
+        first: ParseTreeNode,
+        first_span: Span,
+    ) -> Result<ParseTreeNode, String> {
+        let mut targets = vec![(first, first_span)];
+        while self.current().is_some_and(|t| t.token_type == TokenType::Comma) {
+            self.consume();
+            let span = self.current().map(|t| t.span).unwrap_or_default();
+            targets.push((self.parse_identifier()?, span));
+        }
+        let equals_terminal = self.expect_terminal(
+            TokenType::Eq,
+            ParseTreeSymbol::ParseTreeSymbolTerminalEquals,
+            "'='",
+        )?;
+
+        let mut values = vec![self.parse_expression()?];
+        while self.current().is_some_and(|t| t.token_type == TokenType::Comma) {
+            self.consume();
+            values.push(self.parse_expression()?);
+        }
+        let semi_terminal = self.expect_terminal(
+            TokenType::Semi,
+            ParseTreeSymbol::ParseTreeSymbolTerminalSemicolon,
+            "';'",
+        )?;
+        if values.len() != targets.len() {
+            return Err(format!(
+                "ParseError: Expected {} values, one for each variable, found {}",
+                targets.len(),
+                values.len()
+            ));
+        }
+
+        let comma = || ParseTreeNode {
+            symbol: ParseTreeSymbol::ParseTreeSymbolTerminalComma,
+            children: vec![],
+            value: None,
+            span: Span::default(),
+        };
+        let mut children = Vec::new();
+        let mut assigned = HashSet::new();
+        for (mut target, span) in targets {
+            let name = target.value.expect("Identifier should have a value");
+            if !assigned.insert(name) {
+                return Err(format!(
+                    "ParseError: {} is assigned more than once in one assignment",
+                    name
+                ));
+            }
+            self.check_assignment_target(name, span)?;
+            let terminal = match &self.symbols.resolve(name).unwrap().type_ {
+                Type::I32S => ParseTreeSymbol::ParseTreeSymbolTerminalI32S,
+                Type::F32S => ParseTreeSymbol::ParseTreeSymbolTerminalF32S,
+                Type::Bool => ParseTreeSymbol::ParseTreeSymbolTerminalBool,
+                Type::Char => ParseTreeSymbol::ParseTreeSymbolTerminalChar,
+                type_ => {
+                    return Err(format!(
+                        "ParseError: Cannot assign to {} in parallel, since it has type {}",
+                        name, type_
+                    ));
+                }
+            };
+            target.children.push(ParseTreeNode {
+                symbol: ParseTreeSymbol::ParseTreeSymbolNodeType,
+                children: vec![ParseTreeNode {
+                    symbol: terminal,
+                    children: Vec::new(),
+                    value: None,
+                    span: Span::default(),
+                }],
+                value: None,
+                span: Span::default(),
+            });
+            if !children.is_empty() {
+                children.push(comma());
+            }
+            children.push(target);
+        }
+        children.push(equals_terminal);
+        for (i, value) in values.into_iter().enumerate() {
+            if i > 0 {
+                children.push(comma());
+            }
+            children.push(value);
+        }
+        children.push(semi_terminal);
+
+        Ok(ParseTreeNode {
+            symbol: ParseTreeSymbol::ParseTreeSymbolNodeParallelAssignment,
+            children,
+            value: None,
+            span: Span::default(),
+        })
+    }
+
+    // Whether a variable can be assigned, recording the use of it for references
+    fn check_assignment_target(&mut self, var_name: Name, target_span: Span) -> Result<(), String> {
+        self.assume_declared(var_name, false);
+        match self.symbols.resolve(var_name) {
+            None => return Err(self.undefined(var_name, false)),
+            Some(symbol) if matches!(symbol.kind, SymbolKind::Function { .. }) => {
+                return Err(format!("ParseError: Cannot assign to function {}", var_name));
+            }
+            Some(symbol) if matches!(symbol.kind, SymbolKind::Constant { .. }) => {
+                return Err(format!(
+                    "ParseError: Cannot assign to constant {} (declared at {}:{})",
+                    var_name, symbol.span.line, symbol.span.column
+                ));
+            }
+            Some(symbol) if matches!(symbol.kind, SymbolKind::Define) => {
+                return Err(format!(
+                    "ParseError: Cannot assign to define {} (declared at {}:{})",
+                    var_name, symbol.span.line, symbol.span.column
+                ));
+            }
+            Some(symbol) if !symbol.mutable => {
+                return Err(format!(
+                    "ParseError: Cannot assign to immutable variable {} (declared at {}:{})",
+                    var_name, symbol.span.line, symbol.span.column
+                ));
+            }
+            Some(symbol) if symbol.span.line > 0 => {
+                let declared_at = symbol.span;
+                self.references.push((target_span, declared_at));
+            }
+            Some(_) => {}
         }
+        Ok(())
     }
 
-    fn parse_variable_declaration(&mut self) -> Result<ParseTreeNode, String> {
-        let type_node = self.parse_type()?;
-
-        let ident_terminal = self.parse_expression()?;
+    // "Undefined variable x", noting the name in scope most like it: a function's for a
+    // call and a variable's or constant's otherwise
+    fn undefined(&mut self, name: Name, call: bool) -> String {
+        let candidates: Vec<_> = self
+            .symbols
+            .visible()
+            .filter(|symbol| matches!(symbol.kind, SymbolKind::Function { .. }) == call)
+            .map(|symbol| symbol.name.text())
+            .collect();
+        self.suggest(name, candidates.iter().map(|candidate| &**candidate));
+        format!("ParseError: Undefined variable {}", name)
+    }
 
-        let equals_token = self
-            .current()
-            .ok_or("ParseError: Expected '=', found end of input")?;
-        if equals_token.token_type != TokenType::TokenTypeEquals {
-            return Err(format!(
-                "ParseError: Expected '=', found {:?}",
-                equals_token.token_type
-            ));
+    // In a fragment, declares a name that is not yet declared the way it is used: as a
+    // mutable i32s variable, or a function returning one when it is called
+    fn assume_declared(&mut self, name: Name, call: bool) {
+        if self.fragment && self.symbols.resolve(name).is_none() {
+            let kind = if call {
+                SymbolKind::Function { params: Vec::new() }
+            } else {
+                SymbolKind::Variable
+            };
+            let _ = self.symbols.declare(Symbol {
+                name,
+                kind,
+                type_: Type::I32S,
+                span: Span::default(),
+                mutable: true,
+            });
         }
-        let equals_terminal = ParseTreeNode {
-            symbol: ParseTreeSymbol::ParseTreeSymbolTerminalEquals,
-            children: vec![],
-            value: None,
-        };
-        self.consume();
-
-        let expr_node = self.parse_expression()?;
+    }
 
-        let semi_token = self
-            .current()
-            .ok_or("ParseError: Expected semicolon, found end of input")?;
-        if semi_token.token_type != TokenType::TokenTypeSemicolon {
-            return Err(format!(
-                "ParseError: Expected semicolon, found {:?}",
-                semi_token.token_type
-            ));
+    // Notes the candidate closest to a word that is not what was expected, if any is close
+    fn suggest<'a>(&mut self, word: Name, candidates: impl IntoIterator<Item = &'a str>) {
+        if let Some(candidate) = closest(&word.text(), candidates) {
+            self.note = Some(format!("did you mean `{}`?", candidate));
         }
-        let semi_terminal = ParseTreeNode {
-            symbol: ParseTreeSymbol::ParseTreeSymbolTerminalSemicolon,
-            children: vec![],
-            value: None,
-        };
-        self.consume();
+    }
 
-        let var_name = self
-            .find_terminal(&ident_terminal)
-            .value
-            .as_ref()
-            .expect("Identifier should have a value")
-            .clone();
+    // The literal 1 of a variable's type, for x++ and x-- to add or subtract. An
+    // undefined name is reported once the statement has been read.
+    fn one_of_type(&self, name: Name) -> Result<ParseTreeNode, String> {
+        let (symbol, text) = match self.symbols.resolve(name).map(|s| &s.type_) {
+            Some(Type::I32S) | None => {
+                (ParseTreeSymbol::ParseTreeSymbolTerminalIntegerLiteral, "1")
+            }
+            Some(Type::F32S) => (ParseTreeSymbol::ParseTreeSymbolTerminalFloatLiteral, "1.0"),
+            Some(Type::Char) => (ParseTreeSymbol::ParseTreeSymbolTerminalCharLiteral, "\u{1}"),
+            Some(type_) => {
+                return Err(format!(
+                    "ParseError: Cannot increment or decrement {}, which has type {}",
+                    name, type_
+                ));
+            }
+        };
+        Ok(ParseTreeNode {
+            symbol: ParseTreeSymbol::ParseTreeSymbolNodePrimary,
+            children: vec![ParseTreeNode {
+                symbol,
+                children: Vec::new(),
+                value: Some(Name::intern(text)),
+                span: Span::default(),
+            }],
+            value: None,
+            span: Span::default(),
+        })
+    }
 
-        let var_type = self.match_type_in_scope(&type_node);
-        let var_value = self.build_expr(&expr_node);
-        if self.lookup_in_scope(&var_name).is_some() {
+    // The variable after & or *. Only mutable variables can be referred to, since
+    // writing through the reference would change them.
+    fn parse_variable_operand(&mut self, address_of: bool) -> Result<ParseTreeNode, String> {
+        let use_span = self.current().map(|t| t.span).unwrap_or_default();
+        let ident_terminal = self.parse_identifier()?;
+        let name = ident_terminal.value.unwrap();
+        self.assume_declared(name, false);
+        let (declared_at, mutable) = match self.symbols.resolve(name) {
+            None => return Err(self.undefined(name, false)),
+            Some(symbol) => match symbol.kind {
+                SymbolKind::Variable => (symbol.span, symbol.mutable),
+                SymbolKind::Function { .. } => {
+                    return Err(format!("ParseError: {} is a function, not a variable", name));
+                }
+                SymbolKind::Constant { .. } => {
+                    return Err(format!(
+                        "ParseError: constant {} has no storage to refer to",
+                        name
+                    ));
+                }
+                SymbolKind::Define => {
+                    return Err(format!("ParseError: define {} has no storage to refer to", name));
+                }
+            },
+        };
+        if address_of && !mutable {
             return Err(format!(
-                "ParseError: Duplicate variable name in same scope: {:?}",
-                var_name
+                "ParseError: Cannot take a reference to immutable variable {} (declared at {}:{})",
+                name, declared_at.line, declared_at.column
             ));
         }
-        self.insert_in_scope(
-            var_name,
-            VarEntry {
-                var_type,
-                var_value,
-            },
-        );
+        self.references.push((use_span, declared_at));
+        Ok(ident_terminal)
+    }
+
+    // DerefAssignment → "*" Ident "=" Expr ";"
+    fn parse_deref_assignment(&mut self) -> Result<ParseTreeNode, String> {
+        let star = self.expect_terminal(
+            TokenType::Star,
+            ParseTreeSymbol::ParseTreeSymbolTerminalStar,
+            "'*'",
+        )?;
+        let ident_terminal = self.parse_variable_operand(false)?;
+        let equals_terminal = self.expect_terminal(
+            TokenType::Eq,
+            ParseTreeSymbol::ParseTreeSymbolTerminalEquals,
+            "'='",
+        )?;
+        let expr_node = self.parse_expression()?;
+        let semi_terminal = self.expect_terminal(
+            TokenType::Semi,
+            ParseTreeSymbol::ParseTreeSymbolTerminalSemicolon,
+            "';'",
+        )?;
 
         Ok(ParseTreeNode {
-            symbol: ParseTreeSymbol::ParseTreeSymbolNodeVariableDeclaration,
+            symbol: ParseTreeSymbol::ParseTreeSymbolNodeDerefAssignment,
             children: vec![
-                type_node,
+                star,
                 ident_terminal,
                 equals_terminal,
                 expr_node,
                 semi_terminal,
             ],
             value: None,
+            span: Span::default(),
         })
     }
 
-    fn parse_variable_assignment(&mut self) -> Result<ParseTreeNode, String> {
+    fn parse_identifier(&mut self) -> Result<ParseTreeNode, String> {
         let ident_token = self
             .current()
             .ok_or("ParseError: Expected identifier, found end of input")?;
-        if ident_token.token_type != TokenType::TokenTypeIdentifier {
-            return Err(format!(
-                "ParseError: Expected identifier, found {:?}",
-                ident_token.token_type
-            ));
-        }
-        let ident_terminal = ParseTreeNode {
-            symbol: ParseTreeSymbol::ParseTreeSymbolTerminalIdentifier,
-            children: vec![],
-            value: ident_token.value.clone(),
-        };
-        self.consume();
-
-        let equals_token = self
-            .current()
-            .ok_or("ParseError: Expected '=', found end of input")?;
-        if equals_token.token_type != TokenType::TokenTypeEquals {
+        // booleans carry their spelling as the value
+        let value = ident_token.value.map(Name::text);
+        let spelling = value.as_deref().or(ident_token.token_type.spelling());
+        if let Some(spelling) = spelling
+            && keyword(spelling).is_some()
+        {
             return Err(format!(
-                "ParseError: Expected '=', found {:?}",
-                equals_token.token_type
+                "ParseError: cannot use keyword \"{}\" as an identifier at {}:{}",
+                spelling, ident_token.span.line, ident_token.span.column
             ));
         }
-        let equals_terminal = ParseTreeNode {
-            symbol: ParseTreeSymbol::ParseTreeSymbolTerminalEquals,
-            children: vec![],
-            value: None,
-        };
-        self.consume();
-
-        let expr_node = self.parse_expression()?;
-
-        let semi_token = self
-            .current()
-            .ok_or("ParseError: Expected semicolon, found end of input")?;
-        if semi_token.token_type != TokenType::TokenTypeSemicolon {
+        if ident_token.token_type != TokenType::Ident {
             return Err(format!(
-                "ParseError: Expected semicolon, found {:?}",
-                semi_token.token_type
+                "ParseError: Expected identifier, found {}",
+                ident_token.token_type.describe()
             ));
         }
-        let semi_terminal = ParseTreeNode {
-            symbol: ParseTreeSymbol::ParseTreeSymbolTerminalSemicolon,
+        let ident_terminal = ParseTreeNode {
+            symbol: ParseTreeSymbol::ParseTreeSymbolTerminalIdentifier,
             children: vec![],
-            value: None,
+            value: ident_token.value,
+            span: Span::default(),
         };
         self.consume();
-
-        let var_name = ident_terminal
-            .value
-            .as_ref()
-            .expect("Identifier should have a value")
-            .clone();
-        let var_value = self.build_expr(&expr_node).clone();
-        if self.lookup_in_scope(&var_name).is_none() {
-            return Err(format!("Undefined variable {}", var_name));
-        }
-        self.update_in_scope(&var_name, var_value)?;
-
-        Ok(ParseTreeNode {
-            symbol: ParseTreeSymbol::ParseTreeSymbolNodeVariableAssignment,
-            children: vec![ident_terminal, equals_terminal, expr_node, semi_terminal],
-            value: None,
-        })
+        Ok(ident_terminal)
     }
 
     fn parse_type(&mut self) -> Result<ParseTreeNode, String> {
-        if self.current() != None
-            && self.current().unwrap().token_type == TokenType::TokenTypeTypeI32S
+        if self.current().is_some()
+            && self.current().unwrap().token_type == TokenType::I32S
         {
             let node = ParseTreeNode {
                 symbol: ParseTreeSymbol::ParseTreeSymbolNodeType,
@@ -755,13 +1836,15 @@ impl Parser {
                     symbol: ParseTreeSymbol::ParseTreeSymbolTerminalI32S,
                     children: Vec::new(),
                     value: None,
+                    span: Span::default(),
                 }],
                 value: None,
+                span: Span::default(),
             };
             self.consume();
             Ok(node)
-        } else if self.current() != None
-            && self.current().unwrap().token_type == TokenType::TokenTypeTypeF32S
+        } else if self.current().is_some()
+            && self.current().unwrap().token_type == TokenType::F32S
         {
             let node = ParseTreeNode {
                 symbol: ParseTreeSymbol::ParseTreeSymbolNodeType,
@@ -769,13 +1852,15 @@ impl Parser {
                     symbol: ParseTreeSymbol::ParseTreeSymbolTerminalF32S,
                     children: Vec::new(),
                     value: None,
+                    span: Span::default(),
                 }],
                 value: None,
+                span: Span::default(),
             };
             self.consume();
             Ok(node)
-        } else if self.current() != None
-            && self.current().unwrap().token_type == TokenType::TokenTypeTypeBool
+        } else if self.current().is_some()
+            && self.current().unwrap().token_type == TokenType::Bool
         {
             let node = ParseTreeNode {
                 symbol: ParseTreeSymbol::ParseTreeSymbolNodeType,
@@ -783,13 +1868,15 @@ impl Parser {
                     symbol: ParseTreeSymbol::ParseTreeSymbolTerminalBool,
                     children: Vec::new(),
                     value: None,
+                    span: Span::default(),
                 }],
                 value: None,
+                span: Span::default(),
             };
             self.consume();
             Ok(node)
-        } else if self.current() != None
-            && self.current().unwrap().token_type == TokenType::TokenTypeTypeChar
+        } else if self.current().is_some()
+            && self.current().unwrap().token_type == TokenType::Char
         {
             let node = ParseTreeNode {
                 symbol: ParseTreeSymbol::ParseTreeSymbolNodeType,
@@ -797,129 +1884,169 @@ impl Parser {
                     symbol: ParseTreeSymbol::ParseTreeSymbolTerminalChar,
                     children: Vec::new(),
                     value: None,
+                    span: Span::default(),
                 }],
                 value: None,
+                span: Span::default(),
             };
             self.consume();
             Ok(node)
         } else {
+            let found = self.next_token()?;
+            if let Some(word) = found.value.filter(|_| found.token_type == TokenType::Ident) {
+                self.suggest(word, KEYWORDS.iter().filter_map(|(word, token)| {
+                    is_type(*token).then_some(*word)
+                }));
+            }
             Err(format!(
-                "MissingTokenError: expected Type, found: {:?}",
-                self.current().unwrap().token_type
+                "MissingTokenError: expected a type, found {}",
+                self.next_token()?.token_type.describe()
             ))
         }
     }
 
     fn parse_for(&mut self) -> Result<ParseTreeNode, String> {
-        if self.current().unwrap().token_type != TokenType::TokenTypeFor {
+        if self.next_token()?.token_type != TokenType::For {
             return Err(format!(
-                "MissingTokenError: Expected 'for', found: {:?}",
-                self.current().unwrap().token_type
+                "MissingTokenError: Expected 'for', found {}",
+                self.next_token()?.token_type.describe()
             ));
         }
         let terminal_for = ParseTreeNode {
             symbol: ParseTreeSymbol::ParseTreeSymbolTerminalFor,
             children: vec![],
             value: None,
+            span: Span::default(),
         };
         self.consume();
 
-        let ident_node = self.parse_expression()?;
+        // the iterator is i32s unless a type is written before its name
+        let type_node = match self.next_token()?.token_type {
+            TokenType::I32S | TokenType::F32S | TokenType::Bool | TokenType::Char => {
+                Some(self.parse_type()?)
+            }
+            _ => None,
+        };
+
+        let ident_span = self.current().map(|t| t.span).unwrap_or_default();
+        let ident_node = self.parse_identifier()?;
 
-        if self.current().unwrap().token_type != TokenType::TokenTypeForIn {
+        if self.next_token()?.token_type != TokenType::In {
             return Err(format!(
-                "MissingTokenError: Expected 'for_in', found: {:?}",
-                self.current().unwrap().token_type
+                "MissingTokenError: Expected 'in', found {}",
+                self.next_token()?.token_type.describe()
             ));
         }
         let terminal_for_in = ParseTreeNode {
             symbol: ParseTreeSymbol::ParseTreeSymbolTerminalForIn,
             children: vec![],
             value: None,
+            span: Span::default(),
         };
         self.consume();
 
         let lower_bound_node = self.parse_expression()?;
 
-        if self.current().unwrap().token_type != TokenType::TokenTypeForTo {
-            return Err(format!(
-                "MissingTokenError: Expected 'for_dot', found: {:?}",
-                self.current().unwrap().token_type
-            ));
-        }
+        let range_symbol = match self.next_token()?.token_type {
+            TokenType::To => ParseTreeSymbol::ParseTreeSymbolTerminalForTo,
+            TokenType::Until => ParseTreeSymbol::ParseTreeSymbolTerminalForUntil,
+            other => {
+                return Err(format!(
+                    "MissingTokenError: Expected 'to' or 'until', found {}",
+                    other.describe()
+                ));
+            }
+        };
         let terminal_for_dot = ParseTreeNode {
-            symbol: ParseTreeSymbol::ParseTreeSymbolTerminalForTo,
+            symbol: range_symbol,
             children: vec![],
             value: None,
+            span: Span::default(),
         };
         self.consume();
 
         let upper_bound_node = self.parse_expression()?;
 
-        if self.current().unwrap().token_type != TokenType::TokenTypeLeftCurlyBrace {
+        let mut step_nodes = Vec::new();
+        if self.at_word("step") {
+            step_nodes.push(ParseTreeNode {
+                symbol: ParseTreeSymbol::ParseTreeSymbolTerminalForStep,
+                children: vec![],
+                value: None,
+                span: Span::default(),
+            });
+            self.consume();
+            step_nodes.push(self.parse_expression()?);
+        }
+
+        if self.next_token()?.token_type != TokenType::LBrace {
             return Err(format!(
-                "MissingTokenError: Expected 'left_curly_brace', found: {:?}",
-                self.current().unwrap().token_type
+                "MissingTokenError: Expected '{{', found {}",
+                self.next_token()?.token_type.describe()
             ));
         }
 
-        self.push_scope();
+        self.symbols.enter_scope();
 
-        // push iterator while inside the new scope
-        let var_name = self
-            .find_terminal(&ident_node)
+        // declare iterator while inside the new scope; the loop owns its updates
+        let var_name = ident_node
             .value
-            .as_ref()
-            .expect("Identifier should have a value")
-            .clone();
-
-        let var_type = Type::I32S;
-        let var_value = self.build_expr(&lower_bound_node);
-        self.insert_in_scope(
-            var_name,
-            VarEntry {
-                var_type,
-                var_value,
-            },
-        );
+            .expect("Identifier should have a value");
 
-        let block_node = self.parse_block()?;
-        self.pop_scope();
+        let var_type = match &type_node {
+            Some(type_node) => self.match_type_in_scope(type_node),
+            None => Type::I32S,
+        };
+        self.declare(Symbol {
+            name: var_name,
+            kind: SymbolKind::Variable,
+            type_: var_type,
+            span: ident_span,
+            mutable: false,
+        })?;
 
+        let block_node = self.parse_block()?;
+        self.symbols.exit_scope();
+
+        let mut children = vec![terminal_for];
+        children.extend(type_node);
+        children.extend([
+            ident_node,
+            terminal_for_in,
+            lower_bound_node,
+            terminal_for_dot,
+            upper_bound_node,
+        ]);
+        children.extend(step_nodes);
+        children.push(block_node);
         Ok(ParseTreeNode {
             symbol: ParseTreeSymbol::ParseTreeSymbolNodeFor,
-            children: vec![
-                terminal_for,
-                ident_node,
-                terminal_for_in,
-                lower_bound_node,
-                terminal_for_dot,
-                upper_bound_node,
-                block_node,
-            ],
+            children,
             value: None,
+            span: Span::default(),
         })
     }
 
     fn parse_if(&mut self) -> Result<ParseTreeNode, String> {
-        if self.current().unwrap().token_type != TokenType::TokenTypeIf {
+        if self.next_token()?.token_type != TokenType::If {
             return Err(format!(
-                "MissingTokenError: Expected 'if', found: {:?}",
-                self.current().unwrap().token_type
+                "MissingTokenError: Expected 'if', found {}",
+                self.next_token()?.token_type.describe()
             ));
         }
         let if_terminal = ParseTreeNode {
             symbol: ParseTreeSymbol::ParseTreeSymbolTerminalIf,
             children: vec![],
             value: None,
+            span: Span::default(),
         };
         self.consume();
 
         let expr_node = self.parse_expression()?;
 
-        self.push_scope();
+        self.symbols.enter_scope();
         let block_node = self.parse_block()?;
-        self.pop_scope();
+        self.symbols.exit_scope();
 
         let else_node = self.parse_else()?;
 
@@ -927,34 +2054,177 @@ impl Parser {
             symbol: ParseTreeSymbol::ParseTreeSymbolNodeIf,
             children: vec![if_terminal, expr_node, block_node, else_node],
             value: None,
+            span: Span::default(),
+        })
+    }
+
+    fn parse_while(&mut self) -> Result<ParseTreeNode, String> {
+        let while_terminal = self.expect_terminal(
+            TokenType::While,
+            ParseTreeSymbol::ParseTreeSymbolTerminalWhile,
+            "'while'",
+        )?;
+
+        let expr_node = self.parse_expression()?;
+
+        self.symbols.enter_scope();
+        let block_node = self.parse_block()?;
+        self.symbols.exit_scope();
+
+        Ok(ParseTreeNode {
+            symbol: ParseTreeSymbol::ParseTreeSymbolNodeWhile,
+            children: vec![while_terminal, expr_node, block_node],
+            value: None,
+            span: Span::default(),
+        })
+    }
+
+    fn parse_do_while(&mut self) -> Result<ParseTreeNode, String> {
+        let do_terminal = self.expect_terminal(
+            TokenType::Do,
+            ParseTreeSymbol::ParseTreeSymbolTerminalDo,
+            "'do'",
+        )?;
+
+        self.symbols.enter_scope();
+        let block_node = self.parse_block()?;
+        self.symbols.exit_scope();
+
+        let while_terminal = self.expect_terminal(
+            TokenType::While,
+            ParseTreeSymbol::ParseTreeSymbolTerminalWhile,
+            "'while' after do block",
+        )?;
+
+        // the block's scope is closed, so the condition only sees enclosing variables
+        let expr_node = self.parse_expression()?;
+
+        let semi_terminal = self.expect_terminal(
+            TokenType::Semi,
+            ParseTreeSymbol::ParseTreeSymbolTerminalSemicolon,
+            "';' after do-while condition",
+        )?;
+
+        Ok(ParseTreeNode {
+            symbol: ParseTreeSymbol::ParseTreeSymbolNodeDoWhile,
+            children: vec![do_terminal, block_node, while_terminal, expr_node, semi_terminal],
+            value: None,
+            span: Span::default(),
+        })
+    }
+
+    fn parse_match(&mut self) -> Result<ParseTreeNode, String> {
+        let match_terminal = self.expect_terminal(
+            TokenType::Match,
+            ParseTreeSymbol::ParseTreeSymbolTerminalMatch,
+            "'match'",
+        )?;
+
+        let expr_node = self.parse_expression()?;
+
+        let left_brace = self.expect_terminal(
+            TokenType::LBrace,
+            ParseTreeSymbol::ParseTreeSymbolTerminalLeftCurlyBrace,
+            "'{' after match expression",
+        )?;
+
+        let mut children = vec![match_terminal, expr_node, left_brace];
+        while let Some(tok) = self.current() {
+            if tok.token_type == TokenType::RBrace {
+                break;
+            }
+            children.push(self.parse_match_arm()?);
+        }
+
+        children.push(self.expect_terminal(
+            TokenType::RBrace,
+            ParseTreeSymbol::ParseTreeSymbolTerminalRightCurlyBrace,
+            "'}' to close match",
+        )?);
+
+        Ok(ParseTreeNode {
+            symbol: ParseTreeSymbol::ParseTreeSymbolNodeMatch,
+            children,
+            value: None,
+            span: Span::default(),
+        })
+    }
+
+    fn parse_match_arm(&mut self) -> Result<ParseTreeNode, String> {
+        let token = self
+            .current()
+            .ok_or("ParseError: Expected match arm, found end of input")?;
+        let pattern = match token.token_type {
+            TokenType::IntLit => ParseTreeNode {
+                symbol: ParseTreeSymbol::ParseTreeSymbolTerminalIntegerLiteral,
+                children: vec![],
+                value: token.value,
+                span: token.span,
+            },
+            TokenType::Else => ParseTreeNode {
+                symbol: ParseTreeSymbol::ParseTreeSymbolTerminalElse,
+                children: vec![],
+                value: None,
+                span: token.span,
+            },
+            other => {
+                return Err(format!(
+                    "ParseError: Expected integer literal or 'else' as match arm, found {}",
+                    other.describe()
+                ));
+            }
+        };
+        self.consume();
+
+        let arrow = self.expect_terminal(
+            TokenType::FatArrow,
+            ParseTreeSymbol::ParseTreeSymbolTerminalFatArrow,
+            "'=>' after match pattern",
+        )?;
+
+        self.symbols.enter_scope();
+        let block_node = self.parse_block()?;
+        self.symbols.exit_scope();
+
+        Ok(ParseTreeNode {
+            symbol: ParseTreeSymbol::ParseTreeSymbolNodeMatchArm,
+            children: vec![pattern, arrow, block_node],
+            value: None,
+            span: Span::default(),
         })
     }
 
     fn parse_else(&mut self) -> Result<ParseTreeNode, String> {
-        if self.current().unwrap().token_type != TokenType::TokenTypeElse {
+        // an if may be the last statement of the program
+        if self.current().is_none_or(|t| t.token_type != TokenType::Else) {
             return Ok(ParseTreeNode {
                 symbol: ParseTreeSymbol::ParseTreeSymbolNodeElse,
                 children: vec![],
                 value: None,
+                span: Span::default(),
             });
         }
         let else_terminal = ParseTreeNode {
             symbol: ParseTreeSymbol::ParseTreeSymbolTerminalElse,
             children: vec![],
             value: None,
+            span: Span::default(),
         };
         self.consume();
 
         let child: ParseTreeNode = match self.current().map(|t| t.token_type) {
-            Some(TokenType::TokenTypeIf) => self.parse_if()?,
-            Some(TokenType::TokenTypeLeftCurlyBrace) => {
-                self.push_scope();
+            Some(TokenType::If) => self.parse_if()?,
+            Some(TokenType::LBrace) => {
+                self.symbols.enter_scope();
                 let block = self.parse_block()?;
-                self.pop_scope();
+                self.symbols.exit_scope();
                 block
             }
             other => {
-                return Err(format!("Unexpected token after `else`: {:?}", other));
+                return Err(format!(
+                    "ParseError: Expected 'if' or '{{' after 'else', found {}",
+                    other.map_or("end of input".to_string(), |t| t.describe())
+                ));
             }
         };
 
@@ -962,27 +2232,29 @@ impl Parser {
             symbol: ParseTreeSymbol::ParseTreeSymbolNodeElse,
             children: vec![else_terminal, child],
             value: None,
+            span: Span::default(),
         })
     }
 
     fn parse_block(&mut self) -> Result<ParseTreeNode, String> {
-        if self.current().unwrap().token_type != TokenType::TokenTypeLeftCurlyBrace {
+        if self.next_token()?.token_type != TokenType::LBrace {
             return Err(format!(
-                "MissingTokenError: Expected 'left_curly_brace', found: {:?}",
-                self.current().unwrap().token_type
+                "MissingTokenError: Expected '{{', found {}",
+                self.next_token()?.token_type.describe()
             ));
         }
         let left_bracket_terminal = ParseTreeNode {
             symbol: ParseTreeSymbol::ParseTreeSymbolTerminalLeftCurlyBrace,
             children: vec![],
             value: None,
+            span: Span::default(),
         };
         self.consume();
 
         let mut statements = Vec::new();
 
         while let Some(tok) = self.current() {
-            if tok.token_type == TokenType::TokenTypeRightCurlyBrace {
+            if tok.token_type == TokenType::RBrace {
                 break; // end of block
             }
 
@@ -990,16 +2262,17 @@ impl Parser {
             statements.push(stmt);
         }
 
-        if self.current().unwrap().token_type != TokenType::TokenTypeRightCurlyBrace {
+        if self.next_token()?.token_type != TokenType::RBrace {
             return Err(format!(
-                "MissingTokenError: Expected 'right_curly_brace', found: {:?}",
-                self.current().unwrap().token_type
+                "MissingTokenError: Expected '}}', found {}",
+                self.next_token()?.token_type.describe()
             ));
         }
         let right_bracket_terminal = ParseTreeNode {
             symbol: ParseTreeSymbol::ParseTreeSymbolTerminalRightCurlyBrace,
             children: vec![],
             value: None,
+            span: Span::default(),
         };
         self.consume();
 
@@ -1009,45 +2282,366 @@ impl Parser {
         children.push(right_bracket_terminal);
 
         Ok(ParseTreeNode {
-            symbol: ParseTreeSymbol::ParseTreeSymbolNodeBlock,
-            children,
+            symbol: ParseTreeSymbol::ParseTreeSymbolNodeBlock,
+            children,
+            value: None,
+            span: Span::default(),
+        })
+    }
+
+    // Function → "export"? "fn" Signature ("=" Expr ";" | Block) | "extern" "fn" Signature ";"
+    // Signature → Ident "(" Params? ")" "->" Type
+    fn parse_function(&mut self) -> Result<ParseTreeNode, String> {
+        if !self.symbols.is_global_scope() {
+            return Err("ParseError: Functions may only be declared at the top level".to_string());
+        }
+        let mut children = Vec::new();
+        let linkage = self.current().map(|t| t.token_type);
+        if linkage == Some(TokenType::Extern) {
+            children.push(self.expect_terminal(
+                TokenType::Extern,
+                ParseTreeSymbol::ParseTreeSymbolTerminalExtern,
+                "'extern'",
+            )?);
+        } else if linkage == Some(TokenType::Export) {
+            children.push(self.expect_terminal(
+                TokenType::Export,
+                ParseTreeSymbol::ParseTreeSymbolTerminalExport,
+                "'export'",
+            )?);
+        }
+        children.push(self.expect_terminal(
+            TokenType::Fn,
+            ParseTreeSymbol::ParseTreeSymbolTerminalFn,
+            "'fn'",
+        )?);
+
+        let ident_span = self.current().map(|t| t.span).unwrap_or_default();
+        let ident_terminal = self.parse_identifier()?;
+
+        let left_paren = self.expect_terminal(
+            TokenType::LParen,
+            ParseTreeSymbol::ParseTreeSymbolTerminalLeftParen,
+            "'('",
+        )?;
+
+        let mut params_node = ParseTreeNode {
+            symbol: ParseTreeSymbol::ParseTreeSymbolNodeParameters,
+            children: vec![],
+            value: None,
+            span: Span::default(),
+        };
+        let mut params: Vec<(Name, Type, Span)> = Vec::new();
+        while self
+            .current()
+            .is_some_and(|t| t.token_type != TokenType::RParen)
+        {
+            if !params.is_empty() {
+                params_node.children.push(self.expect_terminal(
+                    TokenType::Comma,
+                    ParseTreeSymbol::ParseTreeSymbolTerminalComma,
+                    "','",
+                )?);
+            }
+            let mut children = Vec::new();
+            if self
+                .current()
+                .is_some_and(|t| t.token_type == TokenType::Ref)
+            {
+                children.push(self.expect_terminal(
+                    TokenType::Ref,
+                    ParseTreeSymbol::ParseTreeSymbolTerminalRef,
+                    "'ref'",
+                )?);
+            }
+            children.push(self.parse_type()?);
+            let param_span = self.current().map(|t| t.span).unwrap_or_default();
+            children.push(self.parse_identifier()?);
+            let param = ParseTreeNode {
+                symbol: ParseTreeSymbol::ParseTreeSymbolNodeParameter,
+                children,
+                value: None,
+                span: Span::default(),
+            };
+            let (name, type_) = self.build_parameter(&param);
+            params.push((name, type_, param_span));
+            params_node.children.push(param);
+        }
+
+        let right_paren = self.expect_terminal(
+            TokenType::RParen,
+            ParseTreeSymbol::ParseTreeSymbolTerminalRightParen,
+            "')'",
+        )?;
+        let arrow = self.expect_terminal(
+            TokenType::Arrow,
+            ParseTreeSymbol::ParseTreeSymbolTerminalArrow,
+            "'->'",
+        )?;
+        let return_type_node = if self
+            .current()
+            .is_some_and(|t| t.token_type == TokenType::Void)
+        {
+            self.consume();
+            ParseTreeNode {
+                symbol: ParseTreeSymbol::ParseTreeSymbolNodeType,
+                children: vec![ParseTreeNode {
+                    symbol: ParseTreeSymbol::ParseTreeSymbolTerminalVoid,
+                    children: Vec::new(),
+                    value: None,
+                    span: Span::default(),
+                }],
+                value: None,
+                span: Span::default(),
+            }
+        } else {
+            self.parse_type()?
+        };
+
+        // declared before the body is parsed so the function can refer to itself
+        let return_type = self.match_type_in_scope(&return_type_node);
+        self.declare(Symbol {
+            name: ident_terminal.value.unwrap(),
+            kind: SymbolKind::Function {
+                params: params.iter().map(|(_, t, _)| t.clone()).collect(),
+            },
+            type_: return_type,
+            span: ident_span,
+            mutable: false,
+        })?;
+
+        children.extend([
+            ident_terminal,
+            left_paren,
+            params_node,
+            right_paren,
+            arrow,
+            return_type_node,
+        ]);
+        // defined in another object file, so there is no body to parse
+        if linkage == Some(TokenType::Extern) {
+            children.push(self.expect_terminal(
+                TokenType::Semi,
+                ParseTreeSymbol::ParseTreeSymbolTerminalSemicolon,
+                "';'",
+            )?);
+            return Ok(ParseTreeNode {
+                symbol: ParseTreeSymbol::ParseTreeSymbolNodeFunction,
+                children,
+                value: None,
+                span: Span::default(),
+            });
+        }
+
+        self.symbols.enter_scope();
+        for (name, type_, span) in params {
+            self.declare(Symbol {
+                name,
+                kind: SymbolKind::Variable,
+                type_,
+                span,
+                mutable: false,
+            })?;
+        }
+        self.in_function = true;
+        let body = self.parse_function_body();
+        self.in_function = false;
+        self.symbols.exit_scope();
+
+        children.extend(body?);
+        Ok(ParseTreeNode {
+            symbol: ParseTreeSymbol::ParseTreeSymbolNodeFunction,
+            children,
+            value: None,
+            span: Span::default(),
+        })
+    }
+
+    // Either "=" Expr ";" or a block of statements ending in return
+    fn parse_function_body(&mut self) -> Result<Vec<ParseTreeNode>, String> {
+        if self
+            .current()
+            .is_some_and(|t| t.token_type == TokenType::LBrace)
+        {
+            return Ok(vec![self.parse_block()?]);
+        }
+        let equals = self.expect_terminal(
+            TokenType::Eq,
+            ParseTreeSymbol::ParseTreeSymbolTerminalEquals,
+            "'=' or '{'",
+        )?;
+        let body = self.parse_expression()?;
+        let semi = self.expect_terminal(
+            TokenType::Semi,
+            ParseTreeSymbol::ParseTreeSymbolTerminalSemicolon,
+            "';'",
+        )?;
+        Ok(vec![equals, body, semi])
+    }
+
+    // ExpressionStatement → Call ";", the value is discarded
+    fn parse_expression_statement(&mut self) -> Result<ParseTreeNode, String> {
+        let expr = self.parse_expression()?;
+        let semi = self.expect_terminal(
+            TokenType::Semi,
+            ParseTreeSymbol::ParseTreeSymbolTerminalSemicolon,
+            "';'",
+        )?;
+        Ok(ParseTreeNode {
+            symbol: ParseTreeSymbol::ParseTreeSymbolNodeExpressionStatement,
+            children: vec![expr, semi],
+            value: None,
+            span: Span::default(),
+        })
+    }
+
+    // Return → "return" Expr? ";"
+    fn parse_return(&mut self) -> Result<ParseTreeNode, String> {
+        if !self.in_function {
+            return Err("ParseError: return is only allowed inside a function body".to_string());
+        }
+        let return_terminal = self.expect_terminal(
+            TokenType::Return,
+            ParseTreeSymbol::ParseTreeSymbolTerminalReturn,
+            "'return'",
+        )?;
+        let mut children = vec![return_terminal];
+        if !self
+            .current()
+            .is_some_and(|t| t.token_type == TokenType::Semi)
+        {
+            children.push(self.parse_expression()?);
+        }
+        children.push(self.expect_terminal(
+            TokenType::Semi,
+            ParseTreeSymbol::ParseTreeSymbolTerminalSemicolon,
+            "';'",
+        )?);
+        Ok(ParseTreeNode {
+            symbol: ParseTreeSymbol::ParseTreeSymbolNodeReturn,
+            children,
+            value: None,
+            span: Span::default(),
+        })
+    }
+
+    // Call → Ident "(" (Expr ("," Expr)*)? ")"
+    fn parse_call(&mut self) -> Result<ParseTreeNode, String> {
+        let ident_terminal = self.parse_identifier()?;
+        let left_paren = self.expect_terminal(
+            TokenType::LParen,
+            ParseTreeSymbol::ParseTreeSymbolTerminalLeftParen,
+            "'('",
+        )?;
+
+        let mut args_node = ParseTreeNode {
+            symbol: ParseTreeSymbol::ParseTreeSymbolNodeArguments,
+            children: vec![],
+            value: None,
+            span: Span::default(),
+        };
+        while self
+            .current()
+            .is_some_and(|t| t.token_type != TokenType::RParen)
+        {
+            if !args_node.children.is_empty() {
+                args_node.children.push(self.expect_terminal(
+                    TokenType::Comma,
+                    ParseTreeSymbol::ParseTreeSymbolTerminalComma,
+                    "','",
+                )?);
+            }
+            args_node.children.push(self.parse_expression()?);
+        }
+
+        let right_paren = self.expect_terminal(
+            TokenType::RParen,
+            ParseTreeSymbol::ParseTreeSymbolTerminalRightParen,
+            "')'",
+        )?;
+
+        Ok(ParseTreeNode {
+            symbol: ParseTreeSymbol::ParseTreeSymbolNodeCall,
+            children: vec![ident_terminal, left_paren, args_node, right_paren],
+            value: None,
+            span: Span::default(),
+        })
+    }
+
+    fn expect_terminal(
+        &mut self,
+        token_type: TokenType,
+        symbol: ParseTreeSymbol,
+        description: &str,
+    ) -> Result<ParseTreeNode, String> {
+        let token = self
+            .current()
+            .ok_or(format!("ParseError: Expected {}, found end of input", description))?;
+        if token.token_type != token_type {
+            return Err(format!(
+                "ParseError: Expected {}, found {}",
+                description,
+                token.token_type.describe()
+            ));
+        }
+        self.consume();
+        Ok(ParseTreeNode {
+            symbol,
+            children: vec![],
             value: None,
+            span: Span::default(),
         })
     }
 
-    pub fn print_ast(&mut self, node: &AbstractSyntaxTreeNode, indent: usize) {
-        for _i in 0..indent {
-            print!("  ");
+    // Each node's id and symbol, with the statements nested in it indented below;
+    // bodies in the symbol show as the ids of those statements
+    pub fn print_ast(ast: &Ast, id: NodeId, indent: usize) {
+        for visit in ast.iter_from(id) {
+            for _i in 0..indent + visit.depth {
+                print!("  ");
+            }
+            println!("{:?} {:?}", visit.id, visit.node.stmt);
         }
-        println!("{:?}", node.symbol);
+    }
 
-        for child in &node.children {
-            self.print_ast(child, indent + 1);
+    // Lowers a whole parse tree from parse()
+    pub fn build_ast(&mut self, parse_tree: &ParseTreeNode) -> Ast {
+        let root = self.build_node(parse_tree);
+        Ast {
+            nodes: std::mem::take(&mut self.nodes),
+            root,
         }
     }
 
-    pub fn build_ast(&mut self, parse_tree: &ParseTreeNode) -> AbstractSyntaxTreeNode {
+    fn build_node(&mut self, parse_tree: &ParseTreeNode) -> NodeId {
+        let node = self.lower(parse_tree);
+        self.nodes.alloc(node)
+    }
+
+    fn lower(&mut self, parse_tree: &ParseTreeNode) -> Node {
         match parse_tree.symbol {
             ParseTreeSymbol::ParseTreeSymbolNodeEntryPoint => {
-                let entry_node = AbstractSyntaxTreeNode {
-                    symbol: AbstractSyntaxTreeSymbol::AbstractSyntaxTreeSymbolEntry,
+                Node {
+                    stmt: Stmt::Entry,
                     children: parse_tree
                         .children
                         .iter()
-                        .filter_map(|child| match child.symbol {
-                            ParseTreeSymbol::ParseTreeSymbolNodeStatement => {
-                                Some(self.build_ast(child))
-                            }
-                            _ => None,
+                        .filter(|child| {
+                            child.symbol == ParseTreeSymbol::ParseTreeSymbolNodeStatement
                         })
+                        .flat_map(|child| self.build_statement(child))
                         .collect(),
-                };
-                entry_node
+                    span: Span::default(),
+                }
             }
 
             ParseTreeSymbol::ParseTreeSymbolNodeStatement => {
                 if let Some(first_child) = parse_tree.children.first() {
-                    self.build_ast(first_child)
+                    let enclosing = std::mem::replace(&mut self.statement_span, parse_tree.span);
+                    let mut node = self.lower(first_child);
+                    self.statement_span = enclosing;
+                    node.span = parse_tree.span;
+                    node
                 } else {
                     panic!("Statement node has no children");
                 }
@@ -1060,142 +2654,243 @@ impl Parser {
                     .iter()
                     .find(|c| c.symbol == ParseTreeSymbol::ParseTreeSymbolNodeExpression)
                 {
-                    let value_child_node = self.find_terminal(&expr_node);
-                    let expr = match value_child_node.symbol {
-                        ParseTreeSymbol::ParseTreeSymbolTerminalIntegerLiteral => {
-                            let v = value_child_node
-                                .value
-                                .as_ref()
-                                .unwrap()
-                                .parse::<i32>()
-                                .unwrap();
-                            Expr::Int(v)
-                        }
-                        ParseTreeSymbol::ParseTreeSymbolTerminalFloatLiteral => {
-                            let v = value_child_node
-                                .value
-                                .as_ref()
-                                .unwrap()
-                                .parse::<f32>()
-                                .unwrap();
-                            Expr::Float(v)
-                        }
-                        ParseTreeSymbol::ParseTreeSymbolTerminalBooleanLiteral => {
-                            let v = value_child_node
-                                .value
-                                .as_ref()
-                                .unwrap()
-                                .parse::<bool>()
-                                .unwrap();
-                            Expr::Bool(v)
-                        }
-                        ParseTreeSymbol::ParseTreeSymbolTerminalIdentifier => {
-                            let name = value_child_node.value.as_ref().unwrap().to_string();
-                            Expr::Ident(name)
-                        }
-                        _ => panic!("Invalid expression in exit"),
-                    };
+                    let expr = self.build_expr(expr_node);
 
-                    AbstractSyntaxTreeNode {
-                        symbol: AbstractSyntaxTreeSymbol::AbstractSyntaxTreeSymbolExit(expr),
+                    Node {
+                        stmt: Stmt::Exit(expr),
                         children: Vec::new(),
+                        span: Span::default(),
                     }
                 } else {
                     panic!("Exit statement has no expression child");
                 }
             }
 
-            ParseTreeSymbol::ParseTreeSymbolNodeVariableDeclaration => {
-                // Children:
-                // [0] = type
-                // [1] = identifier
-                // [2] = "="
-                // [3] = expression
-                // [4] = ";"
+            ParseTreeSymbol::ParseTreeSymbolNodePrintInt => {
+                // [print_int, (, expression, ), semicolon]
+                let expr = self.build_expr(&parse_tree.children[2]);
+                Node {
+                    stmt: Stmt::PrintInt(expr),
+                    children: Vec::new(),
+                    span: Span::default(),
+                }
+            }
 
-                let type_node = &parse_tree.children[0];
-                let ident_node = &parse_tree.children[1];
-                let expr_node = &parse_tree.children[3];
+            ParseTreeSymbol::ParseTreeSymbolNodeConstantDeclaration => {
+                // [const, identifier, equals, expression, semicolon]; an initializer that
+                // cannot be evaluated was reported when the declaration was parsed
+                let name = parse_tree.children[1].value.unwrap();
+                let expr = self.build_expr(&parse_tree.children[3]);
+                let value = consteval::evaluate(&expr).unwrap_or(Value::Int(0));
+                Node {
+                    stmt: Stmt::ConstantDeclaration {
+                        name,
+                        value,
+                    },
+                    children: Vec::new(),
+                    span: Span::default(),
+                }
+            }
 
-                let name = self
-                    .find_terminal(ident_node)
-                    .value
-                    .as_ref()
-                    .unwrap()
-                    .clone();
+            ParseTreeSymbol::ParseTreeSymbolNodeDefine => {
+                // [define, identifier, expression, semicolon]; problems with the
+                // expression, like a literal out of range, are reported where it is used
+                let name = parse_tree.children[1].value.unwrap();
+                let reported = self.errors.len();
+                let value = self.build_expr(&parse_tree.children[2]);
+                self.errors.truncate(reported);
+                Node {
+                    stmt: Stmt::Define { name, value },
+                    children: Vec::new(),
+                    span: Span::default(),
+                }
+            }
 
-                let value_expr = self.build_expr(expr_node);
+            ParseTreeSymbol::ParseTreeSymbolNodeAssert => {
+                // [assert, expression, semicolon]
+                let expr = self.build_expr(&parse_tree.children[1]);
+                Node {
+                    stmt: Stmt::Assert(expr),
+                    children: Vec::new(),
+                    span: Span::default(),
+                }
+            }
 
-                AbstractSyntaxTreeNode {
-                    symbol: AbstractSyntaxTreeSymbol::AbstractSyntaxTreeSymbolVariableDeclaration {
-                        name,
-                        type_: self.match_type_in_scope(type_node),
-                        value: value_expr,
-                    },
-                    children: vec![],
+            ParseTreeSymbol::ParseTreeSymbolNodeReturn => {
+                // [return, expression?, semicolon]
+                let expr = (parse_tree.children.len() == 3)
+                    .then(|| self.build_expr(&parse_tree.children[1]));
+                Node {
+                    stmt: Stmt::Return(expr),
+                    children: Vec::new(),
+                    span: Span::default(),
                 }
             }
 
-            ParseTreeSymbol::ParseTreeSymbolNodeVariableAssignment => {
-                if let Some(terminal_id_node) = parse_tree
-                    .children
-                    .iter()
-                    .find(|c| c.symbol == ParseTreeSymbol::ParseTreeSymbolTerminalIdentifier)
-                {
-                    let name = terminal_id_node.value.as_ref().expect("Missing terminal");
-                    let entry = self.lookup_in_scope(name).unwrap();
-
-                    AbstractSyntaxTreeNode {
-                        symbol:
-                            AbstractSyntaxTreeSymbol::AbstractSyntaxTreeSymbolVariableAssignment {
-                                name: name.to_string(),
-                                value: entry.var_value.clone(),
-                            },
-                        children: Vec::new(),
-                    }
-                } else {
-                    panic!("Variable node has no terminal identifier");
+            ParseTreeSymbol::ParseTreeSymbolNodeExpressionStatement => {
+                // [expression, semicolon]
+                let expr = self.build_expr(&parse_tree.children[0]);
+                Node {
+                    stmt: Stmt::Expression(expr),
+                    children: Vec::new(),
+                    span: Span::default(),
                 }
             }
 
-            ParseTreeSymbol::ParseTreeSymbolNodeFor => {
-                let mut expr_nodes = parse_tree
+            ParseTreeSymbol::ParseTreeSymbolNodeParallelAssignment => {
+                // [target, (",", target)*, "=", expression, (",", expression)*, ";"], where
+                // each target's child is its type. `a, b = b, a;` becomes
+                // `{ i32s a.new = b; b = a; a = a.new; }`: the values of all but the last
+                // target wait in temporaries until every value has been read.
+                let equals = parse_tree
                     .children
                     .iter()
-                    .filter(|c| c.symbol == ParseTreeSymbol::ParseTreeSymbolNodeExpression);
+                    .position(|c| c.symbol == ParseTreeSymbol::ParseTreeSymbolTerminalEquals)
+                    .unwrap();
+                let (targets, rest) = parse_tree.children.split_at(equals);
+                let targets: Vec<_> = targets
+                    .iter()
+                    .filter(|c| c.symbol == ParseTreeSymbol::ParseTreeSymbolTerminalIdentifier)
+                    .collect();
+                let values: Vec<_> = rest
+                    .iter()
+                    .filter(|c| c.symbol == ParseTreeSymbol::ParseTreeSymbolNodeExpression)
+                    .collect();
 
-                let id_expr = expr_nodes.next().expect("Missing iterator expression");
-                let begin_expr = expr_nodes.next().expect("Missing begin expression");
-                let end_expr = expr_nodes.next().expect("Missing end expression");
+                let mut body = Vec::new();
+                let mut assignments = Vec::new();
+                let last = targets.len() - 1;
+                for (i, (target, value)) in targets.into_iter().zip(values).enumerate() {
+                    let name = target.value.unwrap();
+                    let value = self.build_expr(value);
+                    let stmt = if i == last {
+                        Stmt::VariableAssignment { name, value }
+                    } else {
+                        let temporary = Name::intern(&format!("{}.new", name));
+                        assignments.push(Stmt::VariableAssignment {
+                            name,
+                            value: ExprKind::Ident(temporary).into(),
+                        });
+                        Stmt::VariableDeclaration {
+                            name: temporary,
+                            type_: self.match_type_in_scope(&target.children[0]),
+                            value: Some(value),
+                        }
+                    };
+                    body.push(self.nodes.alloc(Node {
+                        stmt,
+                        children: vec![],
+                        span: self.statement_span,
+                    }));
+                }
+                for stmt in assignments {
+                    body.push(self.nodes.alloc(Node {
+                        stmt,
+                        children: vec![],
+                        span: self.statement_span,
+                    }));
+                }
 
-                let iterator_name = self.find_terminal(&id_expr).value.as_ref().unwrap().clone();
+                Node {
+                    stmt: Stmt::Block { body },
+                    children: vec![],
+                    span: Span::default(),
+                }
+            }
 
-                let iterator_begin = {
-                    let lit = self.find_terminal(&begin_expr);
-                    Expr::Int(lit.value.as_ref().unwrap().parse().unwrap())
+            ParseTreeSymbol::ParseTreeSymbolNodeVariableAssignment => {
+                // Children:
+                // [0] = identifier
+                // [1] = "=", or "++" or "--"
+                // [2] = expression, or the 1 that "++" adds and "--" subtracts
+                // [3] = ";"
+                let name = parse_tree.children[0]
+                    .value
+                    .expect("Missing terminal");
+                let value = self.build_expr(&parse_tree.children[2]);
+                let op = match parse_tree.children[1].symbol {
+                    ParseTreeSymbol::ParseTreeSymbolTerminalPlusPlus => Some(BinOpType::Add),
+                    ParseTreeSymbol::ParseTreeSymbolTerminalMinusMinus => {
+                        Some(BinOpType::Subtract)
+                    }
+                    _ => None,
+                };
+                let value = match op {
+                    Some(op) => ExprKind::BinaryOp {
+                        left: Box::new(ExprKind::Ident(name).into()),
+                        op,
+                        right: Box::new(value),
+                    }
+                    .into(),
+                    None => value,
                 };
 
-                let iterator_end = {
-                    let lit = self.find_terminal(&end_expr);
-                    Expr::Int(lit.value.as_ref().unwrap().parse().unwrap())
+                Node {
+                    stmt: Stmt::VariableAssignment {
+                        name,
+                        value,
+                    },
+                    children: Vec::new(),
+                    span: Span::default(),
+                }
+            }
+
+            ParseTreeSymbol::ParseTreeSymbolNodeDerefAssignment => {
+                // [*, identifier, equals, expression, semicolon]
+                let name = parse_tree.children[1].value.unwrap();
+                let value = self.build_expr(&parse_tree.children[3]);
+                Node {
+                    stmt: Stmt::DerefAssignment {
+                        name,
+                        value,
+                    },
+                    children: Vec::new(),
+                    span: Span::default(),
+                }
+            }
+
+            ParseTreeSymbol::ParseTreeSymbolNodeFor => {
+                // Children:
+                // [0] = "for"
+                // then an optional type, which shifts the rest by one
+                // [1] = identifier
+                // [2] = "in"
+                // [3] = begin expression
+                // [4] = "to"
+                // [5] = end expression
+                // then "step" and its expression, if given
+                // last = block
+                let (type_, rest) = match parse_tree.children[1].symbol {
+                    ParseTreeSymbol::ParseTreeSymbolNodeType => (
+                        self.match_type_in_scope(&parse_tree.children[1]),
+                        &parse_tree.children[1..],
+                    ),
+                    _ => (Type::I32S, &parse_tree.children[..]),
                 };
+                let iterator_name = rest[1].value.unwrap();
 
-                let mut stmt_nodes = Vec::new();
-                self.find_statements(parse_tree, &mut stmt_nodes);
+                let iterator_begin = self.build_expr(&rest[3]);
+                let iterator_end = self.build_expr(&rest[5]);
 
-                let body: Vec<AbstractSyntaxTreeNode> = stmt_nodes
-                    .into_iter()
-                    .map(|stmt| self.build_ast(stmt))
-                    .collect();
+                let inclusive = rest[4].symbol == ParseTreeSymbol::ParseTreeSymbolTerminalForTo;
 
-                AbstractSyntaxTreeNode {
-                    symbol: AbstractSyntaxTreeSymbol::AbstractSyntaxTreeSymbolFor {
+                let step = (rest[6].symbol == ParseTreeSymbol::ParseTreeSymbolTerminalForStep)
+                    .then(|| self.build_expr(&rest[7]));
+
+                let body = self.build_node(rest.last().unwrap());
+
+                Node {
+                    stmt: Stmt::For {
                         iterator_name,
+                        type_,
                         iterator_begin,
                         iterator_end,
+                        inclusive,
+                        step,
                         body,
                     },
                     children: vec![],
+                    span: Span::default(),
                 }
             }
 
@@ -1215,73 +2910,177 @@ impl Parser {
                 let condition_node = &parse_tree.children[1];
                 let condition = self.build_expr(condition_node);
 
-                let mut stmt_nodes = Vec::new();
-                self.find_statements(&parse_tree.children[2], &mut stmt_nodes);
-                let body: Vec<AbstractSyntaxTreeNode> = stmt_nodes
-                    .into_iter()
-                    .map(|stmt| self.build_ast(stmt))
-                    .collect();
+                let body = self.build_node(&parse_tree.children[2]);
 
                 if parse_tree.children[3].children.is_empty() {
                     // there is no else
-                    AbstractSyntaxTreeNode {
-                        symbol: AbstractSyntaxTreeSymbol::AbstractSyntaxTreeSymbolIf {
+                    Node {
+                        stmt: Stmt::If {
                             condition,
                             body,
                             else_body: None,
                         },
                         children: vec![],
+                        span: Span::default(),
                     }
                 } else if parse_tree.children[3].children[1].symbol
                     == ParseTreeSymbol::ParseTreeSymbolNodeIf
                 {
                     // there is an else if
-                    let else_if = self.build_ast(&parse_tree.children[3].children[1]);
-                    AbstractSyntaxTreeNode {
-                        symbol: AbstractSyntaxTreeSymbol::AbstractSyntaxTreeSymbolIf {
+                    let else_if = self.build_node(&parse_tree.children[3].children[1]);
+                    Node {
+                        stmt: Stmt::If {
                             condition,
                             body,
-                            else_body: Some(Box::new(else_if)),
+                            else_body: Some(else_if),
                         },
                         children: vec![],
+                        span: Span::default(),
                     }
                 } else if parse_tree.children[3].children[1].symbol
                     == ParseTreeSymbol::ParseTreeSymbolNodeBlock
                 {
-                    // there is an else
-                    let mut else_stmts = Vec::new();
-                    self.find_statements(&parse_tree.children[3].children[1], &mut else_stmts);
-                    let else_body = AbstractSyntaxTreeNode {
-                        symbol: AbstractSyntaxTreeSymbol::AbstractSyntaxTreeSymbolBlock {
-                            body: else_stmts.into_iter().map(|s| self.build_ast(s)).collect(),
-                        },
-                        children: vec![],
-                    };
-                    AbstractSyntaxTreeNode {
-                        symbol: AbstractSyntaxTreeSymbol::AbstractSyntaxTreeSymbolIf {
+                    // there is an else, whose block is lowered like any other
+                    let else_body = self.build_node(&parse_tree.children[3].children[1]);
+                    Node {
+                        stmt: Stmt::If {
                             condition,
                             body,
-                            else_body: Some(Box::new(else_body)),
+                            else_body: Some(else_body),
                         },
                         children: vec![],
+                        span: Span::default(),
                     }
                 } else {
                     panic!("Unexpected parse tree node: {:?}", parse_tree.symbol);
                 }
             }
 
-            ParseTreeSymbol::ParseTreeSymbolNodeBlock => {
-                let mut stmt_nodes = Vec::new();
-                self.find_statements(parse_tree, &mut stmt_nodes);
+            ParseTreeSymbol::ParseTreeSymbolNodeWhile => {
+                // [while, expression, block]
+                let condition = self.build_expr(&parse_tree.children[1]);
+
+                let body = self.build_node(&parse_tree.children[2]);
+
+                Node {
+                    stmt: Stmt::While { condition, body },
+                    children: vec![],
+                    span: Span::default(),
+                }
+            }
+
+            ParseTreeSymbol::ParseTreeSymbolNodeDoWhile => {
+                // [do, block, while, expression, semicolon]
+                let body = self.build_node(&parse_tree.children[1]);
+                let condition = self.build_expr(&parse_tree.children[3]);
+
+                Node {
+                    stmt: Stmt::DoWhile { body, condition },
+                    children: vec![],
+                    span: Span::default(),
+                }
+            }
+
+            ParseTreeSymbol::ParseTreeSymbolNodeMatch => {
+                // [match, expression, {, arm*, }]
+                let scrutinee = self.build_expr(&parse_tree.children[1]);
+
+                let mut arms = Vec::new();
+                let mut else_body = None;
+                for arm in &parse_tree.children[3..parse_tree.children.len() - 1] {
+                    // [pattern, =>, block]
+                    let body = self.build_node(&arm.children[2]);
+
+                    let pattern = &arm.children[0];
+                    if pattern.symbol == ParseTreeSymbol::ParseTreeSymbolTerminalElse {
+                        if else_body.is_some() {
+                            self.error(format!(
+                                "ParseError: match has more than one else arm (second at {}:{})",
+                                pattern.span.line, pattern.span.column
+                            ));
+                        }
+                        else_body = Some(body);
+                    } else {
+                        let text = pattern.value.map_or("0".into(), Name::text);
+                        if let ExprKind::Int(value) = self.build_int_literal(&text).kind {
+                            arms.push((value, body));
+                        }
+                    }
+                }
+
+                Node {
+                    stmt: Stmt::Match {
+                        scrutinee,
+                        arms,
+                        else_body,
+                    },
+                    children: vec![],
+                    span: Span::default(),
+                }
+            }
 
-                let body: Vec<AbstractSyntaxTreeNode> = stmt_nodes
-                    .into_iter()
-                    .map(|stmt| self.build_ast(stmt))
+            ParseTreeSymbol::ParseTreeSymbolNodeFunction => {
+                // Children, after an optional "extern" or "export":
+                // [1] = identifier
+                // [3] = parameters
+                // [6] = return type
+                // [7] = block, or
+                // [8] = body expression after "=", or
+                // [7] = semicolon of an extern function
+                let linkage = match parse_tree.children[0].symbol {
+                    ParseTreeSymbol::ParseTreeSymbolTerminalExtern => Linkage::Extern,
+                    ParseTreeSymbol::ParseTreeSymbolTerminalExport => Linkage::Export,
+                    _ => Linkage::Internal,
+                };
+                let children = match linkage {
+                    Linkage::Internal => &parse_tree.children[..],
+                    _ => &parse_tree.children[1..],
+                };
+                let name = children[1].value.unwrap();
+                let params = children[3]
+                    .children
+                    .iter()
+                    .filter(|c| c.symbol == ParseTreeSymbol::ParseTreeSymbolNodeParameter)
+                    .map(|param| self.build_parameter(param))
                     .collect();
+                let return_type = self.match_type_in_scope(&children[6]);
+                let body = if linkage == Linkage::Extern {
+                    Vec::new()
+                } else if children[7].symbol == ParseTreeSymbol::ParseTreeSymbolNodeBlock {
+                    self.build_block(&children[7])
+                } else {
+                    let value = Some(self.build_expr(&children[8]));
+                    vec![self.nodes.alloc(Node {
+                        stmt: Stmt::Return(value),
+                        children: vec![],
+                        span: Span::default(),
+                    })]
+                };
+                let body = self.nodes.alloc(Node {
+                    stmt: Stmt::Block { body },
+                    children: vec![],
+                    span: Span::default(),
+                });
 
-                AbstractSyntaxTreeNode {
-                    symbol: AbstractSyntaxTreeSymbol::AbstractSyntaxTreeSymbolBlock { body },
+                Node {
+                    stmt: Stmt::FunctionDeclaration {
+                        name,
+                        params,
+                        return_type,
+                        body,
+                        linkage,
+                    },
+                    children: vec![],
+                    span: Span::default(),
+                }
+            }
+
+            ParseTreeSymbol::ParseTreeSymbolNodeBlock => {
+                let body = self.build_block(parse_tree);
+                Node {
+                    stmt: Stmt::Block { body },
                     children: vec![],
+                    span: Span::default(),
                 }
             }
 
@@ -1301,189 +3100,161 @@ impl Parser {
             return self.build_expr(&node.children[1]);
         }
 
-        // Simple literal / identifier
+        // Simple literal / identifier / call
         let child = node.children.first().unwrap();
         match child.symbol {
+            ParseTreeSymbol::ParseTreeSymbolNodeCall => {
+                // children: Ident, "(", Arguments, ")"
+                let name = child.children[0].value.unwrap();
+                let args = child.children[2]
+                    .children
+                    .iter()
+                    .filter(|c| c.symbol == ParseTreeSymbol::ParseTreeSymbolNodeExpression)
+                    .map(|arg| self.build_expr(arg))
+                    .collect();
+                ExprKind::Call { name, args }.into()
+            }
             ParseTreeSymbol::ParseTreeSymbolTerminalIntegerLiteral => {
-                let value = child.value.as_ref().unwrap().parse::<i32>().unwrap();
-                Expr::Int(value)
+                self.build_int_literal(&child.value.unwrap().text())
+            }
+            ParseTreeSymbol::ParseTreeSymbolNodeAddressOf => {
+                // children: "&", Ident
+                ExprKind::AddressOf(child.children[1].value.unwrap()).into()
+            }
+            ParseTreeSymbol::ParseTreeSymbolNodeDereference => {
+                // children: "*", Ident
+                ExprKind::Deref(child.children[1].value.unwrap()).into()
+            }
+            ParseTreeSymbol::ParseTreeSymbolNodeNegate => {
+                // children: "-", Primary; a negated literal folds here so that it is
+                // a constant wherever a plain literal is
+                let operand = &child.children[1];
+                if let [literal] = operand.children.as_slice()
+                    && literal.symbol == ParseTreeSymbol::ParseTreeSymbolTerminalIntegerLiteral
+                {
+                    return self.build_negative_int_literal(&literal.value.unwrap().text());
+                }
+                let value = self.build_primary(operand);
+                match value.kind {
+                    ExprKind::Int(int) if let Some(negated) = int.checked_neg() => {
+                        ExprKind::Int(negated).into()
+                    }
+                    ExprKind::Float(float) => ExprKind::Float(-float).into(),
+                    _ => ExprKind::Negate {
+                        value: Box::new(value),
+                    }
+                    .into(),
+                }
+            }
+            // the expression stands in whole, so it keeps its grouping wherever it is used
+            ParseTreeSymbol::ParseTreeSymbolNodeDefined => self.build_expr(&child.children[0]),
+            ParseTreeSymbol::ParseTreeSymbolNodeConversion => {
+                // children: Type, "(", Expression, ")"
+                ExprKind::Convert {
+                    to: self.match_type_in_scope(&child.children[0]),
+                    value: Box::new(self.build_expr(&child.children[2])),
+                }
+                .into()
+            }
+            ParseTreeSymbol::ParseTreeSymbolNodeConstant => {
+                // the literal holds the value itself, which unlike source literals may be negative
+                let literal = &child.children[0];
+                match literal.symbol {
+                    ParseTreeSymbol::ParseTreeSymbolTerminalIntegerLiteral => {
+                        ExprKind::Int(literal.value.unwrap().text().parse().unwrap()).into()
+                    }
+                    _ => self.build_primary(child),
+                }
             }
             ParseTreeSymbol::ParseTreeSymbolTerminalFloatLiteral => {
-                let value = child.value.as_ref().unwrap().parse::<f32>().unwrap();
-                Expr::Float(value)
+                let text = child.value.unwrap().text();
+                let value = strip_literal_suffix(&text).replace('_', "").parse::<f32>().unwrap();
+                if value.is_infinite() {
+                    self.error(format!(
+                        "ParseError: float literal {} does not fit in f32s",
+                        text
+                    ));
+                }
+                ExprKind::Float(value).into()
             }
             ParseTreeSymbol::ParseTreeSymbolTerminalBooleanLiteral => {
-                let value = child.value.as_ref().unwrap().parse::<bool>().unwrap();
-                Expr::Bool(value)
+                let value = child.value.unwrap().text().parse::<bool>().unwrap();
+                ExprKind::Bool(value).into()
             }
             ParseTreeSymbol::ParseTreeSymbolTerminalIdentifier => {
-                let ident = child.value.as_ref().unwrap().clone();
-                if self.lookup_in_scope(&ident).is_none() {
-                    panic!("Undefined identifier {}", ident);
-                }
-                Expr::Ident(ident)
+                ExprKind::Ident(child.value.unwrap()).into()
             }
             ParseTreeSymbol::ParseTreeSymbolTerminalCharLiteral => {
-                let value = child.value.as_ref().unwrap().chars().next().unwrap();
-                Expr::Char(value)
+                let value = child.value.unwrap().text().chars().next().unwrap();
+                ExprKind::Char(value).into()
             }
             _ => panic!("Unsupported expression type: {:?}", child.symbol),
         }
     }
 
-    fn build_mul(&mut self, node: &ParseTreeNode) -> Expr {
-        let mut expr = self.build_expr(&node.children[0]);
-
-        let mut i = 1;
-        while i < node.children.len() {
-            let op = &node.children[i].symbol;
-            let right = self.build_expr(&node.children[i + 1]);
-
-            expr = match op {
-                ParseTreeSymbol::ParseTreeSymbolTerminalStar => Expr::BinaryOp {
-                    left: Box::new(expr),
-                    op: BinOpType::Multiply,
-                    right: Box::new(right),
-                },
-
-                ParseTreeSymbol::ParseTreeSymbolTerminalSlash => Expr::BinaryOp {
-                    left: Box::new(expr),
-                    op: BinOpType::Divide,
-                    right: Box::new(right),
-                },
-
-                _ => panic!("Unexpected operator in Mul node"),
-            };
-            i += 2;
-        }
-        expr
-    }
-
-    fn build_add(&mut self, node: &ParseTreeNode) -> Expr {
-        let mut expr = self.build_expr(&node.children[0]);
-
-        let mut i = 1;
-        while i < node.children.len() {
-            let op = &node.children[i].symbol;
-            let right = self.build_expr(&node.children[i + 1]);
-
-            expr = match op {
-                ParseTreeSymbol::ParseTreeSymbolTerminalPlus => Expr::BinaryOp {
-                    left: Box::new(expr),
-                    op: BinOpType::Add,
-                    right: Box::new(right),
-                },
-
-                ParseTreeSymbol::ParseTreeSymbolTerminalMinus => Expr::BinaryOp {
-                    left: Box::new(expr),
-                    op: BinOpType::Subtract,
-                    right: Box::new(right),
-                },
-
-                _ => panic!("Unexpected operator in Add node"),
-            };
-            i += 2;
-        }
-        expr
-    }
-
-    fn build_comparison(&mut self, node: &ParseTreeNode) -> Expr {
-        let mut expr = self.build_expr(&node.children[0]);
-
-        let mut i = 1;
-        while i < node.children.len() {
-            let op = &node.children[i].symbol;
-            let right = self.build_expr(&node.children[i + 1]);
-
-            expr = match op {
-                ParseTreeSymbol::ParseTreeSymbolTerminalLessThan => Expr::BinaryOp {
-                    left: Box::new(expr),
-                    op: BinOpType::LessThan,
-                    right: Box::new(right),
-                },
-
-                ParseTreeSymbol::ParseTreeSymbolTerminalLessThanOrEqual => Expr::BinaryOp {
-                    left: Box::new(expr),
-                    op: BinOpType::LessThanOrEqual,
-                    right: Box::new(right),
-                },
-
-                ParseTreeSymbol::ParseTreeSymbolTerminalGreaterThan => Expr::BinaryOp {
-                    left: Box::new(expr),
-                    op: BinOpType::GreaterThan,
-                    right: Box::new(right),
-                },
-
-                ParseTreeSymbol::ParseTreeSymbolTerminalGreaterThanOrEqual => Expr::BinaryOp {
-                    left: Box::new(expr),
-                    op: BinOpType::GreaterThanOrEqual,
-                    right: Box::new(right),
-                },
-
-                _ => panic!("Unexpected operator in Comparison node"),
-            };
-            i += 2;
-        }
-        expr
-    }
-
-    fn build_equality(&mut self, node: &ParseTreeNode) -> Expr {
-        let mut expr = self.build_expr(&node.children[0]);
-
-        let mut i = 1;
-        while i < node.children.len() {
-            let op = &node.children[i].symbol;
-            let right = self.build_expr(&node.children[i + 1]);
-
-            expr = match op {
-                ParseTreeSymbol::ParseTreeSymbolTerminalEqualsEquals => Expr::BinaryOp {
-                    left: Box::new(expr),
-                    op: BinOpType::Equal,
-                    right: Box::new(right),
-                },
-
-                ParseTreeSymbol::ParseTreeSymbolTerminalNotEquals => Expr::BinaryOp {
-                    left: Box::new(expr),
-                    op: BinOpType::NotEqual,
-                    right: Box::new(right),
-                },
-
-                _ => panic!("Unexpected operator in Equality node"),
-            };
-            i += 2;
-        }
-        expr
-    }
-
-    fn push_scope(&mut self) {
-        self.scopes.push(HashMap::new());
+    fn build_int_literal(&mut self, text: &str) -> Expr {
+        self.build_signed_int_literal(text, false)
     }
 
-    fn pop_scope(&mut self) {
-        self.scopes.pop();
+    // -text, range checked as a whole so that the most negative i32s is accepted
+    fn build_negative_int_literal(&mut self, text: &str) -> Expr {
+        self.build_signed_int_literal(text, true)
     }
 
-    fn lookup_in_scope(&self, name: &str) -> Option<&VarEntry> {
-        for scope in self.scopes.iter().rev() {
-            if let Some(v) = scope.get(name) {
-                return Some(v);
+    fn build_signed_int_literal(&mut self, text: &str, negative: bool) -> Expr {
+        let value = match parse_int_literal(strip_literal_suffix(text)) {
+            Ok(value) if negative => -value,
+            Ok(value) => value,
+            Err(e) => {
+                self.error(e);
+                return ExprKind::Int(0).into();
+            }
+        };
+        let text = if negative { format!("-{}", text) } else { text.to_string() };
+        match i32::try_from(value) {
+            Ok(value) => ExprKind::Int(value).into(),
+            Err(_) => {
+                let (min, max) = Type::I32S.integer_range().unwrap();
+                let target = match &self.declaration {
+                    Some((name, Type::I32S)) => format!("{}'s declared type i32s", name),
+                    _ => "i32s".to_string(),
+                };
+                // i32s is the widest integer type, so there is nothing to promote to
+                self.error(format!(
+                    "ParseError: integer literal {} is out of range for {}, which holds {} to {} (no wider integer type exists)",
+                    text, target, min, max
+                ));
+                ExprKind::Int(0).into()
             }
         }
-        None
     }
 
-    fn insert_in_scope(&mut self, name: String, entry: VarEntry) {
-        self.scopes.last_mut().unwrap().insert(name, entry);
+    // [left, operator, right], with the AST operator taken from INFIX_OPERATORS
+    fn build_binary(&mut self, node: &ParseTreeNode) -> Expr {
+        let left = self.build_expr(&node.children[0]);
+        let right = self.build_expr(&node.children[2]);
+        let operator = INFIX_OPERATORS
+            .iter()
+            .find(|o| o.terminal == node.children[1].symbol)
+            .unwrap_or_else(|| panic!("Unexpected operator {:?}", node.children[1].symbol));
+        ExprKind::BinaryOp {
+            left: Box::new(left),
+            op: operator.op.clone(),
+            right: Box::new(right),
+        }
+        .into()
     }
 
-    fn update_in_scope(&mut self, name: &str, value: Expr) -> Result<(), String> {
-        for scope in self.scopes.iter_mut().rev() {
-            if let Some(var) = scope.get_mut(name) {
-                var.var_value = value;
-                return Ok(());
-            }
+    // [ref?, type, identifier]
+    fn build_parameter(&mut self, param: &ParseTreeNode) -> (Name, Type) {
+        let count = param.children.len();
+        let name = param.children[count - 1].value.unwrap();
+        let type_ = self.match_type_in_scope(&param.children[count - 2]);
+        if param.children[0].symbol == ParseTreeSymbol::ParseTreeSymbolTerminalRef {
+            (name, Type::Ref(Box::new(type_)))
+        } else {
+            (name, type_)
         }
-        Err(format!("Undefined variable {}", name))
     }
 
     fn match_type_in_scope(&mut self, node: &ParseTreeNode) -> Type {
@@ -1492,52 +3263,260 @@ impl Parser {
             ParseTreeSymbol::ParseTreeSymbolTerminalF32S => Type::F32S,
             ParseTreeSymbol::ParseTreeSymbolTerminalBool => Type::Bool,
             ParseTreeSymbol::ParseTreeSymbolTerminalChar => Type::Char,
+            ParseTreeSymbol::ParseTreeSymbolTerminalVoid => Type::Void,
             _ => panic!("Unsupported type node"),
         }
     }
 
     fn build_expr(&mut self, node: &ParseTreeNode) -> Expr {
-        let child: &ParseTreeNode;
-        if node.symbol == ParseTreeSymbol::ParseTreeSymbolNodeExpression {
-            child = node.children.first().unwrap();
+        let child: &ParseTreeNode = if node.symbol == ParseTreeSymbol::ParseTreeSymbolNodeExpression {
+            node.children.first().unwrap()
         } else {
-            child = node;
-        }
+            node
+        };
         match child.symbol {
             ParseTreeSymbol::ParseTreeSymbolNodePrimary => self.build_primary(child),
-            ParseTreeSymbol::ParseTreeSymbolNodeMul => self.build_mul(child),
-            ParseTreeSymbol::ParseTreeSymbolNodeAdd => self.build_add(child),
-            ParseTreeSymbol::ParseTreeSymbolNodeComparison => self.build_comparison(child),
-            ParseTreeSymbol::ParseTreeSymbolNodeEquality => self.build_equality(child),
+            ParseTreeSymbol::ParseTreeSymbolNodeMul
+            | ParseTreeSymbol::ParseTreeSymbolNodeAdd
+            | ParseTreeSymbol::ParseTreeSymbolNodeComparison
+            | ParseTreeSymbol::ParseTreeSymbolNodeEquality => self.build_binary(child),
             _ => panic!("Unknown expression node: {:?}", node.symbol),
         }
     }
 
-    fn find_terminal<'a>(&mut self, node: &'a ParseTreeNode) -> &'a ParseTreeNode {
-        match node.symbol {
-            ParseTreeSymbol::ParseTreeSymbolTerminalIntegerLiteral
-            | ParseTreeSymbol::ParseTreeSymbolTerminalFloatLiteral
-            | ParseTreeSymbol::ParseTreeSymbolTerminalBooleanLiteral
-            | ParseTreeSymbol::ParseTreeSymbolTerminalCharLiteral
-            | ParseTreeSymbol::ParseTreeSymbolTerminalIdentifier => return node,
-
-            _ => {}
+    // A statement's nodes. A declaration of several variables becomes a declaration of
+    // each in turn, in the enclosing scope; every other statement is a single node.
+    fn build_statement(&mut self, statement: &ParseTreeNode) -> Vec<NodeId> {
+        let declaration = &statement.children[0];
+        if declaration.symbol != ParseTreeSymbol::ParseTreeSymbolNodeVariableDeclaration {
+            return vec![self.build_node(statement)];
         }
-
-        for child in &node.children {
-            let result = self.find_terminal(child);
-            return result;
+        // Children:
+        // [0] = type
+        // then, separated by ",", each declarator:
+        //   identifier
+        //   "=" and expression  (absent in `i32s x;`)
+        // and last ";"
+        let enclosing = std::mem::replace(&mut self.statement_span, statement.span);
+        let type_ = self.match_type_in_scope(&declaration.children[0]);
+        let declarators = &declaration.children[1..declaration.children.len() - 1];
+        let mut ids = Vec::new();
+        for declarator in
+            declarators.split(|c| c.symbol == ParseTreeSymbol::ParseTreeSymbolTerminalComma)
+        {
+            let name = declarator[0].value.unwrap();
+            let value = declarator.get(2).map(|expr_node| {
+                self.declaration = Some((name, type_.clone()));
+                let value = self.build_expr(expr_node);
+                self.declaration = None;
+                value
+            });
+            ids.push(self.nodes.alloc(Node {
+                stmt: Stmt::VariableDeclaration {
+                    name,
+                    type_: type_.clone(),
+                    value,
+                },
+                children: vec![],
+                span: statement.span,
+            }));
         }
+        self.statement_span = enclosing;
+        ids
+    }
 
-        panic!("No terminal node found in subtree");
+    // The statements of a block, lowered in order. Every body is built here and wrapped
+    // in a Stmt::Block, the one statement that is nothing but a scope: a bare block, and
+    // the body of each loop, branch, match arm and function.
+    fn build_block(&mut self, block: &ParseTreeNode) -> Vec<NodeId> {
+        let mut statements = Vec::new();
+        self.find_statements(block, &mut statements);
+        statements
+            .into_iter()
+            .flat_map(|statement| self.build_statement(statement))
+            .collect()
     }
 
     fn find_statements<'a>(&self, node: &'a ParseTreeNode, out: &mut Vec<&'a ParseTreeNode>) {
+        // a statement's own nested statements belong to it, not to the enclosing body
         if node.symbol == ParseTreeSymbol::ParseTreeSymbolNodeStatement {
             out.push(node);
+            return;
         }
         for child in &node.children {
             self.find_statements(child, out);
         }
     }
 }
+
+// Accepts decimal, 0x hexadecimal, 0o octal and 0b binary digits, with `_` separators
+// Parses the tokens of `text` with `parse` as a fragment. Each token type that could
+// come next is found by putting one of it after the input and parsing again: the parser
+// accepted it if it ran out of input after it rather than stopping on it. What is
+// parsed is the longest run of tokens from the start that parses once closed off.
+fn fragment<T>(
+    text: &str,
+    statements: bool,
+    parse: impl Fn(&mut Parser) -> Result<T, String>,
+) -> Fragment<T> {
+    let Ok(tokens) = Tokenizer::new(text.to_string()).try_tokenize() else {
+        return Fragment {
+            parsed: None,
+            span: Span::default(),
+            expected: Vec::new(),
+            names: Vec::new(),
+        };
+    };
+    let mut expected = Vec::new();
+    let mut names = Vec::new();
+    for &token_type in &TOKEN_TYPES[1..] {
+        let mut probed = tokens.clone();
+        probed.push(after(&tokens, token_type));
+        let (result, parser) = parse_fragment(probed, &parse);
+        if result.is_ok() || parser.is_at_end() {
+            expected.push(token_type);
+        }
+        // a name at the cursor is read in the scopes open there
+        if token_type == TokenType::Ident {
+            names = parser.at_end;
+        }
+    }
+    for count in (1..=tokens.len()).rev() {
+        for closed in close(&tokens[..count], statements) {
+            if let (Ok(parsed), _) = parse_fragment(closed, &parse) {
+                let span = match (tokens.get(1), tokens[count - 1].span) {
+                    (Some(first), last) if count > 1 => Span {
+                        end: last.end,
+                        ..first.span
+                    },
+                    _ => Span::default(),
+                };
+                return Fragment {
+                    parsed: Some(parsed),
+                    span,
+                    expected,
+                    names,
+                };
+            }
+        }
+    }
+    Fragment {
+        parsed: None,
+        span: Span::default(),
+        expected,
+        names,
+    }
+}
+
+// What `parse` gave for the tokens, and the parser as it left it
+fn parse_fragment<T>(
+    tokens: Vec<Token>,
+    parse: &impl Fn(&mut Parser) -> Result<T, String>,
+) -> (Result<T, String>, Parser) {
+    let mut parser = Parser::new(tokens);
+    parser.fragment = true;
+    parser.consume();
+    let result = parse(&mut parser);
+    (result, parser)
+}
+
+// The tokens with each '(' and '{' they leave open closed, innermost first. A statement
+// may also have been cut short before its ';', so for statements the tokens are also
+// given with a ';' before the first '}'.
+fn close(tokens: &[Token], statements: bool) -> Vec<Vec<Token>> {
+    let mut open = Vec::new();
+    for token in tokens {
+        match token.token_type {
+            TokenType::LParen | TokenType::LBrace => open.push(token.token_type),
+            TokenType::RParen | TokenType::RBrace => {
+                open.pop();
+            }
+            _ => {}
+        }
+    }
+    let closers: Vec<TokenType> = open
+        .iter()
+        .rev()
+        .map(|&open| if open == TokenType::LParen { TokenType::RParen } else { TokenType::RBrace })
+        .collect();
+    let mut endings = vec![closers.clone()];
+    if statements {
+        let mut ending = closers.clone();
+        let parentheses = closers.iter().take_while(|&&t| t == TokenType::RParen).count();
+        ending.insert(parentheses, TokenType::Semi);
+        endings.push(ending);
+    }
+    endings
+        .into_iter()
+        .map(|ending| {
+            let mut closed = tokens.to_vec();
+            for token_type in ending {
+                closed.push(after(tokens, token_type));
+            }
+            closed
+        })
+        .collect()
+}
+
+// A token of the type, placed where the tokens end. A name is one no source can spell.
+fn after(tokens: &[Token], token_type: TokenType) -> Token {
+    let last = tokens.last().map(|t| t.span).unwrap_or_default();
+    let value = match token_type {
+        TokenType::Ident => Some("cursor."),
+        TokenType::IntLit => Some("0"),
+        TokenType::FloatLit => Some("0.0"),
+        TokenType::CharLit => Some("a"),
+        TokenType::BoolLit => Some("true"),
+        _ => None,
+    };
+    Token {
+        token_type,
+        value: value.map(Name::intern),
+        span: Span {
+            start: last.end,
+            end: last.end,
+            line: last.line,
+            column: last.column + (last.end - last.start),
+        },
+    }
+}
+
+// The names a parse tree uses, each once, in the order they first appear
+fn identifiers(node: &ParseTreeNode, names: &mut Vec<Name>) {
+    if node.symbol == ParseTreeSymbol::ParseTreeSymbolTerminalIdentifier
+        && let Some(name) = node.value
+        && !names.contains(&name)
+    {
+        names.push(name);
+    }
+    for child in &node.children {
+        identifiers(child, names);
+    }
+}
+
+fn parse_int_literal(text: &str) -> Result<i128, String> {
+    let digits: String = text.chars().filter(|c| *c != '_').collect();
+    let (radix, body, kind) = if let Some(rest) = digits.strip_prefix("0x") {
+        (16, rest, "hexadecimal")
+    } else if let Some(rest) = digits.strip_prefix("0o") {
+        (8, rest, "octal")
+    } else if let Some(rest) = digits.strip_prefix("0b") {
+        (2, rest, "binary")
+    } else {
+        (10, digits.as_str(), "decimal")
+    };
+
+    if body.is_empty() {
+        return Err(format!("ParseError: {} literal {} has no digits", kind, text));
+    }
+    if let Some(bad) = body.chars().find(|c| !c.is_digit(radix)) {
+        return Err(format!(
+            "ParseError: invalid digit '{}' in {} literal {}",
+            bad, kind, text
+        ));
+    }
+    // digits are valid at this point, so the only possible failure is overflowing u128
+    Ok(u128::from_str_radix(body, radix)
+        .map(|v| v.min(i128::MAX as u128) as i128)
+        .unwrap_or(i128::MAX))
+}
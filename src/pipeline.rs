@@ -0,0 +1,390 @@
+use crate::asm::{Line, Syntax};
+use crate::ast::Ast;
+use crate::diagnostic::{Diagnostic, Severity};
+use crate::error::CompileError;
+use crate::generate::Generator;
+use crate::lint;
+use crate::optimize;
+use crate::parse::{DEFAULT_MAX_DEPTH, ParseTreeNode, Parser};
+use crate::target::{self, TargetSpec};
+use crate::tokenize::{Token, Tokenizer};
+use crate::trace::{Stage, Stats, Tracer};
+use crate::typecheck::TypeChecker;
+
+type Hook<'a, T> = Box<dyn FnMut(&mut T) + 'a>;
+
+// The whole compilation from source text to assembly text, with a callback after each
+// stage that may inspect or rewrite what the stage produced. The passes of one -O
+// level are optimize::PassManager; this runs them as part of compiling.
+//
+//     let assembly = Pipeline::new()
+//         .opt_level(2)
+//         .on_ast(|ast| println!("{} nodes", ast.node_count()))
+//         .compile(&source)?
+//         .assembly;
+pub struct Pipeline<'a> {
+    file: String,
+    entry: Option<String>,
+    target: Option<&'static TargetSpec>,
+    freestanding: bool,
+    syntax: Syntax,
+    opt_level: u8,
+    asserts: bool,
+    checked_arithmetic: bool,
+    strict_numerics: bool,
+    max_nesting: usize,
+    lints: lint::Levels,
+    print_passes: bool,
+    stats: Option<&'a mut Stats>,
+    tracer: Option<&'a Tracer>,
+    on_tokens: Option<Hook<'a, Vec<Token>>>,
+    on_parse_tree: Option<Hook<'a, ParseTreeNode>>,
+    on_parsed_ast: Option<Hook<'a, Ast>>,
+    on_warnings: Option<Hook<'a, Vec<Diagnostic>>>,
+    on_ast: Option<Hook<'a, Ast>>,
+    on_ir: Option<Hook<'a, Vec<Line>>>,
+    on_asm: Option<Hook<'a, String>>,
+}
+
+// What a successful compilation produced
+pub struct Output {
+    pub assembly: String,
+    pub warnings: Vec<Diagnostic>,
+}
+
+impl Default for Pipeline<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> Pipeline<'a> {
+    // The defaults of the command line: a Windows build at -O0 with asserts, in
+    // Intel syntax
+    pub fn new() -> Self {
+        Self {
+            file: "<input>".to_string(),
+            entry: None,
+            target: None,
+            freestanding: false,
+            syntax: Syntax::Intel,
+            opt_level: 0,
+            asserts: true,
+            checked_arithmetic: false,
+            strict_numerics: false,
+            max_nesting: DEFAULT_MAX_DEPTH,
+            lints: lint::Levels::new(),
+            print_passes: false,
+            stats: None,
+            tracer: None,
+            on_tokens: None,
+            on_parse_tree: None,
+            on_parsed_ast: None,
+            on_warnings: None,
+            on_ast: None,
+            on_ir: None,
+            on_asm: None,
+        }
+    }
+
+    // The name failed asserts and checks report the program as
+    pub fn file(mut self, file: &str) -> Self {
+        self.file = file.to_string();
+        self
+    }
+
+    // The entry symbol, by default the target's: _start on Linux, mainCRTStartup on Windows
+    pub fn entry(mut self, entry: &str) -> Self {
+        self.entry = Some(entry.to_string());
+        self
+    }
+
+    // x86_64-windows unless set, or x86_64-linux with freestanding(true)
+    pub fn target(mut self, target: &'static TargetSpec) -> Self {
+        self.target = Some(target);
+        self
+    }
+
+    // Requires a freestanding target, x86_64-linux unless target() names another. With
+    // a target that builds on the C runtime, compiling fails whatever the call order.
+    pub fn freestanding(mut self, freestanding: bool) -> Self {
+        self.freestanding = freestanding;
+        self
+    }
+
+    pub fn syntax(mut self, syntax: Syntax) -> Self {
+        self.syntax = syntax;
+        self
+    }
+
+    pub fn opt_level(mut self, level: u8) -> Self {
+        self.opt_level = level;
+        self
+    }
+
+    pub fn asserts(mut self, enabled: bool) -> Self {
+        self.asserts = enabled;
+        self
+    }
+
+    pub fn checked_arithmetic(mut self, enabled: bool) -> Self {
+        self.checked_arithmetic = enabled;
+        self
+    }
+
+    pub fn strict_numerics(mut self, strict: bool) -> Self {
+        self.strict_numerics = strict;
+        self
+    }
+
+    pub fn max_nesting(mut self, depth: usize) -> Self {
+        self.max_nesting = depth;
+        self
+    }
+
+    pub fn lints(mut self, levels: lint::Levels) -> Self {
+        self.lints = levels;
+        self
+    }
+
+    // Reports the time and instruction counts of each pass of the -O level on stderr
+    pub fn print_passes(mut self, enabled: bool) -> Self {
+        self.print_passes = enabled;
+        self
+    }
+
+    // Where the time spent in each stage and the size of what it produced are added up
+    pub fn stats(mut self, stats: &'a mut Stats) -> Self {
+        self.stats = Some(stats);
+        self
+    }
+
+    // Where each stage reports its progress
+    pub fn tracer(mut self, tracer: &'a Tracer) -> Self {
+        self.tracer = Some(tracer);
+        self
+    }
+
+    // Called with the token stream, before parsing
+    pub fn on_tokens(mut self, hook: impl FnMut(&mut Vec<Token>) + 'a) -> Self {
+        self.on_tokens = Some(Box::new(hook));
+        self
+    }
+
+    // Called with the concrete parse tree, before it is lowered. Without this hook each
+    // statement's tree is dropped as soon as it is lowered, so the whole tree is only
+    // kept when asked for.
+    pub fn on_parse_tree(mut self, hook: impl FnMut(&mut ParseTreeNode) + 'a) -> Self {
+        self.on_parse_tree = Some(Box::new(hook));
+        self
+    }
+
+    // Called with the AST as the parser lowered it, before type checking
+    pub fn on_parsed_ast(mut self, hook: impl FnMut(&mut Ast) + 'a) -> Self {
+        self.on_parsed_ast = Some(Box::new(hook));
+        self
+    }
+
+    // Called with the warnings of the type checker, then with those of the lints, each
+    // before the errors of the same check stop the pipeline
+    pub fn on_warnings(mut self, hook: impl FnMut(&mut Vec<Diagnostic>) + 'a) -> Self {
+        self.on_warnings = Some(Box::new(hook));
+        self
+    }
+
+    // Called with the type-checked AST, before the AST passes of the -O level. Code is
+    // generated from the types the checker put on each expression, so any expression
+    // the hook adds needs one (Expr::typed).
+    pub fn on_ast(mut self, hook: impl FnMut(&mut Ast) + 'a) -> Self {
+        self.on_ast = Some(Box::new(hook));
+        self
+    }
+
+    // Called with the generated instructions, before the assembly passes of the -O level
+    pub fn on_ir(mut self, hook: impl FnMut(&mut Vec<Line>) + 'a) -> Self {
+        self.on_ir = Some(Box::new(hook));
+        self
+    }
+
+    // Called with the finished assembly text
+    pub fn on_asm(mut self, hook: impl FnMut(&mut String) + 'a) -> Self {
+        self.on_asm = Some(Box::new(hook));
+        self
+    }
+
+    // Errors stop the pipeline at the stage that found them, like the command line
+    pub fn compile(&mut self, source: &str) -> Result<Output, CompileError> {
+        let (ast, warnings) = self.front_end(source)?;
+        let generator = self.generate(source, &ast)?;
+
+        let (mut bytes, syntax) = (Vec::new(), self.syntax);
+        self.time(Stage::Write, || generator.write(&mut bytes, syntax))
+            .map_err(CompileError::Codegen)?;
+        self.count(Stage::Write, bytes.len(), "bytes");
+        let mut assembly = String::from_utf8_lossy(&bytes).into_owned();
+        if let Some(hook) = &mut self.on_asm {
+            hook(&mut assembly);
+        }
+        Ok(Output { assembly, warnings })
+    }
+
+    // The front end alone: tokenize, parse, type-check and lint, for commands that run
+    // the program in the interpreter or only check it. Warnings reach on_warnings.
+    pub fn check(&mut self, source: &str) -> Result<Ast, CompileError> {
+        self.front_end(source).map(|(ast, _)| ast)
+    }
+
+    // The back end alone, from an AST check() accepted: the passes of the -O level
+    // around code generation, leaving the generator for the caller to write out
+    pub fn generate(&mut self, source: &str, ast: &Ast) -> Result<Generator, CompileError> {
+        let target = self.resolved_target()?;
+        target.check_generator()?;
+        self.trace(
+            Stage::Optimize,
+            &format!("optimization level {}", self.opt_level),
+        );
+        let mut passes = optimize::PassManager::new(self.opt_level);
+        let optimized;
+        let ast = if passes.has_ast_passes() {
+            let mut ast = ast.clone();
+            self.time(Stage::Optimize, || passes.run_ast(&mut ast));
+            optimized = ast;
+            &optimized
+        } else {
+            ast
+        };
+        let entry = self.entry.as_deref().unwrap_or(target.entry);
+        let mut generator = Generator::new(&self.file, source, entry, target);
+        generator.set_asserts(self.asserts);
+        generator.set_checked_arithmetic(self.checked_arithmetic);
+        self.time(Stage::Generate, || {
+            generator.generate_boilerplate();
+            generator.generate_x64(ast);
+        });
+        if let Some(hook) = &mut self.on_ir {
+            hook(generator.lines_mut());
+        }
+        self.time(Stage::Optimize, || generator.optimize(&mut passes));
+        if self.print_passes {
+            passes.report();
+        }
+        self.count(
+            Stage::Generate,
+            generator.instruction_count(),
+            "instructions",
+        );
+        Ok(generator)
+    }
+
+    fn front_end(&mut self, source: &str) -> Result<(Ast, Vec<Diagnostic>), CompileError> {
+        let target = self.resolved_target()?;
+        let mut tokenizer = Tokenizer::new(source.to_string());
+        let mut tokens = self
+            .time(Stage::Tokenize, || tokenizer.try_tokenize())
+            .map_err(CompileError::Tokenize)?;
+        self.count(Stage::Tokenize, tokens.len(), "tokens");
+        self.trace(Stage::Tokenize, &format!("{} tokens", tokens.len()));
+        if let Some(hook) = &mut self.on_tokens {
+            hook(&mut tokens);
+        }
+
+        let mut parser = Parser::new(tokens);
+        parser.set_max_depth(self.max_nesting);
+        let mut ast = match self.on_parse_tree.take() {
+            Some(mut hook) => {
+                let mut tree = self.time(Stage::Parse, || parser.parse());
+                let parsed = parser.errors().is_empty();
+                if parsed {
+                    hook(&mut tree);
+                }
+                self.on_parse_tree = Some(hook);
+                if !parsed {
+                    return Err(CompileError::Parse(parser.errors().to_vec()));
+                }
+                self.time(Stage::Parse, || parser.build_ast(&tree))
+            }
+            None => self.time(Stage::Parse, || parser.parse_to_ast()),
+        };
+        self.count(Stage::Parse, ast.node_count(), "AST nodes");
+        let statements = ast.root().children.len();
+        self.trace(
+            Stage::Parse,
+            &format!("{} top-level statements", statements),
+        );
+        if !parser.errors().is_empty() {
+            return Err(CompileError::Parse(parser.errors().to_vec()));
+        }
+        if let Some(hook) = &mut self.on_parsed_ast {
+            hook(&mut ast);
+        }
+
+        // the checker also records the type of every expression on the tree for codegen
+        let mut checker = TypeChecker::new();
+        checker.set_strict_numerics(self.strict_numerics);
+        checker.set_target(target);
+        let diagnostics = self.time(Stage::TypeCheck, || checker.check(&mut ast));
+        let (errors, mut warnings): (Vec<_>, Vec<_>) = diagnostics
+            .into_iter()
+            .partition(|d| d.severity == Severity::Error);
+        self.trace(Stage::TypeCheck, &format!("{} errors", errors.len()));
+        self.warn(&mut warnings);
+        if !errors.is_empty() {
+            return Err(CompileError::Type(errors));
+        }
+        let (errors, mut lint_warnings): (Vec<_>, Vec<_>) = lint::run(&ast, source, &self.lints)
+            .into_iter()
+            .partition(|d| d.severity == Severity::Error);
+        self.warn(&mut lint_warnings);
+        if !errors.is_empty() {
+            return Err(CompileError::Lint(errors));
+        }
+        warnings.extend(lint_warnings);
+        if let Some(hook) = &mut self.on_ast {
+            hook(&mut ast);
+        }
+        Ok((ast, warnings))
+    }
+
+    // The rule of --freestanding and --target on the command line
+    fn resolved_target(&self) -> Result<&'static TargetSpec, CompileError> {
+        match self.target {
+            Some(target) if self.freestanding && !target.freestanding => {
+                Err(CompileError::Target(format!(
+                    "freestanding(true) conflicts with the target {}, which builds on the C \
+                     runtime",
+                    target.name
+                )))
+            }
+            Some(target) => Ok(target),
+            None if self.freestanding => Ok(&target::X86_64_LINUX),
+            None => Ok(&target::X86_64_WINDOWS),
+        }
+    }
+
+    fn warn(&mut self, warnings: &mut Vec<Diagnostic>) {
+        if let Some(hook) = &mut self.on_warnings
+            && !warnings.is_empty()
+        {
+            hook(warnings);
+        }
+    }
+
+    fn time<T>(&mut self, stage: Stage, f: impl FnOnce() -> T) -> T {
+        match &mut self.stats {
+            Some(stats) => stats.time(stage, f),
+            None => f(),
+        }
+    }
+
+    fn count(&mut self, stage: Stage, count: usize, unit: &'static str) {
+        if let Some(stats) = &mut self.stats {
+            stats.count(stage, count, unit);
+        }
+    }
+
+    fn trace(&self, stage: Stage, message: &str) {
+        if let Some(tracer) = self.tracer {
+            tracer.trace(stage, message);
+        }
+    }
+}
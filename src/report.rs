@@ -0,0 +1,164 @@
+use crate::diagnostic::{Diagnostic, Severity};
+use crate::json::Json;
+
+const RESET: &str = "\x1b[0m";
+const RED: &str = "\x1b[1;31m";
+const YELLOW: &str = "\x1b[1;33m";
+const BLUE: &str = "\x1b[1;34m";
+const UNDERLINE: &str = "\x1b[4m";
+
+// Renders diagnostics for a terminal: the message, then the source line it
+// points at with the offending span marked. Colors are ANSI escapes and are
+// left out entirely when `color` is false.
+pub struct Reporter<'a> {
+    file: &'a str,
+    source: &'a str,
+    color: bool,
+}
+
+impl<'a> Reporter<'a> {
+    pub fn new(file: &'a str, source: &'a str, color: bool) -> Self {
+        Self {
+            file,
+            // a byte order mark takes no column, so it is not shown either
+            source: source.strip_prefix('\u{FEFF}').unwrap_or(source),
+            color,
+        }
+    }
+
+    pub fn render(&self, diagnostic: &Diagnostic) -> String {
+        let style = match diagnostic.severity {
+            Severity::Error => RED,
+            Severity::Warning => YELLOW,
+            Severity::Note => BLUE,
+        };
+        let mut out = self.paint(style, &diagnostic.message);
+
+        // spans without a line were never tied to the source
+        let span = diagnostic.span;
+        let line = span
+            .line
+            .checked_sub(1)
+            .and_then(|index| self.source.lines().nth(index));
+        let gutter = match line {
+            Some(_) => " ".repeat(span.line.to_string().len()),
+            None => String::new(),
+        };
+        if let Some(line) = line {
+            out.push_str(&self.snippet(line, diagnostic, style, &gutter));
+        }
+        for note in &diagnostic.children {
+            out.push_str(&format!(
+                "\n{} {} note: {}",
+                gutter,
+                self.paint(BLUE, "="),
+                note.message
+            ));
+        }
+        out
+    }
+
+    fn snippet(&self, line: &str, diagnostic: &Diagnostic, style: &str, gutter: &str) -> String {
+        let span = diagnostic.span;
+        let number = span.line.to_string();
+        let mut out = String::new();
+        let chars: Vec<char> = line.chars().collect();
+        // spans may run over several lines; only the first one is marked
+        let start = span.column.saturating_sub(1).min(chars.len());
+        let end = (start + span.end.saturating_sub(span.start)).min(chars.len());
+        let marked: String = chars[start..end].iter().collect();
+        let before: String = chars[..start].iter().collect();
+        let after: String = chars[end..].iter().collect();
+
+        out.push_str(&format!(
+            "\n{}{} {}:{}:{}",
+            gutter,
+            self.paint(BLUE, "-->"),
+            self.file,
+            span.line,
+            span.column
+        ));
+        out.push_str(&format!("\n{} {}", gutter, self.paint(BLUE, "|")));
+        out.push_str(&format!(
+            "\n{} {}{}{}",
+            self.paint(BLUE, &format!("{} |", number)),
+            before,
+            self.paint(UNDERLINE, &marked),
+            after
+        ));
+        out.push_str(&format!(
+            "\n{} {} {}{}",
+            gutter,
+            self.paint(BLUE, "|"),
+            " ".repeat(start),
+            self.paint(style, &"^".repeat((end - start).max(1)))
+        ));
+        out
+    }
+
+    fn paint(&self, style: &str, text: &str) -> String {
+        if self.color && !text.is_empty() {
+            format!("{}{}{}", style, text, RESET)
+        } else {
+            text.to_string()
+        }
+    }
+}
+
+// One diagnostic on one line, file:line:column: message, for --error-format short
+pub fn to_line(diagnostic: &Diagnostic, file: &str) -> String {
+    let span = diagnostic.span;
+    if span.line > 0 {
+        format!(
+            "{}:{}:{}: {}",
+            file, span.line, span.column, diagnostic.message
+        )
+    } else {
+        format!("{}: {}", file, diagnostic.message)
+    }
+}
+
+// One diagnostic as a self-contained JSON object, for --error-format json
+pub fn to_json(diagnostic: &Diagnostic, file: &str) -> Json {
+    let (code, text) = diagnostic.code_and_text();
+    let span = diagnostic.span;
+    Json::object(vec![
+        ("code", Json::String(code)),
+        ("message", Json::String(text.to_string())),
+        ("file", Json::String(file.to_string())),
+        (
+            "span",
+            if span.line > 0 {
+                Json::object(vec![
+                    ("line", Json::Number(span.line as f64)),
+                    ("column", Json::Number(span.column as f64)),
+                    ("start", Json::Number(span.start as f64)),
+                    ("end", Json::Number(span.end as f64)),
+                ])
+            } else {
+                Json::Null
+            },
+        ),
+        (
+            "severity",
+            Json::String(
+                match diagnostic.severity {
+                    Severity::Error => "error",
+                    Severity::Warning => "warning",
+                    Severity::Note => "note",
+                }
+                .to_string(),
+            ),
+        ),
+        (
+            "children",
+            Json::Array(
+                diagnostic
+                    .children
+                    .iter()
+                    .map(|child| to_json(child, file))
+                    .collect(),
+            ),
+        ),
+    ])
+}
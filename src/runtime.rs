@@ -0,0 +1,281 @@
+use crate::asm::{self, Line};
+use crate::target::TargetSpec;
+
+// Support routines for the I/O builtins and the runtime checks, assembled once as their
+// own module and linked with every program rather than copied into each output file.
+// The I/O routines take and return their value in eax. Hosted builds go through the
+// C runtime (Windows x64 calling convention); freestanding builds talk to the Linux
+// kernel directly.
+
+// every routine, label and string of the runtime module starts with this
+pub const PREFIX: &str = "noble_";
+
+pub const PRINT_INT: &str = "noble_print_int";
+pub const READ_INT: &str = "noble_read_int";
+pub const EXIT: &str = "noble_exit";
+pub const ASSERT_FAIL: &str = "noble_assert_fail";
+pub const OVERFLOW_FAIL: &str = "noble_overflow_fail";
+pub const DIVISION_BY_ZERO_FAIL: &str = "noble_division_by_zero_fail";
+
+// Handler and exit status of each runtime check. A failed assert exits like C's abort(),
+// a zero divisor like the SIGFPE an unchecked idiv dies of, and overflow between the two.
+pub const FAILURE_HANDLERS: [(&str, i32); 3] = [
+    (ASSERT_FAIL, 134),
+    (OVERFLOW_FAIL, 135),
+    (DIVISION_BY_ZERO_FAIL, 136),
+];
+
+// C functions the hosted routines depend on
+const HOSTED_EXTERNS: [&str; 4] = ["printf", "fflush", "scanf", "exit"];
+
+const PRINT_INT_FORMAT: &str = "noble_print_int_format";
+const READ_INT_FORMAT: &str = "noble_read_int_format";
+const FAILURE_FORMAT: &str = "noble_failure_format";
+
+const HOSTED_PRINT_INT: &[&str] = &[
+    "noble_print_int:",
+    "push rbp",
+    "mov rbp, rsp",
+    // 16-byte alignment plus the 32-byte shadow space the callee may use
+    "and rsp, -16",
+    "sub rsp, 32",
+    "lea rcx, [noble_print_int_format]",
+    "mov edx, eax",
+    "call printf",
+    "xor ecx, ecx",
+    "call fflush",
+    "mov rsp, rbp",
+    "pop rbp",
+    "ret",
+];
+
+const HOSTED_READ_INT: &[&str] = &[
+    "noble_read_int:",
+    "push rbp",
+    "mov rbp, rsp",
+    "and rsp, -16",
+    "sub rsp, 48",
+    "mov dword [rbp-4], 0",
+    "lea rcx, [noble_read_int_format]",
+    "lea rdx, [rbp-4]",
+    "call scanf",
+    "mov eax, dword [rbp-4]",
+    "mov rsp, rbp",
+    "pop rbp",
+    "ret",
+];
+
+const FREESTANDING_PRINT_INT: &[&str] = &[
+    "noble_print_int:",
+    "push rbp",
+    "mov rbp, rsp",
+    "sub rsp, 32",
+    // digits are written backwards from the end of the buffer, newline last
+    "lea rsi, [rbp-1]",
+    "mov byte [rsi], 10",
+    "mov r8d, eax",
+    "mov ecx, 10",
+    "test eax, eax",
+    "jns noble_print_int_digits",
+    "neg eax",
+    "noble_print_int_digits:",
+    "xor edx, edx",
+    "div ecx",
+    "add dl, 48",
+    "dec rsi",
+    "mov byte [rsi], dl",
+    "test eax, eax",
+    "jnz noble_print_int_digits",
+    "test r8d, r8d",
+    "jns noble_print_int_write",
+    "dec rsi",
+    "mov byte [rsi], 45",
+    "noble_print_int_write:",
+    "mov eax, 1",
+    "mov edi, 1",
+    "lea rdx, [rbp]",
+    "sub rdx, rsi",
+    "syscall",
+    "mov rsp, rbp",
+    "pop rbp",
+    "ret",
+];
+
+const FREESTANDING_READ_INT: &[&str] = &[
+    "noble_read_int:",
+    "push rbp",
+    "mov rbp, rsp",
+    "sub rsp, 16",
+    // [rbp-4] value, [rbp-8] negative, [rbp-12] digit seen, [rbp-16] input byte
+    "mov dword [rbp-4], 0",
+    "mov dword [rbp-8], 0",
+    "mov dword [rbp-12], 0",
+    "noble_read_int_next:",
+    "xor eax, eax",
+    "xor edi, edi",
+    "lea rsi, [rbp-16]",
+    "mov edx, 1",
+    "syscall",
+    "cmp rax, 1",
+    "jne noble_read_int_done",
+    "movzx eax, byte [rbp-16]",
+    "cmp eax, 45",
+    "je noble_read_int_minus",
+    "sub eax, 48",
+    "cmp eax, 9",
+    "ja noble_read_int_other",
+    "mov ecx, dword [rbp-4]",
+    "imul ecx, ecx, 10",
+    "add ecx, eax",
+    "mov dword [rbp-4], ecx",
+    "mov dword [rbp-12], 1",
+    "jmp noble_read_int_next",
+    "noble_read_int_minus:",
+    "mov dword [rbp-8], 1",
+    "jmp noble_read_int_next",
+    // anything else is skipped before the number and ends it afterwards
+    "noble_read_int_other:",
+    "cmp dword [rbp-12], 0",
+    "je noble_read_int_next",
+    "noble_read_int_done:",
+    "mov eax, dword [rbp-4]",
+    "cmp dword [rbp-8], 0",
+    "je noble_read_int_return",
+    "neg eax",
+    "noble_read_int_return:",
+    "mov rsp, rbp",
+    "pop rbp",
+    "ret",
+];
+
+// Ends the process with the status in eax. Every exit statement gets here, at whatever
+// depth of loops and calls, so nothing is returned to and the stack is realigned as is.
+const HOSTED_EXIT: &[&str] = &[
+    "noble_exit:",
+    "and rsp, -16",
+    "sub rsp, 32",
+    "mov ecx, eax",
+    "call exit",
+];
+
+const FREESTANDING_EXIT: &[&str] = &[
+    "noble_exit:",
+    // Linux exit(status) keeps only the low 8 bits
+    "movzx edi, al",
+    "mov eax, 60",
+    "syscall",
+];
+
+// The whole runtime module for one target: every routine, exported under the names the
+// generator calls, with the exit sequence of the target's operating system
+pub fn module(target: &TargetSpec) -> Vec<Line> {
+    let freestanding = target.freestanding;
+    let mut lines = Vec::new();
+    if !freestanding {
+        for name in HOSTED_EXTERNS {
+            lines.push(Line::Extern(name.to_string()));
+        }
+    }
+    lines.push(Line::Section(".text".to_string()));
+    let routines = [PRINT_INT, READ_INT, EXIT].into_iter();
+    for name in routines.chain(FAILURE_HANDLERS.iter().map(|(name, _)| *name)) {
+        lines.push(Line::Global(name.to_string()));
+    }
+
+    lines.extend(print_int(freestanding));
+    lines.extend(read_int(freestanding));
+    lines.extend(exit(freestanding));
+    for (handler, status) in FAILURE_HANDLERS {
+        lines.extend(failure_handler(handler, status, freestanding));
+    }
+    if !freestanding {
+        lines.push(Line::Blank);
+        lines.push(Line::Section(".data".to_string()));
+        lines.extend(format_strings());
+    }
+    lines
+}
+
+fn print_int(freestanding: bool) -> Vec<Line> {
+    let source = if freestanding {
+        FREESTANDING_PRINT_INT
+    } else {
+        HOSTED_PRINT_INT
+    };
+    routine(source)
+}
+
+fn exit(freestanding: bool) -> Vec<Line> {
+    let source = if freestanding {
+        FREESTANDING_EXIT
+    } else {
+        HOSTED_EXIT
+    };
+    routine(source)
+}
+
+fn read_int(freestanding: bool) -> Vec<Line> {
+    let source = if freestanding {
+        FREESTANDING_READ_INT
+    } else {
+        HOSTED_READ_INT
+    };
+    routine(source)
+}
+
+// Prints the message of a failed runtime check and exits with `status`; never returns.
+// Hosted builds pass the message in rcx, freestanding ones in rsi with its length in edx.
+fn failure_handler(name: &str, status: i32, freestanding: bool) -> Vec<Line> {
+    let source = if freestanding {
+        vec![
+            format!("{}:", name),
+            // write(2, message, length)
+            "mov eax, 1".to_string(),
+            "mov edi, 2".to_string(),
+            "syscall".to_string(),
+            format!("mov edi, {}", status),
+            "mov eax, 60".to_string(),
+            "syscall".to_string(),
+        ]
+    } else {
+        vec![
+            format!("{}:", name),
+            "and rsp, -16".to_string(),
+            "sub rsp, 32".to_string(),
+            "mov rdx, rcx".to_string(),
+            format!("lea rcx, [{}]", FAILURE_FORMAT),
+            "call printf".to_string(),
+            "xor ecx, ecx".to_string(),
+            "call fflush".to_string(),
+            format!("mov ecx, {}", status),
+            "call exit".to_string(),
+        ]
+    };
+    routine(&source)
+}
+
+fn format_strings() -> Vec<Line> {
+    [
+        (PRINT_INT_FORMAT, "%d\n"),
+        (READ_INT_FORMAT, "%d"),
+        (FAILURE_FORMAT, "%s"),
+    ]
+    .into_iter()
+    .map(|(name, text)| Line::Asciz {
+        name: name.to_string(),
+        text: text.to_string(),
+    })
+    .collect()
+}
+
+fn routine(source: &[impl AsRef<str>]) -> Vec<Line> {
+    let mut lines = vec![Line::Blank];
+    for line in source {
+        let line = line.as_ref();
+        match line.strip_suffix(':') {
+            Some(label) => lines.push(Line::Label(label.to_string())),
+            None => lines.push(asm::parse_instruction(line)),
+        }
+    }
+    lines
+}
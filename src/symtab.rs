@@ -0,0 +1,117 @@
+use crate::ast::Type;
+use crate::consteval::Value;
+use crate::intern::Name;
+use crate::tokenize::Span;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone)]
+pub enum SymbolKind {
+    Variable,
+    // type_ of a function symbol is its return type
+    Function { params: Vec<Type> },
+    // uses are replaced by the value while parsing
+    Constant { value: Value },
+    // uses are replaced by a copy of the expression while parsing
+    Define,
+}
+
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    pub name: Name,
+    pub kind: SymbolKind,
+    pub type_: Type,
+    pub span: Span,
+    pub mutable: bool,
+}
+
+// Functions provided by the compiler rather than declared in source: (name, params, return type)
+pub const BUILTIN_FUNCTIONS: [(&str, &[Type], Type); 1] = [("read_int", &[], Type::I32S)];
+
+// Stack of lexical scopes, innermost last. The global scope is never popped.
+pub struct SymbolTable {
+    scopes: Vec<HashMap<Name, Symbol>>,
+}
+
+impl Default for SymbolTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        let mut globals = HashMap::new();
+        for (name, params, return_type) in BUILTIN_FUNCTIONS {
+            globals.insert(
+                Name::intern(name),
+                Symbol {
+                    name: Name::intern(name),
+                    kind: SymbolKind::Function {
+                        params: params.to_vec(),
+                    },
+                    type_: return_type,
+                    span: Span::default(),
+                    mutable: false,
+                },
+            );
+        }
+        Self {
+            scopes: vec![globals],
+        }
+    }
+
+    pub fn enter_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    pub fn exit_scope(&mut self) {
+        if self.scopes.len() > 1 {
+            self.scopes.pop();
+        }
+    }
+
+    pub fn declare(&mut self, symbol: Symbol) -> Result<(), String> {
+        let scope = self.scopes.last_mut().unwrap();
+        if let Some(existing) = scope.get(&symbol.name) {
+            if BUILTIN_FUNCTIONS
+                .iter()
+                .any(|(name, _, _)| symbol.name == *name)
+            {
+                return Err(format!(
+                    "ParseError: {} is a builtin function and cannot be redeclared",
+                    symbol.name
+                ));
+            }
+            let first = match existing.kind {
+                SymbolKind::Variable => existing.type_.to_string(),
+                SymbolKind::Function { .. } => "a function".to_string(),
+                SymbolKind::Constant { .. } => "a constant".to_string(),
+                SymbolKind::Define => "a define".to_string(),
+            };
+            return Err(format!(
+                "ParseError: Duplicate variable name in same scope: {} (first declared as {} at {}:{})",
+                symbol.name, first, existing.span.line, existing.span.column
+            ));
+        }
+        scope.insert(symbol.name, symbol);
+        Ok(())
+    }
+
+    // Leaves every scope but the global one, as after a statement that failed to parse
+    pub fn exit_to_global_scope(&mut self) {
+        self.scopes.truncate(1);
+    }
+
+    pub fn resolve(&self, name: Name) -> Option<&Symbol> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(&name))
+    }
+
+    // Every symbol in scope, including the ones an inner declaration shadows
+    pub fn visible(&self) -> impl Iterator<Item = &Symbol> {
+        self.scopes.iter().flat_map(|scope| scope.values())
+    }
+
+    pub fn is_global_scope(&self) -> bool {
+        self.scopes.len() == 1
+    }
+}
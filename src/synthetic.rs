@@ -0,0 +1,47 @@
+// Large, valid Noble programs for measuring compiler throughput. The same count always
+// gives the same text, so timings of two builds of the compiler can be compared.
+
+// A program of `units` top-level constructs, cycling through functions, declarations,
+// loops, conditionals and matches so that every stage has work of each kind
+pub fn program(units: usize) -> String {
+    let mut out = String::from("// synthetic benchmark program\ni32s total = 0;\n");
+    // the most recent function, which the units after it call
+    let mut function = None;
+    for k in 0..units {
+        match k % 5 {
+            0 => {
+                out.push_str(&format!(
+                    "fn f{k}(i32s a, i32s b) -> i32s {{\n    i32s r = a;\n    if a > b {{\n        r = a - b;\n    }} else {{\n        r = b - a;\n    }}\n    return r * 2 + {k};\n}}\n"
+                ));
+                function = Some(k);
+            }
+            1 => {
+                out.push_str(&format!(
+                    "i32s v{k} = ({k} + 7) * 3 - total / 2;\ntotal = total + v{k};\n"
+                ));
+            }
+            2 => {
+                out.push_str(&format!(
+                    "for i in 0 until {} {{\n    total = total + i * 2 - total / 3;\n}}\n",
+                    k % 7 + 1
+                ));
+            }
+            3 => {
+                let call = match function {
+                    Some(f) => format!("f{}(total, {})", f, k),
+                    None => k.to_string(),
+                };
+                out.push_str(&format!(
+                    "if total < {k} {{\n    total = total + {call};\n}} else {{\n    bool small = total < 100;\n    if small {{\n        total = total + 1;\n    }}\n    total = total / 2;\n}}\n"
+                ));
+            }
+            _ => {
+                out.push_str(&format!(
+                    "match total - (total / 4) * 4 {{\n    0 => {{ total = total + {k}; }}\n    1 => {{ total = total - 1; }}\n    2 => {{ total = total * 2; }}\n    else => {{ total = total / 3; }}\n}}\n"
+                ));
+            }
+        }
+    }
+    out.push_str("exit 0;\n");
+    out
+}
@@ -0,0 +1,230 @@
+use crate::error::CompileError;
+
+// Everything that differs between the platforms Noble builds for, looked up by name so
+// that any of them can be chosen on any host
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Arch {
+    X86_64,
+    Aarch64,
+}
+
+// The C calling convention extern and export functions follow
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Abi {
+    // four argument positions shared by integers and floats, with 32 bytes of shadow
+    // space above the return address
+    Win64,
+    // six integer and eight vector argument registers, counted apart
+    SysV,
+    // eight integer and eight vector argument registers, counted apart
+    Aapcs64,
+}
+
+impl Abi {
+    pub fn calling_convention(self) -> &'static CallingConvention {
+        match self {
+            Abi::Win64 => &WIN64,
+            Abi::SysV => &SYSV,
+            Abi::Aapcs64 => &AAPCS64,
+        }
+    }
+}
+
+// How a C call passes its arguments and what the caller owes the callee on the stack
+#[derive(Debug, PartialEq)]
+pub struct CallingConvention {
+    // argument registers for integers and pointers, in order
+    pub integer_registers: &'static [&'static str],
+    // number of vector argument registers, xmm0 or v0 onwards
+    pub vector_registers: usize,
+    // whether an argument takes the position of its index whatever its kind, rather
+    // than the next free register of its kind
+    pub shared_positions: bool,
+    // bytes the caller reserves just above the return address for the callee to use
+    pub shadow_space: usize,
+    // the stack pointer is a multiple of this at every call instruction
+    pub stack_alignment: usize,
+    // whether variadic callees read the number of vector registers used in al
+    pub vector_count_in_al: bool,
+}
+
+pub static WIN64: CallingConvention = CallingConvention {
+    integer_registers: &["rcx", "rdx", "r8", "r9"],
+    vector_registers: 4,
+    shared_positions: true,
+    shadow_space: 32,
+    stack_alignment: 16,
+    vector_count_in_al: false,
+};
+
+pub static SYSV: CallingConvention = CallingConvention {
+    integer_registers: &["rdi", "rsi", "rdx", "rcx", "r8", "r9"],
+    vector_registers: 8,
+    shared_positions: false,
+    shadow_space: 0,
+    stack_alignment: 16,
+    vector_count_in_al: true,
+};
+
+pub static AAPCS64: CallingConvention = CallingConvention {
+    integer_registers: &["x0", "x1", "x2", "x3", "x4", "x5", "x6", "x7"],
+    vector_registers: 8,
+    shared_positions: false,
+    shadow_space: 0,
+    stack_alignment: 16,
+    vector_count_in_al: false,
+};
+
+// Where a C call puts one argument
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArgumentLocation {
+    Register(&'static str),
+    // index of the vector register
+    Vector(usize),
+    // byte offset from the first stack argument
+    Stack(usize),
+}
+
+impl CallingConvention {
+    // The location of each argument, given whether each is a float
+    pub fn arguments(&self, floats: impl IntoIterator<Item = bool>) -> Vec<ArgumentLocation> {
+        let (mut integers, mut vectors, mut stack) = (0, 0, 0);
+        let mut locations = Vec::new();
+        for (i, float) in floats.into_iter().enumerate() {
+            if self.shared_positions {
+                (integers, vectors) = (i, i);
+            }
+            let location = if float && vectors < self.vector_registers {
+                ArgumentLocation::Vector(vectors)
+            } else if !float && integers < self.integer_registers.len() {
+                ArgumentLocation::Register(self.integer_registers[integers])
+            } else {
+                stack += 8;
+                ArgumentLocation::Stack(stack - 8)
+            };
+            match location {
+                ArgumentLocation::Vector(_) => vectors += 1,
+                ArgumentLocation::Register(_) => integers += 1,
+                ArgumentLocation::Stack(_) => {}
+            }
+            locations.push(location);
+        }
+        locations
+    }
+
+    // Bytes to reserve below the stack pointer, before aligning it, for a call that
+    // passes `stack_bytes` of arguments on the stack
+    pub fn call_frame(&self, stack_bytes: usize) -> usize {
+        self.shadow_space + stack_bytes
+    }
+
+    // Offset of the first stack argument from the stack pointer on entry to the callee,
+    // past the return address and the shadow space
+    pub fn first_stack_argument(&self) -> usize {
+        8 + self.shadow_space
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ObjectFormat {
+    Coff,
+    Elf,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Linker {
+    // link.exe
+    Msvc,
+    // GNU ld
+    Ld,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct TargetSpec {
+    // the spelling --target and noble.toml use
+    pub name: &'static str,
+    pub arch: Arch,
+    pub abi: Abi,
+    // no C runtime: the runtime talks to the Linux kernel with syscalls and exit ends
+    // the process with the exit syscall
+    pub freestanding: bool,
+    pub entry: &'static str,
+    // nasm's -f for the target, None where nasm cannot assemble for it
+    pub nasm_format: Option<&'static str>,
+    pub object_format: ObjectFormat,
+    pub object_extension: &'static str,
+    // empty for none
+    pub executable_extension: &'static str,
+    pub linker: Linker,
+}
+
+pub static X86_64_WINDOWS: TargetSpec = TargetSpec {
+    name: "x86_64-windows",
+    arch: Arch::X86_64,
+    abi: Abi::Win64,
+    freestanding: false,
+    entry: "mainCRTStartup",
+    nasm_format: Some("win64"),
+    object_format: ObjectFormat::Coff,
+    object_extension: "obj",
+    executable_extension: "exe",
+    linker: Linker::Msvc,
+};
+
+pub static X86_64_LINUX: TargetSpec = TargetSpec {
+    name: "x86_64-linux",
+    arch: Arch::X86_64,
+    abi: Abi::SysV,
+    freestanding: true,
+    entry: "_start",
+    nasm_format: Some("elf64"),
+    object_format: ObjectFormat::Elf,
+    object_extension: "o",
+    executable_extension: "",
+    linker: Linker::Ld,
+};
+
+pub static AARCH64_LINUX: TargetSpec = TargetSpec {
+    name: "aarch64-linux",
+    arch: Arch::Aarch64,
+    abi: Abi::Aapcs64,
+    freestanding: true,
+    entry: "_start",
+    nasm_format: None,
+    object_format: ObjectFormat::Elf,
+    object_extension: "o",
+    executable_extension: "",
+    linker: Linker::Ld,
+};
+
+// Every target, the default first
+pub static TARGETS: [&TargetSpec; 3] = [&X86_64_WINDOWS, &X86_64_LINUX, &AARCH64_LINUX];
+
+pub fn lookup(name: &str) -> Option<&'static TargetSpec> {
+    TARGETS.iter().copied().find(|target| target.name == name)
+}
+
+pub fn names() -> Vec<&'static str> {
+    TARGETS.iter().map(|target| target.name).collect()
+}
+
+impl TargetSpec {
+    // The code generator only writes x86-64 so far; the other targets are known so
+    // that projects can name them, and fail here rather than emit the wrong machine
+    pub fn check_generator(&self) -> Result<(), CompileError> {
+        match self.arch {
+            Arch::X86_64 => Ok(()),
+            Arch::Aarch64 => Err(CompileError::Target(format!(
+                "{} has no code generator yet; the targets Noble generates code for are {}",
+                self.name,
+                TARGETS
+                    .iter()
+                    .filter(|target| target.arch == Arch::X86_64)
+                    .map(|target| target.name)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ))),
+        }
+    }
+}
@@ -1,50 +1,352 @@
+use crate::diagnostic::Diagnostic;
+use crate::intern::Name;
+use crate::json::Json;
+use std::fmt;
 use std::process::exit;
 
+// The kind of a token. The names are part of the tokens output (`--emit tokens`) and
+// of the library API, so a variant is only ever added, never renamed. Display writes
+// the token the way it appears in source, e.g. `;` or `==`, or what it stands for,
+// e.g. `identifier`.
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub enum TokenType {
-    TokenTypeEntryPoint,
-    TokenTypeExit,
-    TokenTypeIntegerLiteral,
-    TokenTypeSemicolon,
-    TokenTypeEquals,
-    TokenTypeIdentifier,
-    TokenTypeTypeI32S,
-    TokenTypeTypeF32S,
-    TokenTypeTypeBool,
-    TokenTypeTypeChar,
-    TokenTypeFloatLiteral,
-    TokenTypeCharLiteral,
-    TokenTypeBooleanLiteral,
-    TokenTypeFor,
-    TokenTypeForIn,
-    TokenTypeForTo,
-    TokenTypeIf,
-    TokenTypeElse,
-    TokenTypeLeftCurlyBrace,
-    TokenTypeRightCurlyBrace,
-    TokenTypePlus,
-    TokenTypeMinus,
-    TokenTypeMultiply,
-    TokenTypeDivide,
-    TokenTypeLessThan,
-    TokenTypeLessThanOrEqual,
-    TokenTypeGreaterThan,
-    TokenTypeGreaterThanOrEqual,
-    TokenTypeEqualsEquals,
-    TokenTypeNotEquals,
-    TokenTypeLeftParen,
-    TokenTypeRightParen,
+    // the first token of every stream, before any source text
+    EntryPoint,
+
+    // names and literals, whose text is the token's value
+    Ident,
+    IntLit,
+    FloatLit,
+    CharLit,
+    BoolLit,
+
+    // keywords
+    Exit,
+    PrintInt,
+    I32S,
+    F32S,
+    Bool,
+    Char,
+    Void,
+    For,
+    In,
+    To,
+    Until,
+    If,
+    Else,
+    Fn,
+    While,
+    Do,
+    Match,
+    Return,
+    Assert,
+    Const,
+    Define,
+    Ref,
+    Extern,
+    Export,
+
+    // operators
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    EqEq,
+    NotEq,
+    Amp,
+    // x++ and x--, statements of their own
+    PlusPlus,
+    MinusMinus,
+
+    // punctuation
+    Semi,
+    Eq,
+    LBrace,
+    RBrace,
+    LParen,
+    RParen,
+    Comma,
+    Arrow,
+    FatArrow,
 }
 
-#[derive(Debug, PartialEq)]
+// Every token type, in the order they are declared
+pub const TOKEN_TYPES: [TokenType; 52] = [
+    TokenType::EntryPoint,
+    TokenType::Ident, TokenType::IntLit, TokenType::FloatLit, TokenType::CharLit,
+    TokenType::BoolLit,
+    TokenType::Exit, TokenType::PrintInt, TokenType::I32S, TokenType::F32S, TokenType::Bool,
+    TokenType::Char, TokenType::Void, TokenType::For, TokenType::In, TokenType::To,
+    TokenType::Until, TokenType::If, TokenType::Else, TokenType::Fn, TokenType::While,
+    TokenType::Do, TokenType::Match, TokenType::Return, TokenType::Assert,
+    TokenType::Const, TokenType::Define, TokenType::Ref, TokenType::Extern, TokenType::Export,
+    TokenType::Plus, TokenType::Minus, TokenType::Star, TokenType::Slash, TokenType::Lt,
+    TokenType::Le, TokenType::Gt, TokenType::Ge, TokenType::EqEq, TokenType::NotEq,
+    TokenType::Amp, TokenType::PlusPlus, TokenType::MinusMinus,
+    TokenType::Semi, TokenType::Eq, TokenType::LBrace, TokenType::RBrace, TokenType::LParen,
+    TokenType::RParen, TokenType::Comma, TokenType::Arrow, TokenType::FatArrow,
+];
+
+// Reserved words; adding a keyword is one entry here plus its TokenType
+pub const KEYWORDS: [(&str, TokenType); 26] = [
+    ("exit", TokenType::Exit),
+    ("print_int", TokenType::PrintInt),
+    ("i32s", TokenType::I32S),
+    ("f32s", TokenType::F32S),
+    ("bool", TokenType::Bool),
+    ("char", TokenType::Char),
+    ("void", TokenType::Void),
+    ("true", TokenType::BoolLit),
+    ("false", TokenType::BoolLit),
+    ("for", TokenType::For),
+    ("in", TokenType::In),
+    ("to", TokenType::To),
+    ("until", TokenType::Until),
+    ("if", TokenType::If),
+    ("else", TokenType::Else),
+    ("fn", TokenType::Fn),
+    ("while", TokenType::While),
+    ("do", TokenType::Do),
+    ("match", TokenType::Match),
+    ("return", TokenType::Return),
+    ("assert", TokenType::Assert),
+    ("const", TokenType::Const),
+    ("define", TokenType::Define),
+    ("ref", TokenType::Ref),
+    ("extern", TokenType::Extern),
+    ("export", TokenType::Export),
+];
+
+// Words that are keywords only where the grammar expects them, and names everywhere
+// else: `step` after the bounds of a for loop. They are tokenized as identifiers.
+pub const CONTEXTUAL_KEYWORDS: [&str; 1] = ["step"];
+
+pub fn keyword(word: &str) -> Option<TokenType> {
+    KEYWORDS
+        .iter()
+        .find(|(spelling, _)| *spelling == word)
+        .map(|(_, token_type)| *token_type)
+}
+
+// Type suffixes a decimal literal may end with, and the kind of literal each makes:
+// `3f32` is the float 3.0 and `1.5i32` is an error
+pub const LITERAL_SUFFIXES: [(&str, TokenType); 2] =
+    [("i32", TokenType::IntLit), ("f32", TokenType::FloatLit)];
+
+// The literal's text without its type suffix, if it has one
+pub fn strip_literal_suffix(text: &str) -> &str {
+    LITERAL_SUFFIXES
+        .iter()
+        .find_map(|(suffix, _)| text.strip_suffix(suffix))
+        .unwrap_or(text)
+}
+
+// Coarse token categories for syntax highlighting
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TokenClass {
+    Keyword,
+    Literal,
+    Identifier,
+    Operator,
+    Comment,
+}
+
+impl TokenType {
+    pub fn class(&self) -> TokenClass {
+        match self {
+            TokenType::EntryPoint
+            | TokenType::Exit
+            | TokenType::I32S
+            | TokenType::F32S
+            | TokenType::Bool
+            | TokenType::Char
+            | TokenType::Void
+            | TokenType::For
+            | TokenType::In
+            | TokenType::To
+            | TokenType::Until
+            | TokenType::If
+            | TokenType::Else
+            | TokenType::Fn
+            | TokenType::While
+            | TokenType::Do
+            | TokenType::Match
+            | TokenType::PrintInt
+            | TokenType::Return
+            | TokenType::Assert
+            | TokenType::Const
+            | TokenType::Define
+            | TokenType::Ref
+            | TokenType::Extern
+            | TokenType::Export => TokenClass::Keyword,
+            TokenType::IntLit
+            | TokenType::FloatLit
+            | TokenType::CharLit
+            | TokenType::BoolLit => TokenClass::Literal,
+            TokenType::Ident => TokenClass::Identifier,
+            // punctuation is grouped with the operators
+            TokenType::Semi
+            | TokenType::Eq
+            | TokenType::LBrace
+            | TokenType::RBrace
+            | TokenType::Plus
+            | TokenType::Minus
+            | TokenType::Star
+            | TokenType::Slash
+            | TokenType::Lt
+            | TokenType::Le
+            | TokenType::Gt
+            | TokenType::Ge
+            | TokenType::EqEq
+            | TokenType::NotEq
+            | TokenType::LParen
+            | TokenType::RParen
+            | TokenType::Comma
+            | TokenType::Arrow
+            | TokenType::FatArrow
+            | TokenType::Amp
+            | TokenType::PlusPlus
+            | TokenType::MinusMinus => TokenClass::Operator,
+        }
+    }
+
+    // How the token is written, for tokens that are always written the same way
+    pub fn spelling(&self) -> Option<&'static str> {
+        let punctuation = match self {
+            TokenType::Semi => ";",
+            TokenType::Eq => "=",
+            TokenType::LBrace => "{",
+            TokenType::RBrace => "}",
+            TokenType::Plus => "+",
+            TokenType::Minus => "-",
+            TokenType::Star => "*",
+            TokenType::Slash => "/",
+            TokenType::Lt => "<",
+            TokenType::Le => "<=",
+            TokenType::Gt => ">",
+            TokenType::Ge => ">=",
+            TokenType::EqEq => "==",
+            TokenType::NotEq => "!=",
+            TokenType::LParen => "(",
+            TokenType::RParen => ")",
+            TokenType::Comma => ",",
+            TokenType::Arrow => "->",
+            TokenType::FatArrow => "=>",
+            TokenType::Amp => "&",
+            TokenType::PlusPlus => "++",
+            TokenType::MinusMinus => "--",
+            // true and false share a token type
+            TokenType::BoolLit => return None,
+            _ => {
+                return KEYWORDS
+                    .iter()
+                    .find(|(_, token_type)| token_type == self)
+                    .map(|(spelling, _)| *spelling);
+            }
+        };
+        Some(punctuation)
+    }
+
+    // The variant name, e.g. "Ident", as written by --emit tokens
+    pub fn name(&self) -> String {
+        format!("{:?}", self)
+    }
+
+    // The token for a diagnostic: its spelling in quotes, e.g. "';'", or what it
+    // stands for, e.g. "identifier"
+    pub fn describe(&self) -> String {
+        match self.spelling() {
+            Some(spelling) => format!("'{}'", spelling),
+            None => self.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for TokenType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(spelling) = self.spelling() {
+            return write!(f, "{}", spelling);
+        }
+        let description = match self {
+            TokenType::EntryPoint => "start of input",
+            TokenType::Ident => "identifier",
+            TokenType::IntLit => "integer literal",
+            TokenType::FloatLit => "float literal",
+            TokenType::CharLit => "character literal",
+            TokenType::BoolLit => "true or false",
+            _ => unreachable!("{:?} has a spelling", self),
+        };
+        write!(f, "{}", description)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+#[derive(Debug, PartialEq, Clone)]
 pub struct Token {
     pub token_type: TokenType,
-    pub value: Option<String>,
+    pub value: Option<Name>,
+    pub span: Span,
+}
+
+impl Token {
+    pub fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("type", Json::String(self.token_type.name())),
+            (
+                "value",
+                match &self.value {
+                    Some(value) => Json::String(value.to_string()),
+                    None => Json::Null,
+                },
+            ),
+            (
+                "span",
+                Json::object(vec![
+                    ("line", Json::Number(self.span.line as f64)),
+                    ("column", Json::Number(self.span.column as f64)),
+                    ("start", Json::Number(self.span.start as f64)),
+                    ("end", Json::Number(self.span.end as f64)),
+                ]),
+            ),
+        ])
+    }
+}
+
+// One token per line for --emit tokens-text: `line:column start..end Type "value"`
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{} {}..{} {}",
+            self.span.line,
+            self.span.column,
+            self.span.start,
+            self.span.end,
+            self.token_type.name()
+        )?;
+        if let Some(value) = &self.value {
+            write!(f, " {:?}", value)?;
+        }
+        Ok(())
+    }
 }
 
 pub struct Tokenizer {
     chars: Vec<char>,
     index: usize,
+    line: usize,
+    column: usize,
 }
 
 impl Tokenizer {
@@ -52,129 +354,192 @@ impl Tokenizer {
         Self {
             chars: input_string.chars().collect(),
             index: 0,
+            line: 1,
+            column: 1,
         }
     }
 
+    // Exits the process on malformed input; see try_tokenize for a recoverable version
     pub fn tokenize(&mut self) -> Vec<Token> {
+        match self.try_tokenize() {
+            Ok(tokens) => tokens,
+            Err(e) => {
+                eprintln!("{}", e);
+                exit(1);
+            }
+        }
+    }
+
+    pub fn try_tokenize(&mut self) -> Result<Vec<Token>, Diagnostic> {
         let mut tokens: Vec<Token> = Vec::new();
         let mut buffer: Vec<char> = Vec::new();
 
         tokens.push(Token {
-            token_type: TokenType::TokenTypeEntryPoint,
+            token_type: TokenType::EntryPoint,
             value: None,
+            span: self.position(),
         });
 
         while !self.is_at_end() {
-            if self.current().unwrap().is_ascii_alphabetic() {
+            let start = self.position();
+            if self.current().unwrap().is_ascii_alphabetic() || self.current().unwrap() == '_' {
                 buffer.push(self.consume());
-                while self.current() != None && self.current().unwrap().is_ascii_alphanumeric() {
+                while self.current().is_some()
+                    && (self.current().unwrap().is_ascii_alphanumeric()
+                        || self.current().unwrap() == '_')
+                {
                     buffer.push(self.consume());
                 }
-                if buffer == ['e', 'x', 'i', 't'] {
-                    tokens.push(Token {
-                        token_type: TokenType::TokenTypeExit,
+                let word: String = buffer.iter().collect();
+                let token = match keyword(&word) {
+                    // booleans keep their spelling as the literal's value
+                    Some(TokenType::BoolLit) => Token {
+                        token_type: TokenType::BoolLit,
+                        value: Some(Name::intern(&word)),
+                        span: self.span_from(start),
+                    },
+                    Some(token_type) => Token {
+                        token_type,
                         value: None,
-                    });
-                } else if buffer == ['i', '3', '2', 's'] {
-                    tokens.push(Token {
-                        token_type: TokenType::TokenTypeTypeI32S,
-                        value: None,
-                    });
-                } else if buffer == ['f', '3', '2', 's'] {
-                    tokens.push(Token {
-                        token_type: TokenType::TokenTypeTypeF32S,
-                        value: None,
-                    });
-                } else if buffer == ['b', 'o', 'o', 'l'] {
-                    tokens.push(Token {
-                        token_type: TokenType::TokenTypeTypeBool,
-                        value: None,
-                    });
-                } else if buffer == ['c', 'h', 'a', 'r'] {
-                    tokens.push(Token {
-                        token_type: TokenType::TokenTypeTypeChar,
-                        value: None,
-                    });
-                } else if buffer == ['t', 'r', 'u', 'e'] {
-                    tokens.push(Token {
-                        token_type: TokenType::TokenTypeBooleanLiteral,
-                        value: Some("true".to_string()),
-                    });
-                } else if buffer == ['f', 'a', 'l', 's', 'e'] {
-                    tokens.push(Token {
-                        token_type: TokenType::TokenTypeBooleanLiteral,
-                        value: Some("false".to_string()),
-                    });
-                } else if buffer == ['f', 'o', 'r'] {
-                    tokens.push(Token {
-                        token_type: TokenType::TokenTypeFor,
-                        value: None,
-                    })
-                } else if buffer == ['i', 'n'] {
-                    tokens.push(Token {
-                        token_type: TokenType::TokenTypeForIn,
-                        value: None,
-                    })
-                } else if buffer == ['t', 'o'] {
-                    tokens.push(Token {
-                        token_type: TokenType::TokenTypeForTo,
-                        value: None,
-                    })
-                } else if buffer == ['i', 'f'] {
-                    tokens.push(Token {
-                        token_type: TokenType::TokenTypeIf,
-                        value: None,
-                    })
-                } else if buffer == ['e', 'l', 's', 'e'] {
-                    tokens.push(Token {
-                        token_type: TokenType::TokenTypeElse,
-                        value: None,
-                    })
-                } else {
+                        span: self.span_from(start),
+                    },
                     // If not a keyword, it is an identifier
+                    None => Token {
+                        token_type: TokenType::Ident,
+                        value: Some(Name::intern(&word)),
+                        span: self.span_from(start),
+                    },
+                };
+                tokens.push(token);
+            } else if self.current().unwrap().is_ascii_digit() || self.at_leading_point() {
+                buffer.push(self.consume());
+                let radix_prefix = buffer[0] == '0'
+                    && matches!(self.current(), Some('x') | Some('o') | Some('b'));
+                if radix_prefix {
+                    // 0x / 0o / 0b: digits are validated when the literal is converted
+                    buffer.push(self.consume());
+                    while self.current().is_some()
+                        && (self.current().unwrap().is_ascii_alphanumeric()
+                            || self.current().unwrap() == '_')
+                    {
+                        buffer.push(self.consume());
+                    }
                     tokens.push(Token {
-                        token_type: TokenType::TokenTypeIdentifier,
-                        value: Some(buffer.iter().collect()),
+                        token_type: TokenType::IntLit,
+                        value: Some(Name::intern(&buffer.iter().collect::<String>())),
+                        span: self.span_from(start),
                     });
+                    buffer.clear();
+                    continue;
                 }
-            } else if self.current().unwrap().is_ascii_digit() {
-                buffer.push(self.consume());
-                while self.current() != None && self.current().unwrap().is_ascii_digit() {
+                // the integer part, or the fraction of a literal such as .5
+                while self.current().is_some()
+                    && (self.current().unwrap().is_ascii_digit() || self.current().unwrap() == '_')
+                {
                     buffer.push(self.consume());
                 }
-                if self.current() != None && self.current().unwrap() == '.' {
+                // the fraction may be empty, as in 5.
+                let mut is_float = buffer[0] == '.';
+                if !is_float && self.current() == Some('.') {
+                    is_float = true;
                     buffer.push(self.consume());
-                    while self.current() != None && self.current().unwrap().is_ascii_digit() {
+                    while self.current().is_some_and(|c| c.is_ascii_digit())
+                        || (self.current() == Some('_') && !buffer.ends_with(&['.']))
+                    {
                         buffer.push(self.consume());
                     }
-                    tokens.push(Token {
-                        token_type: TokenType::TokenTypeFloatLiteral,
-                        value: Some(buffer.iter().collect()),
-                    });
+                }
+                if matches!(self.current(), Some('e') | Some('E')) {
+                    is_float = true;
+                    buffer.push(self.consume());
+                    if matches!(self.current(), Some('+') | Some('-')) {
+                        buffer.push(self.consume());
+                    }
+                    if !self.current().is_some_and(|c| c.is_ascii_digit()) {
+                        return Err(Diagnostic::new(
+                            format!(
+                                "Tokenization Error: malformed exponent in float literal {} at {}:{}",
+                                buffer.iter().collect::<String>(),
+                                start.line,
+                                start.column
+                            ),
+                            self.span_from(start),
+                        ));
+                    }
+                    while self.current().is_some() && self.current().unwrap().is_ascii_digit() {
+                        buffer.push(self.consume());
+                    }
+                }
+                let literal: String = buffer.iter().collect();
+                let mut token_type = if is_float {
+                    TokenType::FloatLit
                 } else {
-                    tokens.push(Token {
-                        token_type: TokenType::TokenTypeIntegerLiteral,
-                        value: Some(buffer.iter().collect()),
-                    });
+                    TokenType::IntLit
+                };
+                if self.current().is_some_and(|c| c.is_ascii_alphabetic() || c == '_') {
+                    let mut suffix = String::new();
+                    while self
+                        .current()
+                        .is_some_and(|c| c.is_ascii_alphanumeric() || c == '_')
+                    {
+                        suffix.push(self.consume());
+                    }
+                    token_type = match LITERAL_SUFFIXES.iter().find(|(s, _)| *s == suffix) {
+                        Some((_, TokenType::IntLit)) if is_float => {
+                            return Err(Diagnostic::new(
+                                format!(
+                                    "Tokenization Error: float literal {} cannot have the integer suffix {} at {}:{}",
+                                    literal, suffix, start.line, start.column
+                                ),
+                                self.span_from(start),
+                            ));
+                        }
+                        Some((_, suffix_type)) => *suffix_type,
+                        None => {
+                            return Err(Diagnostic::new(
+                                format!(
+                                    "Tokenization Error: unknown suffix '{}' on numeric literal {} at {}:{}; the suffixes are i32 and f32",
+                                    suffix, literal, start.line, start.column
+                                ),
+                                self.span_from(start),
+                            ));
+                        }
+                    };
+                    buffer.extend(suffix.chars());
                 }
+                tokens.push(Token {
+                    token_type,
+                    value: Some(Name::intern(&buffer.iter().collect::<String>())),
+                    span: self.span_from(start),
+                });
             } else if self.current().unwrap() == ';' {
                 self.consume();
                 tokens.push(Token {
-                    token_type: TokenType::TokenTypeSemicolon,
+                    token_type: TokenType::Semi,
                     value: None,
+                    span: self.span_from(start),
                 });
             } else if self.current().unwrap() == '=' {
                 self.consume();
                 if self.current() == Some('=') {
                     self.consume();
                     tokens.push(Token {
-                        token_type: TokenType::TokenTypeEqualsEquals,
+                        token_type: TokenType::EqEq,
+                        value: None,
+                        span: self.span_from(start),
+                    });
+                } else if self.current() == Some('>') {
+                    self.consume();
+                    tokens.push(Token {
+                        token_type: TokenType::FatArrow,
                         value: None,
+                        span: self.span_from(start),
                     });
                 } else {
                     tokens.push(Token {
-                        token_type: TokenType::TokenTypeEquals,
+                        token_type: TokenType::Eq,
                         value: None,
+                        span: self.span_from(start),
                     });
                 }
             } else if self.current().unwrap() == '!' {
@@ -182,25 +547,30 @@ impl Tokenizer {
                 if self.current() == Some('=') {
                     self.consume();
                     tokens.push(Token {
-                        token_type: TokenType::TokenTypeNotEquals,
+                        token_type: TokenType::NotEq,
                         value: None,
+                        span: self.span_from(start),
                     });
                 } else {
-                    eprintln!("{:?}", "Tokenization Error: '!' must be followed by '='");
-                    exit(1);
+                    return Err(Diagnostic::new(
+                        "Tokenization Error: '!' must be followed by '='",
+                        self.span_from(start),
+                    ));
                 }
             } else if self.current().unwrap() == '<' {
                 self.consume();
                 if self.current() == Some('=') {
                     self.consume();
                     tokens.push(Token {
-                        token_type: TokenType::TokenTypeLessThanOrEqual,
+                        token_type: TokenType::Le,
                         value: None,
+                        span: self.span_from(start),
                     });
                 } else {
                     tokens.push(Token {
-                        token_type: TokenType::TokenTypeLessThan,
+                        token_type: TokenType::Lt,
                         value: None,
+                        span: self.span_from(start),
                     });
                 }
             } else if self.current().unwrap() == '>' {
@@ -208,85 +578,172 @@ impl Tokenizer {
                 if self.current() == Some('=') {
                     self.consume();
                     tokens.push(Token {
-                        token_type: TokenType::TokenTypeGreaterThanOrEqual,
+                        token_type: TokenType::Ge,
                         value: None,
+                        span: self.span_from(start),
                     });
                 } else {
                     tokens.push(Token {
-                        token_type: TokenType::TokenTypeGreaterThan,
+                        token_type: TokenType::Gt,
                         value: None,
+                        span: self.span_from(start),
                     });
                 }
             } else if self.current().unwrap() == '+' {
+                self.consume();
+                if self.current() == Some('+') {
+                    self.consume();
+                    tokens.push(Token {
+                        token_type: TokenType::PlusPlus,
+                        value: None,
+                        span: self.span_from(start),
+                    });
+                } else {
+                    tokens.push(Token {
+                        token_type: TokenType::Plus,
+                        value: None,
+                        span: self.span_from(start),
+                    });
+                }
+            } else if self.current().unwrap() == '-' {
+                self.consume();
+                if self.current() == Some('>') {
+                    self.consume();
+                    tokens.push(Token {
+                        token_type: TokenType::Arrow,
+                        value: None,
+                        span: self.span_from(start),
+                    });
+                } else if self.current() == Some('-') {
+                    self.consume();
+                    tokens.push(Token {
+                        token_type: TokenType::MinusMinus,
+                        value: None,
+                        span: self.span_from(start),
+                    });
+                } else {
+                    tokens.push(Token {
+                        token_type: TokenType::Minus,
+                        value: None,
+                        span: self.span_from(start),
+                    });
+                }
+            } else if self.current().unwrap() == ',' {
                 self.consume();
                 tokens.push(Token {
-                    token_type: TokenType::TokenTypePlus,
+                    token_type: TokenType::Comma,
                     value: None,
+                    span: self.span_from(start),
                 });
-            } else if self.current().unwrap() == '-' {
+            } else if self.current().unwrap() == '*' {
                 self.consume();
                 tokens.push(Token {
-                    token_type: TokenType::TokenTypeMinus,
+                    token_type: TokenType::Star,
                     value: None,
+                    span: self.span_from(start),
                 });
-            } else if self.current().unwrap() == '*' {
+            } else if self.current().unwrap() == '&' {
                 self.consume();
                 tokens.push(Token {
-                    token_type: TokenType::TokenTypeMultiply,
+                    token_type: TokenType::Amp,
                     value: None,
+                    span: self.span_from(start),
                 });
             } else if self.current().unwrap() == '/' {
                 self.consume();
+                if self.current() == Some('/') {
+                    // line comment: runs to the end of the line
+                    while self.current().is_some_and(|c| c != '\n') {
+                        self.consume();
+                    }
+                    continue;
+                }
                 tokens.push(Token {
-                    token_type: TokenType::TokenTypeDivide,
+                    token_type: TokenType::Slash,
                     value: None,
+                    span: self.span_from(start),
                 });
             } else if self.current().unwrap() == '(' {
                 self.consume();
                 tokens.push(Token {
-                    token_type: TokenType::TokenTypeLeftParen,
+                    token_type: TokenType::LParen,
                     value: None,
+                    span: self.span_from(start),
                 });
             } else if self.current().unwrap() == ')' {
                 self.consume();
                 tokens.push(Token {
-                    token_type: TokenType::TokenTypeRightParen,
+                    token_type: TokenType::RParen,
                     value: None,
+                    span: self.span_from(start),
                 });
             } else if self.current().unwrap() == '{' {
                 self.consume();
                 tokens.push(Token {
-                    token_type: TokenType::TokenTypeLeftCurlyBrace,
+                    token_type: TokenType::LBrace,
                     value: None,
+                    span: self.span_from(start),
                 });
             } else if self.current().unwrap() == '}' {
                 self.consume();
                 tokens.push(Token {
-                    token_type: TokenType::TokenTypeRightCurlyBrace,
+                    token_type: TokenType::RBrace,
                     value: None,
+                    span: self.span_from(start),
                 });
             } else if self.current().unwrap() == '\'' {
                 self.consume(); // opening quote
-                let char_val = self.consume();
-                if self.current().unwrap() == '\'' {
+                let char_val = if self.is_at_end() { None } else { Some(self.consume()) };
+                if let Some(char_val) = char_val
+                    && self.current() == Some('\'')
+                {
                     self.consume(); // closing quote
                     tokens.push(Token {
-                        token_type: TokenType::TokenTypeCharLiteral,
-                        value: Some(char_val.to_string()),
+                        token_type: TokenType::CharLit,
+                        value: Some(Name::intern(&char_val.to_string())),
+                        span: self.span_from(start),
                     });
                 } else {
-                    eprintln!("Tokenization Error: Expected closing quote for char literal");
-                    exit(1);
+                    return Err(Diagnostic::new(
+                        "Tokenization Error: Expected closing quote for char literal",
+                        self.span_from(start),
+                    ));
                 }
-            } else if self.current().unwrap().is_ascii_whitespace() {
+            } else if self.current().unwrap().is_ascii_whitespace()
+                || (self.index == 0 && self.current() == Some(BYTE_ORDER_MARK))
+            {
                 self.consume();
             } else {
-                eprintln!("{:?}", "Tokenization Error!");
-                exit(1);
+                let c = self.current().unwrap();
+                // identifiers are [A-Za-z_][A-Za-z0-9_]*, anything non-ASCII is rejected outright
+                let message = if c.is_ascii() {
+                    format!(
+                        "Tokenization Error: unexpected character '{}' at {}:{}",
+                        c, start.line, start.column
+                    )
+                } else {
+                    format!(
+                        "Tokenization Error: non-ASCII character '{}' (U+{:04X}) at {}:{}; identifiers may only contain ASCII letters, digits and '_'",
+                        c, c as u32, start.line, start.column
+                    )
+                };
+                return Err(Diagnostic::new(
+                    message,
+                    Span {
+                        end: start.start + 1,
+                        ..start
+                    },
+                ));
             }
             buffer.clear();
         }
-        tokens
+        Ok(tokens)
+    }
+
+    // A '.' that starts a float literal such as .5
+    fn at_leading_point(&self) -> bool {
+        self.chars.get(self.index) == Some(&'.')
+            && self.chars.get(self.index + 1).is_some_and(|c| c.is_ascii_digit())
     }
 
     pub fn current(&mut self) -> Option<char> {
@@ -303,7 +760,221 @@ impl Tokenizer {
 
     pub fn consume(&mut self) -> char {
         let c: char = self.chars[self.index];
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else if takes_column(&self.chars, self.index) {
+            self.column += 1;
+        }
         self.index += 1;
         c
     }
+
+    fn position(&self) -> Span {
+        Span {
+            start: self.index,
+            end: self.index,
+            line: self.line,
+            column: self.column,
+        }
+    }
+
+    fn span_from(&self, start: Span) -> Span {
+        Span {
+            end: self.index,
+            ..start
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TriviaKind {
+    Whitespace,
+    LineComment,
+}
+
+// Source text between tokens that the parser never sees
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trivia {
+    pub kind: TriviaKind,
+    pub text: String,
+    pub span: Span,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct LosslessToken {
+    pub token: Token,
+    // exact source text of the token, e.g. "0x1F" where the value may be normalized
+    pub text: String,
+    pub leading_trivia: Vec<Trivia>,
+}
+
+// Tokens plus every piece of whitespace and comment between them, so the
+// original source can be reproduced byte for byte
+#[derive(Debug, PartialEq)]
+pub struct LosslessTokens {
+    pub tokens: Vec<LosslessToken>,
+    // trivia after the last token
+    pub trailing_trivia: Vec<Trivia>,
+}
+
+impl LosslessTokens {
+    pub fn to_source(&self) -> String {
+        let mut source = String::new();
+        for token in &self.tokens {
+            for trivia in &token.leading_trivia {
+                source.push_str(&trivia.text);
+            }
+            source.push_str(&token.text);
+        }
+        for trivia in &self.trailing_trivia {
+            source.push_str(&trivia.text);
+        }
+        source
+    }
+}
+
+pub fn tokenize_lossless(source: &str) -> Result<LosslessTokens, Diagnostic> {
+    let chars: Vec<char> = source.chars().collect();
+    let tokens = Tokenizer::new(source.to_string()).try_tokenize()?;
+
+    let mut lossless = Vec::new();
+    let mut cursor = Span {
+        start: 0,
+        end: 0,
+        line: 1,
+        column: 1,
+    };
+    for token in tokens {
+        if token.token_type == TokenType::EntryPoint {
+            continue;
+        }
+        let leading_trivia = split_trivia(&chars, cursor, token.span.start);
+        let text = chars[token.span.start..token.span.end].iter().collect();
+        cursor = advance(&chars, token.span, token.span.end);
+        lossless.push(LosslessToken {
+            token,
+            text,
+            leading_trivia,
+        });
+    }
+
+    Ok(LosslessTokens {
+        tokens: lossless,
+        trailing_trivia: split_trivia(&chars, cursor, chars.len()),
+    })
+}
+
+// Highlighting classes for every token and comment in source order. Source
+// that fails to tokenize yields no classes, editors fall back to plain text.
+pub fn classify(source: &str) -> Vec<(Span, TokenClass)> {
+    let Ok(lossless) = tokenize_lossless(source) else {
+        return Vec::new();
+    };
+    let comments = |trivia: &[Trivia]| -> Vec<(Span, TokenClass)> {
+        trivia
+            .iter()
+            .filter(|t| t.kind == TriviaKind::LineComment)
+            .map(|t| (t.span, TokenClass::Comment))
+            .collect()
+    };
+
+    let mut classes = Vec::new();
+    for token in &lossless.tokens {
+        classes.extend(comments(&token.leading_trivia));
+        classes.push((token.token.span, token.token.token_type.class()));
+    }
+    classes.extend(comments(&lossless.trailing_trivia));
+    classes
+}
+
+// Breaks the gap [from, to) into whitespace runs and line comments
+fn split_trivia(chars: &[char], from: Span, to: usize) -> Vec<Trivia> {
+    let mut trivia = Vec::new();
+    let mut position = from;
+    while position.start < to {
+        let is_comment = chars[position.start] == '/';
+        let mut end = position.start;
+        if is_comment {
+            while end < to && chars[end] != '\n' {
+                end += 1;
+            }
+        } else {
+            while end < to && chars[end] != '/' {
+                end += 1;
+            }
+        }
+        let span = Span {
+            end,
+            ..position
+        };
+        trivia.push(Trivia {
+            kind: if is_comment {
+                TriviaKind::LineComment
+            } else {
+                TriviaKind::Whitespace
+            },
+            text: chars[position.start..end].iter().collect(),
+            span,
+        });
+        position = advance(chars, position, end);
+    }
+    trivia
+}
+
+// Position just past chars[from.start..to], tracking lines and columns
+fn advance(chars: &[char], from: Span, to: usize) -> Span {
+    let mut position = from;
+    for index in from.start..to {
+        if chars[index] == '\n' {
+            position.line += 1;
+            position.column = 1;
+        } else if takes_column(chars, index) {
+            position.column += 1;
+        }
+    }
+    Span {
+        start: to,
+        end: to,
+        ..position
+    }
+}
+
+const BYTE_ORDER_MARK: char = '\u{FEFF}';
+
+// A byte order mark starting the file and the \r of a \r\n line ending take no
+// column, so positions in files saved on Windows match those of the same text with \n
+fn takes_column(chars: &[char], index: usize) -> bool {
+    match chars[index] {
+        BYTE_ORDER_MARK => index > 0,
+        '\r' => chars.get(index + 1) != Some(&'\n'),
+        _ => true,
+    }
+}
+
+// Source text from the bytes of a file. Invalid UTF-8 is reported at the first bad
+// byte, by its offset in the file and by the line and column it falls on.
+pub fn decode(bytes: Vec<u8>) -> Result<String, Diagnostic> {
+    String::from_utf8(bytes).map_err(|error| {
+        let offset = error.utf8_error().valid_up_to();
+        let bytes = error.as_bytes();
+        let valid: Vec<char> = String::from_utf8_lossy(&bytes[..offset]).chars().collect();
+        let start = Span {
+            start: 0,
+            end: 0,
+            line: 1,
+            column: 1,
+        };
+        let position = advance(&valid, start, valid.len());
+        Diagnostic::new(
+            format!(
+                "Tokenization Error: invalid UTF-8 byte 0x{:02X} at byte offset {} ({}:{}); source files must be UTF-8 encoded",
+                bytes[offset], offset, position.line, position.column
+            ),
+            Span {
+                end: position.start + 1,
+                ..position
+            },
+        )
+    })
 }
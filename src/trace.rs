@@ -0,0 +1,140 @@
+use std::fmt;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stage {
+    Read,
+    Tokenize,
+    // parsing and lowering to the AST, which happen statement by statement
+    Parse,
+    TypeCheck,
+    // the passes of the -O level, before and after code generation
+    Optimize,
+    Generate,
+    Write,
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Stage::Read => "read",
+            Stage::Tokenize => "tokenize",
+            Stage::Parse => "parse",
+            Stage::TypeCheck => "typecheck",
+            Stage::Optimize => "optimize",
+            Stage::Generate => "generate",
+            Stage::Write => "write",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// Stage-tagged progress messages, only printed when verbose output is requested.
+// Goes to stderr so dumps on stdout stay clean.
+pub struct Tracer {
+    verbose: bool,
+}
+
+impl Tracer {
+    pub fn new(verbose: bool) -> Self {
+        Self { verbose }
+    }
+
+    pub fn is_verbose(&self) -> bool {
+        self.verbose
+    }
+
+    pub fn trace(&self, stage: Stage, message: &str) {
+        if self.verbose {
+            eprintln!("[{}] {}", stage, message);
+        }
+    }
+}
+
+struct StageStats {
+    stage: Stage,
+    elapsed: Duration,
+    counts: Vec<(usize, &'static str)>,
+}
+
+// Per-stage wall-clock time and output sizes, printed by --timings
+pub struct Stats {
+    stages: Vec<StageStats>,
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        Self { stages: Vec::new() }
+    }
+
+    pub fn time<T>(&mut self, stage: Stage, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = f();
+        let elapsed = start.elapsed();
+        match self.stages.iter_mut().find(|s| s.stage == stage) {
+            Some(entry) => entry.elapsed += elapsed,
+            None => self.stages.push(StageStats {
+                stage,
+                elapsed,
+                counts: Vec::new(),
+            }),
+        }
+        result
+    }
+
+    pub fn count(&mut self, stage: Stage, count: usize, unit: &'static str) {
+        match self.stages.iter_mut().find(|s| s.stage == stage) {
+            Some(entry) => entry.counts.push((count, unit)),
+            None => self.stages.push(StageStats {
+                stage,
+                elapsed: Duration::ZERO,
+                counts: vec![(count, unit)],
+            }),
+        }
+    }
+
+    pub fn report(&self) {
+        let mut total = Duration::ZERO;
+        for entry in &self.stages {
+            total += entry.elapsed;
+            let counts: Vec<String> = entry
+                .counts
+                .iter()
+                .map(|(count, unit)| match rate(*count, entry.elapsed) {
+                    Some(rate) => format!("{} {} ({} {}/s)", count, unit, rate, unit),
+                    None => format!("{} {}", count, unit),
+                })
+                .collect();
+            let line = format!(
+                "{:<10} {:>10.3}ms  {}",
+                entry.stage.to_string(),
+                entry.elapsed.as_secs_f64() * 1000.0,
+                counts.join(", ")
+            );
+            eprintln!("{}", line.trim_end());
+        }
+        eprintln!("{:<10} {:>10.3}ms", "total", total.as_secs_f64() * 1000.0);
+    }
+}
+
+// Throughput like "2.4M", or None for a stage too quick to time
+pub fn rate(count: usize, elapsed: Duration) -> Option<String> {
+    let seconds = elapsed.as_secs_f64();
+    if seconds < 1e-6 {
+        return None;
+    }
+    let per_second = count as f64 / seconds;
+    Some(if per_second >= 1e6 {
+        format!("{:.1}M", per_second / 1e6)
+    } else if per_second >= 1e3 {
+        format!("{:.1}k", per_second / 1e3)
+    } else {
+        format!("{:.0}", per_second)
+    })
+}
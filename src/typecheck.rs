@@ -0,0 +1,567 @@
+use crate::arena::Arena;
+use crate::ast::{Ast, BinOpType, Expr, ExprKind, Linkage, Node, NodeId, Stmt, Type};
+use crate::consteval::{self, Value};
+use crate::definite;
+use crate::diagnostic::Diagnostic;
+use crate::diverge::Divergence;
+use crate::generate;
+use crate::intern::Name;
+use crate::symtab::{Symbol, SymbolKind, SymbolTable};
+use crate::target::{self, TargetSpec};
+use crate::tokenize::Span;
+
+pub struct TypeChecker {
+    symbols: SymbolTable,
+    // errors and warnings, in the order they were found
+    diagnostics: Vec<Diagnostic>,
+    // statement being checked, used as the location of errors
+    span: Span,
+    // name and return type of the function whose body is being checked
+    function: Option<(Name, Type)>,
+    // mixing i32s and f32s operands is an error rather than a promotion to f32s
+    strict_numerics: bool,
+    // which of the functions checked so far never return
+    divergence: Divergence,
+    // where the program will run, for what its process exit status can hold
+    target: &'static TargetSpec,
+}
+
+impl Default for TypeChecker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TypeChecker {
+    pub fn new() -> Self {
+        Self {
+            symbols: SymbolTable::new(),
+            diagnostics: Vec::new(),
+            span: Span::default(),
+            function: None,
+            strict_numerics: false,
+            divergence: Divergence::new(),
+            target: &target::X86_64_WINDOWS,
+        }
+    }
+
+    pub fn set_strict_numerics(&mut self, strict_numerics: bool) {
+        self.strict_numerics = strict_numerics;
+    }
+
+    pub fn set_target(&mut self, target: &'static TargetSpec) {
+        self.target = target;
+    }
+
+    pub fn check(mut self, ast: &mut Ast) -> Vec<Diagnostic> {
+        self.check_node(&mut ast.nodes, ast.root);
+        self.diagnostics.extend(definite::check(ast));
+        self.diagnostics
+    }
+
+    fn check_node(&mut self, nodes: &mut Arena<Node>, id: NodeId) {
+        let enclosing = self.span;
+        if nodes[id].span.line > 0 {
+            self.span = nodes[id].span;
+        }
+        self.check_statement(nodes, id);
+        self.span = enclosing;
+    }
+
+    // Nested statements are checked through `nodes`, so each arm is done with the
+    // node's own fields (cloning the ids it still needs) before descending
+    fn check_statement(&mut self, nodes: &mut Arena<Node>, id: NodeId) {
+        let node = &mut nodes[id];
+        match &mut node.stmt {
+            Stmt::Entry => {
+                let children = node.children.clone();
+                for &child in &children {
+                    self.check_node(nodes, child);
+                }
+                // falling off the end exits with 0, which is only worth a warning when
+                // the program exits explicitly elsewhere and this path may be an oversight
+                if contains_exit(nodes, &children)
+                    && self.divergence.finishes(nodes, &children)
+                    && let Some(&last) = children.last()
+                {
+                    self.diagnostics.push(
+                        Diagnostic::warning(
+                            "Warning: the program can reach its end without an exit statement",
+                            nodes[last].span,
+                        )
+                        .with_note(
+                            "it then exits with status 0; add exit 0 at the end to make that explicit",
+                        ),
+                    );
+                }
+            }
+
+            Stmt::Exit(expr) => {
+                self.expect_type(expr, &Type::I32S, "exit");
+                // a freestanding program exits through the Linux exit syscall, and POSIX
+                // only keeps the low 8 bits of the status
+                if self.target.freestanding
+                    && let Ok(Value::Int(code)) = consteval::evaluate(expr)
+                    && !(0..=255).contains(&code)
+                {
+                    self.diagnostics.push(
+                        Diagnostic::warning(
+                            format!(
+                                "Warning: exit code {} at line {} is outside 0-255 and will be reported as {}",
+                                code,
+                                node.span.line,
+                                code & 0xff
+                            ),
+                            node.span,
+                        )
+                        .with_note("POSIX keeps only the low 8 bits of a process exit status"),
+                    );
+                }
+            }
+
+            Stmt::PrintInt(expr) => {
+                self.expect_type(expr, &Type::I32S, "print_int");
+            }
+
+            Stmt::Assert(expr) => {
+                self.expect_type(expr, &Type::Bool, "assert");
+            }
+
+            Stmt::VariableDeclaration { name, type_, value } => {
+                if let Some(value) = value {
+                    self.expect_type(value, type_, &format!("declaration of {}", name));
+                }
+                self.declare_variable(*name, type_.clone());
+            }
+
+            Stmt::VariableAssignment { name, value } => {
+                match self.symbols.resolve(*name).map(|s| s.type_.clone()) {
+                    Some(type_) => {
+                        self.expect_type(value, &type_, &format!("assignment to {}", name))
+                    }
+                    None => {
+                        self.type_of(value);
+                    }
+                }
+            }
+
+            Stmt::DerefAssignment { name, value } => {
+                match self.symbols.resolve(*name).map(|s| s.type_.clone()) {
+                    Some(Type::Ref(inner)) => {
+                        self.expect_type(value, &inner, &format!("assignment through {}", name))
+                    }
+                    Some(type_) => {
+                        self.error(format!(
+                            "TypeError: cannot assign through {}, which has type {} rather than a reference",
+                            name, type_
+                        ));
+                        self.type_of(value);
+                    }
+                    None => {
+                        self.type_of(value);
+                    }
+                }
+            }
+
+            Stmt::For {
+                iterator_name,
+                type_,
+                iterator_begin,
+                iterator_end,
+                step,
+                body,
+                ..
+            } => {
+                if !matches!(type_, Type::I32S | Type::F32S) {
+                    self.error(format!(
+                        "TypeError: for-loop iterator {} has type {}; only i32s and f32s count",
+                        iterator_name, type_
+                    ));
+                }
+                self.expect_type(iterator_begin, type_, "for-loop bound");
+                self.expect_type(iterator_end, type_, "for-loop bound");
+                if let Some(step) = step {
+                    self.expect_type(step, type_, "for-loop step");
+                    // a step that is not positive would never reach the bound
+                    if let Some(value) = constant_step(step)
+                        && (value <= 0.0 || value.is_nan())
+                    {
+                        self.error(format!(
+                            "TypeError: for-loop step must be positive, found {}",
+                            value
+                        ));
+                    }
+                }
+                let (type_, body) = (type_.clone(), *body);
+                self.symbols.enter_scope();
+                self.declare_variable(*iterator_name, type_);
+                self.check_node(nodes, body);
+                self.symbols.exit_scope();
+            }
+
+            Stmt::If {
+                condition,
+                body,
+                else_body,
+            } => {
+                self.expect_type(condition, &Type::Bool, "if condition");
+                let (body, else_body) = (*body, *else_body);
+                self.check_node(nodes, body);
+                if let Some(else_body) = else_body {
+                    self.check_node(nodes, else_body);
+                }
+            }
+
+            Stmt::While { condition, body } => {
+                self.expect_type(condition, &Type::Bool, "while condition");
+                let body = *body;
+                self.check_node(nodes, body);
+            }
+
+            Stmt::DoWhile { body, .. } => {
+                let body = *body;
+                self.check_node(nodes, body);
+                if let Stmt::DoWhile { condition, .. } = &mut nodes[id].stmt {
+                    self.expect_type(condition, &Type::Bool, "do-while condition");
+                }
+            }
+
+            Stmt::Match {
+                scrutinee,
+                arms,
+                else_body,
+            } => {
+                self.expect_type(scrutinee, &Type::I32S, "match expression");
+                let (arms, else_body) = (arms.clone(), *else_body);
+
+                let mut seen = Vec::new();
+                for (value, body) in arms {
+                    if seen.contains(&value) {
+                        self.error(format!("TypeError: duplicate match arm {}", value));
+                    }
+                    seen.push(value);
+                    self.check_node(nodes, body);
+                }
+
+                // integers cannot be enumerated, so only an else arm makes a match exhaustive
+                match else_body {
+                    Some(body) => self.check_node(nodes, body),
+                    None => self.error(
+                        "TypeError: match on i32s is not exhaustive; add an else arm".to_string(),
+                    ),
+                }
+            }
+
+            Stmt::Block { body } => {
+                let body = body.clone();
+                self.check_body(nodes, &body);
+            }
+
+            Stmt::FunctionDeclaration {
+                name,
+                params,
+                return_type,
+                body,
+                linkage,
+            } => {
+                let linkage = *linkage;
+                let (name, params, return_type, body) =
+                    (*name, params.clone(), return_type.clone(), *body);
+                let _ = self.symbols.declare(Symbol {
+                    name,
+                    kind: SymbolKind::Function {
+                        params: params.iter().map(|(_, t)| t.clone()).collect(),
+                    },
+                    type_: return_type.clone(),
+                    span: Span::default(),
+                    mutable: false,
+                });
+                // C code knows extern and export functions by their own names, which the
+                // assembly must not already use for something else
+                let keyword = match linkage {
+                    Linkage::Internal => None,
+                    Linkage::Extern => Some("extern"),
+                    Linkage::Export => Some("export"),
+                };
+                if let Some(keyword) = keyword
+                    && let Some(use_) = generate::reserved_symbol(&name.text())
+                {
+                    self.error(format!(
+                        "TypeError: {} fn {} cannot keep its name in the assembly, where it is {}",
+                        keyword, name, use_
+                    ));
+                }
+                // the body is in another object file
+                if linkage == Linkage::Extern {
+                    return;
+                }
+
+                self.symbols.enter_scope();
+                for (param, type_) in &params {
+                    self.declare_variable(*param, type_.clone());
+                }
+                self.function = Some((name, return_type.clone()));
+                self.check_node(nodes, body);
+                self.function = None;
+                self.symbols.exit_scope();
+
+                if return_type != Type::Void && self.divergence.statement_finishes(nodes, body) {
+                    self.error(format!(
+                        "TypeError: function {} does not return a value on every path",
+                        name
+                    ));
+                }
+                self.divergence.declare(nodes, name, body);
+            }
+
+            Stmt::Return(expr) => {
+                match (self.function.clone(), expr) {
+                    (Some((name, return_type)), Some(expr)) => {
+                        self.expect_type(expr, &return_type, &format!("return from {}", name))
+                    }
+                    (Some((name, return_type)), None) if return_type != Type::Void => {
+                        self.error(format!(
+                            "TypeError: function {} must return a value of type {}",
+                            name, return_type
+                        ))
+                    }
+                    (Some(_), None) => {}
+                    // the parser only accepts return inside a function body
+                    (None, _) => self.error("TypeError: return outside of a function".to_string()),
+                }
+            }
+
+            Stmt::Expression(expr) => {
+                self.type_of(expr);
+            }
+
+            // evaluated while parsing
+            Stmt::ConstantDeclaration { .. } | Stmt::Define { .. } => {}
+        }
+    }
+
+    fn check_body(&mut self, nodes: &mut Arena<Node>, body: &[NodeId]) {
+        self.symbols.enter_scope();
+        for &stmt in body {
+            self.check_node(nodes, stmt);
+        }
+        self.symbols.exit_scope();
+    }
+
+    fn error(&mut self, message: String) {
+        self.diagnostics.push(Diagnostic::new(message, self.span));
+    }
+
+    // Shadowing a variable of an enclosing scope is allowed, and reported by the
+    // shadowing lint; redeclaring one in the same scope is a parse error
+    fn declare_variable(&mut self, name: Name, type_: Type) {
+        let _ = self.symbols.declare(Symbol {
+            name,
+            kind: SymbolKind::Variable,
+            type_,
+            span: self.span,
+            mutable: true,
+        });
+    }
+
+    fn expect_type(&mut self, expr: &mut Expr, expected: &Type, context: &str) {
+        if let Some(found) = self.type_of(expr)
+            && found != *expected
+        {
+            self.error(format!(
+                "TypeError: expected {}, found {} in {}",
+                expected, found, context
+            ));
+        }
+    }
+
+    // None means the expression is already ill-typed and an error has been recorded.
+    // The type is kept on the expression, and on each one inside it, for codegen.
+    fn type_of(&mut self, expr: &mut Expr) -> Option<Type> {
+        let type_ = self.infer(&mut expr.kind);
+        expr.type_ = type_.clone();
+        type_
+    }
+
+    fn infer(&mut self, expr: &mut ExprKind) -> Option<Type> {
+        match expr {
+            ExprKind::Int(_) => Some(Type::I32S),
+            ExprKind::Float(_) => Some(Type::F32S),
+            ExprKind::Bool(_) => Some(Type::Bool),
+            ExprKind::Char(_) => Some(Type::Char),
+            ExprKind::Ident(name) => self.symbols.resolve(*name).map(|s| s.type_.clone()),
+            ExprKind::Call { name, args } => self.type_of_call(*name, args),
+            ExprKind::AddressOf(name) => self
+                .symbols
+                .resolve(*name)
+                .map(|s| Type::Ref(Box::new(s.type_.clone()))),
+            ExprKind::Deref(name) => match self.symbols.resolve(*name).map(|s| s.type_.clone())? {
+                Type::Ref(inner) => Some(*inner),
+                type_ => {
+                    self.error(format!(
+                        "TypeError: cannot dereference {}, which has type {} rather than a reference",
+                        name, type_
+                    ));
+                    None
+                }
+            },
+            ExprKind::Convert { value, to } => {
+                let value_type = self.type_of(value)?;
+                if !matches!(value_type, Type::I32S | Type::F32S) {
+                    self.error(format!(
+                        "TypeError: cannot convert {} to {}; only i32s and f32s convert",
+                        value_type, to
+                    ));
+                    return None;
+                }
+                Some(to.clone())
+            }
+            ExprKind::Negate { value } => {
+                let value_type = self.type_of(value)?;
+                if !matches!(value_type, Type::I32S | Type::F32S) {
+                    self.error(format!(
+                        "TypeError: unary - cannot be applied to {}; only i32s and f32s negate",
+                        value_type
+                    ));
+                    return None;
+                }
+                Some(value_type)
+            }
+            ExprKind::BinaryOp { left, op, right } => {
+                let left_type = self.type_of(left);
+                let right_type = self.type_of(right);
+                let (mut left_type, mut right_type) = (left_type?, right_type?);
+
+                // i32s operands of a mixed operation are converted to f32s, which
+                // holds every i32s to within rounding
+                if let (Type::I32S, Type::F32S) | (Type::F32S, Type::I32S) =
+                    (&left_type, &right_type)
+                {
+                    if self.strict_numerics {
+                        self.error(format!(
+                            "TypeError: mismatched operand types {} and {} for operator {}; convert one with f32s(...) or i32s(...)",
+                            left_type, right_type, op
+                        ));
+                        return None;
+                    }
+                    let promoted = if left_type == Type::I32S { left } else { right };
+                    promote(promoted);
+                    left_type = Type::F32S;
+                    right_type = Type::F32S;
+                }
+                if left_type == Type::Void || right_type == Type::Void {
+                    self.error(format!(
+                        "TypeError: operator {} cannot be applied to a void value",
+                        op
+                    ));
+                    return None;
+                }
+                if matches!(left_type, Type::Ref(_)) || matches!(right_type, Type::Ref(_)) {
+                    self.error(format!(
+                        "TypeError: operator {} cannot be applied to a reference; read the value with *",
+                        op
+                    ));
+                    return None;
+                }
+                if left_type != right_type {
+                    self.error(format!(
+                        "TypeError: mismatched operand types {} and {} for operator {}",
+                        left_type, right_type, op
+                    ));
+                    return None;
+                }
+                if op.is_arithmetic() {
+                    if left_type == Type::Bool {
+                        self.error(format!(
+                            "TypeError: operator {} cannot be applied to bool operands",
+                            op
+                        ));
+                        return None;
+                    }
+                    Some(left_type)
+                } else {
+                    if left_type == Type::Bool
+                        && !matches!(op, BinOpType::Equal | BinOpType::NotEqual)
+                    {
+                        self.error(format!(
+                            "TypeError: operator {} cannot be applied to bool operands",
+                            op
+                        ));
+                        return None;
+                    }
+                    Some(Type::Bool)
+                }
+            }
+        }
+    }
+
+    fn type_of_call(&mut self, name: Name, args: &mut [Expr]) -> Option<Type> {
+        let (params, return_type) = match self.symbols.resolve(name) {
+            Some(Symbol {
+                kind: SymbolKind::Function { params },
+                type_,
+                ..
+            }) => (params.clone(), type_.clone()),
+            _ => {
+                self.error(format!("TypeError: {} is not a function", name));
+                return None;
+            }
+        };
+
+        if params.len() != args.len() {
+            self.error(format!(
+                "TypeError: function {} takes {} argument(s) but {} were supplied",
+                name,
+                params.len(),
+                args.len()
+            ));
+        }
+
+        for (i, (arg, param_type)) in args.iter_mut().zip(params.iter()).enumerate() {
+            if let Some(arg_type) = self.type_of(arg)
+                && arg_type != *param_type
+            {
+                self.error(format!(
+                    "TypeError: argument {} of {} expects {}, found {}",
+                    i + 1,
+                    name,
+                    param_type,
+                    arg_type
+                ));
+            }
+        }
+
+        Some(return_type)
+    }
+}
+
+// The value of a step written as a constant, widened so both kinds compare with zero
+fn constant_step(step: &Expr) -> Option<f64> {
+    match &step.kind {
+        ExprKind::Float(f) => Some(*f as f64),
+        ExprKind::Negate { value } => constant_step(value).map(|value| -value),
+        _ => match consteval::evaluate(step) {
+            Ok(Value::Int(n)) => Some(n as f64),
+            _ => None,
+        },
+    }
+}
+
+fn promote(expr: &mut Expr) {
+    let value = std::mem::replace(expr, ExprKind::Int(0).into());
+    let kind = ExprKind::Convert {
+        value: Box::new(value),
+        to: Type::F32S,
+    };
+    *expr = Expr::typed(kind, Type::F32S);
+}
+
+// Whether an exit statement appears anywhere in these statements, outside the bodies
+// of the functions they declare
+fn contains_exit(nodes: &Arena<Node>, body: &[NodeId]) -> bool {
+    body.iter().any(|&stmt| match &nodes[stmt].stmt {
+        Stmt::Exit(_) => true,
+        Stmt::FunctionDeclaration { .. } => false,
+        _ => contains_exit(nodes, &nodes[stmt].child_ids()),
+    })
+}
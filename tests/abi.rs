@@ -0,0 +1,163 @@
+// Calls into C code: where each convention puts the arguments, and the stack at the call.
+
+mod common;
+
+use noble::asm::{Line, Operand};
+use noble::runtime;
+use noble::target::{
+    AAPCS64, ArgumentLocation, CallingConvention, SYSV, WIN64, X86_64_LINUX, X86_64_WINDOWS,
+};
+
+const PROGRAM: &str = "
+extern fn mixed(i32s a, f32s b, i32s c, f32s d, i32s e, f32s f) -> i32s;
+extern fn many(i32s a, i32s b, i32s c, i32s d, i32s e, i32s f, i32s g, i32s h) -> i32s;
+extern fn none() -> i32s;
+print_int(mixed(1, 2.0, 3, 4.0, 5, 6.0) + many(1, 2, 3, 4, 5, 6, 7, 8) + none());
+exit read_int();
+";
+
+fn instruction<'l>(line: &'l Line, mnemonic: &str) -> Option<&'l [Operand]> {
+    match line {
+        Line::Instruction {
+            mnemonic: m,
+            operands,
+        } if m == mnemonic => Some(operands),
+        _ => None,
+    }
+}
+
+// The bytes reserved below the stack pointer and whether it was aligned, since the last
+// push or call, before each call to a function for which `is_c` holds: the arguments
+// are evaluated onto the stack first, then moved to where the callee expects them
+fn c_calls(lines: &[Line], is_c: impl Fn(&str) -> bool) -> Vec<(String, i64, bool)> {
+    let (mut reserved, mut aligned) = (0, false);
+    let mut calls = Vec::new();
+    for line in lines {
+        if let Some([Operand::Register(rsp), Operand::Immediate(bytes)]) = instruction(line, "sub")
+            && rsp == "rsp"
+        {
+            reserved += bytes;
+        }
+        if let Some([Operand::Register(rsp), Operand::Immediate(-16)]) = instruction(line, "and")
+            && rsp == "rsp"
+        {
+            aligned = true;
+        }
+        if let Some([Operand::Label(callee)]) = instruction(line, "call") {
+            if is_c(callee) {
+                calls.push((callee.clone(), reserved, aligned));
+            }
+            (reserved, aligned) = (0, false);
+        }
+        // a routine's prologue reserves its frame's slots before the arguments of its
+        // first call are pushed, and that belongs to the frame rather than the call
+        if instruction(line, "push").is_some() {
+            (reserved, aligned) = (0, false);
+        }
+    }
+    calls
+}
+
+#[test]
+fn arguments_go_where_each_convention_puts_them() {
+    use ArgumentLocation::{Register, Stack, Vector};
+    let mixed = [false, true, false, true, false, true];
+    let expected: [(&CallingConvention, Vec<ArgumentLocation>); 3] = [
+        (
+            &WIN64,
+            vec![
+                Register("rcx"),
+                Vector(1),
+                Register("r8"),
+                Vector(3),
+                Stack(0),
+                Stack(8),
+            ],
+        ),
+        (
+            &SYSV,
+            vec![
+                Register("rdi"),
+                Vector(0),
+                Register("rsi"),
+                Vector(1),
+                Register("rdx"),
+                Vector(2),
+            ],
+        ),
+        (
+            &AAPCS64,
+            vec![
+                Register("x0"),
+                Vector(0),
+                Register("x1"),
+                Vector(1),
+                Register("x2"),
+                Vector(2),
+            ],
+        ),
+    ];
+    for (convention, locations) in expected {
+        assert_eq!(convention.arguments(mixed), locations);
+    }
+
+    let integers = SYSV.arguments([false; 8]);
+    assert_eq!(integers[5], Register("r9"));
+    assert_eq!(&integers[6..], &[Stack(0), Stack(8)]);
+    assert_eq!(WIN64.arguments([true; 5])[4], Stack(0));
+}
+
+#[test]
+fn extern_calls_align_the_stack_and_reserve_shadow_space() {
+    for (target, shadow) in [(&X86_64_WINDOWS, 32), (&X86_64_LINUX, 0)] {
+        let lines = common::generated(PROGRAM, |pipeline| pipeline.target(target));
+        let calls = c_calls(&lines, |callee| ["mixed", "many", "none"].contains(&callee));
+        assert_eq!(calls.len(), 3);
+        let convention = target.abi.calling_convention();
+        for (callee, reserved, aligned) in calls {
+            let arguments = match callee.as_str() {
+                "mixed" => convention.arguments([false, true, false, true, false, true]),
+                "many" => convention.arguments([false; 8]),
+                _ => Vec::new(),
+            };
+            let stack = arguments
+                .iter()
+                .filter(|location| matches!(location, ArgumentLocation::Stack(_)))
+                .count() as i64;
+            assert!(
+                aligned,
+                "{} is called on {} without aligning the stack",
+                callee, target.name
+            );
+            assert_eq!(
+                reserved,
+                shadow + 8 * stack,
+                "{} on {}",
+                callee,
+                target.name
+            );
+        }
+    }
+}
+
+#[test]
+fn hosted_runtime_calls_align_the_stack_and_reserve_shadow_space() {
+    let lines = runtime::module(&X86_64_WINDOWS);
+    // each routine starts after a blank line, aligns the stack before its first call
+    // and keeps that frame for the calls after it
+    let mut called_printf = false;
+    for routine in lines.split(|line| *line == Line::Blank) {
+        let calls = c_calls(routine, |callee| !callee.starts_with(runtime::PREFIX));
+        if let Some((callee, reserved, aligned)) = calls.first() {
+            assert!(*aligned, "{} is called without aligning the stack", callee);
+            assert!(
+                *reserved >= WIN64.shadow_space as i64,
+                "{} is called with {} bytes of shadow space",
+                callee,
+                reserved
+            );
+        }
+        called_printf |= calls.iter().any(|(callee, _, _)| callee == "printf");
+    }
+    assert!(called_printf);
+}
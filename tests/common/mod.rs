@@ -0,0 +1,25 @@
+// Compiling a test program with the settings `configure` makes and keeping what a stage gave.
+
+// each test file uses only some of these
+#![allow(dead_code)]
+
+use noble::asm::Line;
+use noble::pipeline::Pipeline;
+
+// The generated instructions, before the assembly passes of the -O level
+pub fn generated(source: &str, configure: impl FnOnce(Pipeline) -> Pipeline) -> Vec<Line> {
+    let mut lines = Vec::new();
+    configure(Pipeline::new())
+        .on_ir(|ir| lines = ir.clone())
+        .compile(source)
+        .unwrap_or_else(|error| panic!("{}", error));
+    lines
+}
+
+// The finished assembly text
+pub fn assembly(source: &str, configure: impl FnOnce(Pipeline) -> Pipeline) -> String {
+    configure(Pipeline::new())
+        .compile(source)
+        .unwrap_or_else(|error| panic!("{}", error))
+        .assembly
+}
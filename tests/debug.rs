@@ -0,0 +1,111 @@
+// Scripted `noble debug` sessions: the commands come from a string and what the session
+// says is compared line by line, with the interpreter run the way the command runs it.
+
+use noble::ast::Ast;
+use noble::debug::Session;
+use noble::interpret::{InterpretError, Interpreter};
+use noble::pipeline::Pipeline;
+use std::io::Cursor;
+
+const PROGRAM: &str = "\
+fn square(i32s n) -> i32s {
+    i32s r = n * n;
+    return r;
+}
+fn bump(ref i32s x) -> void {
+    *x = *x + 1;
+}
+i32s total = 0;
+for i in 0 to 3 { total = total + square(i); }
+bump(&total);
+exit total;
+";
+
+fn checked(source: &str) -> Ast {
+    let mut checked = None;
+    Pipeline::new()
+        .on_ast(|ast| checked = Some(ast.clone()))
+        .compile(source)
+        .unwrap_or_else(|error| panic!("{}", error));
+    checked.unwrap()
+}
+
+// What the session printed and how the run ended
+fn debug(commands: &str) -> (String, Result<i32, InterpretError>) {
+    let ast = checked(PROGRAM);
+    let mut transcript = Vec::new();
+    let result = {
+        let mut interpreter = Interpreter::new(&ast);
+        interpreter.set_debugger(Session::new(
+            PROGRAM,
+            &ast,
+            Cursor::new(commands),
+            &mut transcript,
+        ));
+        interpreter.run().map(|outcome| outcome.status)
+    };
+    let transcript = String::from_utf8(transcript)
+        .unwrap()
+        .replace("(noble) ", "");
+    (transcript, result)
+}
+
+#[test]
+fn stops_at_breakpoints_and_prints_variables() {
+    let (transcript, result) = debug("b 9\nc\np total\nc\np tota\nlocals\nc\nq\n");
+    assert_eq!(
+        transcript.lines().collect::<Vec<_>>(),
+        [
+            "8:1 i32s total = 0;",
+            "breakpoint at line 9",
+            "9:1 for i in 0 to 3 { total = total + square(i); }",
+            "total = 0",
+            "9:19 for i in 0 to 3 { total = total + square(i); }",
+            "no variable tota in scope; did you mean total?",
+            "total = 0",
+            "i = 1",
+            "9:19 for i in 0 to 3 { total = total + square(i); }",
+        ]
+    );
+    // quit ends the run where it stopped
+    assert!(matches!(result, Err(InterpretError::Stopped)));
+}
+
+#[test]
+fn step_enters_calls_that_next_runs_over() {
+    let (transcript, _) = debug("b 9\nc\nn\ns\np n\nfinish\ndelete\nb 10\nc\nq\n");
+    let stops: Vec<&str> = transcript
+        .lines()
+        .filter(|line| line.contains(':'))
+        .collect();
+    assert_eq!(
+        stops,
+        [
+            "8:1 i32s total = 0;",
+            "9:1 for i in 0 to 3 { total = total + square(i); }",
+            "9:19 for i in 0 to 3 { total = total + square(i); }",
+            "2:5     i32s r = n * n;",
+            "9:19 for i in 0 to 3 { total = total + square(i); }",
+            "10:1 bump(&total);",
+        ]
+    );
+    assert!(transcript.contains("\nn = 0\n"));
+}
+
+#[test]
+fn refs_print_what_they_point_at_and_the_run_finishes() {
+    let (transcript, result) = debug("b 6\nc\np x\nlocals\ndelete\nc\n");
+    assert!(
+        transcript.contains("\n*x = 14\n*x = 14\n"),
+        "{}",
+        transcript
+    );
+    assert_eq!(result.unwrap(), 15);
+}
+
+#[test]
+fn breakpoints_move_to_the_next_statement() {
+    let (transcript, _) = debug("b 4\nb 12\nquit\n");
+    assert!(transcript.contains("breakpoint at line 6\n"));
+    assert!(transcript.contains("no statement at or after line 12\n"));
+}
@@ -0,0 +1,90 @@
+// `define`: a use stands for the expression itself, grouping and all, and the names in
+// it mean what they meant where it was defined.
+
+use noble::error::CompileError;
+use noble::format::unparse;
+use noble::pipeline::Pipeline;
+
+fn unparsed(source: &str) -> String {
+    let mut unparsed = String::new();
+    Pipeline::new()
+        .on_ast(|ast| unparsed = unparse(ast))
+        .compile(source)
+        .unwrap_or_else(|error| panic!("{}", error));
+    unparsed
+}
+
+fn parse_error(source: &str) -> String {
+    match Pipeline::new().compile(source) {
+        Err(CompileError::Parse(errors)) => errors[0].message.clone(),
+        _ => panic!("{} was accepted", source),
+    }
+}
+
+#[test]
+fn uses_are_the_expression_written_out() {
+    let defined = unparsed(
+        "i32s a = 1;\ni32s b = 2;\ndefine sum a + b;\ndefine twice sum * 2;\n\
+         i32s c = twice - sum;\nexit -sum * c;\n",
+    );
+    let written =
+        unparsed("i32s a = 1;\ni32s b = 2;\ni32s c = (a + b) * 2 - (a + b);\nexit -(a + b) * c;\n");
+    let uses: Vec<&str> = defined
+        .lines()
+        .filter(|line| !line.starts_with("define "))
+        .collect();
+    assert_eq!(uses, written.lines().collect::<Vec<_>>());
+}
+
+#[test]
+fn names_keep_the_meaning_they_had_where_defined() {
+    for (source, message) in [
+        (
+            "i32s x = 1;\ndefine d x + 1;\n{\n    i32s x = 5;\n    exit d;\n}\n",
+            "define d uses x declared at 1:6, but here x is the one declared at 4:10",
+        ),
+        (
+            "i32s x = 1;\ndefine d x;\nfn f(i32s x) -> i32s { return d; }\nexit f(2);\n",
+            "define d uses x declared at 1:6, but here x is the one declared at 3:11",
+        ),
+        (
+            "{\n    i32s x = 1;\n    define d x;\n}\nexit d;\n",
+            "Undefined variable d",
+        ),
+        ("define d 1;\nd = 2;\n", "Cannot assign to define d"),
+        ("define d 1;\nexit d();\n", "d is not a function"),
+    ] {
+        let error = parse_error(source);
+        assert!(error.contains(message), "{}: {}", source, error);
+    }
+    // the names still in scope are fine wherever the define is used
+    unparsed("i32s x = 1;\ndefine d x;\nfn f(i32s y) -> i32s { return d + y; }\nexit f(2);\n");
+}
+
+#[test]
+fn a_repeated_name_is_reported_where_it_is_repeated() {
+    for (source, message, column) in [
+        (
+            "define d 1;\ni32s d = 2;\n",
+            "Duplicate variable name in same scope: d (first declared as a define at 1:8)",
+            6,
+        ),
+        (
+            "i32s d = 1;\ndefine d 2;\n",
+            "Duplicate variable name in same scope: d (first declared as i32s at 1:6)",
+            8,
+        ),
+        (
+            "define read_int 1;\n",
+            "read_int is a builtin function and cannot be redeclared",
+            8,
+        ),
+    ] {
+        let errors = match Pipeline::new().compile(source) {
+            Err(CompileError::Parse(errors)) => errors,
+            _ => panic!("{} was accepted", source),
+        };
+        assert_eq!(errors[0].message, format!("ParseError: {}", message));
+        assert_eq!(errors[0].span.column, column, "{}", source);
+    }
+}
@@ -0,0 +1,108 @@
+// Parsing input cut off at a cursor, as an editor asks for completions: the tree made
+// from as much as parses, and the tokens that could come next.
+
+use noble::ast::Stmt;
+use noble::format;
+use noble::parse::Parser;
+use noble::tokenize::TokenType;
+
+const BINARY: [TokenType; 10] = [
+    TokenType::Plus,
+    TokenType::Minus,
+    TokenType::Star,
+    TokenType::Slash,
+    TokenType::Lt,
+    TokenType::Le,
+    TokenType::Gt,
+    TokenType::Ge,
+    TokenType::EqEq,
+    TokenType::NotEq,
+];
+
+#[test]
+fn expressions_are_closed_where_they_stop() {
+    let fragment = Parser::parse_expression_fragment("a + f(b, ");
+    let parsed = fragment.parsed.expect("a + f(b) parses");
+    assert_eq!(format::source(&parsed), "a + f(b)");
+    assert_eq!((fragment.span.start, fragment.span.end), (0, 7));
+    // anything an operand can start with
+    assert!(fragment.expected.contains(&TokenType::Ident));
+    assert!(fragment.expected.contains(&TokenType::IntLit));
+    assert!(fragment.expected.contains(&TokenType::LParen));
+    assert!(!fragment.expected.contains(&TokenType::RParen));
+    assert!(!fragment.expected.contains(&TokenType::Semi));
+
+    let fragment = Parser::parse_expression_fragment("(a * 2");
+    let mut expected = BINARY.to_vec();
+    expected.push(TokenType::RParen);
+    assert_eq!(fragment.expected, expected);
+}
+
+#[test]
+fn statements_before_the_cursor_are_kept() {
+    let fragment = Parser::parse_statement_fragment("i32s x = 1;\nif x > 1 { y = f(x");
+    let ast = fragment.parsed.expect("the fragment parses");
+    assert_eq!(
+        format::unparse(&ast),
+        "i32s x = 1;\nif x > 1 {\n    y = f(x);\n}\n"
+    );
+    let mut expected = BINARY.to_vec();
+    expected.extend([TokenType::RParen, TokenType::Comma]);
+    assert_eq!(fragment.expected, expected);
+
+    // x is a variable, so it can only be assigned
+    let fragment = Parser::parse_statement_fragment("i32s x = 1;\nx");
+    assert_eq!(
+        fragment.expected,
+        [
+            TokenType::PlusPlus,
+            TokenType::MinusMinus,
+            TokenType::Eq,
+            TokenType::Comma
+        ]
+    );
+
+    // spans point into the text; the block of a body is part of the statement it belongs
+    // to and has none of its own
+    let fragment = Parser::parse_statement_fragment("exit 1;\nfn g() -> i32s {\n    return 2");
+    let ast = fragment.parsed.unwrap();
+    let spans: Vec<(usize, usize)> = ast
+        .iter()
+        .skip(1)
+        .filter(|visit| !matches!(visit.node.stmt, Stmt::Block { .. }))
+        .map(|visit| (visit.node.span.line, visit.node.span.column))
+        .collect();
+    assert_eq!(spans, [(1, 1), (2, 1), (3, 5)]);
+    assert!(fragment.expected.contains(&TokenType::Semi));
+}
+
+#[test]
+fn nothing_is_expected_after_an_error() {
+    let fragment = Parser::parse_expression_fragment("1 +* 2");
+    assert!(fragment.expected.is_empty());
+    let fragment = Parser::parse_statement_fragment("exit 1 1");
+    assert!(fragment.expected.is_empty());
+    // an empty program is a program; an empty expression is not
+    assert!(Parser::parse_statement_fragment("").parsed.is_some());
+    assert!(Parser::parse_expression_fragment("").parsed.is_none());
+}
+
+#[test]
+fn names_are_the_ones_in_scope_at_the_cursor() {
+    let fragment =
+        Parser::parse_statement_fragment("i32s x = 1;\nfn g(i32s a) -> i32s {\n    i32s b = ");
+    let names: Vec<String> = fragment
+        .names
+        .iter()
+        .map(|symbol| symbol.name.to_string())
+        .collect();
+    // g can call itself, and b is not declared until its initializer has been read
+    assert_eq!(names, ["a", "g", "read_int", "x"]);
+    let fragment = Parser::parse_statement_fragment("i32s x = 1;\nfn g(i32s a) -> i32s {}\n");
+    let names: Vec<String> = fragment
+        .names
+        .iter()
+        .map(|symbol| symbol.name.to_string())
+        .collect();
+    assert_eq!(names, ["g", "read_int", "x"]);
+}
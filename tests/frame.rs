@@ -0,0 +1,156 @@
+// The stack frame every routine opens in its prologue and takes down at each return.
+
+mod common;
+
+use noble::asm::{Line, Operand};
+use noble::target::{TargetSpec, X86_64_LINUX, X86_64_WINDOWS};
+
+const PROGRAM: &str = "
+i32s total = 0;
+fn none() -> void { total = total + 1; }
+fn one(i32s a) -> i32s = a + 1;
+fn locals(i32s a, i32s b) -> i32s {
+    i32s c = a * b;
+    for i32s i in 0 to 3 {
+        i32s d = i + c;
+        if d > 4 { i32s e = d; c = c + e; }
+    }
+    { i32s f = c; c = f + 1; }
+    return c;
+}
+fn many(i32s a, i32s b, i32s c, i32s d, i32s e, i32s f, i32s g) -> i32s {
+    i32s h = a + b + c + d + e + f + g;
+    return h;
+}
+export fn twice(i32s x) -> i32s = x * 2;
+export fn spread(i32s a, f32s b, i32s c, i32s d, i32s e, i32s f, i32s g) -> i32s = a + g;
+none();
+exit locals(one(1), 2) + many(1, 2, 3, 4, 5, 6, 7) + twice(3) + total;
+";
+
+// mnemonic and operands
+type Instruction = (String, Vec<Operand>);
+
+// The instructions of each routine, by the label it starts at
+fn routines(target: &'static TargetSpec) -> Vec<(String, Vec<Instruction>)> {
+    let lines = common::generated(PROGRAM, |pipeline| pipeline.target(target));
+    let starts = |name: &str| {
+        name == target.entry
+            || !name.contains('.')
+            || name.starts_with("fn.") && !name.ends_with(".return")
+    };
+    let mut routines: Vec<(String, Vec<_>)> = Vec::new();
+    for line in lines {
+        match line {
+            Line::Label(name) if starts(&name) => routines.push((name, Vec::new())),
+            Line::Instruction { mnemonic, operands } => {
+                if let Some((_, instructions)) = routines.last_mut() {
+                    instructions.push((mnemonic, operands));
+                }
+            }
+            _ => {}
+        }
+    }
+    routines
+}
+
+fn register(name: &str) -> Operand {
+    Operand::Register(name.to_string())
+}
+
+#[test]
+fn every_routine_opens_an_aligned_frame_big_enough_for_its_slots() {
+    for target in [&X86_64_WINDOWS, &X86_64_LINUX] {
+        let routines = routines(target);
+        assert_eq!(routines.len(), 9, "{}", target.name);
+        for (name, instructions) in routines {
+            let mut code = instructions.iter().peekable();
+            // the freestanding entry point is not called, so nothing above its frame
+            let mut pushed = if name == target.entry && target.freestanding {
+                assert_eq!(code.next(), Some(&("xor".into(), vec![register("ebp"); 2])));
+                0
+            } else {
+                8
+            };
+            while let Some((_, operands)) = code.next_if(|(mnemonic, _)| mnemonic == "push") {
+                pushed += 8;
+                if operands == &[register("rbp")] {
+                    break;
+                }
+            }
+            let setup = code.next();
+            assert_eq!(
+                setup,
+                Some(&("mov".into(), vec![register("rbp"), register("rsp")])),
+                "{} on {}",
+                name,
+                target.name
+            );
+            let size = match code.peek() {
+                Some((mnemonic, operands)) if mnemonic == "sub" => match operands.as_slice() {
+                    [rsp, Operand::Immediate(size)] if *rsp == register("rsp") => *size,
+                    _ => 0,
+                },
+                _ => 0,
+            };
+            assert_eq!((pushed + size) % 16, 0, "{} on {}", name, target.name);
+
+            let lowest = instructions
+                .iter()
+                .flat_map(|(_, operands)| operands)
+                .filter_map(|operand| match operand {
+                    Operand::Memory { base, offset, .. } if base == "rbp" => Some(*offset),
+                    _ => None,
+                })
+                .min()
+                .unwrap_or(0);
+            assert!(
+                lowest >= -size,
+                "{} on {} uses [rbp{}] below its {}-byte frame",
+                name,
+                target.name,
+                lowest,
+                size
+            );
+        }
+    }
+}
+
+#[test]
+fn every_return_takes_the_frame_down() {
+    for target in [&X86_64_WINDOWS, &X86_64_LINUX] {
+        for (name, instructions) in routines(target) {
+            let saved: Vec<_> = instructions
+                .iter()
+                .take_while(|(mnemonic, _)| mnemonic == "push")
+                .map(|(_, operands)| operands.clone())
+                .collect();
+            let returns: Vec<_> = instructions
+                .iter()
+                .enumerate()
+                .filter(|(_, (mnemonic, _))| mnemonic == "ret")
+                .map(|(i, _)| i)
+                .collect();
+            // the program ends in the runtime's exit rather than returning
+            assert_eq!(returns.is_empty(), name == target.entry, "{}", name);
+            for i in returns {
+                let epilogue = &instructions[i - saved.len() - 1..i];
+                assert_eq!(
+                    epilogue[0],
+                    ("mov".into(), vec![register("rsp"), register("rbp")]),
+                    "{} on {}",
+                    name,
+                    target.name
+                );
+                let popped: Vec<_> = epilogue[1..]
+                    .iter()
+                    .map(|(mnemonic, operands)| {
+                        assert_eq!(mnemonic, "pop");
+                        operands.clone()
+                    })
+                    .collect();
+                assert_eq!(popped, saved.iter().rev().cloned().collect::<Vec<_>>());
+            }
+        }
+    }
+}
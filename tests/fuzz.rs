@@ -0,0 +1,95 @@
+// Programs from noble::fuzz. Each must build at every optimization level and do the same
+// in the interpreter whether or not its AST was optimized, and between them the programs
+// must use every token the language has, so that the generator keeps up with the grammar.
+
+#![cfg(feature = "grammar-fuzz")]
+
+use noble::ast::Ast;
+use noble::fuzz::Generator;
+use noble::interpret::Interpreter;
+use noble::optimize;
+use noble::pipeline::Pipeline;
+use noble::tokenize::{TOKEN_TYPES, TokenType, Tokenizer};
+
+const SEEDS: u64 = 200;
+
+fn programs() -> impl Iterator<Item = (u64, String)> {
+    (0..SEEDS).map(|seed| (seed, Generator::new(seed).program()))
+}
+
+// every other seed is built and run with checked arithmetic
+fn checked(seed: u64, source: &str, opt_level: u8) -> Ast {
+    let mut checked = None;
+    Pipeline::new()
+        .opt_level(opt_level)
+        .checked_arithmetic(seed.is_multiple_of(2))
+        .on_ast(|ast| checked = Some(ast.clone()))
+        .compile(source)
+        .unwrap_or_else(|error| panic!("seed {}: {}\n{}", seed, error, source));
+    checked.unwrap()
+}
+
+// The exit status and what was printed
+fn run(seed: u64, ast: &Ast) -> (i32, String) {
+    let mut interpreter = Interpreter::new(ast);
+    interpreter.set_checked_arithmetic(seed.is_multiple_of(2));
+    let outcome = interpreter
+        .run()
+        .unwrap_or_else(|error| panic!("seed {}: {}", seed, error));
+    (outcome.status, outcome.output)
+}
+
+#[test]
+fn programs_build_and_optimizing_keeps_what_they_do() {
+    for (seed, source) in programs() {
+        for opt_level in [1, 2] {
+            checked(seed, &source, opt_level);
+        }
+        let ast = checked(seed, &source, 0);
+        let mut optimized = ast.clone();
+        optimize::PassManager::new(2).run_ast(&mut optimized);
+        assert_eq!(
+            run(seed, &ast),
+            run(seed, &optimized),
+            "seed {}\n{}",
+            seed,
+            source
+        );
+    }
+}
+
+#[test]
+fn programs_use_every_token() {
+    let mut used = Vec::new();
+    for (_, source) in programs() {
+        let tokens = Tokenizer::new(source).try_tokenize().unwrap();
+        used.extend(tokens.iter().map(|token| token.token_type));
+    }
+    let unused: Vec<&TokenType> = TOKEN_TYPES
+        .iter()
+        .filter(|token_type| !used.contains(token_type))
+        .collect();
+    assert!(unused.is_empty(), "never generated: {:?}", unused);
+}
+
+#[test]
+fn settings_shape_the_programs() {
+    assert_eq!(Generator::new(7).program(), Generator::new(7).program());
+    let mut generator = Generator::new(7)
+        .statement_depth(0)
+        .expression_depth(0)
+        .identifiers(1);
+    for _ in 0..20 {
+        let source = generator.program();
+        assert!(!source.contains("v1"), "{}", source);
+        // with no nesting, blocks only come from functions
+        assert!(
+            source
+                .lines()
+                .all(|line| !line.starts_with("    ") || !line.ends_with('{')),
+            "{}",
+            source
+        );
+        checked(0, &source, 0);
+    }
+}
@@ -0,0 +1,140 @@
+// The grammar table against everything it describes: the documents that print it, the
+// tokenizer's keywords and punctuation, and the parser's precedence levels.
+
+use noble::grammar::{RULES, Symbol, ebnf};
+use noble::parse::LEVELS;
+use noble::tokenize::{CONTEXTUAL_KEYWORDS, KEYWORDS, TokenType, Tokenizer};
+use std::collections::HashSet;
+
+// Every symbol in a production, the production itself first
+fn symbols(symbol: &'static Symbol, out: &mut Vec<&'static Symbol>) {
+    out.push(symbol);
+    match symbol {
+        Symbol::Sequence(parts) | Symbol::Choice(parts) => {
+            for part in *parts {
+                symbols(part, out);
+            }
+        }
+        Symbol::Optional(part) | Symbol::ZeroOrMore(part) | Symbol::OneOrMore(part) => {
+            symbols(part, out)
+        }
+        _ => {}
+    }
+}
+
+fn all_symbols() -> Vec<&'static Symbol> {
+    let mut out = Vec::new();
+    for rule in &RULES {
+        symbols(&rule.production, &mut out);
+    }
+    out
+}
+
+// The rules a production names, counting the next level a precedence level chains over
+fn referenced() -> HashSet<&'static str> {
+    let mut names = HashSet::new();
+    for symbol in all_symbols() {
+        match symbol {
+            Symbol::Rule(name) => {
+                names.insert(*name);
+            }
+            Symbol::Level(precedence) => {
+                names.insert(
+                    LEVELS
+                        .get(*precedence as usize)
+                        .map_or("Primary", |next| next.rule),
+                );
+            }
+            _ => {}
+        }
+    }
+    names
+}
+
+#[test]
+fn documents_print_the_grammar() {
+    let grammar = ebnf();
+    for path in ["docs/grammar.md", "README.md"] {
+        let text =
+            std::fs::read_to_string(format!("{}/{}", env!("CARGO_MANIFEST_DIR"), path)).unwrap();
+        assert!(
+            text.contains(&format!("```\n{}```", grammar)),
+            "{} does not hold the output of --emit grammar-ebnf",
+            path
+        );
+    }
+}
+
+#[test]
+fn every_rule_is_defined_once_and_used() {
+    let mut defined = HashSet::new();
+    for rule in &RULES {
+        assert!(defined.insert(rule.name), "{} is defined twice", rule.name);
+    }
+    let referenced = referenced();
+    for name in &referenced {
+        assert!(defined.contains(name), "{} is used but never defined", name);
+    }
+    // the start rule, and comments, which the tokenizer drops between any two tokens
+    for rule in RULES.iter().skip(1).filter(|rule| rule.name != "Comment") {
+        assert!(
+            referenced.contains(rule.name),
+            "{} is never used",
+            rule.name
+        );
+    }
+}
+
+#[test]
+fn tokens_are_single_tokens_and_cover_the_keywords() {
+    let mut spelled = HashSet::new();
+    for symbol in all_symbols() {
+        let Symbol::Token(spelling) = symbol else {
+            continue;
+        };
+        spelled.insert(*spelling);
+        // comments never reach the token stream
+        if *spelling == "//" {
+            continue;
+        }
+        // after the entry point token every stream starts with
+        let tokens = Tokenizer::new(spelling.to_string()).try_tokenize().unwrap();
+        assert_eq!(tokens.len(), 2, "{:?} is not one token", spelling);
+        let token = &tokens[1].token_type;
+        // a contextual keyword is an identifier the parser looks at the name of
+        let contextual = CONTEXTUAL_KEYWORDS.contains(spelling) && *token == TokenType::Ident;
+        assert!(
+            token.spelling() == Some(*spelling) || *token == TokenType::BoolLit || contextual,
+            "{:?} is not spelled like its token {:?}",
+            spelling,
+            token
+        );
+    }
+    for keyword in KEYWORDS
+        .iter()
+        .map(|(keyword, _)| keyword)
+        .chain(&CONTEXTUAL_KEYWORDS)
+    {
+        assert!(
+            spelled.contains(keyword),
+            "keyword {} is not in the grammar",
+            keyword
+        );
+    }
+}
+
+#[test]
+fn every_precedence_level_has_its_rule() {
+    for (index, level) in LEVELS.iter().enumerate() {
+        let rule = RULES
+            .iter()
+            .find(|rule| rule.name == level.rule)
+            .unwrap_or_else(|| panic!("level {} has no rule {}", level.name, level.rule));
+        assert!(
+            matches!(rule.production, Symbol::Level(p) if p as usize == index + 1),
+            "rule {} is not written out from level {}",
+            rule.name,
+            index + 1
+        );
+    }
+}
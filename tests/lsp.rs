@@ -0,0 +1,190 @@
+// The language server's completion, hover and diagnostics, driven over its JSON-RPC
+// framing the way an editor drives it.
+
+use noble::json::Json;
+use noble::lsp::Server;
+use noble::target::{TargetSpec, X86_64_LINUX, X86_64_WINDOWS};
+use std::io::Cursor;
+
+const DOCUMENT: &str = "\
+const LIMIT = 10;
+fn square(i32s n) -> i32s {
+    return n * n;
+}
+i32s total = 0;
+for i in 0 to LIMIT {
+    total = total + square(i);
+}
+";
+
+fn frame(message: &str) -> String {
+    format!("Content-Length: {}\r\n\r\n{}", message.len(), message)
+}
+
+// The replies to requests with ids, after opening DOCUMENT, by id
+fn ask(requests: &[(&str, &str)]) -> Vec<Json> {
+    let open = format!(
+        r#"{{"jsonrpc":"2.0","method":"textDocument/didOpen","params":{{"textDocument":{{"uri":"file:///a.nbl","text":{}}}}}}}"#,
+        Json::String(DOCUMENT.to_string())
+    );
+    let mut input = frame(&open);
+    for (id, (method, params)) in requests.iter().enumerate() {
+        input.push_str(&frame(&format!(
+            r#"{{"jsonrpc":"2.0","id":{},"method":"{}","params":{}}}"#,
+            id, method, params
+        )));
+    }
+    input.push_str(&frame(r#"{"jsonrpc":"2.0","method":"exit"}"#));
+    let mut output = Vec::new();
+    Server::new()
+        .run(&mut Cursor::new(input), &mut output)
+        .unwrap();
+    let output = String::from_utf8(output).unwrap();
+    output
+        .split("Content-Length: ")
+        .filter_map(|message| message.split_once("\r\n\r\n"))
+        .map(|(_, body)| Json::parse(body).unwrap())
+        .filter(|reply| reply.get("id").is_some())
+        .map(|reply| reply.get("result").unwrap().clone())
+        .collect()
+}
+
+// The messages of the diagnostics published for `text` when checked for `target`
+fn published(text: &str, target: &'static TargetSpec) -> Vec<String> {
+    let open = format!(
+        r#"{{"jsonrpc":"2.0","method":"textDocument/didOpen","params":{{"textDocument":{{"uri":"file:///a.nbl","text":{}}}}}}}"#,
+        Json::String(text.to_string())
+    );
+    let mut output = Vec::new();
+    Server::new()
+        .target(target)
+        .run(&mut Cursor::new(frame(&open)), &mut output)
+        .unwrap();
+    let output = String::from_utf8(output).unwrap();
+    let (_, body) = output.split_once("\r\n\r\n").unwrap();
+    match Json::parse(body)
+        .unwrap()
+        .get("params")
+        .and_then(|p| p.get("diagnostics"))
+    {
+        Some(Json::Array(diagnostics)) => diagnostics
+            .iter()
+            .map(|d| d.get("message").and_then(Json::as_str).unwrap().to_string())
+            .collect(),
+        other => panic!("no diagnostics published: {:?}", other),
+    }
+}
+
+fn at(line: usize, character: usize) -> String {
+    format!(
+        r#"{{"textDocument":{{"uri":"file:///a.nbl"}},"position":{{"line":{},"character":{}}}}}"#,
+        line, character
+    )
+}
+
+fn labels(items: &Json) -> Vec<(String, Option<String>)> {
+    match items {
+        Json::Array(items) => items
+            .iter()
+            .map(|item| {
+                (
+                    item.get("label")
+                        .and_then(Json::as_str)
+                        .unwrap()
+                        .to_string(),
+                    item.get("detail")
+                        .and_then(Json::as_str)
+                        .map(str::to_string),
+                )
+            })
+            .collect(),
+        _ => panic!("not a list of items: {}", items),
+    }
+}
+
+#[test]
+fn completion_offers_keywords_and_names_in_scope() {
+    // inside the loop, at the start of `total` and part way through it
+    let replies = ask(&[
+        ("textDocument/completion", &at(6, 4)),
+        ("textDocument/completion", &at(6, 7)),
+        ("textDocument/completion", &at(2, 11)),
+    ]);
+    let items = labels(&replies[0]);
+    assert_eq!(items, labels(&replies[1]));
+    for expected in [
+        ("LIMIT", "const LIMIT = 10"),
+        ("i", "i32s i"),
+        ("read_int", "fn read_int() -> i32s"),
+        ("square", "fn square(i32s) -> i32s"),
+        ("total", "i32s total"),
+    ] {
+        assert!(
+            items.contains(&(expected.0.to_string(), Some(expected.1.to_string()))),
+            "{:?} missing from {:?}",
+            expected,
+            items
+        );
+    }
+    assert!(items.contains(&("while".to_string(), None)));
+    assert!(!items.contains(&("return".to_string(), None)));
+    assert!(!items.iter().any(|(label, _)| label == "n"));
+
+    // in an expression in the function: its parameter, and no statement keywords
+    let items = labels(&replies[2]);
+    assert!(items.contains(&("n".to_string(), Some("i32s n".to_string()))));
+    assert!(items.contains(&("true".to_string(), None)));
+    assert!(
+        !items
+            .iter()
+            .any(|(label, _)| label == "total" || label == "while")
+    );
+}
+
+#[test]
+fn hover_shows_the_declaration() {
+    let replies = ask(&[
+        ("textDocument/hover", &at(6, 24)),
+        ("textDocument/hover", &at(4, 6)),
+        ("textDocument/hover", &at(5, 15)),
+        ("textDocument/hover", &at(3, 0)),
+    ]);
+    let contents = |reply: &Json| {
+        reply
+            .get("contents")
+            .and_then(|c| c.get("value"))
+            .and_then(Json::as_str)
+            .unwrap()
+            .to_string()
+    };
+    assert_eq!(
+        contents(&replies[0]),
+        "```noble\nfn square(i32s) -> i32s\n```\ndeclared at 2:4"
+    );
+    // on the declaration itself
+    assert_eq!(
+        contents(&replies[1]),
+        "```noble\ni32s total\n```\ndeclared at 5:6"
+    );
+    assert_eq!(
+        contents(&replies[2]),
+        "```noble\nconst LIMIT = 10\n```\ndeclared at 1:7"
+    );
+    assert!(matches!(replies[3], Json::Null));
+}
+
+#[test]
+fn out_of_range_exit_codes_are_reported_for_targets_that_truncate_them() {
+    let linux = published("exit 200 + 100;\n", &X86_64_LINUX);
+    assert_eq!(linux.len(), 1, "{:?}", linux);
+    assert!(linux[0].contains("exit code 300"), "{}", linux[0]);
+    assert!(linux[0].contains("reported as 44"), "{}", linux[0]);
+    assert_eq!(
+        published("exit 300;\n", &X86_64_WINDOWS),
+        Vec::<String>::new()
+    );
+    assert_eq!(
+        published("exit 255;\n", &X86_64_LINUX),
+        Vec::<String>::new()
+    );
+}
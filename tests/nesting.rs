@@ -0,0 +1,31 @@
+// The nesting limit against long operator chains, which build trees as deep as they are long.
+
+use noble::error::CompileError;
+use noble::pipeline::Pipeline;
+
+fn chain(terms: usize) -> String {
+    format!("i32s x = {};\nexit x;\n", vec!["1"; terms].join(" + "))
+}
+
+#[test]
+fn a_chain_past_the_limit_is_a_parse_error() {
+    match Pipeline::new().check(&chain(20_000)) {
+        Err(CompileError::Parse(errors)) => {
+            assert_eq!(errors.len(), 1, "{:?}", errors);
+            assert!(
+                errors[0].message.contains("expression too deeply nested"),
+                "{}",
+                errors[0].message
+            );
+        }
+        Err(error) => panic!("{}", error),
+        Ok(_) => panic!("a 20000-term chain was accepted"),
+    }
+}
+
+#[test]
+fn a_chain_within_the_limit_builds() {
+    let output = Pipeline::new().opt_level(2).compile(&chain(200));
+    assert!(output.is_ok(), "{}", output.err().unwrap());
+    assert!(Pipeline::new().max_nesting(20).check(&chain(30)).is_err());
+}
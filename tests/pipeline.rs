@@ -0,0 +1,61 @@
+// Pipeline settings that depend on each other, whatever order they are made in.
+
+mod common;
+
+use noble::error::CompileError;
+use noble::pipeline::Pipeline;
+use noble::target::{AARCH64_LINUX, X86_64_LINUX, X86_64_WINDOWS};
+
+const PROGRAM: &str = "exit 3;";
+
+#[test]
+fn freestanding_with_a_hosted_target_fails_in_either_order() {
+    for mut pipeline in [
+        Pipeline::new().target(&X86_64_WINDOWS).freestanding(true),
+        Pipeline::new().freestanding(true).target(&X86_64_WINDOWS),
+    ] {
+        match pipeline.compile(PROGRAM) {
+            Err(CompileError::Target(message)) => {
+                assert!(message.contains("x86_64-windows"), "{}", message)
+            }
+            Err(error) => panic!("{}", error),
+            Ok(_) => panic!("a hosted target was built freestanding"),
+        }
+    }
+}
+
+#[test]
+fn freestanding_keeps_the_target_it_is_given() {
+    let linux = common::assembly(PROGRAM, |pipeline| pipeline.target(&X86_64_LINUX));
+    assert_eq!(
+        common::assembly(PROGRAM, |pipeline| pipeline.freestanding(true)),
+        linux
+    );
+    assert_eq!(
+        common::assembly(PROGRAM, |pipeline| pipeline
+            .target(&X86_64_LINUX)
+            .freestanding(false)),
+        linux
+    );
+    assert_eq!(
+        common::assembly(PROGRAM, |pipeline| pipeline
+            .freestanding(true)
+            .target(&X86_64_LINUX)),
+        linux
+    );
+    assert_ne!(
+        common::assembly(PROGRAM, |pipeline| pipeline.freestanding(false)),
+        linux
+    );
+
+    // the target stays whatever it is, even one the generator cannot build for
+    for mut pipeline in [
+        Pipeline::new().target(&AARCH64_LINUX).freestanding(false),
+        Pipeline::new().freestanding(false).target(&AARCH64_LINUX),
+    ] {
+        assert!(matches!(
+            pipeline.compile(PROGRAM),
+            Err(CompileError::Target(message)) if message.contains("aarch64")
+        ));
+    }
+}
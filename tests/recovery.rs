@@ -0,0 +1,43 @@
+// Parse errors after a statement fails, including a '{' the input never closes.
+
+use noble::error::CompileError;
+use noble::pipeline::Pipeline;
+
+fn parse_errors(source: &str) -> Vec<String> {
+    match Pipeline::new().check(source) {
+        Err(CompileError::Parse(errors)) => errors.into_iter().map(|e| e.message).collect(),
+        Err(error) => panic!("{}", error),
+        Ok(_) => panic!("{:?} parsed", source),
+    }
+}
+
+#[test]
+fn an_unclosed_brace_is_reported_after_an_error_inside_it() {
+    let errors =
+        parse_errors("i32s x = 0;\nwhile x < 5 {\n    x = x + ;\n    x = x + 1;\n\nexit x;\n");
+    assert_eq!(
+        errors,
+        [
+            "ParseError: expected an expression, found ';'",
+            "ParseError: unclosed '{' (opened at 2:13)",
+        ]
+    );
+}
+
+#[test]
+fn an_unclosed_brace_at_the_end_of_the_input_is_reported_once() {
+    let errors =
+        parse_errors("i32s x = 0;\nwhile x < 5 {\n    if x > 2 {\n        x = 1;\n    }\n");
+    assert_eq!(errors, ["ParseError: unclosed '{' (opened at 2:13)"]);
+}
+
+#[test]
+fn the_statements_after_a_closed_block_are_still_parsed() {
+    let errors = parse_errors("while true {\n    i32s y = ;\n}\nexit 1 +;\n");
+    assert_eq!(errors.len(), 2, "{:?}", errors);
+    assert!(
+        errors.iter().all(|e| !e.contains("unclosed")),
+        "{:?}",
+        errors
+    );
+}
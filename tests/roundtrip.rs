@@ -0,0 +1,533 @@
+// Round trip through the printer: a random program's AST, written out with
+// format::unparse, must already be in the layout `noble fmt` gives, and tokenizing and
+// parsing that text must give the same tree back. The programs are valid as far as the
+// parser checks (names in scope, return only in a function, functions only at the top
+// level); they need not type check.
+
+use noble::arena::Arena;
+use noble::ast::{Ast, BinOpType, Expr, ExprKind, Linkage, Node, NodeId, Stmt, Type};
+use noble::consteval::Value;
+use noble::format::{format_source, unparse};
+use noble::intern::Name;
+use noble::parse::Parser;
+use noble::tokenize::{Span, Tokenizer};
+use proptest::collection::{btree_map, vec};
+use proptest::option;
+use proptest::prelude::*;
+use std::collections::BTreeMap;
+
+// The shape of a program as proptest draws it. Names are indices into whatever is in
+// scope where they are used, so that shrinking a program keeps it valid.
+#[derive(Debug, Clone)]
+enum ExprShape {
+    Int(i32),
+    Float(f32),
+    Bool(bool),
+    Char(char),
+    Variable(usize),
+    AddressOf(usize),
+    Deref(usize),
+    Call(usize, Vec<ExprShape>),
+    Binary(Box<ExprShape>, usize, Box<ExprShape>),
+    Negate(Box<ExprShape>),
+    // true for f32s(...), false for i32s(...)
+    Convert(bool, Box<ExprShape>),
+}
+
+#[derive(Debug, Clone)]
+enum StmtShape {
+    Exit(ExprShape),
+    PrintInt(ExprShape),
+    Assert(ExprShape),
+    Declare(usize, Option<ExprShape>),
+    Assign(usize, ExprShape),
+    DerefAssign(usize, ExprShape),
+    Call(usize, Vec<ExprShape>),
+    Return(Option<ExprShape>),
+    Const(Value),
+    Block(Vec<StmtShape>),
+    // an else that is not itself an if becomes an else block holding it
+    If(ExprShape, Vec<StmtShape>, Option<Box<StmtShape>>),
+    While(ExprShape, Vec<StmtShape>),
+    DoWhile(Vec<StmtShape>, ExprShape),
+    // begin, end, inclusive, the iterator's type and the step
+    For(
+        ExprShape,
+        ExprShape,
+        bool,
+        usize,
+        Option<ExprShape>,
+        Vec<StmtShape>,
+    ),
+    Match(
+        ExprShape,
+        BTreeMap<u16, Vec<StmtShape>>,
+        Option<Vec<StmtShape>>,
+    ),
+}
+
+#[derive(Debug, Clone)]
+enum Item {
+    Statement(StmtShape),
+    Function {
+        linkage: usize,
+        // (type, whether it is a ref parameter)
+        params: Vec<(usize, bool)>,
+        return_type: usize,
+        body: Vec<StmtShape>,
+    },
+}
+
+const OPERATORS: [BinOpType; 10] = [
+    BinOpType::Multiply,
+    BinOpType::Divide,
+    BinOpType::Add,
+    BinOpType::Subtract,
+    BinOpType::Equal,
+    BinOpType::NotEqual,
+    BinOpType::LessThan,
+    BinOpType::LessThanOrEqual,
+    BinOpType::GreaterThan,
+    BinOpType::GreaterThanOrEqual,
+];
+
+const TYPES: [Type; 4] = [Type::I32S, Type::F32S, Type::Bool, Type::Char];
+
+const LINKAGES: [Linkage; 3] = [Linkage::Internal, Linkage::Extern, Linkage::Export];
+
+// Char literals have no escapes, so any printable ASCII character is written as is
+fn printable() -> impl Strategy<Value = char> {
+    (0x20u8..0x7f).prop_map(char::from)
+}
+
+fn expression() -> impl Strategy<Value = ExprShape> {
+    let leaf = prop_oneof![
+        1 => any::<i32>().prop_map(ExprShape::Int),
+        // NaN and the infinities have no literal
+        1 => any::<f32>()
+            .prop_filter("finite", |float| float.is_finite())
+            .prop_map(ExprShape::Float),
+        1 => any::<bool>().prop_map(ExprShape::Bool),
+        1 => printable().prop_map(ExprShape::Char),
+        3 => (0..8usize).prop_map(ExprShape::Variable),
+        1 => (0..8usize).prop_map(ExprShape::AddressOf),
+        1 => (0..8usize).prop_map(ExprShape::Deref),
+    ];
+    leaf.prop_recursive(4, 24, 3, |inner| {
+        prop_oneof![
+            1 => (0..4usize, vec(inner.clone(), 0..3))
+                .prop_map(|(function, args)| ExprShape::Call(function, args)),
+            3 => (inner.clone(), 0..OPERATORS.len(), inner.clone()).prop_map(
+                |(left, op, right)| ExprShape::Binary(Box::new(left), op, Box::new(right))
+            ),
+            1 => inner
+                .clone()
+                .prop_map(|value| ExprShape::Negate(Box::new(value))),
+            1 => (any::<bool>(), inner).prop_map(|(float, value)| {
+                ExprShape::Convert(float, Box::new(value))
+            }),
+        ]
+    })
+}
+
+fn statement() -> impl Strategy<Value = StmtShape> {
+    let value = prop_oneof![
+        any::<i32>().prop_map(Value::Int),
+        any::<bool>().prop_map(Value::Bool),
+        printable().prop_map(Value::Char),
+    ];
+    let leaf = prop_oneof![
+        1 => expression().prop_map(StmtShape::Exit),
+        1 => expression().prop_map(StmtShape::PrintInt),
+        1 => expression().prop_map(StmtShape::Assert),
+        3 => (0..TYPES.len(), option::of(expression()))
+            .prop_map(|(type_, value)| StmtShape::Declare(type_, value)),
+        2 => (0..8usize, expression())
+            .prop_map(|(variable, value)| StmtShape::Assign(variable, value)),
+        1 => (0..8usize, expression())
+            .prop_map(|(variable, value)| StmtShape::DerefAssign(variable, value)),
+        1 => (0..4usize, vec(expression(), 0..3))
+            .prop_map(|(function, args)| StmtShape::Call(function, args)),
+        1 => option::of(expression()).prop_map(StmtShape::Return),
+        1 => value.prop_map(StmtShape::Const),
+    ];
+    leaf.prop_recursive(3, 24, 4, |inner| {
+        let body = || vec(inner.clone(), 0..4);
+        prop_oneof![
+            1 => body().prop_map(StmtShape::Block),
+            2 => (expression(), body(), option::of(inner.clone().prop_map(Box::new)))
+                .prop_map(|(condition, body, else_)| StmtShape::If(condition, body, else_)),
+            1 => (expression(), body())
+                .prop_map(|(condition, body)| StmtShape::While(condition, body)),
+            1 => (body(), expression())
+                .prop_map(|(body, condition)| StmtShape::DoWhile(body, condition)),
+            1 => (
+                expression(),
+                expression(),
+                any::<bool>(),
+                0..TYPES.len(),
+                option::of(expression()),
+                body()
+            )
+                .prop_map(|(begin, end, inclusive, type_, step, body)| {
+                    StmtShape::For(begin, end, inclusive, type_, step, body)
+                }),
+            1 => (
+                expression(),
+                btree_map(any::<u16>(), body(), 0..3),
+                option::of(body())
+            )
+                .prop_map(|(scrutinee, arms, else_)| StmtShape::Match(scrutinee, arms, else_)),
+        ]
+    })
+}
+
+fn program() -> impl Strategy<Value = Vec<Item>> {
+    let function = (
+        0..LINKAGES.len(),
+        vec((0..TYPES.len(), any::<bool>()), 0..3),
+        0..=TYPES.len(),
+        vec(statement(), 0..4),
+    )
+        .prop_map(|(linkage, params, return_type, body)| Item::Function {
+            linkage,
+            params,
+            return_type,
+            body,
+        });
+    vec(
+        prop_oneof![3 => statement().prop_map(Item::Statement), 1 => function],
+        0..8,
+    )
+}
+
+// Turns a shape into an AST, declaring every variable and function under a fresh name
+struct Builder {
+    nodes: Arena<Node>,
+    // (name, mutable) of the variables declared in each enclosing block, innermost last
+    scopes: Vec<Vec<(Name, bool)>>,
+    // (name, parameter count) of the functions declared so far
+    functions: Vec<(Name, usize)>,
+    in_function: bool,
+    names: usize,
+}
+
+impl Builder {
+    fn build(program: &[Item]) -> Ast {
+        let mut builder = Builder {
+            nodes: Arena::new(),
+            scopes: vec![Vec::new()],
+            functions: vec![(Name::intern("read_int"), 0)],
+            in_function: false,
+            names: 0,
+        };
+        let mut children = Vec::new();
+        for item in program {
+            match item {
+                Item::Statement(shape) => builder.statement(shape, &mut children),
+                Item::Function {
+                    linkage,
+                    params,
+                    return_type,
+                    body,
+                } => children.push(builder.function(*linkage, params, *return_type, body)),
+            }
+        }
+        let root = builder.node(Stmt::Entry);
+        builder.nodes[root].children = children;
+        Ast {
+            nodes: builder.nodes,
+            root,
+        }
+    }
+
+    fn fresh(&mut self, prefix: &str) -> Name {
+        self.names += 1;
+        Name::intern(&format!("{}{}", prefix, self.names))
+    }
+
+    fn node(&mut self, stmt: Stmt) -> NodeId {
+        self.nodes.alloc(Node {
+            stmt,
+            children: Vec::new(),
+            span: Span::default(),
+        })
+    }
+
+    // The nth variable in scope, counting round, of those `filter` accepts
+    fn variable(&self, n: usize, mutable_only: bool) -> Option<Name> {
+        let variables: Vec<Name> = self
+            .scopes
+            .iter()
+            .flatten()
+            .filter(|(_, mutable)| *mutable || !mutable_only)
+            .map(|(name, _)| *name)
+            .collect();
+        (!variables.is_empty()).then(|| variables[n % variables.len()])
+    }
+
+    fn declare(&mut self, name: Name, mutable: bool) {
+        self.scopes.last_mut().unwrap().push((name, mutable));
+    }
+
+    fn expr(&mut self, shape: &ExprShape) -> Expr {
+        let kind = match shape {
+            ExprShape::Int(value) => ExprKind::Int(*value),
+            ExprShape::Float(value) => ExprKind::Float(*value),
+            ExprShape::Bool(value) => ExprKind::Bool(*value),
+            ExprShape::Char(value) => ExprKind::Char(*value),
+            // with nothing in scope to name, a literal stands in
+            ExprShape::Variable(n) => match self.variable(*n, false) {
+                Some(name) => ExprKind::Ident(name),
+                None => ExprKind::Int(*n as i32),
+            },
+            ExprShape::AddressOf(n) => match self.variable(*n, true) {
+                Some(name) => ExprKind::AddressOf(name),
+                None => ExprKind::Int(*n as i32),
+            },
+            ExprShape::Deref(n) => match self.variable(*n, false) {
+                Some(name) => ExprKind::Deref(name),
+                None => ExprKind::Int(*n as i32),
+            },
+            ExprShape::Call(n, args) => {
+                let (name, arity) = self.functions[n % self.functions.len()];
+                let args = (0..arity)
+                    .map(|i| match args.get(i) {
+                        Some(arg) => self.expr(arg),
+                        None => ExprKind::Int(0).into(),
+                    })
+                    .collect();
+                ExprKind::Call { name, args }
+            }
+            ExprShape::Binary(left, op, right) => ExprKind::BinaryOp {
+                left: Box::new(self.expr(left)),
+                op: OPERATORS[*op].clone(),
+                right: Box::new(self.expr(right)),
+            },
+            // the parser folds a negated literal into a negative one, so a literal is
+            // left as it is rather than given a Negate the text cannot express
+            ExprShape::Negate(value) => {
+                let value = self.expr(value);
+                match value.kind {
+                    ExprKind::Int(_) | ExprKind::Float(_) => return value,
+                    _ => ExprKind::Negate {
+                        value: Box::new(value),
+                    },
+                }
+            }
+            ExprShape::Convert(float, value) => ExprKind::Convert {
+                value: Box::new(self.expr(value)),
+                to: if *float { Type::F32S } else { Type::I32S },
+            },
+        };
+        kind.into()
+    }
+
+    fn body(&mut self, shapes: &[StmtShape]) -> Vec<NodeId> {
+        self.scopes.push(Vec::new());
+        let mut body = Vec::new();
+        for shape in shapes {
+            self.statement(shape, &mut body);
+        }
+        self.scopes.pop();
+        body
+    }
+
+    // The body of a loop, branch, match arm or function, which is a block
+    fn block(&mut self, shapes: &[StmtShape]) -> NodeId {
+        let body = self.body(shapes);
+        self.node(Stmt::Block { body })
+    }
+
+    // Appends the statement to `out`, or nothing where it could not be written, e.g. an
+    // assignment with no variable in scope
+    fn statement(&mut self, shape: &StmtShape, out: &mut Vec<NodeId>) {
+        let stmt = match shape {
+            StmtShape::Exit(value) => Stmt::Exit(self.expr(value)),
+            StmtShape::PrintInt(value) => Stmt::PrintInt(self.expr(value)),
+            StmtShape::Assert(value) => Stmt::Assert(self.expr(value)),
+            StmtShape::Declare(type_, value) => {
+                let value = value.as_ref().map(|value| self.expr(value));
+                let name = self.fresh("v");
+                self.declare(name, true);
+                Stmt::VariableDeclaration {
+                    name,
+                    type_: TYPES[*type_].clone(),
+                    value,
+                }
+            }
+            StmtShape::Assign(n, value) => {
+                let Some(name) = self.variable(*n, true) else {
+                    return;
+                };
+                Stmt::VariableAssignment {
+                    name,
+                    value: self.expr(value),
+                }
+            }
+            StmtShape::DerefAssign(n, value) => {
+                let Some(name) = self.variable(*n, false) else {
+                    return;
+                };
+                Stmt::DerefAssignment {
+                    name,
+                    value: self.expr(value),
+                }
+            }
+            StmtShape::Call(n, args) => {
+                Stmt::Expression(self.expr(&ExprShape::Call(*n, args.clone())))
+            }
+            StmtShape::Return(value) => {
+                if !self.in_function {
+                    return;
+                }
+                Stmt::Return(value.as_ref().map(|value| self.expr(value)))
+            }
+            // never referred to: uses of a constant are replaced by its value
+            StmtShape::Const(value) => Stmt::ConstantDeclaration {
+                name: self.fresh("k"),
+                value: *value,
+            },
+            StmtShape::Block(body) => Stmt::Block {
+                body: self.body(body),
+            },
+            StmtShape::If(condition, body, else_) => Stmt::If {
+                condition: self.expr(condition),
+                body: self.block(body),
+                else_body: else_.as_deref().map(|else_| self.else_(else_)),
+            },
+            StmtShape::While(condition, body) => Stmt::While {
+                condition: self.expr(condition),
+                body: self.block(body),
+            },
+            StmtShape::DoWhile(body, condition) => Stmt::DoWhile {
+                body: self.block(body),
+                condition: self.expr(condition),
+            },
+            StmtShape::For(begin, end, inclusive, type_, step, body) => {
+                let iterator_begin = self.expr(begin);
+                let iterator_end = self.expr(end);
+                let step = step.as_ref().map(|step| self.expr(step));
+                let iterator_name = self.fresh("i");
+                self.scopes.push(vec![(iterator_name, false)]);
+                let body = self.block(body);
+                self.scopes.pop();
+                Stmt::For {
+                    iterator_name,
+                    type_: TYPES[*type_].clone(),
+                    iterator_begin,
+                    iterator_end,
+                    inclusive: *inclusive,
+                    step,
+                    body,
+                }
+            }
+            StmtShape::Match(scrutinee, arms, else_) => Stmt::Match {
+                scrutinee: self.expr(scrutinee),
+                arms: arms
+                    .iter()
+                    .map(|(value, body)| (*value as i32, self.block(body)))
+                    .collect(),
+                else_body: else_.as_ref().map(|body| self.block(body)),
+            },
+        };
+        out.push(self.node(stmt));
+    }
+
+    // The node after `else`: an if for else if, otherwise a block
+    fn else_(&mut self, shape: &StmtShape) -> NodeId {
+        if let StmtShape::If(..) = shape {
+            let mut out = Vec::new();
+            self.statement(shape, &mut out);
+            return out[0];
+        }
+        match shape {
+            StmtShape::Block(body) => self.block(body),
+            _ => self.block(std::slice::from_ref(shape)),
+        }
+    }
+
+    fn function(
+        &mut self,
+        linkage: usize,
+        params: &[(usize, bool)],
+        return_type: usize,
+        body: &[StmtShape],
+    ) -> NodeId {
+        let linkage = LINKAGES[linkage];
+        let params: Vec<(Name, Type)> = params
+            .iter()
+            .map(|&(type_, by_ref)| {
+                let type_ = TYPES[type_].clone();
+                let type_ = if by_ref {
+                    Type::Ref(Box::new(type_))
+                } else {
+                    type_
+                };
+                (self.fresh("p"), type_)
+            })
+            .collect();
+        let return_type = TYPES.get(return_type).cloned().unwrap_or(Type::Void);
+        let name = self.fresh("f");
+        // declared before its body, so it may call itself
+        self.functions.push((name, params.len()));
+        let body = match linkage {
+            Linkage::Extern => self.block(&[]),
+            _ => {
+                self.scopes
+                    .push(params.iter().map(|(name, _)| (*name, false)).collect());
+                self.in_function = true;
+                let body = self.block(body);
+                self.in_function = false;
+                self.scopes.pop();
+                body
+            }
+        };
+        self.node(Stmt::FunctionDeclaration {
+            name,
+            params,
+            return_type,
+            body,
+            linkage,
+        })
+    }
+}
+
+// Each statement with its depth, in the order Ast::iter visits them. Node ids are
+// written as `#`: they depend on the order the nodes were allocated in, which is not
+// part of the tree.
+fn structure(ast: &Ast) -> Vec<(usize, String)> {
+    ast.iter()
+        .map(|visit| {
+            let mut text = String::new();
+            let mut in_id = false;
+            for c in format!("{:?}", visit.node.stmt).chars() {
+                if !(in_id && c.is_ascii_digit()) {
+                    text.push(c);
+                }
+                in_id = c == '#' || (in_id && c.is_ascii_digit());
+            }
+            (visit.depth, text)
+        })
+        .collect()
+}
+
+proptest! {
+    #[test]
+    fn unparsed_source_parses_back_to_the_same_tree(program in program()) {
+        let ast = Builder::build(&program);
+        let source = unparse(&ast);
+
+        let formatted = format_source(&source).map_err(|e| TestCaseError::fail(e.to_string()))?;
+        prop_assert_eq!(&formatted, &source, "unparse and noble fmt lay the program out differently");
+
+        let tokens = Tokenizer::new(source.clone())
+            .try_tokenize()
+            .map_err(|e| TestCaseError::fail(format!("{}\n{}", e, source)))?;
+        let mut parser = Parser::new(tokens);
+        let reparsed = parser.parse_to_ast();
+        let errors: Vec<String> = parser.errors().iter().map(|e| e.to_string()).collect();
+        prop_assert!(errors.is_empty(), "{}\n{}", errors.join("\n"), source);
+        prop_assert_eq!(structure(&reparsed), structure(&ast), "{}", source);
+    }
+}
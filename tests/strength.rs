@@ -0,0 +1,100 @@
+// Strength reduction against the instructions it leaves for each factor and divisor.
+
+mod common;
+
+const PROGRAM: &str = "
+fn times_eight(i32s x) -> i32s = x * 8;
+fn eight_times(i32s x) -> i32s = 8 * x;
+fn quarter(i32s x) -> i32s = x / 4;
+fn negative_half(i32s x) -> i32s = x / -2;
+fn times_six(i32s x) -> i32s = x * 6;
+exit times_eight(1) + eight_times(2) + quarter(-7) + negative_half(-7) + times_six(3);
+";
+
+// The instructions of function `name` in the assembly built with these settings
+fn body(name: &str, opt_level: u8, checked_arithmetic: bool) -> Vec<String> {
+    let assembly = common::assembly(PROGRAM, |pipeline| {
+        pipeline
+            .opt_level(opt_level)
+            .checked_arithmetic(checked_arithmetic)
+    });
+    assembly
+        .lines()
+        .map(str::trim)
+        .skip_while(|line| *line != format!("fn.{}:", name))
+        .skip(1)
+        .take_while(|line| *line != format!("fn.{}.return:", name))
+        .filter(|line| !line.starts_with(';'))
+        .map(str::to_string)
+        .collect()
+}
+
+fn has(body: &[String], instruction: &str) -> bool {
+    body.iter().any(|line| line == instruction)
+}
+
+fn mnemonics(body: &[String]) -> Vec<&str> {
+    body.iter()
+        .filter_map(|line| line.split_whitespace().next())
+        .collect()
+}
+
+#[test]
+fn multiplying_by_a_power_of_two_is_a_left_shift() {
+    for name in ["times_eight", "eight_times"] {
+        for opt_level in [1, 2] {
+            let body = body(name, opt_level, false);
+            assert!(
+                has(&body, "shl eax, 3"),
+                "{} at -O{}: {:?}",
+                name,
+                opt_level,
+                body
+            );
+            assert!(!mnemonics(&body).contains(&"imul"), "{:?}", body);
+        }
+    }
+}
+
+#[test]
+fn dividing_by_a_power_of_two_shifts_and_rounds_toward_zero() {
+    let body = body("quarter", 2, false);
+    // cdq fills edx with the sign, so a negative dividend is raised by 3 before the
+    // shift and -7 / 4 is -1 as with idiv, not -2
+    let sequence = ["cdq", "and edx, 3", "add eax, edx", "sar eax, 2"];
+    let start = body.iter().position(|line| line == "cdq");
+    assert_eq!(
+        start.map(|start| &body[start..start + sequence.len()]),
+        Some(&sequence.map(str::to_string)[..]),
+        "{:?}",
+        body
+    );
+    assert!(!mnemonics(&body).contains(&"idiv"), "{:?}", body);
+}
+
+#[test]
+fn other_factors_and_divisors_keep_imul_and_idiv() {
+    let negative_half = body("negative_half", 2, false);
+    assert!(has(&negative_half, "mov ebx, -2"), "{:?}", negative_half);
+    assert!(has(&negative_half, "idiv ebx"), "{:?}", negative_half);
+    assert!(
+        !mnemonics(&negative_half).contains(&"sar"),
+        "{:?}",
+        negative_half
+    );
+
+    let times_six = body("times_six", 2, false);
+    assert!(has(&times_six, "imul eax, ebx"), "{:?}", times_six);
+    assert!(!mnemonics(&times_six).contains(&"shl"), "{:?}", times_six);
+}
+
+#[test]
+fn unoptimized_and_checked_products_are_left_alone() {
+    // without -O the pass does not run, and a checked product needs the overflow flag
+    // imul sets
+    for (opt_level, checked) in [(0, false), (2, true)] {
+        let body = body("times_eight", opt_level, checked);
+        assert!(has(&body, "imul eax, ebx"), "-O{}: {:?}", opt_level, body);
+        assert!(!mnemonics(&body).contains(&"shl"), "{:?}", body);
+    }
+}
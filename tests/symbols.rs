@@ -0,0 +1,151 @@
+// Generated symbols against the names a program can choose, which never collide with them.
+
+mod common;
+
+use noble::asm::{Line, Operand};
+use noble::error::CompileError;
+use noble::pipeline::Pipeline;
+use std::collections::HashSet;
+
+// Source names spelled like the labels of every construct below, as they were spelled
+// before the generator moved its own symbols out of their way
+const PROGRAM: &str = "
+fn f(i32s kind) -> i32s {
+    match kind {
+        0 => { return 1; }
+        1 => { return 2; }
+        2 => { return 3; }
+        3 => { return 4; }
+        else => { return 0; }
+    }
+}
+fn f_return() -> i32s { return 5; }
+export fn fn_f() -> i32s { return f(1); }
+i32s loop_begin_0 = 0;
+i32s program_exit = 0;
+i32s v_0_loop_begin_0 = 7;
+for i in 0 to 4 { loop_begin_0 = loop_begin_0 + i; }
+while program_exit < 3 { program_exit = program_exit + 1; }
+do { program_exit = program_exit - 1; } while program_exit > 1;
+if loop_begin_0 > v_0_loop_begin_0 { program_exit = program_exit * 2; } else { exit 1; }
+assert f(2) / program_exit == 1;
+exit f(3) + f_return() + loop_begin_0 / v_0_loop_begin_0 + fn_f();
+";
+
+fn generated(opt_level: u8) -> Vec<Line> {
+    common::generated(PROGRAM, |pipeline| {
+        pipeline
+            .freestanding(true)
+            .opt_level(opt_level)
+            .checked_arithmetic(true)
+    })
+}
+
+// The symbols the lines define, in order, and the ones their instructions refer to
+fn symbols(lines: &[Line]) -> (Vec<&str>, Vec<&str>) {
+    let mut defined = Vec::new();
+    let mut used = Vec::new();
+    for line in lines {
+        match line {
+            Line::Label(name) | Line::Reserve { name, .. } | Line::Asciz { name, .. } => {
+                defined.push(name.as_str())
+            }
+            Line::Instruction { operands, .. } => {
+                for operand in operands {
+                    match operand {
+                        Operand::Label(name) => used.push(name.as_str()),
+                        Operand::Memory { base, .. } if !noble::asm::is_register(base) => {
+                            used.push(base.as_str())
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    (defined, used)
+}
+
+#[test]
+fn generated_symbols_are_defined_once() {
+    for opt_level in [0, 2] {
+        let lines = generated(opt_level);
+        let (defined, used) = symbols(&lines);
+        let mut seen = HashSet::new();
+        for name in &defined {
+            assert!(seen.insert(*name), "{} is defined twice at -O{}", name, opt_level);
+        }
+        let externs: HashSet<&str> = lines
+            .iter()
+            .filter_map(|line| match line {
+                Line::Extern(name) => Some(name.as_str()),
+                _ => None,
+            })
+            .collect();
+        for name in used {
+            assert!(
+                seen.contains(name) || externs.contains(name),
+                "{} is used but never defined at -O{}",
+                name,
+                opt_level
+            );
+        }
+    }
+}
+
+#[test]
+fn only_linkage_names_lack_a_dot() {
+    let lines = generated(0);
+    let globals: HashSet<&str> = lines
+        .iter()
+        .filter_map(|line| match line {
+            Line::Global(name) => Some(name.as_str()),
+            _ => None,
+        })
+        .collect();
+    assert!(globals.contains("_start") && globals.contains("fn_f"));
+    for name in symbols(&lines).0 {
+        assert!(
+            name.contains('.') || globals.contains(name),
+            "{} can be spelled by a source name",
+            name
+        );
+    }
+}
+
+#[test]
+fn linkage_names_the_assembly_uses_are_rejected() {
+    for name in [
+        "rax",
+        "r8d",
+        "xmm3",
+        "rip",
+        "qword",
+        "wrt",
+        "noble_exit",
+        "noble_print_int_format",
+        "_start",
+        "mainCRTStartup",
+    ] {
+        for source in [
+            format!("extern fn {}() -> i32s;\nexit {}();", name, name),
+            format!("export fn {}() -> i32s {{ return 0; }}\nexit 0;", name),
+        ] {
+            match Pipeline::new().compile(&source) {
+                Err(CompileError::Type(errors)) => {
+                    assert!(
+                        errors[0].message.contains("cannot keep its name in the assembly"),
+                        "{}",
+                        errors[0].message
+                    )
+                }
+                _ => panic!("{} was accepted", source),
+            }
+        }
+    }
+    for name in ["xmm", "xmm16", "ripple", "noble", "start", "words"] {
+        let source = format!("export fn {}() -> i32s {{ return 0; }}\nexit 0;", name);
+        assert!(Pipeline::new().compile(&source).is_ok(), "{} was rejected", name);
+    }
+}